@@ -0,0 +1,128 @@
+// Cold storage for resolved market data
+//
+// The backend pushes each archived market as an opaque blob, in chunks, then
+// commits it and checks the returned SHA-256 against its own. Committed blobs
+// are immutable and served back in chunks for the backend to decode.
+use candid::{CandidType, Deserialize, Principal};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+// Largest chunk accepted or served per call, well under the message size limit
+const MAX_CHUNK_SIZE: usize = 1_000_000;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ArchiveInit {
+    pub writer: Principal, // the backend canister
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct StableState {
+    writer: Option<Principal>,
+    pending: Vec<(u64, Vec<u8>)>,
+    committed: Vec<(u64, Vec<u8>)>,
+}
+
+thread_local! {
+    static WRITER: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    static PENDING: RefCell<BTreeMap<u64, Vec<u8>>> = const { RefCell::new(BTreeMap::new()) };
+    static COMMITTED: RefCell<BTreeMap<u64, Vec<u8>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+fn check_writer() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let allowed = WRITER.with(|writer| *writer.borrow() == Some(caller))
+        || ic_cdk::api::is_controller(&caller);
+    if allowed {
+        Ok(())
+    } else {
+        Err("Only the backend canister can write archives".to_string())
+    }
+}
+
+#[ic_cdk::init]
+fn init(args: ArchiveInit) {
+    WRITER.with(|writer| *writer.borrow_mut() = Some(args.writer));
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let state = StableState {
+        writer: WRITER.with(|writer| *writer.borrow()),
+        pending: PENDING.with(|p| p.borrow().clone().into_iter().collect()),
+        committed: COMMITTED.with(|c| c.borrow().clone().into_iter().collect()),
+    };
+    ic_cdk::storage::stable_save((state,)).expect("Failed to save stable state");
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    let (state,): (StableState,) = ic_cdk::storage::stable_restore().unwrap_or_default();
+    WRITER.with(|writer| *writer.borrow_mut() = state.writer);
+    PENDING.with(|p| *p.borrow_mut() = state.pending.into_iter().collect());
+    COMMITTED.with(|c| *c.borrow_mut() = state.committed.into_iter().collect());
+}
+
+// Appends to the market's pending blob. `offset` must be the bytes received so
+// far, so a retried chunk is rejected instead of duplicated.
+#[ic_cdk::update]
+fn append_chunk(market_id: u64, offset: u64, chunk: Vec<u8>) -> Result<(), String> {
+    check_writer()?;
+    if chunk.len() > MAX_CHUNK_SIZE {
+        return Err(format!("Chunks are limited to {} bytes", MAX_CHUNK_SIZE));
+    }
+    if COMMITTED.with(|c| c.borrow().contains_key(&market_id)) {
+        return Err("Market is already archived".to_string());
+    }
+
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let blob = pending.entry(market_id).or_default();
+        if offset != blob.len() as u64 {
+            return Err(format!("Expected offset {}", blob.len()));
+        }
+        blob.extend_from_slice(&chunk);
+        Ok(())
+    })
+}
+
+// Seals the pending blob and returns its SHA-256
+#[ic_cdk::update]
+fn commit(market_id: u64) -> Result<Vec<u8>, String> {
+    check_writer()?;
+    let blob = PENDING
+        .with(|pending| pending.borrow_mut().remove(&market_id))
+        .ok_or_else(|| "Nothing pending for this market".to_string())?;
+
+    let hash = Sha256::digest(&blob).to_vec();
+    COMMITTED.with(|c| c.borrow_mut().insert(market_id, blob));
+    Ok(hash)
+}
+
+// Drops a pending upload, e.g. after a failed transfer
+#[ic_cdk::update]
+fn abort(market_id: u64) -> Result<(), String> {
+    check_writer()?;
+    PENDING.with(|pending| pending.borrow_mut().remove(&market_id));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_archive_size(market_id: u64) -> Option<u64> {
+    COMMITTED.with(|c| c.borrow().get(&market_id).map(|blob| blob.len() as u64))
+}
+
+#[ic_cdk::query]
+fn get_archive_chunk(market_id: u64, offset: u64, length: u64) -> Option<Vec<u8>> {
+    COMMITTED.with(|c| {
+        let committed = c.borrow();
+        let blob = committed.get(&market_id)?;
+        let start = (offset as usize).min(blob.len());
+        let end = start
+            .saturating_add((length as usize).min(MAX_CHUNK_SIZE))
+            .min(blob.len());
+        Some(blob[start..end].to_vec())
+    })
+}
+
+ic_cdk::export_candid!();
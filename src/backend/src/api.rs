@@ -0,0 +1,259 @@
+// Public API for bots, version 1
+//
+// The api_v1_* endpoints answer with the DTOs below rather than the internal
+// types, so refactoring a Market or Trade does not break a bot. A v1 type never
+// changes shape; anything that would change it goes into a v2 alongside. Every
+// response carries the API version, the canister time and the caller's
+// remaining request budget. All timestamps are nanoseconds.
+//
+// The budget is fixed per caller and window. A plain query cannot keep state,
+// so it only reports the budget; calls that run replicated (a query method
+// called as an update) spend from it and are refused once it is used up.
+use crate::{Market, MarketKind, MarketStatus, Outcome, Trade};
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+pub const API_VERSION: u32 = 1;
+pub const REQUESTS_PER_WINDOW: u64 = 120;
+pub const WINDOW_NANOS: u64 = 60 * 1_000_000_000;
+pub const MAX_PAGE: u64 = 100;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct RateBudgetV1 {
+    pub limit: u64,
+    pub remaining: u64,
+    pub resets_at: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct ResponseMetaV1 {
+    pub api_version: u32,
+    pub canister_time: u64,
+    pub rate: RateBudgetV1,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum ApiErrorV1 {
+    RateLimited { resets_at: u64 },
+    ReadsDisabled,
+    MarketNotFound,
+    MarketArchived { archive: Principal }, // trades are read from the archive canister
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum MarketStatusV1 {
+    Pending,
+    Open,
+    Closed,
+    Resolved,
+    Removed, // rejected or taken down by moderation
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum OutcomeV1 {
+    Yes,
+    No,
+    Invalid,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum MarketKindV1 {
+    Standard,
+    Flash,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct MarketV1 {
+    pub id: u64,
+    pub title: String,
+    pub slug: String,
+    pub description: String,
+    pub category: String,
+    pub creator: Principal,
+    pub status: MarketStatusV1,
+    pub kind: MarketKindV1,
+    pub outcome: Option<OutcomeV1>,
+    pub created_at: u64,
+    pub close_time: u64,
+    pub yes_bps: u64,
+    pub yes_liquidity: u64,
+    pub no_liquidity: u64,
+    pub total_volume: u64,
+    pub archived: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct TradeV1 {
+    pub id: u64,
+    pub market_id: u64,
+    pub trader: Principal,
+    pub yes: bool,
+    pub amount: u64, // fee included
+    pub shares: u64,
+    pub price: u64, // per-mille
+    pub fee: u64,
+    pub timestamp: u64,
+    pub reverted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct MarketPageV1 {
+    pub meta: ResponseMetaV1,
+    pub markets: Vec<MarketV1>,
+    pub next_offset: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct MarketResponseV1 {
+    pub meta: ResponseMetaV1,
+    pub market: MarketV1,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct TradePageV1 {
+    pub meta: ResponseMetaV1,
+    pub trades: Vec<TradeV1>,
+    pub next_cursor: Option<u64>, // pass back as `after` to continue
+}
+
+impl From<&Market> for MarketV1 {
+    fn from(market: &Market) -> Self {
+        MarketV1 {
+            id: market.id,
+            title: market.title.clone(),
+            slug: market.slug.clone(),
+            description: market.description.clone(),
+            category: market.category.clone(),
+            creator: market.creator,
+            status: match market.status {
+                MarketStatus::PendingValidation => MarketStatusV1::Pending,
+                MarketStatus::Active => MarketStatusV1::Open,
+                MarketStatus::Closed => MarketStatusV1::Closed,
+                MarketStatus::Resolved => MarketStatusV1::Resolved,
+                MarketStatus::Rejected | MarketStatus::Archived => MarketStatusV1::Removed,
+            },
+            kind: match market.market_kind {
+                MarketKind::Standard => MarketKindV1::Standard,
+                MarketKind::Flash => MarketKindV1::Flash,
+            },
+            outcome: market.resolved_outcome.map(|outcome| match outcome {
+                Outcome::Yes => OutcomeV1::Yes,
+                Outcome::No => OutcomeV1::No,
+                Outcome::Invalid => OutcomeV1::Invalid,
+            }),
+            created_at: market.created_at,
+            close_time: market.close_date,
+            yes_bps: crate::engine::implied_yes_bps(market),
+            yes_liquidity: market.yes_liquidity,
+            no_liquidity: market.no_liquidity,
+            total_volume: market.total_volume,
+            archived: market.archived_to.is_some(),
+        }
+    }
+}
+
+impl From<&Trade> for TradeV1 {
+    fn from(trade: &Trade) -> Self {
+        TradeV1 {
+            id: trade.id,
+            market_id: trade.market_id,
+            trader: trade.trader,
+            yes: trade.is_yes,
+            amount: trade.amount,
+            shares: trade.shares,
+            price: trade.price,
+            fee: trade.fee,
+            timestamp: trade.timestamp,
+            reverted: trade.reverted_at.is_some(),
+        }
+    }
+}
+
+thread_local! {
+    // (window start, requests spent in it) per caller
+    static BUDGETS: RefCell<HashMap<Principal, (u64, u64)>> = RefCell::new(HashMap::new());
+}
+
+// Reports the caller's budget, spending one request from it when `spend`
+pub fn meta(caller: Principal, now: u64, spend: bool) -> Result<ResponseMetaV1, ApiErrorV1> {
+    let window = now - now % WINDOW_NANOS;
+    let resets_at = window + WINDOW_NANOS;
+
+    let used = BUDGETS.with(|budgets| {
+        let mut budgets = budgets.borrow_mut();
+        let used = match budgets.get(&caller) {
+            Some((start, used)) if *start == window => *used,
+            _ => 0,
+        };
+        if !spend {
+            return Ok(used);
+        }
+        if used >= REQUESTS_PER_WINDOW {
+            return Err(ApiErrorV1::RateLimited { resets_at });
+        }
+        if used == 0 {
+            // First request of a new window; nobody else's old windows matter
+            budgets.retain(|_, (start, _)| *start == window);
+        }
+        budgets.insert(caller, (window, used + 1));
+        Ok(used + 1)
+    })?;
+
+    Ok(ResponseMetaV1 {
+        api_version: API_VERSION,
+        canister_time: now,
+        rate: RateBudgetV1 {
+            limit: REQUESTS_PER_WINDOW,
+            remaining: REQUESTS_PER_WINDOW - used,
+            resets_at,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::CandidType;
+
+    #[test]
+    fn replicated_calls_spend_the_budget_and_queries_only_read_it() {
+        let bot = Principal::from_slice(&[1]);
+        let now = 5 * WINDOW_NANOS + 7;
+
+        let read = meta(bot, now, false).unwrap();
+        assert_eq!(read.rate.remaining, REQUESTS_PER_WINDOW);
+        assert_eq!(read.rate.resets_at, 6 * WINDOW_NANOS);
+
+        for _ in 0..REQUESTS_PER_WINDOW {
+            meta(bot, now, true).unwrap();
+        }
+        assert_eq!(meta(bot, now, false).unwrap().rate.remaining, 0);
+        assert_eq!(
+            meta(bot, now, true),
+            Err(ApiErrorV1::RateLimited {
+                resets_at: 6 * WINDOW_NANOS
+            })
+        );
+        // Other callers have their own budget, and the window rolls over
+        assert!(meta(Principal::from_slice(&[2]), now, true).is_ok());
+        let next = meta(bot, 6 * WINDOW_NANOS, true).unwrap();
+        assert_eq!(next.rate.remaining, REQUESTS_PER_WINDOW - 1);
+    }
+
+    // Bots decode these shapes. A failing assertion here means v1 changed;
+    // put the change in a v2 instead of updating api_v1.did.
+    #[test]
+    fn v1_candid_is_locked() {
+        let actual = [
+            ("MarketPageV1", MarketPageV1::ty()),
+            ("MarketResponseV1", MarketResponseV1::ty()),
+            ("TradePageV1", TradePageV1::ty()),
+            ("ApiErrorV1", ApiErrorV1::ty()),
+        ]
+        .iter()
+        .map(|(name, ty)| format!("type {} = {};\n", name, ty))
+        .collect::<String>();
+        assert_eq!(actual, include_str!("../api_v1.did"));
+    }
+}
@@ -0,0 +1,147 @@
+// Managed market categories
+//
+// Each key is the exact string stored on a market, with the display name,
+// icon and color the frontend shows for it. While no category is configured
+// the taxonomy stays open and any non-empty category is accepted; once admins
+// add one, new and edited markets must use a configured key. Removing a key
+// leaves existing markets untouched.
+use candid::{CandidType, Deserialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const MAX_CATEGORIES: usize = 100;
+const MAX_KEY_LEN: usize = 50;
+const MAX_DISPLAY_NAME_LEN: usize = 50;
+const MAX_ICON_LEN: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Category {
+    pub key: String,
+    pub display_name: String,
+    pub icon: String,  // an emoji or short glyph
+    pub color: String, // "#RRGGBB"
+}
+
+thread_local! {
+    static CATEGORIES: RefCell<BTreeMap<String, Category>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn list() -> Vec<Category> {
+    CATEGORIES.with(|categories| categories.borrow().values().cloned().collect())
+}
+
+pub fn restore(categories: Vec<Category>) {
+    CATEGORIES.with(|current| {
+        *current.borrow_mut() = categories
+            .into_iter()
+            .map(|category| (category.key.clone(), category))
+            .collect()
+    });
+}
+
+// Inserts a new category or replaces the metadata of an existing key
+pub fn upsert(category: Category) -> Result<(), String> {
+    let key_len = category.key.chars().count();
+    if key_len == 0 || key_len > MAX_KEY_LEN || category.key.trim() != category.key {
+        return Err(format!(
+            "Category keys must be 1 to {} characters without surrounding spaces",
+            MAX_KEY_LEN
+        ));
+    }
+    let name_len = category.display_name.trim().chars().count();
+    if name_len == 0 || name_len > MAX_DISPLAY_NAME_LEN {
+        return Err(format!(
+            "Display names must be between 1 and {} characters",
+            MAX_DISPLAY_NAME_LEN
+        ));
+    }
+    if category.icon.chars().count() > MAX_ICON_LEN {
+        return Err(format!("Icons must be at most {} characters", MAX_ICON_LEN));
+    }
+    if !is_hex_color(&category.color) {
+        return Err("Colors must be given as #RRGGBB".to_string());
+    }
+
+    CATEGORIES.with(|categories| {
+        let mut categories = categories.borrow_mut();
+        if !categories.contains_key(&category.key) && categories.len() >= MAX_CATEGORIES {
+            return Err(format!(
+                "At most {} categories can be configured",
+                MAX_CATEGORIES
+            ));
+        }
+        categories.insert(category.key.clone(), category);
+        Ok(())
+    })
+}
+
+pub fn remove(key: &str) -> Result<(), String> {
+    CATEGORIES.with(|categories| {
+        categories
+            .borrow_mut()
+            .remove(key)
+            .map(|_| ())
+            .ok_or_else(|| "Category not found".to_string())
+    })
+}
+
+// Whether a market may be filed under this category
+pub fn validate_key(key: &str) -> Result<(), String> {
+    if key.trim().is_empty() {
+        return Err("Category cannot be empty".to_string());
+    }
+    CATEGORIES.with(|categories| {
+        let categories = categories.borrow();
+        if categories.is_empty() || categories.contains_key(key) {
+            Ok(())
+        } else {
+            Err(format!("Unknown category: {}", key))
+        }
+    })
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(key: &str, color: &str) -> Category {
+        Category {
+            key: key.to_string(),
+            display_name: key.to_string(),
+            icon: "📊".to_string(),
+            color: color.to_string(),
+        }
+    }
+
+    #[test]
+    fn keys_are_open_until_categories_are_configured() {
+        assert!(validate_key("Anything").is_ok());
+        assert!(validate_key(" ").is_err());
+
+        upsert(category("Sports", "#00aa55")).unwrap();
+        assert!(validate_key("Sports").is_ok());
+        assert!(validate_key("Weather").is_err());
+
+        remove("Sports").unwrap();
+        assert!(remove("Sports").is_err());
+        assert!(validate_key("Weather").is_ok());
+    }
+
+    #[test]
+    fn metadata_is_validated() {
+        assert!(upsert(category("", "#000000")).is_err());
+        assert!(upsert(category(" Padded", "#000000")).is_err());
+        assert!(upsert(category("Finance", "green")).is_err());
+        assert!(upsert(category("Finance", "#12345g")).is_err());
+
+        upsert(category("Finance", "#1A2B3C")).unwrap();
+        let mut renamed = category("Finance", "#1A2B3C");
+        renamed.display_name = "Markets & Money".to_string();
+        upsert(renamed.clone()).unwrap();
+        assert_eq!(list(), vec![renamed]);
+    }
+}
@@ -0,0 +1,225 @@
+// Embed cards
+//
+// Plain-text snapshots of a market for social unfurls and bots. Everything is
+// formatted here so every client shows the same strings. The aggregator
+// probability feed is rendered here too, being the other public HTTP payload.
+use crate::{EmbedCard, HttpResponse, ProbQuote};
+
+const EMBED_DESCRIPTION_LEN: usize = 140;
+
+// Single line, cut to the embed length on a char boundary
+pub fn truncate_description(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= EMBED_DESCRIPTION_LEN {
+        return line;
+    }
+    let cut: String = line.chars().take(EMBED_DESCRIPTION_LEN - 1).collect();
+    format!("{}…", cut.trim_end())
+}
+
+pub fn format_probability(yes_bps: u64) -> String {
+    format!("{}%", (yes_bps + 50) / 100)
+}
+
+// "950", "~12.5k", "~3M"
+pub fn format_volume(volume: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1_000_000_000, "B"), (1_000_000, "M"), (1_000, "k")];
+
+    for (scale, suffix) in UNITS {
+        if volume >= scale {
+            let (whole, tenth) = (volume / scale, volume % scale / (scale / 10));
+            return if tenth == 0 {
+                format!("~{}{}", whole, suffix)
+            } else {
+                format!("~{}.{}{}", whole, tenth, suffix)
+            };
+        }
+    }
+    volume.to_string()
+}
+
+// Coarse countdown to close, both in nanoseconds
+pub fn format_time_remaining(close_date: u64, now: u64) -> String {
+    if now >= close_date {
+        return "closed".to_string();
+    }
+
+    let remaining = crate::to_secs(close_date - now);
+    let (count, unit) = match remaining {
+        r if r < 3_600 => ((r / 60).max(1), "minute"),
+        r if r < 86_400 => (r / 3_600, "hour"),
+        r if r < 60 * 86_400 => (r / 86_400, "day"),
+        r if r < 730 * 86_400 => (r / (30 * 86_400), "month"),
+        r => (r / (365 * 86_400), "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("closes in {} {}{}", count, unit, plural)
+}
+
+pub fn render_json(card: &EmbedCard) -> HttpResponse {
+    let body = format!(
+        "{{\"market_id\":{},\"title\":\"{}\",\"description\":\"{}\",\"probability\":\"{}\",\"volume\":\"{}\",\"time_remaining\":\"{}\",\"status\":\"{:?}\"}}",
+        card.market_id,
+        json_escape(&card.title),
+        json_escape(&card.description),
+        card.probability,
+        card.volume,
+        card.time_remaining,
+        card.status,
+    );
+    response(200, "application/json", body)
+}
+
+pub fn render_html(card: &EmbedCard) -> HttpResponse {
+    let title = html_escape(&card.title);
+    let summary = html_escape(&format!(
+        "{} YES · {} volume · {}",
+        card.probability, card.volume, card.time_remaining
+    ));
+    let description = html_escape(&card.description);
+
+    let body = format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<meta property=\"og:type\" content=\"website\">
+<meta property=\"og:site_name\" content=\"ChainPredict\">
+<meta property=\"og:title\" content=\"{title}\">
+<meta property=\"og:description\" content=\"{summary}\">
+<meta name=\"twitter:card\" content=\"summary\">
+</head>
+<body>
+<h1>{title}</h1>
+<p>{summary}</p>
+<p>{description}</p>
+</body>
+</html>
+"
+    );
+    response(200, "text/html; charset=utf-8", body)
+}
+
+// {"as_of":..,"quotes":[{..} or null per requested id]}
+pub fn render_probabilities(quotes: &[Option<ProbQuote>], as_of: u64) -> HttpResponse {
+    let quotes: Vec<String> = quotes
+        .iter()
+        .map(|quote| match quote {
+            Some(quote) => format!(
+                "{{\"market_id\":{},\"yes_bps\":{},\"liquidity\":{},\"last_trade_ts\":{},\"stale\":{},\"as_of\":{}}}",
+                quote.market_id,
+                quote.yes_bps,
+                quote.liquidity,
+                quote
+                    .last_trade_ts
+                    .map_or("null".to_string(), |ts| ts.to_string()),
+                quote.stale,
+                quote.as_of,
+            ),
+            None => "null".to_string(),
+        })
+        .collect();
+    let body = format!("{{\"as_of\":{},\"quotes\":[{}]}}", as_of, quotes.join(","));
+    response(200, "application/json", body)
+}
+
+pub fn bad_request(message: &str) -> HttpResponse {
+    response(400, "text/plain", message.to_string())
+}
+
+pub fn not_found() -> HttpResponse {
+    response(404, "text/plain", "Not found".to_string())
+}
+
+fn response(status_code: u16, content_type: &str, body: String) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        body: body.into_bytes(),
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volumes_are_bucketed_into_human_units() {
+        assert_eq!(format_volume(950), "950");
+        assert_eq!(format_volume(12_500), "~12.5k");
+        assert_eq!(format_volume(12_549), "~12.5k");
+        assert_eq!(format_volume(3_000_000), "~3M");
+        assert_eq!(format_volume(7_250_000_000), "~7.2B");
+    }
+
+    #[test]
+    fn time_remaining_is_coarse() {
+        let at = |secs| crate::to_nanos(secs);
+        assert_eq!(format_time_remaining(at(100), at(100)), "closed");
+        assert_eq!(
+            format_time_remaining(at(130), at(100)),
+            "closes in 1 minute"
+        );
+        assert_eq!(
+            format_time_remaining(at(3 * 86_400 + 500), 0),
+            "closes in 3 days"
+        );
+        assert_eq!(
+            format_time_remaining(at(90 * 86_400), 0),
+            "closes in 3 months"
+        );
+    }
+
+    #[test]
+    fn probabilities_keep_request_order_and_nulls() {
+        let quote = ProbQuote {
+            market_id: 7,
+            yes_bps: 6_250,
+            liquidity: 2_000,
+            last_trade_ts: None,
+            stale: true,
+            as_of: 42,
+        };
+        let response = render_probabilities(&[None, Some(quote)], 42);
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            "{\"as_of\":42,\"quotes\":[null,{\"market_id\":7,\"yes_bps\":6250,\"liquidity\":2000,\"last_trade_ts\":null,\"stale\":true,\"as_of\":42}]}"
+        );
+    }
+
+    #[test]
+    fn descriptions_are_single_line_and_truncated() {
+        assert_eq!(
+            truncate_description("Line one\nline  two"),
+            "Line one line two"
+        );
+
+        let long = "word ".repeat(100);
+        let truncated = truncate_description(&long);
+        assert_eq!(truncated.chars().count(), EMBED_DESCRIPTION_LEN);
+        assert!(truncated.ends_with('…'));
+    }
+}
@@ -0,0 +1,1124 @@
+// Trading engine core
+//
+// Pricing, fees, position accounting and payouts as pure functions over plain
+// data. Nothing here touches canister state or ic_cdk, so the accounting can be
+// exercised natively by the invariant tests below.
+//
+// Rounding policy: prices round half up to the nearest per-mille, shares are
+// floored, and the cash that only paid for the floored-away fraction of a share
+// is dust credited to the treasury. Trades too small to buy one whole share
+// are rejected, so every unit of cash ends up in the pool, the fee or the dust.
+use crate::{CriteriaRule, DepthLevel, Market, MarketStatus, Outcome, Position, XpConfig};
+
+// Trading fee charged on every buy, taken out of the amount bet. Experiments
+// may override it per trader.
+pub const FEE_BPS: u64 = 200;
+
+// Virtual shares added to each side's depth, so empty pools still have a curve
+pub const BASE_DEPTH: u64 = 1_000;
+// Largest price move (per-mille) a single trade can cause
+pub const MAX_PRICE_IMPACT: u64 = 450;
+
+pub struct BuyOutcome {
+    pub price: u64,
+    pub shares: u64,
+    pub fee: u64,
+    pub dust: u64,
+}
+
+pub struct SellOutcome {
+    pub price: u64,
+    pub proceeds: u64, // paid to the seller out of the pool, fee already taken
+    pub fee: u64,      // cut from the curve value; never in the pool
+}
+
+// AMM pricing function using LMSR (simplified)
+pub fn calculate_price(yes_shares: u64, no_shares: u64, buy_yes: bool, amount: u64) -> u64 {
+    let depth = BASE_DEPTH as u128 + if buy_yes { yes_shares } else { no_shares } as u128;
+    let price_impact =
+        ((amount as u128 * 1000 + depth / 2) / depth).min(MAX_PRICE_IMPACT as u128) as u64;
+
+    if buy_yes {
+        500 + price_impact // Price between 50-950 (0.05-0.95 in decimal)
+    } else {
+        500 - price_impact
+    }
+}
+
+// Shares minted for `amount` of cash. Each unit is bought at the marginal
+// price along the same curve as `calculate_price`, so a share costs 0.5 at the
+// start of the trade and more as the order walks the price up. Integrating
+// 0.5 / price(x) over the order gives D/2 * ln(1 + 2a/D) for the sloped part,
+// where D is the side's depth, plus a flat rate once the price hits its cap.
+pub fn shares_for(yes_shares: u64, no_shares: u64, buy_yes: bool, amount: u64) -> u64 {
+    let side_shares = if buy_yes { yes_shares } else { no_shares };
+    let depth = BASE_DEPTH as f64 + side_shares as f64;
+    let amount = amount as f64;
+
+    let sloped = amount.min(0.45 * depth);
+    let capped = amount - sloped;
+    let shares = depth / 2.0 * (2.0 * sloped / depth).ln_1p() + capped * 500.0 / 950.0;
+
+    // Never mint more shares than cash in, whatever the float rounding
+    (shares.floor() as u64).min(amount as u64)
+}
+
+// Cash needed to mint `shares`, the inverse of `shares_for`, rounded up
+pub fn cost_of_shares(yes_shares: u64, no_shares: u64, buy_yes: bool, shares: u64) -> u64 {
+    let side_shares = if buy_yes { yes_shares } else { no_shares };
+    curve_cost(side_shares, shares).ceil().min(u64::MAX as f64) as u64
+}
+
+// Unrounded cost of minting `shares` on a side that has `side_shares` out
+fn curve_cost(side_shares: u64, shares: u64) -> f64 {
+    let depth = BASE_DEPTH as f64 + side_shares as f64;
+    let shares = shares as f64;
+
+    // Shares bought before the price reaches its cap
+    let sloped_shares = depth / 2.0 * 0.9f64.ln_1p();
+    if shares <= sloped_shares {
+        depth / 2.0 * (2.0 * shares / depth).exp_m1()
+    } else {
+        0.45 * depth + (shares - sloped_shares) * 950.0 / 500.0
+    }
+}
+
+// Shares to sell for a fraction of a holding. Partial closes round down; the
+// full 10000 bps is the whole holding, so nothing is left behind.
+pub fn shares_for_fraction(held: u64, fraction_bps: u16) -> u64 {
+    if fraction_bps >= 10_000 {
+        return held;
+    }
+    (held as u128 * fraction_bps as u128 / 10_000) as u64
+}
+
+// Implied YES probability in basis points, derived from pool liquidity
+pub fn implied_yes_bps(market: &Market) -> u64 {
+    let total = market.yes_liquidity as u128 + market.no_liquidity as u128;
+    if total == 0 {
+        return 5_000;
+    }
+    (market.yes_liquidity as u128 * 10_000 / total) as u64
+}
+
+// XP multiplier for a trade placed at `now`: scales linearly from the early
+// multiplier at creation down to the late multiplier at close, with a bonus for
+// trading against the current majority
+pub fn trade_xp_multiplier_bps(config: &XpConfig, market: &Market, is_yes: bool, now: u64) -> u64 {
+    let lifetime = market.close_date.saturating_sub(market.created_at);
+    let elapsed = now.saturating_sub(market.created_at);
+    let elapsed_bps = if lifetime == 0 {
+        10_000
+    } else {
+        (elapsed as u128 * 10_000 / lifetime as u128).min(10_000) as u64
+    };
+
+    let spread = config
+        .early_multiplier_bps
+        .saturating_sub(config.late_multiplier_bps);
+    let time_multiplier = config.early_multiplier_bps - spread * elapsed_bps / 10_000;
+
+    let yes_bps = implied_yes_bps(market);
+    let contrarian = if is_yes {
+        yes_bps < 5_000
+    } else {
+        yes_bps > 5_000
+    };
+
+    if contrarian {
+        time_multiplier * config.contrarian_bonus_bps / 10_000
+    } else {
+        time_multiplier
+    }
+}
+
+pub fn trading_fee(amount: u64, fee_bps: u64) -> u64 {
+    (amount as u128 * fee_bps.min(10_000) as u128 / 10_000) as u64
+}
+
+// Applies a buy to the market. The fee goes to the treasury, the rest of the
+// amount joins the pool. Nothing is mutated if the trade is rejected.
+pub fn apply_buy(
+    market: &mut Market,
+    is_yes: bool,
+    amount: u64,
+    fee_bps: u64,
+) -> Result<BuyOutcome, String> {
+    if amount == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+    if !matches!(market.status, MarketStatus::Active) {
+        return Err("Market is not active".to_string());
+    }
+
+    let overflow = || "Trade amount overflows market state".to_string();
+    let price = calculate_price(market.yes_shares, market.no_shares, is_yes, amount);
+    let minted = shares_for(market.yes_shares, market.no_shares, is_yes, amount);
+    if minted == 0 {
+        return Err("Amount is too small to buy a whole share".to_string());
+    }
+    let fee = trading_fee(amount, fee_bps);
+    let spent = cost_of_shares(market.yes_shares, market.no_shares, is_yes, minted);
+    let dust = amount.saturating_sub(spent).min(amount - fee);
+    let to_pool = amount - fee - dust;
+
+    let (shares, liquidity) = if is_yes {
+        (market.yes_shares, market.yes_liquidity)
+    } else {
+        (market.no_shares, market.no_liquidity)
+    };
+    let shares = shares.checked_add(minted).ok_or_else(overflow)?;
+    let liquidity = liquidity.checked_add(to_pool).ok_or_else(overflow)?;
+    let total_volume = market
+        .total_volume
+        .checked_add(amount)
+        .ok_or_else(overflow)?;
+
+    if is_yes {
+        market.yes_shares = shares;
+        market.yes_liquidity = liquidity;
+    } else {
+        market.no_shares = shares;
+        market.no_liquidity = liquidity;
+    }
+    market.total_volume = total_volume;
+
+    Ok(BuyOutcome {
+        price,
+        shares: minted,
+        fee,
+        dust,
+    })
+}
+
+// Undoes apply_buy for the most recent trade on the market. Only the pool's
+// share of the amount comes out here; the fee and dust sit in the treasury.
+pub fn revert_buy(
+    market: &mut Market,
+    is_yes: bool,
+    amount: u64,
+    outcome: &BuyOutcome,
+) -> Result<(), String> {
+    let underflow = || "Trade no longer matches market state".to_string();
+    let to_pool = amount
+        .checked_sub(outcome.fee + outcome.dust)
+        .ok_or_else(underflow)?;
+    let (shares, liquidity) = if is_yes {
+        (market.yes_shares, market.yes_liquidity)
+    } else {
+        (market.no_shares, market.no_liquidity)
+    };
+    let shares = shares.checked_sub(outcome.shares).ok_or_else(underflow)?;
+    let liquidity = liquidity.checked_sub(to_pool).ok_or_else(underflow)?;
+    let total_volume = market
+        .total_volume
+        .checked_sub(amount)
+        .ok_or_else(underflow)?;
+
+    if is_yes {
+        market.yes_shares = shares;
+        market.yes_liquidity = liquidity;
+    } else {
+        market.no_shares = shares;
+        market.no_liquidity = liquidity;
+    }
+    market.total_volume = total_volume;
+    Ok(())
+}
+
+// Sells shares back down the curve they were minted on, charging the fee once:
+// the seller gets the curve value less the fee. A buy only puts its amount net
+// of the fee into the pool and the treasury already has the rest, so the sale's
+// fee is cash the pool never held and nothing more goes to the treasury. Buying
+// and selling straight back therefore never takes more out of the pool than
+// went in. The
+// cost basis of the sold shares leaves the traded volume, so Invalid refunds to
+// everyone else stay at cost. Like a buy, a sale moves the price by at most
+// MAX_PRICE_IMPACT and fills the rest at the capped rate. Nothing is mutated
+// if it is rejected.
+pub fn apply_sell(
+    market: &mut Market,
+    is_yes: bool,
+    shares: u64,
+    sold_cost: u64,
+    fee_bps: u64,
+) -> Result<SellOutcome, String> {
+    if shares == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+    if !matches!(market.status, MarketStatus::Active) {
+        return Err("Market is not active".to_string());
+    }
+
+    let (side_shares, liquidity) = if is_yes {
+        (market.yes_shares, market.yes_liquidity)
+    } else {
+        (market.no_shares, market.no_liquidity)
+    };
+    let mismatch = || "Trade no longer matches market state".to_string();
+    let remaining = side_shares.checked_sub(shares).ok_or_else(mismatch)?;
+    let total_volume = market
+        .total_volume
+        .checked_sub(sold_cost)
+        .ok_or_else(mismatch)?;
+
+    let gross = curve_cost(remaining, shares).floor().min(u64::MAX as f64) as u64;
+    // Rounds in the pool's favour, like the buy fee it mirrors
+    let value = (gross as u128 * (10_000 - fee_bps.min(10_000)) as u128 / 10_000) as u64;
+    if value > liquidity || value > funded_pool(market) {
+        return Err("The pool cannot cover this sale".to_string());
+    }
+    if value == 0 {
+        return Err("Too few shares to sell for any cash".to_string());
+    }
+
+    if is_yes {
+        market.yes_shares = remaining;
+        market.yes_liquidity = liquidity - value;
+    } else {
+        market.no_shares = remaining;
+        market.no_liquidity = liquidity - value;
+    }
+    market.total_volume = total_volume;
+
+    // The buy price of the same cash on the opposite side, mirrored
+    let price = 1000 - calculate_price(remaining, remaining, is_yes, value);
+    Ok(SellOutcome {
+        price,
+        proceeds: value,
+        fee: gross - value,
+    })
+}
+
+pub fn apply_position_buy(
+    position: &mut Position,
+    is_yes: bool,
+    shares: u64,
+    cost: u64,
+) -> Result<(), String> {
+    let overflow = || "Trade amount overflows position".to_string();
+    if is_yes {
+        position.yes_shares = position
+            .yes_shares
+            .checked_add(shares)
+            .ok_or_else(overflow)?;
+        position.yes_cost = position.yes_cost.checked_add(cost).ok_or_else(overflow)?;
+    } else {
+        position.no_shares = position
+            .no_shares
+            .checked_add(shares)
+            .ok_or_else(overflow)?;
+        position.no_cost = position.no_cost.checked_add(cost).ok_or_else(overflow)?;
+    }
+    Ok(())
+}
+
+pub fn revert_position_buy(
+    position: &mut Position,
+    is_yes: bool,
+    shares: u64,
+    cost: u64,
+) -> Result<(), String> {
+    let (held, paid) = if is_yes {
+        (&mut position.yes_shares, &mut position.yes_cost)
+    } else {
+        (&mut position.no_shares, &mut position.no_cost)
+    };
+    if *held < shares || *paid < cost {
+        return Err("The shares from this trade are no longer held".to_string());
+    }
+    *held -= shares;
+    *paid -= cost;
+    Ok(())
+}
+
+// Removes sold shares with the matching slice of their cost basis, which is
+// returned. Selling the whole side clears its cost exactly.
+pub fn apply_position_sell(
+    position: &mut Position,
+    is_yes: bool,
+    shares: u64,
+) -> Result<u64, String> {
+    let (held, paid) = if is_yes {
+        (&mut position.yes_shares, &mut position.yes_cost)
+    } else {
+        (&mut position.no_shares, &mut position.no_cost)
+    };
+    if shares == 0 || *held < shares {
+        return Err("Insufficient shares".to_string());
+    }
+    let sold_cost = (*paid as u128 * shares as u128 / *held as u128) as u64;
+    *held -= shares;
+    *paid -= sold_cost;
+    Ok(sold_cost)
+}
+
+// Moves shares of one side between positions. The recipient inherits the
+// matching slice of the sender's cost basis, which is returned.
+pub fn transfer_position_shares(
+    from: &mut Position,
+    to: &mut Position,
+    is_yes: bool,
+    shares: u64,
+) -> Result<u64, String> {
+    if shares == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+
+    let (held, cost) = if is_yes {
+        (from.yes_shares, from.yes_cost)
+    } else {
+        (from.no_shares, from.no_cost)
+    };
+    if held < shares {
+        return Err("Insufficient shares".to_string());
+    }
+    let moved_cost = (cost as u128 * shares as u128 / held as u128) as u64;
+
+    let overflow = || "Transfer overflows recipient position".to_string();
+    let (to_shares, to_cost) = if is_yes {
+        (to.yes_shares, to.yes_cost)
+    } else {
+        (to.no_shares, to.no_cost)
+    };
+    let to_shares = to_shares.checked_add(shares).ok_or_else(overflow)?;
+    let to_cost = to_cost.checked_add(moved_cost).ok_or_else(overflow)?;
+
+    if is_yes {
+        from.yes_shares -= shares;
+        from.yes_cost -= moved_cost;
+        to.yes_shares = to_shares;
+        to.yes_cost = to_cost;
+    } else {
+        from.no_shares -= shares;
+        from.no_cost -= moved_cost;
+        to.no_shares = to_shares;
+        to.no_cost = to_cost;
+    }
+    Ok(moved_cost)
+}
+
+pub fn resolve(market: &mut Market, outcome: Outcome) -> Result<(), String> {
+    if !matches!(market.status, MarketStatus::Active | MarketStatus::Closed) {
+        return Err("Only active or closed markets can be resolved".to_string());
+    }
+
+    market.status = MarketStatus::Resolved;
+    market.resolved_outcome = Some(outcome);
+    Ok(())
+}
+
+// Stores the per-criterion verdicts, which must match the statements one to one
+// and imply the outcome under the market's rule
+pub fn apply_verdicts(
+    market: &mut Market,
+    verdicts: Vec<bool>,
+    outcome: bool,
+) -> Result<(), String> {
+    let criteria = market
+        .resolution_criteria
+        .as_mut()
+        .ok_or_else(|| "This market has no resolution criteria".to_string())?;
+    if verdicts.len() != criteria.statements.len() {
+        return Err(format!(
+            "Expected {} verdicts, one per criterion",
+            criteria.statements.len()
+        ));
+    }
+
+    let implied = match criteria.rule {
+        CriteriaRule::All => verdicts.iter().all(|v| *v),
+        CriteriaRule::Any => verdicts.iter().any(|v| *v),
+    };
+    if implied != outcome {
+        return Err("Outcome does not follow from the criteria verdicts".to_string());
+    }
+
+    criteria.verdicts = Some(verdicts);
+    Ok(())
+}
+
+// Cash traders paid into the pool. The seed liquidity a market opens with was
+// never paid in by anyone, so it prices trades but is not there to pay out.
+pub fn funded_pool(market: &Market) -> u64 {
+    market
+        .yes_liquidity
+        .saturating_add(market.no_liquidity)
+        .saturating_sub(market.seed_liquidity)
+}
+
+// Winning shares are paid pro rata out of the funded pool. An Invalid market
+// refunds every position at cost instead; cost includes the fee, so as fees
+// and dust leave the pool short of the volume traded, refunds are scaled down
+// evenly to what the pool holds.
+pub fn winnings_for(market: &Market, position: &Position) -> u64 {
+    let pool = funded_pool(market) as u128;
+    let (shares, winning_total) = match market.resolved_outcome {
+        Some(Outcome::Yes) => (position.yes_shares, market.yes_shares),
+        Some(Outcome::No) => (position.no_shares, market.no_shares),
+        Some(Outcome::Invalid) => {
+            let cost = position.yes_cost as u128 + position.no_cost as u128;
+            let volume = market.total_volume.saturating_sub(market.seed_volume) as u128;
+            return (cost * pool.min(volume))
+                .checked_div(volume)
+                .map_or(0, |refund| refund.min(u64::MAX as u128) as u64);
+        }
+        None => return 0,
+    };
+
+    (shares as u128 * pool)
+        .checked_div(winning_total as u128)
+        .map(|payout| payout.min(u64::MAX as u128) as u64)
+        .unwrap_or(0)
+}
+
+// What the position would be paid if the market resolved to `outcome` now
+pub fn payout_if(market: &Market, position: &Position, outcome: Outcome) -> u64 {
+    let mut resolved = market.clone();
+    resolved.resolved_outcome = Some(outcome);
+    winnings_for(&resolved, position)
+}
+
+// A holding's share of its side's outstanding shares, in basis points
+pub fn pool_share_bps(shares: u64, side_total: u64) -> u64 {
+    (shares as u128 * 10_000)
+        .checked_div(side_total as u128)
+        .map_or(0, |bps| bps.min(10_000) as u64)
+}
+
+// Simulated buys on one side, doubling in size up to half the side's depth.
+// Each level runs the real `apply_buy` on a copy of the market; sizes too
+// small to mint a share are left out.
+pub fn depth_ladder(market: &Market, is_yes: bool, steps: u32, fee_bps: u64) -> Vec<DepthLevel> {
+    let side_shares = if is_yes {
+        market.yes_shares
+    } else {
+        market.no_shares
+    };
+    let max_amount = BASE_DEPTH.saturating_add(side_shares) / 2;
+
+    let mut levels: Vec<DepthLevel> = vec![];
+    for step in 1..=steps {
+        let amount = max_amount >> (steps - step).min(63);
+        if amount == 0 || levels.last().is_some_and(|level| level.amount == amount) {
+            continue;
+        }
+        let mut simulated = market.clone();
+        if let Ok(outcome) = apply_buy(&mut simulated, is_yes, amount, fee_bps) {
+            levels.push(DepthLevel {
+                amount,
+                shares: outcome.shares,
+                price: outcome.price,
+                fee: outcome.fee,
+                post_trade_yes_bps: implied_yes_bps(&simulated),
+            });
+        }
+    }
+    levels
+}
+
+// Marks the position claimed and returns its payout
+pub fn settle_position(market: &Market, position: &mut Position) -> Result<u64, String> {
+    if !matches!(market.status, MarketStatus::Resolved) {
+        return Err("Market is not resolved".to_string());
+    }
+    if position.claimed {
+        return Err("Winnings already claimed".to_string());
+    }
+
+    let payout = winnings_for(market, position);
+    if payout == 0 {
+        return Err("No winnings to claim".to_string());
+    }
+
+    position.claimed = true;
+    Ok(payout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MarketKind;
+    use candid::Principal;
+    use std::collections::BTreeMap;
+
+    const SEED_SHARES: u64 = 500;
+    const SEED_LIQUIDITY: u64 = 5_000;
+
+    // Deterministic xorshift generator so failing sequences are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    fn new_market(id: u64) -> Market {
+        Market {
+            id,
+            title: format!("Market {}", id),
+            slug: format!("market-{}", id),
+            description: "Fuzzed market".to_string(),
+            category: "Test".to_string(),
+            creator: Principal::anonymous(),
+            close_date: 0,
+            status: MarketStatus::Active,
+            yes_shares: SEED_SHARES,
+            no_shares: SEED_SHARES,
+            yes_liquidity: SEED_LIQUIDITY,
+            no_liquidity: SEED_LIQUIDITY,
+            total_volume: 0,
+            seed_liquidity: 2 * SEED_LIQUIDITY,
+            seed_volume: 0,
+            created_at: 0,
+            resolved_outcome: None,
+            comments_locked: false,
+            trading_schedule: None,
+            market_kind: MarketKind::Standard,
+            resolver: None,
+            resolution_criteria: None,
+            archived_to: None,
+            resolution_datetime: None,
+            primary_source_url: None,
+            numeric_target: None,
+            co_owners: vec![],
+            resolution_delegate: None,
+        }
+    }
+
+    fn new_position(owner: Principal, market_id: u64) -> Position {
+        Position {
+            market_id,
+            owner,
+            yes_shares: 0,
+            no_shares: 0,
+            yes_cost: 0,
+            no_cost: 0,
+            claimed: false,
+        }
+    }
+
+    // Whole-system state driven by the fuzzer
+    #[derive(Default)]
+    struct World {
+        markets: BTreeMap<u64, Market>,
+        positions: BTreeMap<(Principal, u64), Position>,
+        balances: BTreeMap<Principal, u64>,
+        treasury: u64,
+        claimed_per_market: BTreeMap<u64, u64>,
+        deposits: u128, // what traders funded their balances with; seeds are not deposits
+    }
+
+    impl World {
+        fn create(&mut self) {
+            let id = self.markets.len() as u64 + 1;
+            self.markets.insert(id, new_market(id));
+        }
+
+        fn deposit(&mut self, trader: Principal, amount: u64) {
+            *self.balances.entry(trader).or_default() += amount;
+            self.deposits += amount as u128;
+        }
+
+        fn buy(&mut self, trader: Principal, market_id: u64, is_yes: bool, amount: u64) {
+            let Some(market) = self.markets.get_mut(&market_id) else {
+                return;
+            };
+            // Buys are paid from the balance, never on credit
+            let balance = self.balances.entry(trader).or_default();
+            if *balance < amount {
+                return;
+            }
+            let before = market.clone();
+            match apply_buy(market, is_yes, amount, FEE_BPS) {
+                Ok(outcome) => {
+                    assert!((50..=950).contains(&outcome.price));
+                    *balance -= amount;
+                    self.treasury += outcome.fee + outcome.dust;
+                    let position = self
+                        .positions
+                        .entry((trader, market_id))
+                        .or_insert_with(|| new_position(trader, market_id));
+                    apply_position_buy(position, is_yes, outcome.shares, amount).unwrap();
+                }
+                Err(_) => assert_eq!(format!("{:?}", before), format!("{:?}", market)),
+            }
+        }
+
+        fn sell(&mut self, trader: Principal, market_id: u64, is_yes: bool, fraction_bps: u16) {
+            let (Some(market), Some(position)) = (
+                self.markets.get_mut(&market_id),
+                self.positions.get_mut(&(trader, market_id)),
+            ) else {
+                return;
+            };
+            let held = if is_yes {
+                position.yes_shares
+            } else {
+                position.no_shares
+            };
+            let shares = shares_for_fraction(held, fraction_bps);
+
+            let mut sold = position.clone();
+            let Ok(sold_cost) = apply_position_sell(&mut sold, is_yes, shares) else {
+                return;
+            };
+            let before = market.clone();
+            match apply_sell(market, is_yes, shares, sold_cost, FEE_BPS) {
+                Ok(outcome) => {
+                    *position = sold;
+                    *self.balances.entry(trader).or_default() += outcome.proceeds;
+                }
+                Err(_) => assert_eq!(format!("{:?}", before), format!("{:?}", market)),
+            }
+        }
+
+        fn close(&mut self, market_id: u64) {
+            if let Some(market) = self.markets.get_mut(&market_id) {
+                if matches!(market.status, MarketStatus::Active) {
+                    market.status = MarketStatus::Closed;
+                }
+            }
+        }
+
+        fn resolve(&mut self, market_id: u64, outcome: Outcome) {
+            if let Some(market) = self.markets.get_mut(&market_id) {
+                let was_resolved = matches!(market.status, MarketStatus::Resolved);
+                let result = resolve(market, outcome);
+                assert_eq!(result.is_err(), was_resolved);
+            }
+        }
+
+        fn claim(&mut self, trader: Principal, market_id: u64) {
+            let (Some(market), Some(position)) = (
+                self.markets.get(&market_id),
+                self.positions.get_mut(&(trader, market_id)),
+            ) else {
+                return;
+            };
+
+            if let Ok(payout) = settle_position(market, position) {
+                *self.balances.entry(trader).or_default() += payout;
+                *self.claimed_per_market.entry(market_id).or_default() += payout;
+
+                // Claiming is idempotent: a second claim pays nothing
+                let balance = self.balances[&trader];
+                assert!(settle_position(market, position).is_err());
+                assert_eq!(self.balances[&trader], balance);
+            }
+        }
+
+        fn transfer(&mut self, from: Principal, to: Principal, market_id: u64, shares: u64) {
+            let active = self
+                .markets
+                .get(&market_id)
+                .is_some_and(|market| matches!(market.status, MarketStatus::Active));
+            if !active || from == to {
+                return;
+            }
+            let Some(mut sender) = self.positions.get(&(from, market_id)).cloned() else {
+                return;
+            };
+            let mut recipient = self
+                .positions
+                .get(&(to, market_id))
+                .cloned()
+                .unwrap_or_else(|| new_position(to, market_id));
+
+            let cost_before = sender.yes_cost as u128 + recipient.yes_cost as u128;
+            if transfer_position_shares(&mut sender, &mut recipient, true, shares).is_ok() {
+                // Cost basis moves with the shares, none is created or lost
+                assert_eq!(
+                    sender.yes_cost as u128 + recipient.yes_cost as u128,
+                    cost_before
+                );
+                self.positions.insert((from, market_id), sender);
+                self.positions.insert((to, market_id), recipient);
+            }
+        }
+
+        fn check_invariants(&self) {
+            let mut held = 0u128;
+            for market in self.markets.values() {
+                let pool = market.yes_liquidity as u128 + market.no_liquidity as u128;
+                // The seed liquidity never leaves the pool
+                assert!(pool >= market.seed_liquidity as u128);
+                let claimed = self
+                    .claimed_per_market
+                    .get(&market.id)
+                    .copied()
+                    .unwrap_or(0);
+
+                // Claims never exceed what traders paid in
+                assert!(
+                    claimed <= funded_pool(market),
+                    "market {} overpaid",
+                    market.id
+                );
+                held += funded_pool(market) as u128 - claimed as u128;
+
+                // Implied probability stays strictly within (0, 1)
+                let yes_bps = implied_yes_bps(market);
+                assert!(yes_bps > 0 && yes_bps < 10_000);
+
+                // Market share counters equal seeds plus every position
+                let (yes, no) = self
+                    .positions
+                    .values()
+                    .filter(|p| p.market_id == market.id)
+                    .fold((0u128, 0u128), |(y, n), p| {
+                        (y + p.yes_shares as u128, n + p.no_shares as u128)
+                    });
+                assert_eq!(market.yes_shares as u128, SEED_SHARES as u128 + yes);
+                assert_eq!(market.no_shares as u128, SEED_SHARES as u128 + no);
+            }
+
+            // Value is conserved: every deposit sits in the treasury, the
+            // funded part of a pool or a user balance
+            let balances: u128 = self.balances.values().map(|b| *b as u128).sum();
+            assert_eq!(self.treasury as u128 + held + balances, self.deposits);
+        }
+    }
+
+    #[test]
+    fn random_operation_sequences_preserve_invariants() {
+        let traders: Vec<Principal> = (1..=5u8).map(|i| Principal::from_slice(&[i])).collect();
+
+        for seed in 1..=200u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1);
+            let mut world = World::default();
+            world.create();
+            for trader in &traders {
+                world.deposit(*trader, 20_000);
+            }
+
+            for _ in 0..100 {
+                let market_id = rng.below(world.markets.len() as u64) + 1;
+                let trader = traders[rng.below(traders.len() as u64) as usize];
+                match rng.below(13) {
+                    0 => world.create(),
+                    1..=5 => {
+                        let amount = match rng.below(4) {
+                            0 => rng.below(10),
+                            1 => rng.below(1_000),
+                            _ => rng.below(100_000),
+                        };
+                        world.buy(trader, market_id, rng.below(2) == 0, amount);
+                    }
+                    6 => world.close(market_id),
+                    7 => {
+                        let outcome = match rng.below(3) {
+                            0 => Outcome::Yes,
+                            1 => Outcome::No,
+                            _ => Outcome::Invalid,
+                        };
+                        world.resolve(market_id, outcome);
+                    }
+                    8 => {
+                        let to = traders[rng.below(traders.len() as u64) as usize];
+                        world.transfer(trader, to, market_id, rng.below(2_000));
+                    }
+                    9 => {
+                        let fraction_bps = rng.below(10_000) as u16 + 1;
+                        world.sell(trader, market_id, rng.below(2) == 0, fraction_bps);
+                    }
+                    10 => world.deposit(trader, rng.below(50_000)),
+                    _ => world.claim(trader, market_id),
+                }
+                world.check_invariants();
+            }
+        }
+    }
+
+    #[test]
+    fn huge_trades_are_rejected_without_overflow() {
+        let mut market = new_market(1);
+        apply_buy(&mut market, true, u64::MAX / 2, FEE_BPS).unwrap();
+
+        let before = format!("{:?}", market);
+        assert!(apply_buy(&mut market, true, u64::MAX, FEE_BPS).is_err());
+        assert_eq!(before, format!("{:?}", market));
+
+        let price = calculate_price(u64::MAX, u64::MAX, false, u64::MAX);
+        assert_eq!(price, 50);
+    }
+
+    #[test]
+    fn reverting_the_last_buy_restores_the_pool() {
+        let trader = Principal::from_slice(&[1]);
+        let mut market = new_market(1);
+        let mut position = new_position(trader, 1);
+        let before = format!("{:?}", market);
+
+        let outcome = apply_buy(&mut market, true, 1_000, FEE_BPS).unwrap();
+        apply_position_buy(&mut position, true, outcome.shares, 1_000).unwrap();
+        revert_buy(&mut market, true, 1_000, &outcome).unwrap();
+        revert_position_buy(&mut position, true, outcome.shares, 1_000).unwrap();
+
+        assert_eq!(before, format!("{:?}", market));
+        assert_eq!((position.yes_shares, position.yes_cost), (0, 0));
+        assert!(revert_position_buy(&mut position, true, outcome.shares, 1_000).is_err());
+    }
+
+    #[test]
+    fn selling_straight_back_never_drains_the_pool() {
+        let trader = Principal::from_slice(&[1]);
+        for amount in [10, 99, 1_000, 2_500, 7_777] {
+            let mut market = new_market(1);
+            let mut position = new_position(trader, 1);
+            let pool = funded_pool(&market);
+
+            let bought = apply_buy(&mut market, false, amount, FEE_BPS).unwrap();
+            apply_position_buy(&mut position, false, bought.shares, amount).unwrap();
+            let shares = shares_for_fraction(position.no_shares, 10_000);
+            let sold_cost = apply_position_sell(&mut position, false, shares).unwrap();
+            let sold = apply_sell(&mut market, false, shares, sold_cost, FEE_BPS).unwrap();
+
+            assert_eq!((position.no_shares, position.no_cost), (0, 0));
+            assert_eq!(market.no_shares, SEED_SHARES);
+            assert_eq!(market.total_volume, 0);
+            assert!(funded_pool(&market) >= pool, "amount {}", amount);
+            assert!(sold.proceeds + bought.fee + bought.dust <= amount);
+            let gross = sold.proceeds + sold.fee;
+            assert!(
+                sold.fee <= trading_fee(gross, FEE_BPS) + 1,
+                "amount {}",
+                amount
+            );
+        }
+    }
+
+    #[test]
+    fn sale_prices_mirror_buys_and_stay_capped() {
+        let mut market = new_market(1);
+        let bought = apply_buy(&mut market, true, 50_000, FEE_BPS).unwrap();
+        assert_eq!(bought.price, 500 + MAX_PRICE_IMPACT);
+
+        let slice = shares_for_fraction(bought.shares, 100);
+        let mut small = market.clone();
+        let sold = apply_sell(&mut small, true, slice, 500, FEE_BPS).unwrap();
+        assert!(sold.price < 500 && sold.price > 500 - MAX_PRICE_IMPACT);
+
+        let sold = apply_sell(&mut market, true, bought.shares, 50_000, FEE_BPS).unwrap();
+        assert_eq!(sold.price, 500 - MAX_PRICE_IMPACT);
+        assert_eq!(market.yes_shares, SEED_SHARES);
+        assert!(market.yes_liquidity >= SEED_LIQUIDITY);
+    }
+
+    #[test]
+    fn fractions_round_down_except_the_whole_position() {
+        assert_eq!(shares_for_fraction(3, 5_000), 1);
+        assert_eq!(shares_for_fraction(3, 9_999), 2);
+        assert_eq!(shares_for_fraction(3, 10_000), 3);
+        assert_eq!(shares_for_fraction(0, 10_000), 0);
+
+        let mut position = new_position(Principal::from_slice(&[1]), 1);
+        apply_position_buy(&mut position, true, 3, 100).unwrap();
+        assert_eq!(apply_position_sell(&mut position, true, 1).unwrap(), 33);
+        assert_eq!(apply_position_sell(&mut position, true, 2).unwrap(), 67);
+        assert_eq!((position.yes_shares, position.yes_cost), (0, 0));
+        assert!(apply_position_sell(&mut position, true, 1).is_err());
+    }
+
+    #[test]
+    fn claims_require_resolution_and_winning_shares() {
+        let trader = Principal::from_slice(&[1]);
+        let mut market = new_market(1);
+        let mut position = new_position(trader, 1);
+
+        let outcome = apply_buy(&mut market, false, 1_000, FEE_BPS).unwrap();
+        apply_position_buy(&mut position, false, outcome.shares, 1_000).unwrap();
+        assert_eq!(
+            settle_position(&market, &mut position),
+            Err("Market is not resolved".to_string())
+        );
+
+        resolve(&mut market, Outcome::Yes).unwrap();
+        assert_eq!(
+            settle_position(&market, &mut position),
+            Err("No winnings to claim".to_string())
+        );
+        assert!(!position.claimed);
+    }
+
+    #[test]
+    fn shares_diverge_from_cash_once_the_price_moves() {
+        let mut market = new_market(1);
+
+        // Small orders on a deep book trade at close to 0.5
+        assert_eq!(shares_for(1_000_000, 1_000_000, true, 100), 99);
+
+        let outcome = apply_buy(&mut market, true, 1_000, FEE_BPS).unwrap();
+        assert!(outcome.price > 500);
+        assert!(outcome.shares < 1_000);
+        assert_eq!(market.yes_shares, SEED_SHARES + outcome.shares);
+        assert_eq!(
+            market.yes_liquidity,
+            SEED_LIQUIDITY + 1_000 - outcome.fee - outcome.dust
+        );
+
+        // Deeper books mint more shares for the same cash
+        let shallow = shares_for(0, 0, false, 1_000);
+        let deep = shares_for(0, 100_000, false, 1_000);
+        assert!(shallow < deep && deep < 1_000);
+
+        // Past the price cap every extra unit buys 500/950 of a share
+        let capped = shares_for(0, 0, true, 10_000) - shares_for(0, 0, true, 9_000);
+        assert!((526..=527).contains(&capped));
+    }
+
+    #[test]
+    fn repeated_trades_account_for_dust() {
+        let mut market = new_market(1);
+        let (mut paid, mut fees, mut dust, mut shares) = (0u64, 0u64, 0u64, 0u64);
+
+        assert!(apply_buy(&mut market, true, 1, FEE_BPS).is_err());
+
+        // Small trades and trades running into the price cap, where a share
+        // costs close to 2 and floored fractions are worth whole units
+        let amounts = (0..500).map(|_| 3).chain((0..200).map(|i| 700 + i * 7));
+        for amount in amounts {
+            let outcome = apply_buy(&mut market, true, amount, FEE_BPS).unwrap();
+            paid += amount;
+            fees += outcome.fee;
+            dust += outcome.dust;
+            shares += outcome.shares;
+            assert!(outcome.dust < 2);
+        }
+
+        // Dust is collected rather than vanishing, and every unit paid is accounted for
+        assert!(dust > 0);
+        assert_eq!(market.yes_shares, SEED_SHARES + shares);
+        assert_eq!(market.yes_liquidity - SEED_LIQUIDITY + fees + dust, paid);
+    }
+
+    #[test]
+    fn verdicts_must_imply_the_outcome() {
+        let mut market = new_market(1);
+        assert!(apply_verdicts(&mut market, vec![], true).is_err());
+
+        market.resolution_criteria = Some(crate::ResolutionCriteria {
+            statements: vec!["A".to_string(), "B".to_string()],
+            rule: CriteriaRule::All,
+            verdicts: None,
+        });
+        assert!(apply_verdicts(&mut market, vec![true], true).is_err());
+        assert!(apply_verdicts(&mut market, vec![true, false], true).is_err());
+        apply_verdicts(&mut market, vec![true, false], false).unwrap();
+
+        let criteria = market.resolution_criteria.as_mut().unwrap();
+        criteria.rule = CriteriaRule::Any;
+        assert!(apply_verdicts(&mut market, vec![true, false], false).is_err());
+        apply_verdicts(&mut market, vec![true, false], true).unwrap();
+        assert_eq!(
+            market.resolution_criteria.unwrap().verdicts,
+            Some(vec![true, false])
+        );
+    }
+
+    // Pins cash vs shares: fees and volume follow the cash paid, payouts
+    // follow the shares held
+    #[test]
+    fn payouts_follow_shares_not_cash() {
+        let mut market = new_market(1);
+        let outcome = apply_buy(&mut market, true, 100, FEE_BPS).unwrap();
+        assert_eq!((outcome.shares, outcome.fee, outcome.dust), (93, 2, 0));
+        assert_eq!(market.total_volume, 100);
+        assert_eq!(market.yes_liquidity, SEED_LIQUIDITY + 98);
+
+        let mut position = new_position(Principal::anonymous(), 1);
+        apply_position_buy(&mut position, true, outcome.shares, 100).unwrap();
+        assert_eq!(
+            pool_share_bps(position.yes_shares, market.yes_shares),
+            1_568
+        );
+        // 93 of 593 YES shares claim that slice of the 98 paid in; the seed
+        // liquidity is not paid out
+        assert_eq!(funded_pool(&market), 98);
+        assert_eq!(payout_if(&market, &position, Outcome::Yes), 15);
+        assert_eq!(payout_if(&market, &position, Outcome::No), 0);
+        assert_eq!(market.resolved_outcome, None);
+
+        assert_eq!(pool_share_bps(1, 0), 0);
+        assert_eq!(shares_for(0, 0, true, 1_000), 610);
+    }
+
+    #[test]
+    fn invalid_markets_refund_at_cost() {
+        let mut market = new_market(1);
+        let mut yes = new_position(Principal::from_slice(&[1]), 1);
+        let mut no = new_position(Principal::from_slice(&[2]), 1);
+        let outcome = apply_buy(&mut market, true, 100, FEE_BPS).unwrap();
+        apply_position_buy(&mut yes, true, outcome.shares, 100).unwrap();
+        let outcome = apply_buy(&mut market, false, 300, FEE_BPS).unwrap();
+        apply_position_buy(&mut no, false, outcome.shares, 300).unwrap();
+
+        // The fees left the funded pool 392 short of the 400 traded
+        resolve(&mut market, Outcome::Invalid).unwrap();
+        assert_eq!(winnings_for(&market, &yes), 98);
+        assert_eq!(winnings_for(&market, &no), 294);
+
+        // A pool short of the volume scales every refund down alike
+        market.yes_liquidity = SEED_LIQUIDITY + 150;
+        market.no_liquidity = SEED_LIQUIDITY + 50;
+        assert_eq!(winnings_for(&market, &yes), 50);
+        assert_eq!(settle_position(&market, &mut no), Ok(150));
+        assert!(settle_position(&market, &mut no).is_err());
+    }
+
+    #[test]
+    fn depth_ladder_doubles_up_to_half_the_depth() {
+        let market = new_market(1);
+        let ladder = depth_ladder(&market, true, 4, FEE_BPS);
+        // Depth is 1_000 + 500 seed shares, so the ladder tops out at 750
+        let amounts: Vec<u64> = ladder.iter().map(|level| level.amount).collect();
+        assert_eq!(amounts, vec![93, 187, 375, 750]);
+        assert!(ladder.windows(2).all(|pair| {
+            pair[1].price >= pair[0].price
+                && pair[1].post_trade_yes_bps > pair[0].post_trade_yes_bps
+        }));
+
+        // Each level matches a real trade of that size
+        let mut traded = market.clone();
+        let outcome = apply_buy(&mut traded, true, 375, FEE_BPS).unwrap();
+        assert_eq!(ladder[2].shares, outcome.shares);
+        assert_eq!(ladder[2].post_trade_yes_bps, implied_yes_bps(&traded));
+
+        let no_side = depth_ladder(&market, false, 20, FEE_BPS);
+        assert!(no_side.len() < 20 && no_side.iter().all(|level| level.shares > 0));
+        assert!(no_side
+            .windows(2)
+            .all(|pair| pair[1].post_trade_yes_bps < pair[0].post_trade_yes_bps));
+    }
+
+    #[test]
+    fn prices_round_half_up() {
+        // 1 * 1000 / 2000 = 0.5 rounds up to one per-mille of impact
+        assert_eq!(calculate_price(1_000, 1_000, true, 1), 501);
+        assert_eq!(calculate_price(1_000, 1_000, false, 1), 499);
+        // 0.4 rounds down
+        assert_eq!(calculate_price(1_500, 0, true, 1), 500);
+    }
+
+    #[test]
+    fn xp_multiplier_falls_over_the_market_lifetime() {
+        let config = XpConfig::default();
+        let mut market = new_market(1);
+        market.created_at = crate::to_nanos(1_000);
+        market.close_date = crate::to_nanos(3_000);
+
+        let at = |secs| trade_xp_multiplier_bps(&config, &market, false, crate::to_nanos(secs));
+        assert_eq!(at(1_000), 20_000);
+        assert_eq!(at(2_000), 15_000);
+        assert_eq!(at(3_000), 10_000);
+        assert_eq!(at(4_000), 10_000);
+    }
+}
@@ -0,0 +1,276 @@
+// A/B experiments on trading parameters
+//
+// An experiment overrides one or more parameters for a deterministic slice of
+// principals while it runs. Buckets come from hashing (principal, experiment
+// id), so a principal stays in the same bucket for the experiment's lifetime.
+// Exposure and outcome metrics are accumulated per bucket as trades happen.
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+const MAX_EXPERIMENT_NAME_LEN: usize = 100;
+const MAX_EXPERIMENT_FEE_BPS: u64 = 1_000;
+const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct ExperimentOverrides {
+    pub fee_bps: Option<u64>,
+    pub xp_per_amount_divisor: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Experiment {
+    pub id: u64,
+    pub name: String,
+    pub overrides: ExperimentOverrides,
+    pub traffic_bps: u64, // share of principals in the treatment bucket
+    pub starts_at: u64,
+    pub ends_at: u64, // expires on its own at this time
+    pub stopped: bool,
+    pub created_by: Principal,
+}
+
+// Bucket a trade was placed under, recorded on the trade
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ExperimentAssignment {
+    pub experiment_id: u64,
+    pub treatment: bool,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct BucketMetrics {
+    pub exposed: u64, // principals who traded while assigned
+    pub trades: u64,
+    pub volume: u64,
+    pub returning: u64, // exposed principals who traded again on a later day
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ExperimentResults {
+    pub experiment: Experiment,
+    pub control: BucketMetrics,
+    pub treatment: BucketMetrics,
+}
+
+struct Exposure {
+    first_day: u64,
+    returned: bool,
+}
+
+thread_local! {
+    static EXPERIMENTS: RefCell<BTreeMap<u64, Experiment>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_EXPERIMENT_ID: RefCell<u64> = const { RefCell::new(1) };
+    static EXPOSURES: RefCell<HashMap<(u64, Principal), Exposure>> = RefCell::new(HashMap::new());
+    static METRICS: RefCell<HashMap<(u64, bool), BucketMetrics>> = RefCell::new(HashMap::new());
+}
+
+pub fn create(
+    name: String,
+    overrides: ExperimentOverrides,
+    traffic_bps: u64,
+    starts_at: u64,
+    ends_at: u64,
+    created_by: Principal,
+    now: u64,
+) -> Result<u64, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_EXPERIMENT_NAME_LEN {
+        return Err(format!(
+            "Experiment name must be between 1 and {} characters",
+            MAX_EXPERIMENT_NAME_LEN
+        ));
+    }
+    if overrides.fee_bps.is_none() && overrides.xp_per_amount_divisor.is_none() {
+        return Err("Experiment must override at least one parameter".to_string());
+    }
+    if overrides
+        .fee_bps
+        .is_some_and(|fee| fee > MAX_EXPERIMENT_FEE_BPS)
+    {
+        return Err(format!(
+            "Fee override cannot exceed {} bps",
+            MAX_EXPERIMENT_FEE_BPS
+        ));
+    }
+    if overrides.xp_per_amount_divisor == Some(0) {
+        return Err("XP divisor must be greater than 0".to_string());
+    }
+    if traffic_bps == 0 || traffic_bps > 10_000 {
+        return Err("Traffic must be between 1 and 10000 bps".to_string());
+    }
+    if ends_at <= starts_at || ends_at <= now {
+        return Err("Experiment must end after it starts and in the future".to_string());
+    }
+
+    // One running experiment per parameter, so results are not confounded
+    let conflict = EXPERIMENTS.with(|experiments| {
+        experiments
+            .borrow()
+            .values()
+            .find(|other| {
+                !other.stopped
+                    && other.ends_at > now
+                    && other.starts_at < ends_at
+                    && starts_at < other.ends_at
+                    && ((overrides.fee_bps.is_some() && other.overrides.fee_bps.is_some())
+                        || (overrides.xp_per_amount_divisor.is_some()
+                            && other.overrides.xp_per_amount_divisor.is_some()))
+            })
+            .map(|other| other.id)
+    });
+    if let Some(other) = conflict {
+        return Err(format!(
+            "Experiment {} already overrides the same parameter in that period",
+            other
+        ));
+    }
+
+    let id = NEXT_EXPERIMENT_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+    EXPERIMENTS.with(|experiments| {
+        experiments.borrow_mut().insert(
+            id,
+            Experiment {
+                id,
+                name,
+                overrides,
+                traffic_bps,
+                starts_at,
+                ends_at,
+                stopped: false,
+                created_by,
+            },
+        );
+    });
+    Ok(id)
+}
+
+pub fn stop(id: u64) -> Result<(), String> {
+    EXPERIMENTS.with(|experiments| {
+        let mut experiments = experiments.borrow_mut();
+        let experiment = experiments
+            .get_mut(&id)
+            .ok_or_else(|| "Experiment not found".to_string())?;
+        experiment.stopped = true;
+        Ok(())
+    })
+}
+
+pub fn list() -> Vec<Experiment> {
+    EXPERIMENTS.with(|experiments| experiments.borrow().values().cloned().collect())
+}
+
+// Overrides applying to the principal right now, and the buckets it is in
+pub fn overrides_for(
+    principal: Principal,
+    now: u64,
+) -> (ExperimentOverrides, Vec<ExperimentAssignment>) {
+    let mut overrides = ExperimentOverrides::default();
+    let mut assignments = vec![];
+
+    EXPERIMENTS.with(|experiments| {
+        for experiment in experiments.borrow().values() {
+            if experiment.stopped || now < experiment.starts_at || now >= experiment.ends_at {
+                continue;
+            }
+
+            let treatment = bucket(principal, experiment.id) < experiment.traffic_bps;
+            if treatment {
+                overrides.fee_bps = overrides.fee_bps.or(experiment.overrides.fee_bps);
+                overrides.xp_per_amount_divisor = overrides
+                    .xp_per_amount_divisor
+                    .or(experiment.overrides.xp_per_amount_divisor);
+            }
+            assignments.push(ExperimentAssignment {
+                experiment_id: experiment.id,
+                treatment,
+            });
+        }
+    });
+
+    (overrides, assignments)
+}
+
+pub fn record_trade(
+    principal: Principal,
+    assignments: &[ExperimentAssignment],
+    volume: u64,
+    now: u64,
+) {
+    let day = now / NANOS_PER_DAY;
+
+    for assignment in assignments {
+        let key = (assignment.experiment_id, principal);
+        let (first_exposure, returned_now) = EXPOSURES.with(|exposures| {
+            let mut exposures = exposures.borrow_mut();
+            match exposures.get_mut(&key) {
+                None => {
+                    exposures.insert(
+                        key,
+                        Exposure {
+                            first_day: day,
+                            returned: false,
+                        },
+                    );
+                    (true, false)
+                }
+                Some(exposure) if !exposure.returned && day > exposure.first_day => {
+                    exposure.returned = true;
+                    (false, true)
+                }
+                Some(_) => (false, false),
+            }
+        });
+
+        METRICS.with(|metrics| {
+            let mut metrics = metrics.borrow_mut();
+            let bucket = metrics
+                .entry((assignment.experiment_id, assignment.treatment))
+                .or_default();
+            bucket.trades += 1;
+            bucket.volume = bucket.volume.saturating_add(volume);
+            if first_exposure {
+                bucket.exposed += 1;
+            }
+            if returned_now {
+                bucket.returning += 1;
+            }
+        });
+    }
+}
+
+pub fn results(id: u64) -> Option<ExperimentResults> {
+    let experiment = EXPERIMENTS.with(|experiments| experiments.borrow().get(&id).cloned())?;
+    let bucket = |treatment| {
+        METRICS.with(|metrics| {
+            metrics
+                .borrow()
+                .get(&(id, treatment))
+                .cloned()
+                .unwrap_or_default()
+        })
+    };
+
+    Some(ExperimentResults {
+        control: bucket(false),
+        treatment: bucket(true),
+        experiment,
+    })
+}
+
+// Stable bucket in [0, 10000) from an FNV-1a hash of (principal, experiment)
+fn bucket(principal: Principal, experiment_id: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in principal
+        .as_slice()
+        .iter()
+        .chain(experiment_id.to_le_bytes().iter())
+    {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash % 10_000
+}
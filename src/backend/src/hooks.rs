@@ -0,0 +1,242 @@
+// Resolution hooks for composing canisters
+//
+// Admins register a canister method to be told when a market resolves.
+// Settling a market only queues a delivery; the scheduler makes the calls
+// later, so a slow or failing hook never holds up resolution or payouts. A
+// failed delivery is retried on later runs, and a hook that fails
+// MAX_CONSECUTIVE_FAILURES times in a row is disabled with its delivery dropped.
+use crate::Outcome;
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+pub const MAX_HOOKS_PER_MARKET: usize = 3;
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+const MAX_METHOD_LEN: usize = 64;
+
+// Sent as the single candid argument of the hook method
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct ResolutionPayload {
+    pub market_id: u64,
+    pub outcome: Outcome,
+    pub resolved_at: u64, // ns
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CanisterHook {
+    pub id: u64,
+    pub market_id: u64,
+    pub canister: Principal,
+    pub method: String,
+    pub enabled: bool,
+    pub pending: Option<ResolutionPayload>, // queued and not delivered yet
+    pub in_flight: bool,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<u64>,
+}
+
+// (hook id, canister, method, payload) of a call to make
+pub type Delivery = (u64, Principal, String, ResolutionPayload);
+
+thread_local! {
+    static HOOKS: RefCell<BTreeMap<u64, CanisterHook>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_HOOK_ID: RefCell<u64> = const { RefCell::new(1) };
+}
+
+pub fn register(market_id: u64, canister: Principal, method: String) -> Result<u64, String> {
+    if method.is_empty()
+        || method.len() > MAX_METHOD_LEN
+        || !method
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!(
+            "Method names are 1 to {} letters, digits or underscores",
+            MAX_METHOD_LEN
+        ));
+    }
+
+    HOOKS.with(|hooks| {
+        let mut hooks = hooks.borrow_mut();
+        let existing: Vec<&CanisterHook> = hooks
+            .values()
+            .filter(|h| h.market_id == market_id)
+            .collect();
+        if existing
+            .iter()
+            .any(|h| h.canister == canister && h.method == method)
+        {
+            return Err("This hook is already registered".to_string());
+        }
+        if existing.len() >= MAX_HOOKS_PER_MARKET {
+            return Err(format!(
+                "A market can have at most {} hooks",
+                MAX_HOOKS_PER_MARKET
+            ));
+        }
+
+        let id = NEXT_HOOK_ID.with(|next| next.replace_with(|id| *id + 1));
+        hooks.insert(
+            id,
+            CanisterHook {
+                id,
+                market_id,
+                canister,
+                method,
+                enabled: true,
+                pending: None,
+                in_flight: false,
+                consecutive_failures: 0,
+                last_error: None,
+                delivered_at: None,
+            },
+        );
+        Ok(id)
+    })
+}
+
+pub fn remove(hook_id: u64) -> Result<(), String> {
+    HOOKS.with(|hooks| {
+        hooks
+            .borrow_mut()
+            .remove(&hook_id)
+            .map(|_| ())
+            .ok_or_else(|| "Hook not found".to_string())
+    })
+}
+
+// For deleted markets
+pub fn clear(market_id: u64) {
+    HOOKS.with(|hooks| hooks.borrow_mut().retain(|_, h| h.market_id != market_id));
+}
+
+pub fn list(market_id: u64) -> Vec<CanisterHook> {
+    HOOKS.with(|hooks| {
+        hooks
+            .borrow()
+            .values()
+            .filter(|h| h.market_id == market_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// Called on resolution; disabled hooks are not told
+pub fn queue(payload: ResolutionPayload) {
+    HOOKS.with(|hooks| {
+        for hook in hooks
+            .borrow_mut()
+            .values_mut()
+            .filter(|h| h.market_id == payload.market_id && h.enabled)
+        {
+            hook.pending = Some(payload.clone());
+        }
+    });
+}
+
+// Marks every queued delivery in flight and hands it out to be sent
+pub fn take_due() -> Vec<Delivery> {
+    HOOKS.with(|hooks| {
+        hooks
+            .borrow_mut()
+            .values_mut()
+            .filter(|h| h.enabled && !h.in_flight)
+            .filter_map(|hook| {
+                let payload = hook.pending.clone()?;
+                hook.in_flight = true;
+                Some((hook.id, hook.canister, hook.method.clone(), payload))
+            })
+            .collect()
+    })
+}
+
+pub fn record(hook_id: u64, result: Result<(), String>, now: u64) {
+    HOOKS.with(|hooks| {
+        let mut hooks = hooks.borrow_mut();
+        // Removed while the call was out
+        let Some(hook) = hooks.get_mut(&hook_id) else {
+            return;
+        };
+        hook.in_flight = false;
+        match result {
+            Ok(()) => {
+                hook.pending = None;
+                hook.consecutive_failures = 0;
+                hook.delivered_at = Some(now);
+            }
+            Err(error) => {
+                hook.consecutive_failures += 1;
+                hook.last_error = Some(error);
+                if hook.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                    hook.enabled = false;
+                    hook.pending = None;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(market_id: u64) -> ResolutionPayload {
+        ResolutionPayload {
+            market_id,
+            outcome: Outcome::Yes,
+            resolved_at: 7,
+        }
+    }
+
+    #[test]
+    fn hooks_are_validated_and_bounded_per_market() {
+        let dao = Principal::from_slice(&[1]);
+        assert!(register(1, dao, "on resolve".to_string()).is_err());
+        let id = register(1, dao, "on_resolve".to_string()).unwrap();
+        assert_eq!(
+            register(1, dao, "on_resolve".to_string()).unwrap_err(),
+            "This hook is already registered"
+        );
+        register(1, dao, "second".to_string()).unwrap();
+        register(1, dao, "third".to_string()).unwrap();
+        assert_eq!(
+            register(1, dao, "fourth".to_string()).unwrap_err(),
+            "A market can have at most 3 hooks"
+        );
+
+        remove(id).unwrap();
+        assert_eq!(remove(id).unwrap_err(), "Hook not found");
+        assert_eq!(list(1).len(), 2);
+    }
+
+    #[test]
+    fn deliveries_are_retried_until_the_hook_is_disabled() {
+        let game = Principal::from_slice(&[2]);
+        let flaky = register(2, game, "flaky".to_string()).unwrap();
+        let steady = register(2, game, "steady".to_string()).unwrap();
+        assert!(take_due().is_empty());
+
+        queue(payload(2));
+        assert_eq!(take_due().len(), 2);
+        // Nothing goes out twice while a call is in flight
+        assert!(take_due().is_empty());
+        record(steady, Ok(()), 10);
+
+        for attempt in 1..=MAX_CONSECUTIVE_FAILURES {
+            record(flaky, Err("rejected".to_string()), 10);
+            let due = take_due();
+            if attempt < MAX_CONSECUTIVE_FAILURES {
+                assert_eq!(due, vec![(flaky, game, "flaky".to_string(), payload(2))]);
+            } else {
+                assert!(due.is_empty());
+            }
+        }
+
+        let hooks = list(2);
+        assert!(!hooks[0].enabled && hooks[0].pending.is_none());
+        assert_eq!(hooks[0].last_error.as_deref(), Some("rejected"));
+        assert_eq!(hooks[1].delivered_at, Some(10));
+        assert!(hooks[1].pending.is_none());
+    }
+}
@@ -0,0 +1,302 @@
+// Maker incentive programs
+//
+// Admins enable a program on a thin market to reward the flow it lacks.
+// Qualifying trades get part of their fee back from the market's incentive
+// pool, which admins fund from the treasury and sponsors from their balance,
+// up to the pool cap. A per-trader daily cap keeps the pool from being farmed
+// by trading back and forth. Whatever is left when the program ends goes back
+// to its funders pro rata.
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const DAY_NANOS: u64 = 86_400 * 1_000_000_000;
+const EVEN_BPS: u64 = 5_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum IncentiveRule {
+    TowardBalance, // the trade leaves the YES probability closer to 50%
+    MinoritySide,  // the trade buys the side priced below 50%
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct IncentiveConfig {
+    pub rule: IncentiveRule,
+    pub rebate_bps: u64,       // share of the trading fee returned
+    pub pool_cap: u64,         // most the pool may hold at once
+    pub daily_trader_cap: u64, // most one trader may receive per day
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct IncentiveInfo {
+    pub market_id: u64,
+    pub config: IncentiveConfig,
+    pub pool: u64,
+    pub paid_out: u64,
+    pub remaining_today: u64, // what the caller can still receive today
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Program {
+    config: IncentiveConfig,
+    pool: u64,
+    paid_out: u64,
+    funders: Vec<(Option<Principal>, u64)>, // None is the treasury
+    rebated: BTreeMap<Principal, (u64, u64)>, // trader -> (day, amount that day)
+}
+
+thread_local! {
+    static PROGRAMS: RefCell<BTreeMap<u64, Program>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn qualifies(
+    rule: IncentiveRule,
+    is_yes: bool,
+    yes_bps_before: u64,
+    yes_bps_after: u64,
+) -> bool {
+    match rule {
+        IncentiveRule::TowardBalance => {
+            yes_bps_after.abs_diff(EVEN_BPS) < yes_bps_before.abs_diff(EVEN_BPS)
+        }
+        IncentiveRule::MinoritySide => {
+            if is_yes {
+                yes_bps_before < EVEN_BPS
+            } else {
+                yes_bps_before > EVEN_BPS
+            }
+        }
+    }
+}
+
+// Starts a program, or changes the terms of a running one
+pub fn enable(market_id: u64, config: IncentiveConfig) -> Result<(), String> {
+    if config.rebate_bps == 0 || config.rebate_bps > 10_000 {
+        return Err("Rebate must be between 1 and 10000 bps".to_string());
+    }
+    if config.pool_cap == 0 || config.daily_trader_cap == 0 {
+        return Err("Pool and daily caps must be greater than 0".to_string());
+    }
+
+    PROGRAMS.with(|programs| {
+        let mut programs = programs.borrow_mut();
+        match programs.get_mut(&market_id) {
+            Some(program) if program.pool > config.pool_cap => {
+                Err("The pool already holds more than the new cap".to_string())
+            }
+            Some(program) => {
+                program.config = config;
+                Ok(())
+            }
+            None => {
+                programs.insert(
+                    market_id,
+                    Program {
+                        config,
+                        pool: 0,
+                        paid_out: 0,
+                        funders: vec![],
+                        rebated: BTreeMap::new(),
+                    },
+                );
+                Ok(())
+            }
+        }
+    })
+}
+
+// Checked before the funds are taken from the funder
+pub fn check_funding(market_id: u64, amount: u64) -> Result<(), String> {
+    PROGRAMS.with(|programs| {
+        let programs = programs.borrow();
+        let program = programs
+            .get(&market_id)
+            .ok_or_else(|| "No incentive program for this market".to_string())?;
+        let room = program.config.pool_cap.saturating_sub(program.pool);
+        if amount > room {
+            return Err(format!("The incentive pool can take at most {} more", room));
+        }
+        Ok(())
+    })
+}
+
+pub fn fund(market_id: u64, funder: Option<Principal>, amount: u64) {
+    PROGRAMS.with(|programs| {
+        let mut programs = programs.borrow_mut();
+        let Some(program) = programs.get_mut(&market_id) else {
+            return;
+        };
+        program.pool += amount;
+        match program.funders.iter_mut().find(|(f, _)| *f == funder) {
+            Some((_, funded)) => *funded += amount,
+            None => program.funders.push((funder, amount)),
+        }
+    });
+}
+
+// Draws the rebate for a trade from the pool, zero if it does not qualify
+pub fn rebate(
+    market_id: u64,
+    trader: Principal,
+    is_yes: bool,
+    yes_bps_before: u64,
+    yes_bps_after: u64,
+    fee: u64,
+    now: u64,
+) -> u64 {
+    PROGRAMS.with(|programs| {
+        let mut programs = programs.borrow_mut();
+        let Some(program) = programs.get_mut(&market_id) else {
+            return 0;
+        };
+        if !qualifies(program.config.rule, is_yes, yes_bps_before, yes_bps_after) {
+            return 0;
+        }
+
+        let day = now / DAY_NANOS;
+        let today = program.rebated.entry(trader).or_insert((day, 0));
+        if today.0 != day {
+            *today = (day, 0);
+        }
+        let rebate = (fee as u128 * program.config.rebate_bps as u128 / 10_000) as u64;
+        let rebate = rebate
+            .min(program.pool)
+            .min(program.config.daily_trader_cap.saturating_sub(today.1));
+        today.1 += rebate;
+        program.pool -= rebate;
+        program.paid_out += rebate;
+        rebate
+    })
+}
+
+pub fn info(market_id: u64, caller: Principal, now: u64) -> Option<IncentiveInfo> {
+    PROGRAMS.with(|programs| {
+        let programs = programs.borrow();
+        let program = programs.get(&market_id)?;
+        let rebated_today = program
+            .rebated
+            .get(&caller)
+            .filter(|(day, _)| *day == now / DAY_NANOS)
+            .map_or(0, |(_, amount)| *amount);
+        Some(IncentiveInfo {
+            market_id,
+            config: program.config.clone(),
+            pool: program.pool,
+            paid_out: program.paid_out,
+            remaining_today: program
+                .config
+                .daily_trader_cap
+                .saturating_sub(rebated_today)
+                .min(program.pool),
+        })
+    })
+}
+
+// Ends the program and splits what is left of the pool among its funders in
+// proportion to what they put in; rounding dust goes to the treasury
+pub fn close(market_id: u64) -> Vec<(Option<Principal>, u64)> {
+    let Some(program) = PROGRAMS.with(|programs| programs.borrow_mut().remove(&market_id)) else {
+        return vec![];
+    };
+
+    let funded: u128 = program
+        .funders
+        .iter()
+        .map(|(_, amount)| *amount as u128)
+        .sum();
+    let mut refunds: Vec<(Option<Principal>, u64)> = program
+        .funders
+        .iter()
+        .filter_map(|(funder, amount)| {
+            let share = (program.pool as u128 * *amount as u128).checked_div(funded)? as u64;
+            (share > 0).then_some((*funder, share))
+        })
+        .collect();
+    let dust = program.pool - refunds.iter().map(|(_, share)| share).sum::<u64>();
+    if dust > 0 {
+        match refunds.iter_mut().find(|(funder, _)| funder.is_none()) {
+            Some((_, share)) => *share += dust,
+            None => refunds.push((None, dust)),
+        }
+    }
+    refunds
+}
+
+pub fn snapshot() -> Vec<(u64, Program)> {
+    PROGRAMS.with(|programs| {
+        programs
+            .borrow()
+            .iter()
+            .map(|(id, program)| (*id, program.clone()))
+            .collect()
+    })
+}
+
+pub fn restore(programs: Vec<(u64, Program)>) {
+    PROGRAMS.with(|current| *current.borrow_mut() = programs.into_iter().collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rule: IncentiveRule) -> IncentiveConfig {
+        IncentiveConfig {
+            rule,
+            rebate_bps: 5_000,
+            pool_cap: 1_000,
+            daily_trader_cap: 30,
+        }
+    }
+
+    #[test]
+    fn rules_reward_the_thin_side() {
+        assert!(qualifies(IncentiveRule::TowardBalance, true, 3_000, 4_500));
+        // Overshooting past 50% widens the gap again
+        assert!(!qualifies(IncentiveRule::TowardBalance, true, 4_000, 6_500));
+        assert!(!qualifies(
+            IncentiveRule::TowardBalance,
+            false,
+            3_000,
+            2_500
+        ));
+
+        assert!(qualifies(IncentiveRule::MinoritySide, true, 4_000, 6_500));
+        assert!(qualifies(IncentiveRule::MinoritySide, false, 7_000, 6_000));
+        assert!(!qualifies(IncentiveRule::MinoritySide, true, 5_000, 5_500));
+    }
+
+    #[test]
+    fn rebates_stop_at_the_daily_cap_and_the_pool() {
+        let trader = Principal::from_slice(&[1]);
+        enable(1, config(IncentiveRule::MinoritySide)).unwrap();
+        assert_eq!(
+            check_funding(1, 1_001).unwrap_err(),
+            "The incentive pool can take at most 1000 more"
+        );
+        fund(1, None, 40);
+
+        assert_eq!(rebate(1, trader, true, 4_000, 4_200, 40, 0), 20);
+        assert_eq!(rebate(1, trader, false, 4_200, 4_000, 40, 0), 0);
+        assert_eq!(rebate(1, trader, true, 4_000, 4_200, 40, 0), 10);
+        assert_eq!(rebate(1, trader, true, 4_000, 4_200, 40, DAY_NANOS), 10);
+        assert_eq!(info(1, trader, DAY_NANOS).unwrap().remaining_today, 0);
+        assert_eq!(info(1, trader, DAY_NANOS).unwrap().paid_out, 40);
+    }
+
+    #[test]
+    fn leftovers_are_refunded_pro_rata() {
+        let sponsor = Principal::from_slice(&[2]);
+        enable(2, config(IncentiveRule::TowardBalance)).unwrap();
+        fund(2, Some(sponsor), 50);
+        fund(2, None, 200);
+        fund(2, Some(sponsor), 50);
+        rebate(2, Principal::from_slice(&[3]), true, 3_000, 3_500, 40, 0);
+
+        // 280 left: a third to the sponsor, the rest and the rounding dust
+        // back to the treasury
+        assert_eq!(close(2), vec![(Some(sponsor), 93), (None, 187)]);
+        assert!(close(2).is_empty());
+        assert!(info(2, sponsor, 0).is_none());
+    }
+}
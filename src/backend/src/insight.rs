@@ -0,0 +1,527 @@
+// AI insight generation
+//
+// Prompt building, backend selection and response handling as pure functions.
+// The canister decides once per job whether to ask the LLM canister or to
+// produce the built-in mock, so both paths stay compiled and tested; only the
+// inter-canister call itself lives in lib.rs.
+use crate::{
+    AIInsight, ChatMessageV0, ChatRequestV0, ChatRole, CommentSentiment, InsightStatus, Market,
+    Outcome,
+};
+use candid::Principal;
+
+const MODEL: &str = "gpt-4o-mini";
+const SYSTEM_PROMPT: &str = "You are an expert financial analyst specializing in prediction markets. Provide clear, objective analysis based on market data.";
+
+// Keyword lists for the mock sentiment, matched against lowercased words
+const BULLISH_WORDS: [&str; 8] = [
+    "yes",
+    "bullish",
+    "up",
+    "moon",
+    "likely",
+    "buy",
+    "long",
+    "confident",
+];
+const BEARISH_WORDS: [&str; 8] = [
+    "no", "bearish", "down", "dump", "unlikely", "sell", "short", "doubt",
+];
+
+const MAX_DIGEST_LEN: usize = 2_000;
+
+// What a category digest is written from: the day's biggest probability
+// moves (bps), highest-volume markets and fresh resolutions
+pub struct DigestInput {
+    pub category: String,
+    pub movers: Vec<(u64, String, i64)>,
+    pub top_volume: Vec<(u64, String, u64)>,
+    pub resolved: Vec<(u64, String, Outcome)>,
+}
+
+impl DigestInput {
+    // Every market the digest may mention, in first-mention order
+    pub fn market_ids(&self) -> Vec<u64> {
+        let mut ids = vec![];
+        let mentioned = self
+            .movers
+            .iter()
+            .map(|(id, _, _)| *id)
+            .chain(self.top_volume.iter().map(|(id, _, _)| *id))
+            .chain(self.resolved.iter().map(|(id, _, _)| *id));
+        for id in mentioned {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Backend {
+    Mock,
+    Llm(Principal),
+}
+
+// The single mock-vs-real decision. An enabled flag with a bad canister id
+// is a configuration error rather than a silent fallback to the mock.
+pub fn select_backend(llm_enabled: bool, canister_id: &str) -> Result<Backend, String> {
+    if !llm_enabled {
+        return Ok(Backend::Mock);
+    }
+    Principal::from_text(canister_id)
+        .map(Backend::Llm)
+        .map_err(|_| "Invalid LLM canister ID configuration".to_string())
+}
+
+pub fn build_prompt(market: &Market) -> String {
+    format!(
+        "Analyze this prediction market and provide insights:
+
+        Title: {}
+        Description: {}
+        Category: {}
+        Resolution deadline: {}
+        Primary source: {}
+        Numeric target: {}
+        Check that these structured fields agree with the description.
+
+        Current state:
+        - Yes liquidity: {} ICP
+        - No liquidity: {} ICP
+        - Total volume: {} ICP
+        - Status: {:?}
+
+        Please provide:
+        1. A brief analysis summary (2-3 sentences)
+        2. Your prediction (YES/NO) with confidence level (0-1)
+        3. Key risk factors (list 2-3 main risks)
+
+        Format your response as JSON with keys: summary, prediction, confidence, risks",
+        market.title,
+        market.description,
+        market.category,
+        market
+            .resolution_datetime
+            .map_or("none".to_string(), |ts| crate::to_secs(ts).to_string()),
+        market.primary_source_url.as_deref().unwrap_or("none"),
+        market
+            .numeric_target
+            .as_ref()
+            .map_or("none".to_string(), |(metric, value)| format!(
+                "{} {}",
+                metric, value
+            )),
+        market.yes_liquidity as f64 / 100_000_000.0,
+        market.no_liquidity as f64 / 100_000_000.0,
+        market.total_volume as f64 / 100_000_000.0,
+        market.status
+    )
+}
+
+pub fn build_chat_request(prompt: String) -> ChatRequestV0 {
+    ChatRequestV0 {
+        model: MODEL.to_string(),
+        messages: vec![
+            ChatMessageV0 {
+                role: ChatRole::System,
+                content: SYSTEM_PROMPT.to_string(),
+            },
+            ChatMessageV0 {
+                role: ChatRole::User,
+                content: prompt,
+            },
+        ],
+    }
+}
+
+// Risks that follow from the market definition itself, whichever backend runs
+fn market_risks(market: &Market) -> Vec<String> {
+    let mut risks = structured_field_risks(market);
+    // Free-form resolutions are where most disputes come from
+    if market.resolution_criteria.is_none() {
+        risks.push(
+            "No resolution criteria: define explicit YES conditions to avoid a disputed outcome"
+                .to_string(),
+        );
+    }
+    risks
+}
+
+pub fn mock_insight(market: &Market, now: u64) -> AIInsight {
+    let mut risks = vec![
+        "Market volatility due to external events".to_string(),
+        "Limited trading volume may affect price discovery".to_string(),
+        "Information asymmetry between participants".to_string(),
+    ];
+    risks.extend(market_risks(market));
+
+    AIInsight {
+        market_id: market.id,
+        summary: format!(
+            "🤖 AI Analysis for '{}': Based on current market trends and sentiment analysis, this prediction market shows interesting dynamics. The market sentiment appears to be driven by recent news and social media discussions. Consider both bullish and bearish scenarios before making investment decisions.",
+            market.title
+        ),
+        confidence: 0.75,
+        risks,
+        prediction_lean: Some(true), // Slightly bullish
+        generated_at: now,
+        status: InsightStatus::Ready,
+        job_id: None,
+        generated_yes_bps: Some(crate::engine::implied_yes_bps(market)),
+    }
+}
+
+// The LLM answer is kept verbatim as the summary; there is no JSON parser in
+// the canister, so confidence and lean stay at neutral defaults.
+pub fn parse_response(response: &str, market: &Market, now: u64) -> Result<AIInsight, String> {
+    let summary = response.trim();
+    if summary.is_empty() {
+        return Err("The AI returned an empty response".to_string());
+    }
+    Ok(AIInsight {
+        market_id: market.id,
+        summary: summary.to_string(),
+        confidence: 0.5,
+        risks: market_risks(market),
+        prediction_lean: None,
+        generated_at: now,
+        status: InsightStatus::Ready,
+        job_id: None,
+        generated_yes_bps: Some(crate::engine::implied_yes_bps(market)),
+    })
+}
+
+pub fn build_sentiment_prompt(market: &Market, comments: &[String]) -> String {
+    let lines: Vec<String> = comments
+        .iter()
+        .map(|comment| format!("- {}", comment))
+        .collect();
+    format!(
+        "Classify the sentiment of this prediction market discussion.
+
+        Market: {}
+
+        Comments:
+        {}
+
+        Answer in exactly this format:
+        BULLISH=<percent of comments expecting YES>
+        BEARISH=<percent of comments expecting NO>
+        SUMMARY=<one sentence>",
+        market.title,
+        lines.join("\n        ")
+    )
+}
+
+// Counts bullish and bearish keywords; percentages are of all keyword hits
+pub fn mock_sentiment(comments: &[String], now: u64) -> CommentSentiment {
+    let (mut bullish, mut bearish) = (0u64, 0u64);
+    for comment in comments {
+        for word in comment.to_lowercase().split(|c: char| !c.is_alphanumeric()) {
+            if BULLISH_WORDS.contains(&word) {
+                bullish += 1;
+            } else if BEARISH_WORDS.contains(&word) {
+                bearish += 1;
+            }
+        }
+    }
+
+    let hits = bullish + bearish;
+    let bullish_pct = (bullish * 100).checked_div(hits).unwrap_or(0);
+    CommentSentiment {
+        bullish_pct,
+        bearish_pct: if hits == 0 { 0 } else { 100 - bullish_pct },
+        summary: format!(
+            "Keyword estimate over {} comments: {} bullish and {} bearish mentions",
+            comments.len(),
+            bullish,
+            bearish
+        ),
+        analyzed_at: now,
+    }
+}
+
+pub fn parse_sentiment(response: &str, now: u64) -> Result<CommentSentiment, String> {
+    let field = |key: &str| {
+        response
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(key))
+            .map(|value| value.trim().to_string())
+    };
+    let percent = |key: &str| {
+        field(key)
+            .and_then(|value| value.trim_end_matches('%').parse::<u64>().ok())
+            .filter(|pct| *pct <= 100)
+            .ok_or_else(|| format!("The AI response has no valid {}", key))
+    };
+
+    Ok(CommentSentiment {
+        bullish_pct: percent("BULLISH=")?,
+        bearish_pct: percent("BEARISH=")?,
+        summary: field("SUMMARY=").unwrap_or_default(),
+        analyzed_at: now,
+    })
+}
+
+pub fn build_digest_prompt(input: &DigestInput) -> String {
+    let movers: Vec<String> = input
+        .movers
+        .iter()
+        .map(|(id, title, change)| format!("- #{} {}: {:+} bps", id, title, change))
+        .collect();
+    let volume: Vec<String> = input
+        .top_volume
+        .iter()
+        .map(|(id, title, volume)| format!("- #{} {}: {} traded", id, title, volume))
+        .collect();
+    let resolved: Vec<String> = input
+        .resolved
+        .iter()
+        .map(|(id, title, outcome)| {
+            format!(
+                "- #{} {}: {}",
+                id,
+                title,
+                match outcome {
+                    Outcome::Yes => "YES",
+                    Outcome::No => "NO",
+                    Outcome::Invalid => "N/A",
+                }
+            )
+        })
+        .collect();
+    let section = |lines: &[String]| {
+        if lines.is_empty() {
+            "- none".to_string()
+        } else {
+            lines.join("\n        ")
+        }
+    };
+    format!(
+        "Write a daily digest of the {} prediction markets in 4 to 6 sentences.
+        Refer to markets by their #id. Do not give financial advice.
+
+        Biggest 24h probability moves:
+        {}
+
+        Most traded in the last 24h:
+        {}
+
+        Resolved in the last 24h:
+        {}",
+        input.category,
+        section(&movers),
+        section(&volume),
+        section(&resolved)
+    )
+}
+
+pub fn mock_digest(input: &DigestInput) -> String {
+    let mut sentences = vec![format!(
+        "Here is today's {} digest, covering {} markets.",
+        input.category,
+        input.market_ids().len()
+    )];
+    sentences.push(match input.movers.first() {
+        Some((id, title, change)) => format!(
+            "The biggest mover was #{} {}, which shifted {:+} bps.",
+            id, title, change
+        ),
+        None => "No market moved noticeably over the last day.".to_string(),
+    });
+    sentences.push(match input.top_volume.first() {
+        Some((id, title, volume)) => {
+            format!("#{} {} led trading with {} traded.", id, title, volume)
+        }
+        None => "Trading was quiet.".to_string(),
+    });
+    sentences.push(match input.resolved.len() {
+        0 => "No markets resolved.".to_string(),
+        count => format!("{} market(s) resolved in the last 24 hours.", count),
+    });
+    sentences.join(" ")
+}
+
+// The LLM text is stored as written, within a length bound
+pub fn parse_digest(response: &str) -> Result<String, String> {
+    let text = response.trim();
+    if text.is_empty() {
+        return Err("The AI returned an empty digest".to_string());
+    }
+    Ok(text.chars().take(MAX_DIGEST_LEN).collect())
+}
+
+// Structured fields that the prose does not back up. A numeric target counts
+// as mentioned when its digits appear in the title or description, ignoring
+// separators ("$150,000" matches 150000).
+fn structured_field_risks(market: &Market) -> Vec<String> {
+    let mut risks = vec![];
+    if let Some((metric, value)) = &market.numeric_target {
+        let text = format!("{} {}", market.title, market.description);
+        let target = value.to_string();
+        let mentioned = text
+            .split_whitespace()
+            .map(|word| {
+                word.chars()
+                    .filter(|c| c.is_ascii_digit())
+                    .collect::<String>()
+            })
+            .any(|digits| digits == target);
+        if !mentioned {
+            risks.push(format!(
+                "Numeric target {} = {} is not stated in the description",
+                metric, value
+            ));
+        }
+    }
+    if market.resolution_datetime.is_none() && market.numeric_target.is_some() {
+        risks.push("Threshold market without a resolution deadline".to_string());
+    }
+    risks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MarketKind, MarketStatus};
+
+    fn new_market(id: u64) -> Market {
+        Market {
+            id,
+            title: format!("Market {}", id),
+            slug: format!("market-{}", id),
+            description: "Will BTC close above 150,000?".to_string(),
+            category: "Crypto".to_string(),
+            creator: Principal::anonymous(),
+            close_date: 0,
+            status: MarketStatus::Active,
+            yes_shares: 0,
+            no_shares: 0,
+            yes_liquidity: 250_000_000,
+            no_liquidity: 50_000_000,
+            total_volume: 100_000_000,
+            seed_liquidity: 0,
+            seed_volume: 0,
+            created_at: 0,
+            resolved_outcome: None,
+            comments_locked: false,
+            trading_schedule: None,
+            market_kind: MarketKind::Standard,
+            resolver: None,
+            resolution_criteria: None,
+            archived_to: None,
+            resolution_datetime: None,
+            primary_source_url: None,
+            numeric_target: None,
+            co_owners: vec![],
+            resolution_delegate: None,
+        }
+    }
+
+    #[test]
+    fn prompt_carries_market_data_and_structured_fields() {
+        let mut market = new_market(1);
+        let prompt = build_prompt(&market);
+        assert!(prompt.contains("Title: Market 1"));
+        assert!(prompt.contains("Yes liquidity: 2.5 ICP"));
+        assert!(prompt.contains("Primary source: none"));
+        assert!(prompt.contains("Numeric target: none"));
+
+        market.primary_source_url = Some("https://example.com".to_string());
+        market.numeric_target = Some(("BTC".to_string(), 150_000));
+        market.resolution_datetime = Some(crate::to_nanos(1_767_225_600));
+        let prompt = build_prompt(&market);
+        assert!(prompt.contains("Primary source: https://example.com"));
+        assert!(prompt.contains("Numeric target: BTC 150000"));
+        assert!(prompt.contains("Resolution deadline: 1767225600"));
+
+        let request = build_chat_request(prompt.clone());
+        assert_eq!(request.messages.len(), 2);
+        assert!(matches!(request.messages[0].role, ChatRole::System));
+        assert_eq!(request.messages[1].content, prompt);
+    }
+
+    #[test]
+    fn backend_follows_the_flag() {
+        let id = "w36hm-eqaaa-aaaal-qr76a-cai";
+        assert_eq!(select_backend(false, id), Ok(Backend::Mock));
+        assert_eq!(select_backend(false, "garbage"), Ok(Backend::Mock));
+        assert_eq!(
+            select_backend(true, id),
+            Ok(Backend::Llm(Principal::from_text(id).unwrap()))
+        );
+        assert!(select_backend(true, "garbage").is_err());
+    }
+
+    #[test]
+    fn risks_flag_unbacked_structured_fields() {
+        let mut market = new_market(1);
+        let base = mock_insight(&market, 0).risks.len();
+
+        market.numeric_target = Some(("BTC".to_string(), 150_000));
+        assert_eq!(structured_field_risks(&market).len(), 1); // stated, no deadline
+        market.numeric_target = Some(("BTC".to_string(), 200_000));
+        assert_eq!(structured_field_risks(&market).len(), 2);
+        assert_eq!(mock_insight(&market, 0).risks.len(), base + 2);
+
+        let parsed = parse_response("  Looks bullish.  ", &market, 5).unwrap();
+        assert_eq!(parsed.summary, "Looks bullish.");
+        assert_eq!(parsed.generated_at, 5);
+        assert!(parse_response(" ", &market, 5).is_err());
+    }
+
+    #[test]
+    fn mock_sentiment_counts_keywords() {
+        let comments = vec![
+            "Bullish, this is going up".to_string(),
+            "No way, I doubt it".to_string(),
+            "Buy!".to_string(),
+            "Just watching".to_string(),
+        ];
+        let sentiment = mock_sentiment(&comments, 9);
+        assert_eq!((sentiment.bullish_pct, sentiment.bearish_pct), (60, 40));
+        assert_eq!(sentiment.analyzed_at, 9);
+
+        let quiet = mock_sentiment(&["Hello".to_string()], 0);
+        assert_eq!((quiet.bullish_pct, quiet.bearish_pct), (0, 0));
+
+        let prompt = build_sentiment_prompt(&new_market(1), &comments);
+        assert!(prompt.contains("- Just watching"));
+    }
+
+    #[test]
+    fn digests_mention_every_input_market() {
+        let input = DigestInput {
+            category: "Crypto".to_string(),
+            movers: vec![(2, "ETH flips BTC".to_string(), -350)],
+            top_volume: vec![
+                (1, "BTC 150k".to_string(), 900),
+                (2, "ETH flips BTC".to_string(), 400),
+            ],
+            resolved: vec![(3, "SOL ETF".to_string(), Outcome::Yes)],
+        };
+        assert_eq!(input.market_ids(), vec![2, 1, 3]);
+
+        let prompt = build_digest_prompt(&input);
+        assert!(prompt.contains("- #2 ETH flips BTC: -350 bps"));
+        assert!(prompt.contains("- #3 SOL ETF: YES"));
+
+        let digest = mock_digest(&input);
+        assert!(digest.contains("#2 ETH flips BTC, which shifted -350 bps"));
+        assert_eq!(digest.matches(". ").count() + 1, 4);
+
+        assert_eq!(parse_digest("  Quiet day.  ").unwrap(), "Quiet day.");
+        assert!(parse_digest("\n").is_err());
+    }
+
+    #[test]
+    fn sentiment_responses_need_both_percentages() {
+        let parsed = parse_sentiment("BULLISH=70%\nBEARISH=30\nSUMMARY= Mostly upbeat", 3).unwrap();
+        assert_eq!((parsed.bullish_pct, parsed.bearish_pct), (70, 30));
+        assert_eq!(parsed.summary, "Mostly upbeat");
+        assert!(parse_sentiment("BULLISH=70", 3).is_err());
+        assert!(parse_sentiment("BULLISH=170\nBEARISH=0", 3).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+// XP levels
+//
+// A continuous rank on top of the discrete badges. The threshold table maps
+// the minimum XP of each tier to its rank name; a profile's level is the
+// 1-based index of the highest tier its XP reaches. Admins can replace the
+// table, which must start at 0 XP and strictly increase.
+use std::cell::RefCell;
+
+const MAX_LEVELS: usize = 50;
+const MAX_RANK_NAME_LEN: usize = 30;
+
+thread_local! {
+    static LEVEL_THRESHOLDS: RefCell<Vec<(u64, String)>> = RefCell::new(default_thresholds());
+}
+
+fn default_thresholds() -> Vec<(u64, String)> {
+    [
+        (0, "Novice"),
+        (100, "Apprentice"),
+        (500, "Analyst"),
+        (2_000, "Strategist"),
+        (10_000, "Oracle"),
+    ]
+    .into_iter()
+    .map(|(xp, name)| (xp, name.to_string()))
+    .collect()
+}
+
+pub fn thresholds() -> Vec<(u64, String)> {
+    LEVEL_THRESHOLDS.with(|thresholds| thresholds.borrow().clone())
+}
+
+pub fn set_thresholds(thresholds: Vec<(u64, String)>) -> Result<(), String> {
+    if thresholds.is_empty() || thresholds.len() > MAX_LEVELS {
+        return Err(format!("Between 1 and {} levels are required", MAX_LEVELS));
+    }
+    if thresholds[0].0 != 0 {
+        return Err("The first level must start at 0 XP".to_string());
+    }
+    if thresholds.windows(2).any(|pair| pair[1].0 <= pair[0].0) {
+        return Err("Level thresholds must strictly increase".to_string());
+    }
+    for (_, name) in &thresholds {
+        let len = name.trim().chars().count();
+        if len == 0 || len > MAX_RANK_NAME_LEN {
+            return Err(format!(
+                "Rank names must be between 1 and {} characters",
+                MAX_RANK_NAME_LEN
+            ));
+        }
+    }
+
+    LEVEL_THRESHOLDS.with(|current| *current.borrow_mut() = thresholds);
+    Ok(())
+}
+
+pub fn xp_to_level(xp: u64) -> (u64, String) {
+    LEVEL_THRESHOLDS.with(|thresholds| {
+        let thresholds = thresholds.borrow();
+        // The table starts at 0, so at least one tier always matches
+        let reached = thresholds.partition_point(|(min_xp, _)| *min_xp <= xp);
+        (reached as u64, thresholds[reached - 1].1.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_change_exactly_at_the_thresholds() {
+        assert_eq!(xp_to_level(0), (1, "Novice".to_string()));
+        assert_eq!(xp_to_level(99), (1, "Novice".to_string()));
+        assert_eq!(xp_to_level(100), (2, "Apprentice".to_string()));
+        assert_eq!(xp_to_level(499), (2, "Apprentice".to_string()));
+        assert_eq!(xp_to_level(500), (3, "Analyst".to_string()));
+        assert_eq!(xp_to_level(9_999), (4, "Strategist".to_string()));
+        assert_eq!(xp_to_level(10_000), (5, "Oracle".to_string()));
+        assert_eq!(xp_to_level(u64::MAX), (5, "Oracle".to_string()));
+    }
+
+    #[test]
+    fn replaced_tables_are_validated() {
+        let table = |rows: &[(u64, &str)]| -> Vec<(u64, String)> {
+            rows.iter()
+                .map(|(xp, name)| (*xp, name.to_string()))
+                .collect()
+        };
+        assert!(set_thresholds(vec![]).is_err());
+        assert!(set_thresholds(table(&[(10, "Late")])).is_err());
+        assert!(set_thresholds(table(&[(0, "A"), (50, "B"), (50, "C")])).is_err());
+        assert!(set_thresholds(table(&[(0, "A"), (50, " ")])).is_err());
+
+        set_thresholds(table(&[(0, "Rookie"), (50, "Pro")])).unwrap();
+        assert_eq!(xp_to_level(49), (1, "Rookie".to_string()));
+        assert_eq!(xp_to_level(50), (2, "Pro".to_string()));
+    }
+}
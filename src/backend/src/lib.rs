@@ -1,47 +1,440 @@
-use candid::{CandidType, Deserialize, Principal};
-// use ic_cdk::api::call::call; // Uncomment when using real LLM canister
+use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_cdk::export_candid;
+use sha2::{Digest, Sha256};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+mod api;
+mod categories;
+mod embed;
+mod engine;
+mod experiments;
+mod hooks;
+mod incentives;
+mod insight;
+mod levels;
+mod limits;
+mod odds;
+mod quests;
+mod randomness;
+mod references;
+mod resolvers;
+mod scheduler;
+mod similarity;
+mod surveillance;
+use categories::Category;
+use experiments::{Experiment, ExperimentAssignment, ExperimentOverrides, ExperimentResults};
+use hooks::{CanisterHook, ResolutionPayload};
+use incentives::{IncentiveConfig, IncentiveInfo};
+use limits::{SpendLog, TradingLimits, TradingLimitsStatus, UserLimits};
+use odds::{Odds, OddsFormat};
+use quests::{Quest, QuestEvent, QuestProgress};
+use randomness::Draw;
+use resolvers::{DisputeSettlement, ResolverAssignment, ResolverProfile};
+use scheduler::{Task, TaskStatus};
+use surveillance::{FlagStatus, SurveillanceConfig, SuspiciousFlag};
 
 // Market types and structures
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Market {
     pub id: u64,
     pub title: String,
+    pub slug: String, // human-readable identifier derived from the title
     pub description: String,
     pub category: String,
     pub creator: Principal,
-    pub close_date: u64, // timestamp
+    pub close_date: u64, // ns, like every other timestamp
     pub status: MarketStatus,
     pub yes_shares: u64,
     pub no_shares: u64,
     pub yes_liquidity: u64,
     pub no_liquidity: u64,
     pub total_volume: u64,
+    pub seed_liquidity: u64, // opening liquidity nobody paid in; it shapes prices but is never paid out
+    pub seed_volume: u64,    // part of total_volume no trade paid for
     pub created_at: u64,
-    pub resolved_outcome: Option<bool>, // Some(true) = YES wins, Some(false) = NO wins, None = unresolved
+    pub resolved_outcome: Option<Outcome>, // None = unresolved
+    pub comments_locked: bool,
+    pub trading_schedule: Option<TradingSchedule>, // None = trade any time before close_date
+    pub market_kind: MarketKind,
+    pub resolver: Option<Principal>, // may resolve alongside the creator and admins
+    pub resolution_criteria: Option<ResolutionCriteria>, // None = free-form resolution
+    pub archived_to: Option<Principal>, // detail data lives in this archive canister; reads are slower
+    pub resolution_datetime: Option<u64>, // real-world event deadline (ns), at or after close_date
+    pub primary_source_url: Option<String>, // https only
+    pub numeric_target: Option<(String, u64)>, // (metric, value) for threshold markets
+    pub co_owners: Vec<Principal>,      // share management with the creator, up to MAX_CO_OWNERS
+    pub resolution_delegate: Option<Principal>, // a co-owner the creator let resolve
+}
+
+// Structured resolution: the resolver rules on every statement and the outcome
+// must follow from those verdicts under the rule chosen at creation
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ResolutionCriteria {
+    pub statements: Vec<String>,
+    pub rule: CriteriaRule,
+    pub verdicts: Option<Vec<bool>>, // one per statement, set at resolution
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum CriteriaRule {
+    All, // YES only if every statement holds
+    Any, // YES if at least one statement holds
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum MarketKind {
+    Standard,
+    Flash, // short-horizon market with an accelerated lifecycle, see FlashConfig
 }
 
+// Lifecycle parameters for flash markets. Flash markets skip manual
+// validation and go live on creation with an AI insight as their only review,
+// so only trusted creators may open them.
 #[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FlashConfig {
+    pub min_close_window_seconds: u64,
+    pub max_close_window_seconds: u64,
+    pub dispute_window_seconds: u64,    // applies once disputes exist
+    pub reminder_interval_seconds: u64, // between resolution reminders once closed
+    pub min_creator_xp: u64,            // allowlisted creators and admins are always eligible
+}
+
+impl Default for FlashConfig {
+    fn default() -> Self {
+        FlashConfig {
+            min_close_window_seconds: 30 * 60,
+            max_close_window_seconds: 24 * 60 * 60,
+            dispute_window_seconds: 2 * 60 * 60,
+            reminder_interval_seconds: 15 * 60,
+            min_creator_xp: 500,
+        }
+    }
+}
+
+// Trading hours. Outside the schedule trading is paused, which is distinct
+// from the terminal close_date.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TradingSchedule {
+    Windows(Vec<(u64, u64)>), // (open_ts, close_ts) windows, in ns
+    Daily { open_second: u64, close_second: u64 }, // UTC seconds of day; may wrap midnight
+}
+
+// Optional parameters accepted at market creation
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct MarketOptions {
+    pub trading_schedule: Option<TradingSchedule>,
+    pub market_kind: Option<MarketKind>, // defaults to Standard
+    pub resolver: Option<Principal>,     // designated oracle, see Market::resolver
+    pub resolution_criteria: Option<ResolutionCriteria>, // verdicts must be unset
+    pub resolution_datetime: Option<u64>,
+    pub primary_source_url: Option<String>,
+    pub numeric_target: Option<(String, u64)>,
+    pub co_owners: Option<Vec<Principal>>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
 pub enum MarketStatus {
     PendingValidation,
     Active,
     Closed,
     Resolved,
+    Rejected, // failed review; terminal
+    Archived, // taken down by moderation; terminal
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum Outcome {
+    Yes,
+    No,
+    Invalid, // resolves N/A: positions are refunded at cost and nobody wins
+}
+
+impl Outcome {
+    // The side that wins, None for Invalid
+    pub fn winning_side(self) -> Option<bool> {
+        match self {
+            Outcome::Yes => Some(true),
+            Outcome::No => Some(false),
+            Outcome::Invalid => None,
+        }
+    }
+}
+
+impl From<bool> for Outcome {
+    fn from(yes: bool) -> Self {
+        if yes {
+            Outcome::Yes
+        } else {
+            Outcome::No
+        }
+    }
+}
+
+// Market event log
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum MarketEventKind {
+    Approved,
+    CommentsLocked,
+    CommentsUnlocked,
+    Sponsored,
+    Resolved,
+    ResolutionReminder, // a closed flash market is still waiting for resolution
+    Rejected {
+        reason: String,
+    },
+    Archived {
+        reason: String,
+    },
+    CoOwnerAdded {
+        co_owner: Principal,
+    },
+    CoOwnerRemoved {
+        co_owner: Principal,
+    },
+    ResolutionDelegated {
+        to: Option<Principal>,
+    }, // None: taken back by the creator
+    EarliestCorrect {
+        trader: Principal,
+        draw_id: Option<u64>,
+    }, // draw_id set on a tie
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MarketEvent {
+    pub id: u64,
+    pub market_id: u64,
+    pub kind: MarketEventKind,
+    pub actor: Principal,
+    pub timestamp: u64,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Trade {
+    pub id: u64,
+    pub market_id: u64,
+    pub trader: Principal,
+    pub is_yes: bool,
+    pub amount: u64, // cash paid, fee included
+    pub shares: u64, // shares minted for the amount
+    pub price: u64,
+    pub timestamp: u64,
+    pub xp_multiplier_bps: u64, // XP multiplier applied to this trade (10_000 = 1x)
+    pub fee: u64,
+    pub dust: u64,
+    pub experiments: Vec<ExperimentAssignment>, // buckets the trader was in, for later analysis
+    pub incentive_rebate: Option<u64>, // maker incentive credited back; None in older archives
+    pub reverted_at: Option<u64>,      // set when undone; kept for audit, out of volume and prices
+}
+
+// Shares sold back to the pool. Ids come from the trade sequence, so a sale
+// orders against buys on the same market.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Sale {
     pub id: u64,
     pub market_id: u64,
     pub trader: Principal,
     pub is_yes: bool,
     pub shares: u64,
+    pub proceeds: u64, // credited to the trader, fee already taken
     pub price: u64,
+    pub fee: u64, // charged once on the way out; the treasury took it on the buy
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ClosedPosition {
+    pub sale: Sale,
+    pub remaining: Position,
+}
+
+// Whale-watch feed entry, joined with its market's title
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LargeTrade {
+    pub trade: Trade,
+    pub market_title: String,
+}
+
+// One simulated buy on the depth chart
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DepthLevel {
+    pub amount: u64, // cash in, fee included
+    pub shares: u64,
+    pub price: u64, // execution price as recorded on trades, per-mille
+    pub fee: u64,
+    pub post_trade_yes_bps: u64,
+}
+
+// The pricing curve's inputs alongside simulated buys on each side; see
+// engine::calculate_price and engine::shares_for for the formulas
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DepthChart {
+    pub market_id: u64,
+    pub yes_shares: u64,
+    pub no_shares: u64,
+    pub yes_liquidity: u64,
+    pub no_liquidity: u64,
+    pub base_depth: u64,       // added to a side's shares to get its curve depth
+    pub max_price_impact: u64, // per-mille
+    pub fee_bps: u64,          // the caller's effective fee
+    pub yes_bps: u64,
+    pub yes_levels: Vec<DepthLevel>, // empty unless the market is Active
+    pub no_levels: Vec<DepthLevel>,
+}
+
+// A resolver's reward, held until the resolution can no longer be disputed
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct PendingResolutionReward {
+    market_id: u64,
+    resolver: Principal,
+    amount: u64, // fixed at resolution, before trades can be archived
+    due_at: u64,
+}
+
+// Entry of the recently-resolved feed
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ResolvedMarketView {
+    pub market_id: u64,
+    pub title: String,
+    pub resolved_outcome: Outcome,
+    pub resolved_at: u64,
+    pub total_volume: u64,
+    pub winners: u64, // holders of winning-side shares
+}
+
+// Unique traders per side. A principal who bought both sides counts in
+// yes_traders, no_traders and both.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub struct ParticipationSplit {
+    pub yes_traders: u64,
+    pub no_traders: u64,
+    pub both: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ActiveMarketLimit {
+    pub max_active_markets: u64, // 0 = unlimited
+    pub active_markets: u64,
+}
+
+// Trading parameters in effect for one caller, experiments applied
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct EffectiveConfig {
+    pub fee_bps: u64,
+    pub xp_per_amount_divisor: u64,
+    pub experiments: Vec<ExperimentAssignment>,
+}
+
+// Every admin-settable platform value in one read, for operators and support.
+// Limits of 0 mean unlimited or disabled, as on their individual setters.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PlatformConfig {
+    pub fee_bps: u64, // base fee, before waivers and experiments
+    pub free_trades: u64,
+    pub close_grace_seconds: u64,
+    pub max_position_per_market: u64,
+    pub max_xp_per_trade: u64,
+    pub leaderboard_badge_size: u64,
+    pub max_active_markets: u64,
+    pub min_market_liquidity: u64,
+    pub max_comments_per_market: u64,
+    pub similarity_threshold_bps: u64,
+    pub insight_refresh_threshold_bps: u64,
+    pub resolution_reward_bps: u64, // of the market's fees, paid once the dispute window passes
+    pub link_domains: Vec<String>,  // allowed for external link attachments
+    pub auto_insight_on_approve: bool,
+    pub llm_enabled: bool,
+    pub llm_daily_calls: u64,
+    pub access_mode: AccessMode,
+    pub anonymous_reads_allowed: bool,
+    pub approval_policy: ApprovalPolicy,
+    pub treasury_floor: u64,
+    pub ledger_canister: Principal,
+    pub archive_canister: Option<Principal>,
+    pub auto_archive: AutoArchiveConfig,
+    pub flash: FlashConfig,
+    pub xp: XpConfig,
+}
+
+// A user's holdings in one market
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Position {
+    pub market_id: u64,
+    pub owner: Principal,
+    pub yes_shares: u64,
+    pub no_shares: u64,
+    pub yes_cost: u64,
+    pub no_cost: u64,
+    pub claimed: bool,
+}
+
+// A position valued against its market's current pool. Cost is cash paid
+// (fees included); payouts are what the shares would collect on resolution.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PositionValue {
+    pub position: Position,
+    pub yes_pool_share_bps: u64, // share of all outstanding YES shares
+    pub no_pool_share_bps: u64,
+    pub payout_if_yes: u64,
+    pub payout_if_no: u64,
+    pub fees_paid: u64, // trading fees paid in this market
+}
+
+// Shares moved directly between users. Kept apart from trades so gifts and
+// OTC deals don't show up in price history or volume.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferRecord {
+    pub id: u64,
+    pub market_id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub is_yes: bool,
+    pub shares: u64,
+    pub cost_basis: u64, // part of the sender's cost basis inherited by the recipient
     pub timestamp: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ClaimResult {
+    pub market_id: u64,
+    pub result: Result<u64, String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ClaimAllResult {
+    pub results: Vec<ClaimResult>,
+    pub total_claimed: u64,
+    pub remaining: u32, // claimable markets left for a follow-up call
+}
+
+// Multi-user distributions are queued with the action that triggers them and
+// applied by the scheduler one item at a time, so an upgrade or a trap between
+// batches never loses or repeats a payout
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum PayoutKind {
+    SuccessfulPrediction, // resolution credit on the profile
+    BalanceCredit {
+        amount: u64,
+        reason: Option<BalanceReason>, // absent on items queued by older versions
+    }, // boost pools and prizes
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PayoutItem {
+    pub id: u64,
+    pub market_id: u64,
+    pub recipient: Principal,
+    pub kind: PayoutKind,
+    pub enqueued_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PayoutQueueStatus {
+    pub pending: u64,
+    pub completed: u64,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct UserProfile {
     pub principal: Principal,
@@ -53,6 +446,236 @@ pub struct UserProfile {
     pub created_at: u64,
 }
 
+// Trade XP weighting. Multipliers are in basis points (10_000 = 1x).
+// Resolution XP is granted separately and is not affected by these weights.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct XpConfig {
+    pub xp_per_amount_divisor: u64, // base XP = amount / divisor
+    pub early_multiplier_bps: u64,  // applied to trades at market creation
+    pub late_multiplier_bps: u64,   // applied to trades at close_date
+    pub contrarian_bonus_bps: u64,  // applied when trading against the current majority
+}
+
+impl Default for XpConfig {
+    fn default() -> Self {
+        XpConfig {
+            xp_per_amount_divisor: 10,
+            early_multiplier_bps: 20_000,
+            late_multiplier_bps: 10_000,
+            contrarian_bonus_bps: 15_000,
+        }
+    }
+}
+
+// Every XP grant goes through grant_xp under one of these sources, which
+// applies the source's rate and daily cap and appends a ledger entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, CandidType, Deserialize)]
+pub enum XpSource {
+    Trade,
+    Resolution,
+    Referral,
+    Badge,
+    Quest,
+    Admin,
+}
+
+const XP_SOURCES: [XpSource; 6] = [
+    XpSource::Trade,
+    XpSource::Resolution,
+    XpSource::Referral,
+    XpSource::Badge,
+    XpSource::Quest,
+    XpSource::Admin,
+];
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct XpSourceRule {
+    pub rate_bps: u64,  // applied to the base amount, 10_000 = 1x
+    pub daily_cap: u64, // per principal per UTC day, 0 = uncapped
+}
+
+impl Default for XpSourceRule {
+    fn default() -> Self {
+        XpSourceRule {
+            rate_bps: 10_000,
+            daily_cap: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct XpEntry {
+    pub id: u64,
+    pub principal: Principal,
+    pub source: XpSource,
+    pub amount: u64,    // after rate and cap
+    pub reference: u64, // related trade, market or other entity id; 0 when none
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct XpHistoryPage {
+    pub entries: Vec<XpEntry>, // newest first
+    pub next_cursor: Option<u64>,
+}
+
+// Which creators skip the approval queue. With auto_approve on, allowlisted
+// creators and those meeting either threshold get their markets activated on
+// creation; everyone else still goes to PendingValidation.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct ApprovalPolicy {
+    pub auto_approve: bool,
+    pub min_xp: Option<u64>,                     // None disables the XP path
+    pub min_successful_predictions: Option<u64>, // None disables the predictions path
+}
+
+// Soft launch gating. In AllowList mode only listed principals (and admins)
+// may trade, create markets or comment; reads stay public in both modes.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub enum AccessMode {
+    #[default]
+    Open,
+    AllowList,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AccessRequest {
+    pub principal: Principal,
+    pub requested_at: u64,
+}
+
+// What the frontend needs to pick between the app and the request-access screen
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AccessStatus {
+    pub mode: AccessMode,
+    pub allowed: bool,
+    pub requested: bool, // a request is waiting for review
+}
+
+// General per-user inbox entries
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum UserNotificationKind {
+    AccessGranted,
+    MarketResolved { market_id: u64, outcome: Outcome },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct UserNotification {
+    pub kind: UserNotificationKind,
+    pub created_at: u64,
+}
+
+// Notification kinds a user can switch on or off. Preferences are stored by
+// kind name so a client can keep settings for kinds this canister does not
+// know yet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotificationKind {
+    MarketResolved,
+    MarketClosed,
+    CommentReply,
+    Mention,
+    BadgeEarned,
+    WhaleTrade,
+    PriceAlert,
+    DisputeOpened,
+    QuestAvailable,
+}
+
+const NOTIFICATION_KINDS: [NotificationKind; 9] = [
+    NotificationKind::MarketResolved,
+    NotificationKind::MarketClosed,
+    NotificationKind::CommentReply,
+    NotificationKind::Mention,
+    NotificationKind::BadgeEarned,
+    NotificationKind::WhaleTrade,
+    NotificationKind::PriceAlert,
+    NotificationKind::DisputeOpened,
+    NotificationKind::QuestAvailable,
+];
+
+impl NotificationKind {
+    fn name(self) -> &'static str {
+        match self {
+            NotificationKind::MarketResolved => "MarketResolved",
+            NotificationKind::MarketClosed => "MarketClosed",
+            NotificationKind::CommentReply => "CommentReply",
+            NotificationKind::Mention => "Mention",
+            NotificationKind::BadgeEarned => "BadgeEarned",
+            NotificationKind::WhaleTrade => "WhaleTrade",
+            NotificationKind::PriceAlert => "PriceAlert",
+            NotificationKind::DisputeOpened => "DisputeOpened",
+            NotificationKind::QuestAvailable => "QuestAvailable",
+        }
+    }
+
+    fn default_setting(self) -> NotificationSetting {
+        match self {
+            NotificationKind::WhaleTrade => NotificationSetting::Disabled,
+            _ => NotificationSetting::Enabled,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum NotificationSetting {
+    Enabled,
+    Disabled,
+}
+
+// Sponsor-funded reward boosts
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum BoostKind {
+    VolumeRebate { rebate_bps: u64 }, // share of the trading fee returned to traders
+    XpMultiplier { multiplier_bps: u64 }, // bonus XP, drawn from the pool one unit per XP
+    ResolutionBonus,                  // split among correct predictors at resolution
+}
+
+// Bonus XP a recent trade drew, as its timestamp and (boost id, amount) pairs,
+// so a revert can return it to the sponsors' pools
+type BoostXpDraw = (u64, Vec<(u64, u64)>);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MarketBoost {
+    pub id: u64,
+    pub market_id: u64,
+    pub sponsor: Principal,
+    pub kind: BoostKind,
+    pub funded: u64,
+    pub remaining: u64,
+    pub created_at: u64,
+}
+
+// Badge milestones
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub enum BadgeMetric {
+    Trades,
+    Xp,
+    SuccessfulPredictions,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BadgeProgress {
+    pub badge_name: String,
+    pub current: u64,
+    pub target: u64,
+    pub earned: bool,
+}
+
+// A profile with the level and rank its XP currently reaches
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RankedProfile {
+    pub profile: UserProfile,
+    pub level: u64,
+    pub rank_name: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum InsightStatus {
+    Generating,
+    Ready,
+    Failed(String),
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct AIInsight {
     pub market_id: u64,
@@ -60,9 +683,37 @@ pub struct AIInsight {
     pub confidence: f64, // 0.0 to 1.0
     pub risks: Vec<String>,
     pub prediction_lean: Option<bool>, // Some(true) = leans YES, Some(false) = leans NO
+    pub generated_at: u64, // job start while Generating, completion once Ready or Failed
+    pub status: InsightStatus,
+    pub job_id: Option<u64>,            // None for insights seeded at init
+    pub generated_yes_bps: Option<u64>, // market price it was written at; None in older archives
+}
+
+// Discussion mood, as a share of the comments leaning each way
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CommentSentiment {
+    pub bullish_pct: u64,
+    pub bearish_pct: u64,
+    pub summary: String,
+    pub analyzed_at: u64,
+}
+
+// Daily AI brief for one category
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CategoryDigest {
+    pub category: String,
+    pub date: u64, // days since the epoch
+    pub text: String,
+    pub market_ids: Vec<u64>, // markets the digest was written from
     pub generated_at: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DigestView {
+    pub digest: CategoryDigest,
+    pub markets: Vec<MarketSummary>, // referenced markets that still exist
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct MarketComment {
     pub id: u64,
@@ -70,538 +721,10032 @@ pub struct MarketComment {
     pub author: Principal,
     pub content: String,
     pub timestamp: u64,
+    pub edited_at: Option<u64>,
+    pub market_refs: Vec<u64>, // other markets referenced in the content
+    pub attachment: Option<CommentAttachment>,
 }
 
-// LLM Communication structures
+// At most one per comment. Chart ranges are in nanoseconds, like
+// get_liquidity_history, which the frontend draws them from.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum CommentAttachment {
+    PriceChartRef {
+        market_id: u64,
+        from_ts: u64,
+        to_ts: u64,
+    },
+    ExternalLink {
+        url: String,
+    }, // https, on an allow-listed domain
+}
+
+// Batched discussion notification for a comment subscriber
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct ChatMessageV0 {
-    pub content: String,
-    pub role: ChatRole,
+pub struct CommentNotification {
+    pub market_id: u64,
+    pub new_comments: u64,
+    pub latest_comment_id: u64,
+    pub first_at: u64, // opens the batching window
+    pub last_at: u64,
 }
 
+// Edit history structures
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub enum ChatRole {
-    #[serde(rename = "user")]
-    User,
-    #[serde(rename = "assistant")]
-    Assistant,
-    #[serde(rename = "system")]
-    System,
+pub struct CommentRevision {
+    pub version: u64,
+    pub content: String, // content before the edit
+    pub editor: Principal,
+    pub edited_at: u64,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct ChatRequestV0 {
-    pub model: String,
-    pub messages: Vec<ChatMessageV0>,
+pub struct MarketRevision {
+    pub version: u64,
+    pub title: String,
+    pub description: String, // truncated to MAX_REVISION_DESCRIPTION_LEN
+    pub description_truncated: bool,
+    pub category: String,
+    pub close_date: u64,
+    pub editor: Principal,
+    pub edited_at: u64,
 }
 
-// LLM Canister ID (replace with actual canister ID)
-// const LLM_CANISTER_ID: &str = "w36hm-eqaaa-aaaal-qr76a-cai"; // Uncomment when using real LLM canister
+// Market FAQ maintained by the creator
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FaqEntry {
+    pub id: u64,
+    pub question: String,
+    pub answer: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
 
-// State management
-thread_local! {
-    static MARKETS: RefCell<HashMap<u64, Market>> = RefCell::new(HashMap::new());
-    static TRADES: RefCell<Vec<Trade>> = const { RefCell::new(Vec::new()) };
-    static USER_PROFILES: RefCell<HashMap<Principal, UserProfile>> = RefCell::new(HashMap::new());
-    static AI_INSIGHTS: RefCell<HashMap<u64, AIInsight>> = RefCell::new(HashMap::new());
-    static COMMENTS: RefCell<Vec<MarketComment>> = const { RefCell::new(Vec::new()) };
-    static NEXT_MARKET_ID: RefCell<u64> = const { RefCell::new(1) };
-    static NEXT_TRADE_ID: RefCell<u64> = const { RefCell::new(1) };
-    static NEXT_COMMENT_ID: RefCell<u64> = const { RefCell::new(1) };
-    static TREASURY: RefCell<u64> = const { RefCell::new(0) };
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct MarketFaq {
+    pub entries: Vec<FaqEntry>,
+    pub next_id: u64,
+    pub force_frozen: bool, // set by admins when a creator abuses edits
 }
 
-// Initialize with sample data
-#[ic_cdk::init]
-fn init() {
-    let sample_markets = vec![
-        Market {
-            id: 1,
-            title: "Will Bitcoin reach $150,000 by end of 2025?".to_string(),
-            description: "This market resolves to YES if Bitcoin (BTC) reaches or exceeds $150,000 USD by December 31, 2025.".to_string(),
-            category: "Cryptocurrency".to_string(),
-            creator: Principal::anonymous(),
-            close_date: 1767225600, // Dec 31, 2025
-            status: MarketStatus::Active,
-            yes_shares: 450,
-            no_shares: 550,
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum FaqChange {
+    Added,
+    Updated,
+    Removed,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FaqRevision {
+    pub version: u64,
+    pub faq_id: u64,
+    pub change: FaqChange,
+    pub question: String, // content before the change (empty when added)
+    pub answer: String,
+    pub editor: Principal,
+    pub edited_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MarketDetail {
+    pub market: Market,
+    pub trading_open: bool,
+    pub next_open: Option<u64>, // when trading next reopens if currently paused
+    pub faq: Vec<FaqEntry>,
+    pub faq_frozen: bool,
+    pub boosts: Vec<MarketBoost>,
+    pub resolution_countdown: Option<i64>, // seconds to the resolution deadline, negative once overdue
+    pub flow: FlowInfo,
+    pub ownership_history: Vec<MarketEvent>, // co-owner and delegation changes, oldest first
+    pub resolution_timeline: Option<ResolutionTimeline>, // None while the market is still open
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum ResolutionPhase {
+    AwaitingResolution,
+    DisputeWindow, // resolved by a listed resolver whose outcome can still be disputed
+    Disputed,
+    Final,
+}
+
+// Where a closed market stands on its way to payout, for the status stepper
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ResolutionTimeline {
+    pub phase: ResolutionPhase,
+    pub resolution_deadline: u64, // ns; close_date plus the resolution window
+    pub resolved_at: Option<u64>,
+    pub dispute_window_remaining: Option<u64>, // seconds, during DisputeWindow only
+    pub claimable: u64,                        // the caller's unclaimed winnings
+    pub evidence_submitted: bool,              // per-criterion verdicts recorded
+}
+
+// Buy volume per side over whole clock hours: 1h is the current hour so far,
+// 24h the current hour and the 23 before it
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct FlowInfo {
+    pub yes_vol_1h: u64,
+    pub no_vol_1h: u64,
+    pub yes_vol_24h: u64,
+    pub no_vol_24h: u64,
+    pub net_flow_bps: i64, // 24h (yes - no) / (yes + no); +10000 is all YES
+}
+
+struct FlowBucket {
+    hour: u64, // hours since the epoch
+    yes_volume: u64,
+    no_volume: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TrendingMarket {
+    pub market: Market,
+    pub flow: FlowInfo,
+    pub score: u64, // 24h volume, scaled up by how one-sided the flow is
+}
+
+// Listing summary cached per market so feeds page without scanning trades or
+// comments. Refreshed whenever its market trades, gets discussed or changes
+// status, and in bulk every hour as the 24h windows slide.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct MarketCard {
+    pub market_id: u64,
+    pub title: String,
+    pub slug: String,
+    pub category: String,
+    pub status: MarketStatus,
+    pub close_date: u64,
+    pub yes_bps: u64,
+    pub change_24h_bps: i64,
+    pub total_volume: u64,
+    pub participation: ParticipationSplit,
+    pub comments: u64,
+    pub trending_score: u64, // as in get_trending_markets; 0 unless active
+    pub refreshed_at: u64,   // ns; the windowed stats are as of this time
+}
+
+// Creator track record, updated as their markets are created, approved and
+// resolved. Disputes are not tracked yet and stay at 0.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct CreatorReputation {
+    pub markets_created: u64,
+    pub markets_approved: u64,
+    pub approval_rate_bps: u64,
+    pub disputes: u64,
+    pub resolved_on_time: u64,
+    pub resolved_late: u64,
+}
+
+// Entry of the new-markets feed
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct NewMarket {
+    pub market: Market,
+    pub activated_at: u64,
+    pub creator_reputation: CreatorReputation,
+    pub creator_overdue_resolutions: u64, // closed markets past their resolution deadline
+    pub ai_insight: Option<InsightStatus>, // no dedicated validation report exists yet
+    pub initial_liquidity: u64,
+}
+
+// Implied YES probability after a trade, or at creation
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PriceSnapshot {
+    pub timestamp: u64,
+    pub yes_bps: u64,
+}
+
+// Pool sizes after a trade, or at creation
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LiquiditySnapshot {
+    pub market_id: u64,
+    pub yes_liquidity: u64,
+    pub no_liquidity: u64,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PriceChange {
+    pub from_bps: u64,
+    pub to_bps: u64,
+    pub change_bps: i64, // to - from, in probability points
+}
+
+// One-line market snapshot for social embeds and bots
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct EmbedCard {
+    pub market_id: u64,
+    pub title: String,
+    pub description: String,    // single line, at most 140 chars
+    pub probability: String,    // implied YES probability, e.g. "62%"
+    pub volume: String,         // e.g. "~12.5k"
+    pub time_remaining: String, // e.g. "closes in 3 days"
+    pub status: MarketStatus,
+}
+
+// Both sides of a market in one odds format
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct OddsQuote {
+    pub market_id: u64,
+    pub yes: Odds,
+    pub no: Odds,
+    pub yes_display: String, // e.g. "+150"
+    pub no_display: String,
+}
+
+// Numeric probability quote for external aggregators
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProbQuote {
+    pub market_id: u64,
+    pub yes_bps: u64,   // engine::implied_yes_bps, the figure the trading UI shows
+    pub liquidity: u64, // yes + no pool depth
+    pub last_trade_ts: Option<u64>,
+    pub stale: bool, // no trade in the last 24h
+    pub as_of: u64,  // canister time the quote was taken at
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub enum CommentSort {
+    Newest,
+    Oldest,
+    MostLiked,     // likes are not tracked yet, so this matches Newest
+    StakeWeighted, // biggest holders first, by position bucket
+}
+
+// Coarse size of an author's net position, so ranking never reveals exact holdings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize)]
+pub enum PositionBucket {
+    None,
+    Small,
+    Medium,
+    Large,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RankedComment {
+    pub comment: MarketComment,
+    pub author_position: PositionBucket, // for the holder chip
+}
+
+// Enough of a market to render a hover card for a reference
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MarketSummary {
+    pub id: u64,
+    pub title: String,
+    pub slug: String,
+    pub status: MarketStatus,
+    pub yes_bps: u64, // implied YES probability
+    pub total_volume: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CommentPage {
+    pub comments: Vec<RankedComment>,
+    pub referenced_markets: Vec<MarketSummary>, // every market referenced on the page
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MarketStats {
+    pub market_id: u64,
+    pub total_volume: u64,
+    pub trade_count: u64,
+    pub unique_traders: u64,
+    pub comment_count: u64,
+    pub discussion_mentions: u64, // comments elsewhere that referenced this market
+}
+
+// Detail data of an archived market, candid-encoded into the archive blob
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MarketArchive {
+    pub market_id: u64,
+    pub trades: Vec<Trade>,
+    pub comments: Vec<MarketComment>,
+    pub comment_history: Vec<(u64, Vec<CommentRevision>)>,
+    pub insight: Option<AIInsight>,
+    pub price_history: Vec<PriceSnapshot>,
+    pub liquidity_history: Option<Vec<LiquiditySnapshot>>, // absent in older archives
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TriageResult {
+    pub market_id: u64,
+    pub result: Result<(), String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AutoArchiveConfig {
+    pub enabled: bool,
+    pub older_than_days: u64, // resolved at least this long ago
+}
+
+impl Default for AutoArchiveConfig {
+    fn default() -> Self {
+        AutoArchiveConfig {
+            enabled: false,
+            older_than_days: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AutoArchiveRun {
+    pub started_at: u64,
+    pub archived: u64,
+    pub failed: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ArchiveOutcome {
+    pub market_id: u64,
+    pub result: Result<u64, String>, // archived blob size in bytes
+}
+
+// Zero-stakes opinion poll, one per market
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub enum PollChoice {
+    Yes,
+    No,
+    Unsure,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct PollTally {
+    pub yes: u64,
+    pub no: u64,
+    pub unsure: u64,
+}
+
+// Only counts and who voted are stored, never which way
+#[derive(Default)]
+struct MarketPoll {
+    tally: PollTally,
+    voters: HashSet<Principal>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PollView {
+    pub market_id: u64,
+    pub total_votes: u64,
+    pub has_voted: bool,
+    pub closed: bool,             // voting ended with the market; the tally is final
+    pub tally: Option<PollTally>, // hidden until the caller votes or the poll closes
+}
+
+// How the poll crowd, the closing price and the AI insight leaned against the
+// actual outcome. A lean is None when there was no signal or an exact tie.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ResolutionComparison {
+    pub market_id: u64,
+    pub outcome: Outcome,
+    pub crowd: Option<PollTally>,
+    pub crowd_lean: Option<bool>,
+    pub market_yes_bps: u64, // implied YES probability at resolution
+    pub market_lean: Option<bool>,
+    pub ai_lean: Option<bool>,
+    pub ai_correct: Option<bool>, // None without an AI lean; Invalid neither confirms nor refutes it
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CommentStats {
+    pub total_comments: u64,
+    pub total_likes_received: u64, // comment likes are not tracked yet, always 0
+    pub markets_commented: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CommentVelocity {
+    pub market: Market,
+    pub recent_comments: u64,
+    pub prior_comments: u64,
+    pub recent_per_hour: f64,
+    pub prior_per_hour: f64,
+    pub acceleration: f64, // relative growth of the recent window over the prior one
+}
+
+// Ledger (ICRC-1) structures
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+    pub amount: Nat,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+// Internal balance ledger. Every balance mutation goes through credit_balance or
+// debit_balance, which append the entry in the same message as the change, so
+// replaying a principal's entries from zero always yields their balance.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum BalanceReason {
+    Deposit,
+    Withdraw, // also the credit back when the ledger transfer fails
+    TradeDebit,
+    FeeCharge,
+    WinningsCredit,
+    Refund, // unspent boost budget returned to the sponsor
+    BoostPayout,
+    BoostFunding,
+    ReferralReward,
+    TreasuryOp,
+    ResolverStake, // bond posted or returned
+    ResolverFee,
+    ResolverSlash, // disputer's share of a slashed bond
+    IncentiveFunding,
+    IncentiveRebate,
+    ResolutionReward,
+    TradeRevert, // pool cash returned when a buy is undone
+    SaleProceeds,
+}
+
+// Trading revenue credited to the treasury: the fee plus any share dust
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TreasuryInflow {
+    pub amount: u64,
+    pub market_id: u64,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BalanceEntry {
+    pub id: u64,
+    pub principal: Principal,
+    pub delta: i128,
+    pub balance_after: u64,
+    pub reason: BalanceReason,
+    pub reference: u64, // related sweep, withdrawal, trade or market id, depending on the reason
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BalanceHistoryPage {
+    pub entries: Vec<BalanceEntry>, // newest first
+    pub next_cursor: Option<u64>,   // pass back to continue, None when exhausted
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DepositSweep {
+    pub id: u64,
+    pub principal: Principal,
+    pub amount: u64, // credited to the internal balance, net of the ledger fee
+    pub fee: u64,
+    pub block_index: u64,
+    pub timestamp: u64,
+}
+
+// Integrity check structures
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum IntegrityIssueKind {
+    OrphanTrade,           // trade references a missing market
+    OrphanComment,         // comment references a missing market
+    VolumeBelowTrades,     // market volume is lower than the sum of its trades
+    MarketCounterBehind,   // NEXT_MARKET_ID does not exceed the max market id
+    TradeCounterBehind,    // NEXT_TRADE_ID does not exceed the max trade id
+    CommentCounterBehind,  // NEXT_COMMENT_ID does not exceed the max comment id
+    TreasuryFeeMismatch,   // a market's fees do not match its treasury inflows
+    PositionsExceedShares, // positions hold more shares than the market issued
+    SlugIndexMismatch,     // the slug index disagrees with a market's slug
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum RepairStrategy {
+    RecomputeCounter,
+    PurgeOrphan,
+    RebuildIndexes,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct IntegrityIssue {
+    pub id: u64,
+    pub kind: IntegrityIssueKind,
+    pub entity_id: u64,
+    pub detail: String,
+    pub repaired: Option<RepairStrategy>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum IntegrityCheckStatus {
+    Running,
+    Completed,
+    Failed, // interrupted by an upgrade before it completed
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct IntegrityReport {
+    pub id: u64,
+    pub status: IntegrityCheckStatus,
+    pub started_at: u64,
+    pub completed_at: Option<u64>,
+    pub trades_checked: u64,
+    pub comments_checked: u64,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+// In-progress state of a chunked integrity check
+#[derive(Clone, Debug, Default)]
+struct IntegrityCursor {
+    trade_index: usize,
+    sale_index: usize,
+    comment_index: usize,
+    max_trade_id: u64, // sales included, as they share the sequence
+    max_comment_id: u64,
+    // Inflows older than this had rolled out of the log when the check began
+    inflow_cutoff: u64,
+    market_fees: HashMap<u64, u64>,
+    market_trade_volume: HashMap<u64, u64>,
+}
+
+// LLM Communication structures
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChatMessageV0 {
+    pub content: String,
+    pub role: ChatRole,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ChatRole {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "assistant")]
+    Assistant,
+    #[serde(rename = "system")]
+    System,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChatRequestV0 {
+    pub model: String,
+    pub messages: Vec<ChatMessageV0>,
+}
+
+// Edit history limits
+const MAX_REVISIONS: usize = 10;
+const MAX_REVISION_DESCRIPTION_LEN: usize = 1000;
+
+// XP tuning bounds
+const MAX_TIME_MULTIPLIER_BPS: u64 = 20_000;
+const MAX_CONTRARIAN_BONUS_BPS: u64 = 15_000;
+
+// FAQ limits
+const MAX_FAQ_ENTRIES: usize = 10;
+const MAX_FAQ_QUESTION_LEN: usize = 200;
+const MAX_FAQ_ANSWER_LEN: usize = 1000;
+
+// Upper bound for the close_date grace buffer
+const MAX_CLOSE_GRACE_SECONDS: u64 = 300;
+
+// Badges awarded when a profile stat reaches the target
+const BADGE_MILESTONES: &[(&str, BadgeMetric, u64)] = &[
+    ("First Trade", BadgeMetric::Trades, 1),
+    ("Active Trader", BadgeMetric::Trades, 10),
+    ("Veteran Trader", BadgeMetric::Trades, 100),
+    ("Rising Star", BadgeMetric::Xp, 100),
+    ("XP Master", BadgeMetric::Xp, 1_000),
+    ("Sharp Predictor", BadgeMetric::SuccessfulPredictions, 5),
+    ("Oracle", BadgeMetric::SuccessfulPredictions, 25),
+];
+
+// Awarded for claiming a quest reward on this many consecutive days
+const QUEST_STREAK_BADGE: &str = "Weekly Quester";
+const QUEST_STREAK_DAYS: u64 = 7;
+// Awarded on a trade that extends a streak of active days to this many
+const ACTIVITY_STREAK_BADGE: &str = "Daily Regular";
+const ACTIVITY_STREAK_DAYS: u64 = 7;
+
+// Integrity check tuning
+const INTEGRITY_CHUNK_SIZE: usize = 500;
+const MAX_INTEGRITY_REPORTS: usize = 20;
+
+// Upper bound for sponsored XP multipliers
+const MAX_BOOST_XP_MULTIPLIER_BPS: u64 = 30_000;
+
+// Trading schedule limits
+const MAX_TRADING_WINDOWS: usize = 20;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// Maximum markets settled by one claim_all_winnings call
+const MAX_CLAIMS_PER_CALL: u32 = 50;
+// Maximum orders placed by one buy_shares_batch call
+const MAX_BATCH_ORDERS: usize = 20;
+// How long after a buy the trader may revert it, on markets with undo on
+const REVERT_WINDOW_SECONDS: u64 = 60;
+// Kept by the treasury from a reverted buy, in place of its fee and dust
+const REVERT_PENALTY_BPS: u64 = 50;
+// Flash markets stop taking reverts this long before they close
+const FLASH_REVERT_CUTOFF_SECONDS: u64 = 10 * 60;
+
+// Operators are expected to resolve markets within this long after close
+const RESOLUTION_WINDOW_SECONDS: u64 = 7 * SECONDS_PER_DAY;
+// XP for the first trader to back the side a market resolves to
+const EARLIEST_CORRECT_XP: u64 = 100;
+
+// Principals looked up per get_profiles call; the rest are ignored
+const MAX_PROFILE_BATCH: usize = 100;
+
+// Markets quoted per get_probabilities call; the rest are ignored
+const MAX_PROBABILITY_BATCH: usize = 200;
+
+// Quotes with no trade for this long are flagged stale
+const STALE_QUOTE_NANOS: u64 = SECONDS_PER_DAY * 1_000_000_000;
+
+// Comment subscribers kept per market
+const MAX_COMMENT_SUBSCRIBERS: usize = 1_000;
+
+// Notifications kept per inbox; the oldest are dropped first
+const MAX_COMMENT_NOTIFICATIONS: usize = 100;
+const MAX_USER_NOTIFICATIONS: usize = 100;
+
+// Stored notification preferences and muted markets per user
+const MAX_NOTIFICATION_PREFS: usize = 50;
+const MAX_NOTIFICATION_KIND_LEN: usize = 50;
+const MAX_MUTED_MARKETS: usize = 500;
+
+// A subscriber gets at most one notification per market in this window
+const COMMENT_NOTIFICATION_WINDOW_NANOS: u64 = 3_600 * 1_000_000_000;
+
+// Price snapshots kept per market; the oldest are dropped first
+const MAX_PRICE_SNAPSHOTS: usize = 1_000;
+// Past this, every other liquidity snapshot is dropped so the whole life stays covered
+const MAX_LIQUIDITY_SNAPSHOTS: usize = 500;
+
+// Default per-market comment cap; see get_max_comments_per_market
+const DEFAULT_MAX_COMMENTS_PER_MARKET: u64 = 1_000;
+
+// Trade XP granted per trade, after the timing, contrarian and boost
+// multipliers and before the source rate; 0 means uncapped
+const DEFAULT_MAX_XP_PER_TRADE: u64 = 1_000;
+// Places shown by get_leaderboard
+const LEADERBOARD_SIZE: u64 = 20;
+// Users at or above this place hold the "Top<n>" standing badge
+const DEFAULT_LEADERBOARD_BADGE_SIZE: u64 = 10;
+
+// Treasury inflows kept for revenue reporting; the oldest are dropped first
+const MAX_TREASURY_INFLOWS: usize = 50_000;
+const MAX_REVENUE_DAYS: u64 = 366;
+
+// Longest activity heatmap, in days
+const MAX_HEATMAP_DAYS: u64 = 366;
+
+// Hourly flow buckets kept per market
+const FLOW_WINDOW_HOURS: u64 = 24;
+const MAX_TRENDING_MARKETS: u64 = 50;
+const MAX_MARKET_CARDS_PAGE: u64 = 100;
+
+// Maximum entries per balance history page
+const MAX_BALANCE_HISTORY_PAGE: u64 = 100;
+
+// Maximum entries per XP history page and per top-earners query
+const MAX_XP_HISTORY_PAGE: u64 = 100;
+const MAX_TOP_XP_EARNERS: u64 = 100;
+
+// Net shares at which a position counts as medium or large
+const MEDIUM_POSITION_SHARES: u64 = 500;
+const LARGE_POSITION_SHARES: u64 = 5_000;
+
+// Maximum comments per page
+const MAX_COMMENT_PAGE: u64 = 100;
+
+// Market references kept per comment; the rest are ignored
+const MAX_COMMENT_MARKET_REFS: usize = 3;
+
+// Username length bounds for explicitly chosen names
+const MIN_USERNAME_LEN: usize = 3;
+const MAX_USERNAME_LEN: usize = 20;
+
+// Market ids per bulk moderation call, and the length of a moderation reason
+const MAX_TRIAGE_BATCH: usize = 50;
+const MAX_TRIAGE_REASON_LEN: usize = 200;
+
+// Bytes pushed to or pulled from the archive canister per call
+const ARCHIVE_CHUNK_SIZE: usize = 1_000_000;
+
+// Markets archived per scheduled run, and runs kept in the log
+const AUTO_ARCHIVE_BATCH: usize = 10;
+const MAX_AUTO_ARCHIVE_RUNS: usize = 100;
+
+// Maximum entries in the largest-trades feed
+const MAX_LARGEST_TRADES: u64 = 50;
+const MAX_RECENT_RESOLUTIONS: u64 = 50;
+const MAX_DEPTH_STEPS: u8 = 20;
+
+// Maximum entries per new-markets feed page
+const MAX_FEED_PAGE: u64 = 50;
+
+// Share transfers a user may send per rolling day
+const MAX_SHARE_TRANSFERS_PER_DAY: usize = 10;
+
+// Payout items applied per scheduler run
+const PAYOUT_BATCH_SIZE: usize = 100;
+
+// Upper bound on histogram resolution
+const MAX_HISTOGRAM_BUCKETS: u64 = 100;
+
+// Statements a market's resolution checklist may hold, and their length
+const MAX_RESOLUTION_CRITERIA: usize = 5;
+const MAX_CO_OWNERS: usize = 3;
+// Opaque frontend preferences per user, and how often they may be rewritten
+const MAX_SETTINGS_BYTES: usize = 4 * 1024;
+const MAX_SETTINGS_WRITES_PER_HOUR: usize = 60;
+// Title similarity (bps) from which a new market counts as a duplicate
+const DEFAULT_SIMILARITY_THRESHOLD_BPS: u64 = 8_000;
+const MAX_SIMILAR_MARKETS: usize = 5;
+// Pool depth per side a new market starts with
+const NEW_MARKET_LIQUIDITY: u64 = 5_000;
+// Share of a market's trading fees (bps) paid to whoever resolves it, 0 = off
+const DEFAULT_RESOLUTION_REWARD_BPS: u64 = 0;
+// Price move (bps) since generation after which a trade refreshes the insight
+const DEFAULT_INSIGHT_REFRESH_MOVE_BPS: u64 = 1_000;
+const MAX_CRITERION_LEN: usize = 200;
+
+// Limits on a market's structured description fields
+const MAX_SOURCE_URL_LEN: usize = 500;
+const MAX_LINK_DOMAINS: usize = 100;
+const MAX_TARGET_METRIC_LEN: usize = 50;
+const MAX_NUMERIC_TARGET: u64 = 1_000_000_000_000_000;
+
+// Maximum slug length before any dedup suffix
+const MAX_SLUG_LEN: usize = 80;
+
+// Generating insights older than this are treated as failed
+const INSIGHT_JOB_TIMEOUT_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+// How often a creator may ask for a fresh insight; admins are not limited
+const INSIGHT_REFRESH_COOLDOWN_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+// Cached comment sentiment is served and reused for this long
+const SENTIMENT_TTL_NANOS: u64 = 3_600 * 1_000_000_000;
+// Most recent comments sent for a sentiment analysis
+const MAX_SENTIMENT_COMMENTS: usize = 50;
+
+// LLM calls allowed per day across insights, sentiment and digests
+const DEFAULT_LLM_DAILY_CALLS: u64 = 200;
+// Trades plus resolutions in the last 24h for a category to get a digest
+const MIN_DIGEST_ACTIVITY: u64 = 3;
+const DIGEST_TOP_MARKETS: usize = 3;
+const MAX_DIGEST_HISTORY: usize = 30; // per category
+
+// ICP ledger canister used for deposits
+const LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+// LLM canister asked for insights when LLM_ENABLED is set
+const LLM_CANISTER_ID: &str = "w36hm-eqaaa-aaaal-qr76a-cai";
+
+// State management
+thread_local! {
+    static MARKETS: RefCell<HashMap<u64, Market>> = RefCell::new(HashMap::new());
+    static TRADES: RefCell<Vec<Trade>> = const { RefCell::new(Vec::new()) };
+    static SALES: RefCell<Vec<Sale>> = const { RefCell::new(Vec::new()) };
+    static USER_PROFILES: RefCell<HashMap<Principal, UserProfile>> = RefCell::new(HashMap::new());
+    static AI_INSIGHTS: RefCell<HashMap<u64, AIInsight>> = RefCell::new(HashMap::new());
+    static NEXT_INSIGHT_JOB_ID: RefCell<u64> = const { RefCell::new(1) };
+    // (job id, started at) of jobs replacing a ready insight, which stays up meanwhile
+    static INSIGHT_REFRESHES: RefCell<HashMap<u64, (u64, u64)>> = RefCell::new(HashMap::new());
+    static INSIGHT_REFRESHED_AT: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    // Insights come from the LLM canister only when an admin turns this on
+    static LLM_ENABLED: RefCell<bool> = const { RefCell::new(false) };
+    static COMMENT_SENTIMENT: RefCell<HashMap<u64, CommentSentiment>> = RefCell::new(HashMap::new());
+    static LLM_DAILY_CALLS: RefCell<u64> = const { RefCell::new(DEFAULT_LLM_DAILY_CALLS) };
+    static LLM_CALLS_USED: RefCell<(u64, u64)> = const { RefCell::new((0, 0)) }; // (day, calls)
+    static CATEGORY_DIGESTS: RefCell<BTreeMap<String, VecDeque<CategoryDigest>>> = const { RefCell::new(BTreeMap::new()) };
+    static DIGESTS_IN_FLIGHT: RefCell<bool> = const { RefCell::new(false) };
+    // Comments that ever referenced the market; pruning or archiving them does not undo it
+    static MARKET_MENTIONS: RefCell<HashMap<u64, u64>> = RefCell::new(HashMap::new());
+    static COMMENTS: RefCell<Vec<MarketComment>> = const { RefCell::new(Vec::new()) };
+    static NEXT_MARKET_ID: RefCell<u64> = const { RefCell::new(1) };
+    static NEXT_TRADE_ID: RefCell<u64> = const { RefCell::new(1) };
+    static NEXT_COMMENT_ID: RefCell<u64> = const { RefCell::new(1) };
+    static TREASURY: RefCell<u64> = const { RefCell::new(0) };
+    // Lifetime trading fees and dust taken in, and what withdrawals sent out
+    static FEES_COLLECTED: RefCell<u64> = const { RefCell::new(0) };
+    static FEES_WITHDRAWN: RefCell<u64> = const { RefCell::new(0) };
+    static FLOW_BUCKETS: RefCell<HashMap<u64, VecDeque<FlowBucket>>> = RefCell::new(HashMap::new());
+    static MARKET_CARDS: RefCell<BTreeMap<u64, MarketCard>> = const { RefCell::new(BTreeMap::new()) };
+    static TREASURY_INFLOWS: RefCell<VecDeque<TreasuryInflow>> = const { RefCell::new(VecDeque::new()) };
+    static COMMENT_HISTORY: RefCell<HashMap<u64, Vec<CommentRevision>>> = RefCell::new(HashMap::new());
+    static MARKET_HISTORY: RefCell<HashMap<u64, Vec<MarketRevision>>> = RefCell::new(HashMap::new());
+    static XP_CONFIG: RefCell<XpConfig> = RefCell::new(XpConfig::default());
+    static XP_SOURCE_RULES: RefCell<HashMap<XpSource, XpSourceRule>> = RefCell::new(HashMap::new());
+    static XP_ENTRIES: RefCell<Vec<XpEntry>> = const { RefCell::new(Vec::new()) };
+    static NEXT_XP_ENTRY_ID: RefCell<u64> = const { RefCell::new(1) };
+    // XP granted per (principal, source, UTC day), for caps and farming review
+    static XP_DAILY: RefCell<HashMap<(Principal, XpSource, u64), u64>> = RefCell::new(HashMap::new());
+    static CLOSE_GRACE_SECONDS: RefCell<u64> = const { RefCell::new(0) };
+    static MARKET_FAQS: RefCell<HashMap<u64, MarketFaq>> = RefCell::new(HashMap::new());
+    static FAQ_HISTORY: RefCell<HashMap<u64, Vec<FaqRevision>>> = RefCell::new(HashMap::new());
+    static MARKET_EVENTS: RefCell<Vec<MarketEvent>> = const { RefCell::new(Vec::new()) };
+    static NEXT_EVENT_ID: RefCell<u64> = const { RefCell::new(1) };
+    static BALANCES: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    static ARCHIVE_CANISTER: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    static ARCHIVING: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    static AUTO_ARCHIVE_CONFIG: RefCell<AutoArchiveConfig> = RefCell::new(AutoArchiveConfig::default());
+    static AUTO_ARCHIVE_RUNS: RefCell<VecDeque<AutoArchiveRun>> = const { RefCell::new(VecDeque::new()) };
+    static AUTO_ARCHIVE_IN_FLIGHT: RefCell<bool> = const { RefCell::new(false) };
+    static PRICE_HISTORY: RefCell<HashMap<u64, VecDeque<PriceSnapshot>>> = RefCell::new(HashMap::new());
+    static LIQUIDITY_HISTORY: RefCell<HashMap<u64, Vec<LiquiditySnapshot>>> = RefCell::new(HashMap::new());
+    static BALANCE_ENTRIES: RefCell<Vec<BalanceEntry>> = const { RefCell::new(Vec::new()) };
+    static NEXT_BALANCE_ENTRY_ID: RefCell<u64> = const { RefCell::new(1) };
+    static DEPOSIT_SWEEPS: RefCell<Vec<DepositSweep>> = const { RefCell::new(Vec::new()) };
+    static NEXT_SWEEP_ID: RefCell<u64> = const { RefCell::new(1) };
+    static NEXT_WITHDRAWAL_ID: RefCell<u64> = const { RefCell::new(1) };
+    static SWEEPS_IN_FLIGHT: RefCell<Vec<Principal>> = const { RefCell::new(Vec::new()) };
+    static LEDGER_ID: RefCell<Principal> = RefCell::new(Principal::from_text(LEDGER_CANISTER_ID).unwrap());
+    static INTEGRITY_REPORTS: RefCell<Vec<IntegrityReport>> = const { RefCell::new(Vec::new()) };
+    static INTEGRITY_CURSOR: RefCell<Option<IntegrityCursor>> = const { RefCell::new(None) };
+    static NEXT_INTEGRITY_REPORT_ID: RefCell<u64> = const { RefCell::new(1) };
+    static NEXT_INTEGRITY_ISSUE_ID: RefCell<u64> = const { RefCell::new(1) };
+    static AUTO_INSIGHT_ON_APPROVE: RefCell<bool> = const { RefCell::new(false) };
+    static APPROVAL_POLICY: RefCell<ApprovalPolicy> = RefCell::new(ApprovalPolicy::default());
+    static ACCESS_MODE: RefCell<AccessMode> = RefCell::new(AccessMode::default());
+    static ALLOW_LIST: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+    static ACCESS_REQUESTS: RefCell<BTreeMap<Principal, AccessRequest>> = const { RefCell::new(BTreeMap::new()) };
+    static USER_NOTIFICATIONS: RefCell<HashMap<Principal, VecDeque<UserNotification>>> = RefCell::new(HashMap::new());
+    static TRUSTED_CREATORS: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+    static MARKET_BOOSTS: RefCell<HashMap<u64, Vec<MarketBoost>>> = RefCell::new(HashMap::new());
+    static NEXT_BOOST_ID: RefCell<u64> = const { RefCell::new(1) };
+    static BOOST_XP_DRAWS: RefCell<HashMap<u64, BoostXpDraw>> = RefCell::new(HashMap::new());
+    static MARKET_SLUGS: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    static TREASURY_FLOOR: RefCell<u64> = const { RefCell::new(0) };
+    static FLASH_CONFIG: RefCell<FlashConfig> = RefCell::new(FlashConfig::default());
+    static FLASH_REMINDERS: RefCell<HashMap<u64, u64>> = RefCell::new(HashMap::new());
+    static DUST_COLLECTED: RefCell<u64> = const { RefCell::new(0) };
+    static FREE_TRADES: RefCell<u64> = const { RefCell::new(0) };
+    static MAX_COMMENTS_PER_MARKET: RefCell<u64> = const { RefCell::new(DEFAULT_MAX_COMMENTS_PER_MARKET) };
+    static MAX_POSITION_PER_MARKET: RefCell<u64> = const { RefCell::new(0) };
+    static MAX_XP_PER_TRADE: RefCell<u64> = const { RefCell::new(DEFAULT_MAX_XP_PER_TRADE) };
+    // 0 = no standing badge
+    static LEADERBOARD_BADGE_SIZE: RefCell<u64> = const { RefCell::new(DEFAULT_LEADERBOARD_BADGE_SIZE) };
+    // 0 turns duplicate detection off
+    static SIMILARITY_THRESHOLD_BPS: RefCell<u64> = const { RefCell::new(DEFAULT_SIMILARITY_THRESHOLD_BPS) };
+    // 0 turns trade-triggered insight refreshes off
+    static INSIGHT_REFRESH_MOVE_BPS: RefCell<u64> = const { RefCell::new(DEFAULT_INSIGHT_REFRESH_MOVE_BPS) };
+    static USER_SETTINGS: RefCell<HashMap<Principal, Vec<u8>>> = RefCell::new(HashMap::new());
+    static SETTINGS_WRITES: RefCell<HashMap<Principal, VecDeque<u64>>> = RefCell::new(HashMap::new());
+    static RESOLUTION_REWARD_BPS: RefCell<u64> = const { RefCell::new(DEFAULT_RESOLUTION_REWARD_BPS) };
+    static PENDING_RESOLUTION_REWARDS: RefCell<Vec<PendingResolutionReward>> = const { RefCell::new(Vec::new()) };
+    static RESOLUTION_REWARDS_EARNED: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    // Platform-wide cap on Active markets, 0 = unlimited
+    static MAX_ACTIVE_MARKETS: RefCell<u64> = const { RefCell::new(0) };
+    // Neither pool side of an Active market may be taken below this, 0 = off
+    static MIN_MARKET_LIQUIDITY: RefCell<u64> = const { RefCell::new(0) };
+    // Domains external link attachments may point to; subdomains included
+    static LINK_DOMAINS: RefCell<BTreeSet<String>> = const { RefCell::new(BTreeSet::new()) };
+    static MARKET_POSITION_CAPS: RefCell<HashMap<u64, u64>> = RefCell::new(HashMap::new());
+    // Markets whose traders may revert a buy within REVERT_WINDOW_SECONDS
+    static UNDO_MARKETS: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    // Reverts used per (trader, market, UTC day)
+    static TRADE_REVERTS: RefCell<HashSet<(Principal, u64, u64)>> = RefCell::new(HashSet::new());
+    // Keyed by (owner, market) so a user's positions form a contiguous range
+    static POSITIONS: RefCell<BTreeMap<(Principal, u64), Position>> = const { RefCell::new(BTreeMap::new()) };
+    static ANONYMOUS_READS_ALLOWED: RefCell<bool> = const { RefCell::new(true) };
+    static PAYOUT_QUEUE: RefCell<VecDeque<PayoutItem>> = const { RefCell::new(VecDeque::new()) };
+    static NEXT_PAYOUT_ID: RefCell<u64> = const { RefCell::new(1) };
+    static PAYOUTS_COMPLETED: RefCell<u64> = const { RefCell::new(0) };
+    static SHARE_TRANSFERS: RefCell<Vec<TransferRecord>> = const { RefCell::new(Vec::new()) };
+    static NEXT_TRANSFER_ID: RefCell<u64> = const { RefCell::new(1) };
+    static COMMENT_SUBSCRIBERS: RefCell<HashMap<u64, BTreeSet<Principal>>> = RefCell::new(HashMap::new());
+    static COMMENT_NOTIFICATIONS: RefCell<HashMap<Principal, VecDeque<CommentNotification>>> = RefCell::new(HashMap::new());
+    static COMMENT_AUTO_SUBSCRIBE_DISABLED: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+    // Only explicit choices are stored; missing kinds use their default
+    static NOTIFICATION_PREFS: RefCell<HashMap<Principal, BTreeMap<String, NotificationSetting>>> = RefCell::new(HashMap::new());
+    static MUTED_MARKETS: RefCell<HashMap<Principal, BTreeSet<u64>>> = RefCell::new(HashMap::new());
+    static MARKET_POLLS: RefCell<HashMap<u64, MarketPoll>> = RefCell::new(HashMap::new());
+    static CREATOR_REPUTATION: RefCell<HashMap<Principal, CreatorReputation>> = RefCell::new(HashMap::new());
+}
+
+// Admins are the canister controllers
+fn is_admin(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+}
+
+fn record_event(market_id: u64, kind: MarketEventKind, actor: Principal) {
+    let id = NEXT_EVENT_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    MARKET_EVENTS.with(|events| {
+        events.borrow_mut().push(MarketEvent {
+            id,
+            market_id,
+            kind,
+            actor,
+            timestamp: ic_cdk::api::time(),
+        });
+    });
+}
+
+// Gated deployments (private beta) can hide reads from anonymous callers.
+// Authenticated users and admins are unaffected.
+fn reads_allowed() -> bool {
+    let caller = ic_cdk::caller();
+    caller != Principal::anonymous()
+        || is_admin(&caller)
+        || ANONYMOUS_READS_ALLOWED.with(|allowed| *allowed.borrow())
+}
+
+#[ic_cdk::query]
+fn get_anonymous_reads_allowed() -> bool {
+    ANONYMOUS_READS_ALLOWED.with(|allowed| *allowed.borrow())
+}
+
+#[ic_cdk::update]
+fn set_anonymous_reads_allowed(allowed: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can change read access".to_string());
+    }
+
+    ANONYMOUS_READS_ALLOWED.with(|a| *a.borrow_mut() = allowed);
+    Ok(())
+}
+
+// Trims user-provided text and strips control characters (newlines are kept)
+fn sanitize_text(text: &str) -> String {
+    text.trim()
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n')
+        .collect()
+}
+
+// Lowercases the title, keeps alphanumerics, turns whitespace and dashes into
+// single hyphens and drops other punctuation ("$150,000" becomes "150000")
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug: String = slug.trim_matches('-').chars().take(MAX_SLUG_LEN).collect();
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "market".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// Derives a unique slug for the market and registers it in the reverse index,
+// appending a numeric suffix on collision
+fn assign_slug(market_id: u64, title: &str) -> String {
+    let base = slugify(title);
+
+    MARKET_SLUGS.with(|slugs| {
+        let mut slugs = slugs.borrow_mut();
+        let mut slug = base.clone();
+        let mut suffix = 2;
+        while slugs.contains_key(&slug) {
+            slug = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        slugs.insert(slug.clone(), market_id);
+        slug
+    })
+}
+
+#[ic_cdk::init]
+fn init() {
+    seed_sample_markets();
+    start_scheduler();
+}
+
+// Initialize with sample data
+fn seed_sample_markets() {
+    // The sample markets belong to whoever installed the canister, or to the
+    // canister itself when that was the anonymous principal
+    let owner = match ic_cdk::caller() {
+        caller if caller == Principal::anonymous() => ic_cdk::api::id(),
+        caller => caller,
+    };
+    let sample_markets = vec![
+        Market {
+            id: 1,
+            title: "Will Bitcoin reach $150,000 by end of 2025?".to_string(),
+            slug: String::new(), // assigned below
+            description: "This market resolves to YES if Bitcoin (BTC) reaches or exceeds $150,000 USD by December 31, 2025.".to_string(),
+            category: "Cryptocurrency".to_string(),
+            creator: owner,
+            close_date: to_nanos(1767225600), // Dec 31, 2025
+            status: MarketStatus::Active,
+            yes_shares: 450,
+            no_shares: 550,
             yes_liquidity: 4500,
             no_liquidity: 5500,
             total_volume: 2500,
-            created_at: 1737273600, // Current time
+            seed_liquidity: 10000,
+            seed_volume: 2500,
+            created_at: to_nanos(1737273600), // Jan 19, 2025
             resolved_outcome: None,
+            comments_locked: false,
+            trading_schedule: None,
+            market_kind: MarketKind::Standard,
+            resolver: None,
+            resolution_criteria: None,
+            archived_to: None,
+            resolution_datetime: None,
+            primary_source_url: None,
+            numeric_target: None,
+            co_owners: vec![],
+            resolution_delegate: None,
         },
         Market {
             id: 2,
             title: "Will OpenAI release GPT-5 in 2025?".to_string(),
+            slug: String::new(), // assigned below
             description: "This market resolves to YES if OpenAI officially releases a model called GPT-5 during 2025.".to_string(),
             category: "Technology".to_string(),
-            creator: Principal::anonymous(),
-            close_date: 1767292799,
+            creator: owner,
+            close_date: to_nanos(1767292799),
             status: MarketStatus::Active,
             yes_shares: 600,
             no_shares: 400,
             yes_liquidity: 6000,
             no_liquidity: 4000,
             total_volume: 1800,
-            created_at: 1737273600,
+            seed_liquidity: 10000,
+            seed_volume: 1800,
+            created_at: to_nanos(1737273600),
+            resolved_outcome: None,
+            comments_locked: false,
+            trading_schedule: None,
+            market_kind: MarketKind::Standard,
+            resolver: None,
+            resolution_criteria: None,
+            archived_to: None,
+            resolution_datetime: None,
+            primary_source_url: None,
+            numeric_target: None,
+            co_owners: vec![],
+            resolution_delegate: None,
+        },
+        Market {
+            id: 3,
+            title: "Will Tesla stock reach $500 by Q2 2025?".to_string(),
+            slug: String::new(), // assigned below
+            description: "This market resolves to YES if Tesla (TSLA) stock price reaches or exceeds $500 USD before June 30, 2025.".to_string(),
+            category: "Finance".to_string(),
+            creator: owner,
+            close_date: to_nanos(1767292799),
+            status: MarketStatus::Active,
+            yes_shares: 300,
+            no_shares: 700,
+            yes_liquidity: 3000,
+            no_liquidity: 7000,
+            total_volume: 1200,
+            seed_liquidity: 10000,
+            seed_volume: 1200,
+            created_at: to_nanos(1737273600),
             resolved_outcome: None,
+            comments_locked: false,
+            trading_schedule: None,
+            market_kind: MarketKind::Standard,
+            resolver: None,
+            resolution_criteria: None,
+            archived_to: None,
+            resolution_datetime: None,
+            primary_source_url: None,
+            numeric_target: None,
+            co_owners: vec![],
+            resolution_delegate: None,
+        },
+    ];
+
+    let sample_insights = vec![
+        AIInsight {
+            market_id: 1,
+            summary: "Bitcoin has shown strong institutional adoption and macroeconomic factors favor crypto. However, regulatory uncertainty remains a risk.".to_string(),
+            confidence: 0.72,
+            risks: vec!["Regulatory crackdowns".to_string(), "Market volatility".to_string(), "Macro economic shifts".to_string()],
+            prediction_lean: Some(true),
+            generated_at: to_nanos(1767292799),
+            status: InsightStatus::Ready,
+            job_id: None,
+            generated_yes_bps: Some(4500),
+        },
+        AIInsight {
+            market_id: 2,
+            summary: "OpenAI is likely to continue their rapid development cycle. GPT-5 announcement is probable given competitive pressure from other AI companies.".to_string(),
+            confidence: 0.65,
+            risks: vec!["Technical setbacks".to_string(), "Compute resource limitations".to_string(), "Safety concerns".to_string()],
+            prediction_lean: Some(true),
+            generated_at: to_nanos(1767292799),
+            status: InsightStatus::Ready,
+            job_id: None,
+            generated_yes_bps: Some(6000),
+        },
+        AIInsight {
+            market_id: 3,
+            summary: "Tesla faces production challenges and increased EV competition. Stock price target seems ambitious given current market conditions.".to_string(),
+            confidence: 0.58,
+            risks: vec!["Production delays".to_string(), "Increased competition".to_string(), "Economic recession".to_string()],
+            prediction_lean: Some(false),
+            generated_at: to_nanos(1737273600),
+            status: InsightStatus::Ready,
+            job_id: None,
+            generated_yes_bps: Some(3000),
+        },
+    ];
+
+    MARKETS.with(|markets| {
+        let mut m = markets.borrow_mut();
+        for mut market in sample_markets {
+            market.slug = assign_slug(market.id, &market.title);
+            record_price_snapshot(&market, market.created_at);
+            record_liquidity_snapshot(&market, market.created_at);
+            m.insert(market.id, market);
+        }
+    });
+
+    AI_INSIGHTS.with(|insights| {
+        let mut ai = insights.borrow_mut();
+        for insight in sample_insights {
+            ai.insert(insight.market_id, insight);
+        }
+    });
+
+    NEXT_MARKET_ID.with(|id| *id.borrow_mut() = 4);
+    rebuild_all_market_cards(ic_cdk::api::time());
+}
+
+// Upgrade persistence
+#[derive(CandidType, Deserialize)]
+struct StableState {
+    scheduler: Option<Vec<TaskStatus>>,
+    payout_queue: Option<Vec<PayoutItem>>,
+    next_payout_id: Option<u64>,
+    payouts_completed: Option<u64>,
+    // What queued payouts write to, so a drain resumed after an upgrade
+    // lands on the same ledgers
+    balances: Option<Vec<(Principal, u64)>>,
+    user_profiles: Option<Vec<UserProfile>>,
+    user_settings: Option<Vec<(Principal, Vec<u8>)>>,
+    // Kept with balances so history always replays to the balance
+    balance_entries: Option<Vec<BalanceEntry>>,
+    next_balance_entry_id: Option<u64>,
+    next_withdrawal_id: Option<u64>,
+    treasury_inflows: Option<Vec<TreasuryInflow>>,
+    archive_canister: Option<Principal>,
+    auto_archive: Option<AutoArchiveConfig>,
+    llm_enabled: Option<bool>,
+    llm_daily_calls: Option<u64>,
+    categories: Option<Vec<Category>>,
+    trading_limits: Option<Vec<(Principal, UserLimits)>>,
+    spend_log: Option<SpendLog>,
+    // Reviews of suspicious activity are admin work worth keeping
+    suspicious_flags: Option<Vec<SuspiciousFlag>>,
+    surveillance_config: Option<SurveillanceConfig>,
+    // Published draws must stay verifiable
+    draws: Option<Vec<Draw>>,
+    // Bonds and owed fees are real money, so the registry outlives upgrades
+    resolvers: Option<Vec<ResolverProfile>>,
+    resolver_assignments: Option<Vec<ResolverAssignment>>,
+    // Markets and the trading state built on them; None before they were kept
+    markets: Option<Vec<Market>>,
+    // None when markets were saved with close and resolution dates in seconds
+    market_dates_in_nanos: Option<bool>,
+    trades: Option<Vec<Trade>>,
+    sales: Option<Vec<Sale>>,
+    positions: Option<Vec<Position>>,
+    next_market_id: Option<u64>,
+    next_trade_id: Option<u64>,
+    treasury: Option<u64>,
+    fees_collected: Option<u64>,
+    fees_withdrawn: Option<u64>,
+    dust_collected: Option<u64>,
+    pending_resolution_rewards: Option<Vec<PendingResolutionReward>>,
+    // Sponsors' funds sit in boost pools until their market settles
+    market_boosts: Option<Vec<MarketBoost>>,
+    next_boost_id: Option<u64>,
+    boost_xp_draws: Option<Vec<(u64, BoostXpDraw)>>,
+    // Incentive pools hold treasury and sponsor funds until they close
+    incentive_programs: Option<Vec<(u64, incentives::Program)>>,
+    // Kept to track drift across upgrades; a check in progress is not
+    integrity_reports: Option<Vec<IntegrityReport>>,
+    next_integrity_report_id: Option<u64>,
+    next_integrity_issue_id: Option<u64>,
+    // Market content and audit trails
+    comments: Option<Vec<MarketComment>>,
+    next_comment_id: Option<u64>,
+    comment_history: Option<Vec<(u64, Vec<CommentRevision>)>>,
+    ai_insights: Option<Vec<AIInsight>>,
+    market_faqs: Option<Vec<(u64, MarketFaq)>>,
+    faq_history: Option<Vec<(u64, Vec<FaqRevision>)>>,
+    market_events: Option<Vec<MarketEvent>>,
+    next_event_id: Option<u64>,
+    price_history: Option<Vec<(u64, Vec<PriceSnapshot>)>>,
+    liquidity_history: Option<Vec<(u64, Vec<LiquiditySnapshot>)>>,
+    share_transfers: Option<Vec<TransferRecord>>,
+    next_transfer_id: Option<u64>,
+    // A soft launch must not fall open on upgrade
+    access_mode: Option<AccessMode>,
+    allow_list: Option<Vec<Principal>>,
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let (trading_limits, spend_log) = limits::snapshot();
+    let (suspicious_flags, surveillance_config) = surveillance::snapshot();
+    let (resolvers, resolver_assignments) = resolvers::snapshot();
+    let state = StableState {
+        scheduler: Some(scheduler::snapshot()),
+        payout_queue: Some(PAYOUT_QUEUE.with(|queue| queue.borrow().iter().cloned().collect())),
+        next_payout_id: Some(NEXT_PAYOUT_ID.with(|id| *id.borrow())),
+        payouts_completed: Some(PAYOUTS_COMPLETED.with(|count| *count.borrow())),
+        balances: Some(
+            BALANCES.with(|balances| balances.borrow().iter().map(|(p, b)| (*p, *b)).collect()),
+        ),
+        user_profiles: Some(
+            USER_PROFILES.with(|profiles| profiles.borrow().values().cloned().collect()),
+        ),
+        user_settings: Some(USER_SETTINGS.with(|settings| {
+            settings
+                .borrow()
+                .iter()
+                .map(|(p, blob)| (*p, blob.clone()))
+                .collect()
+        })),
+        balance_entries: Some(BALANCE_ENTRIES.with(|entries| entries.borrow().clone())),
+        next_balance_entry_id: Some(NEXT_BALANCE_ENTRY_ID.with(|id| *id.borrow())),
+        next_withdrawal_id: Some(NEXT_WITHDRAWAL_ID.with(|id| *id.borrow())),
+        treasury_inflows: Some(
+            TREASURY_INFLOWS.with(|inflows| inflows.borrow().iter().cloned().collect()),
+        ),
+        archive_canister: get_archive_canister(),
+        auto_archive: Some(get_auto_archive_config()),
+        llm_enabled: Some(get_llm_enabled()),
+        llm_daily_calls: Some(get_llm_daily_calls()),
+        categories: Some(categories::list()),
+        trading_limits: Some(trading_limits),
+        spend_log: Some(spend_log),
+        suspicious_flags: Some(suspicious_flags),
+        surveillance_config: Some(surveillance_config),
+        draws: Some(randomness::snapshot()),
+        resolvers: Some(resolvers),
+        resolver_assignments: Some(resolver_assignments),
+        markets: Some(MARKETS.with(|markets| markets.borrow().values().cloned().collect())),
+        market_dates_in_nanos: Some(true),
+        trades: Some(TRADES.with(|trades| trades.borrow().clone())),
+        sales: Some(SALES.with(|sales| sales.borrow().clone())),
+        positions: Some(POSITIONS.with(|positions| positions.borrow().values().cloned().collect())),
+        next_market_id: Some(NEXT_MARKET_ID.with(|id| *id.borrow())),
+        next_trade_id: Some(NEXT_TRADE_ID.with(|id| *id.borrow())),
+        treasury: Some(TREASURY.with(|treasury| *treasury.borrow())),
+        fees_collected: Some(FEES_COLLECTED.with(|collected| *collected.borrow())),
+        fees_withdrawn: Some(FEES_WITHDRAWN.with(|withdrawn| *withdrawn.borrow())),
+        dust_collected: Some(DUST_COLLECTED.with(|dust| *dust.borrow())),
+        pending_resolution_rewards: Some(
+            PENDING_RESOLUTION_REWARDS.with(|pending| pending.borrow().clone()),
+        ),
+        market_boosts: Some(
+            MARKET_BOOSTS.with(|boosts| boosts.borrow().values().flatten().cloned().collect()),
+        ),
+        next_boost_id: Some(NEXT_BOOST_ID.with(|id| *id.borrow())),
+        boost_xp_draws: Some(BOOST_XP_DRAWS.with(|draws| {
+            draws
+                .borrow()
+                .iter()
+                .map(|(trade_id, draw)| (*trade_id, draw.clone()))
+                .collect()
+        })),
+        incentive_programs: Some(incentives::snapshot()),
+        integrity_reports: Some(INTEGRITY_REPORTS.with(|reports| reports.borrow().clone())),
+        next_integrity_report_id: Some(NEXT_INTEGRITY_REPORT_ID.with(|id| *id.borrow())),
+        next_integrity_issue_id: Some(NEXT_INTEGRITY_ISSUE_ID.with(|id| *id.borrow())),
+        comments: Some(COMMENTS.with(|comments| comments.borrow().clone())),
+        next_comment_id: Some(NEXT_COMMENT_ID.with(|id| *id.borrow())),
+        comment_history: Some(COMMENT_HISTORY.with(|history| {
+            history
+                .borrow()
+                .iter()
+                .map(|(id, revisions)| (*id, revisions.clone()))
+                .collect()
+        })),
+        ai_insights: Some(
+            AI_INSIGHTS.with(|insights| insights.borrow().values().cloned().collect()),
+        ),
+        market_faqs: Some(MARKET_FAQS.with(|faqs| {
+            faqs.borrow()
+                .iter()
+                .map(|(id, faq)| (*id, faq.clone()))
+                .collect()
+        })),
+        faq_history: Some(FAQ_HISTORY.with(|history| {
+            history
+                .borrow()
+                .iter()
+                .map(|(id, revisions)| (*id, revisions.clone()))
+                .collect()
+        })),
+        market_events: Some(MARKET_EVENTS.with(|events| events.borrow().clone())),
+        next_event_id: Some(NEXT_EVENT_ID.with(|id| *id.borrow())),
+        price_history: Some(PRICE_HISTORY.with(|history| {
+            history
+                .borrow()
+                .iter()
+                .map(|(id, snapshots)| (*id, snapshots.iter().cloned().collect()))
+                .collect()
+        })),
+        liquidity_history: Some(LIQUIDITY_HISTORY.with(|history| {
+            history
+                .borrow()
+                .iter()
+                .map(|(id, snapshots)| (*id, snapshots.clone()))
+                .collect()
+        })),
+        share_transfers: Some(SHARE_TRANSFERS.with(|transfers| transfers.borrow().clone())),
+        next_transfer_id: Some(NEXT_TRANSFER_ID.with(|id| *id.borrow())),
+        access_mode: Some(ACCESS_MODE.with(|mode| mode.borrow().clone())),
+        allow_list: Some(ALLOW_LIST.with(|list| list.borrow().iter().copied().collect())),
+    };
+    ic_cdk::storage::stable_save((state,)).expect("Failed to save stable state");
+}
+
+// Slugs and market cards are derived, so they are rebuilt rather than saved
+fn restore_markets(markets: Vec<Market>) {
+    let markets: HashMap<u64, Market> = markets.into_iter().map(|m| (m.id, m)).collect();
+    rebuild_slug_index(&markets);
+    MARKETS.with(|map| *map.borrow_mut() = markets);
+    rebuild_all_market_cards(ic_cdk::api::time());
+}
+
+// Close, resolution and trading window dates used to be whole seconds
+fn migrate_market_dates(market: &mut Market) {
+    market.close_date = to_nanos(market.close_date);
+    market.resolution_datetime = market.resolution_datetime.map(to_nanos);
+    if let Some(TradingSchedule::Windows(windows)) = &mut market.trading_schedule {
+        for (open, close) in windows.iter_mut() {
+            *open = to_nanos(*open);
+            *close = to_nanos(*close);
+        }
+    }
+}
+
+fn rebuild_slug_index(markets: &HashMap<u64, Market>) {
+    MARKET_SLUGS.with(|slugs| {
+        *slugs.borrow_mut() = markets.values().map(|m| (m.slug.clone(), m.id)).collect()
+    });
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    let mut markets_restored = false;
+    // Older versions saved nothing to stable memory
+    if let Ok((state,)) = ic_cdk::storage::stable_restore::<(StableState,)>() {
+        scheduler::restore(state.scheduler.unwrap_or_default());
+        PAYOUT_QUEUE
+            .with(|queue| *queue.borrow_mut() = state.payout_queue.unwrap_or_default().into());
+        NEXT_PAYOUT_ID.with(|id| *id.borrow_mut() = state.next_payout_id.unwrap_or(1));
+        PAYOUTS_COMPLETED.with(|count| *count.borrow_mut() = state.payouts_completed.unwrap_or(0));
+        BALANCES.with(|balances| {
+            *balances.borrow_mut() = state.balances.unwrap_or_default().into_iter().collect()
+        });
+        USER_PROFILES.with(|profiles| {
+            *profiles.borrow_mut() = state
+                .user_profiles
+                .unwrap_or_default()
+                .into_iter()
+                .map(|profile| (profile.principal, profile))
+                .collect()
+        });
+        USER_SETTINGS.with(|settings| {
+            *settings.borrow_mut() = state
+                .user_settings
+                .unwrap_or_default()
+                .into_iter()
+                .collect()
+        });
+        BALANCE_ENTRIES
+            .with(|entries| *entries.borrow_mut() = state.balance_entries.unwrap_or_default());
+        NEXT_BALANCE_ENTRY_ID
+            .with(|id| *id.borrow_mut() = state.next_balance_entry_id.unwrap_or(1));
+        NEXT_WITHDRAWAL_ID.with(|id| *id.borrow_mut() = state.next_withdrawal_id.unwrap_or(1));
+        TREASURY_INFLOWS.with(|inflows| {
+            *inflows.borrow_mut() = state.treasury_inflows.unwrap_or_default().into()
+        });
+        ARCHIVE_CANISTER.with(|archive| *archive.borrow_mut() = state.archive_canister);
+        AUTO_ARCHIVE_CONFIG
+            .with(|config| *config.borrow_mut() = state.auto_archive.unwrap_or_default());
+        LLM_ENABLED.with(|enabled| *enabled.borrow_mut() = state.llm_enabled.unwrap_or(false));
+        LLM_DAILY_CALLS.with(|calls| {
+            *calls.borrow_mut() = state.llm_daily_calls.unwrap_or(DEFAULT_LLM_DAILY_CALLS)
+        });
+        categories::restore(state.categories.unwrap_or_default());
+        limits::restore(
+            state.trading_limits.unwrap_or_default(),
+            state.spend_log.unwrap_or_default(),
+        );
+        surveillance::restore(
+            state.suspicious_flags.unwrap_or_default(),
+            state.surveillance_config.unwrap_or_default(),
+        );
+        randomness::restore(state.draws.unwrap_or_default());
+        resolvers::restore(
+            state.resolvers.unwrap_or_default(),
+            state.resolver_assignments.unwrap_or_default(),
+        );
+        if let Some(mut markets) = state.markets {
+            if state.market_dates_in_nanos.is_none() {
+                markets.iter_mut().for_each(migrate_market_dates);
+            }
+            restore_markets(markets);
+            markets_restored = true;
+        }
+        TRADES.with(|trades| *trades.borrow_mut() = state.trades.unwrap_or_default());
+        SALES.with(|sales| *sales.borrow_mut() = state.sales.unwrap_or_default());
+        POSITIONS.with(|positions| {
+            *positions.borrow_mut() = state
+                .positions
+                .unwrap_or_default()
+                .into_iter()
+                .map(|position| ((position.owner, position.market_id), position))
+                .collect()
+        });
+        NEXT_MARKET_ID.with(|id| *id.borrow_mut() = state.next_market_id.unwrap_or(1));
+        NEXT_TRADE_ID.with(|id| *id.borrow_mut() = state.next_trade_id.unwrap_or(1));
+        TREASURY.with(|treasury| *treasury.borrow_mut() = state.treasury.unwrap_or(0));
+        FEES_COLLECTED
+            .with(|collected| *collected.borrow_mut() = state.fees_collected.unwrap_or(0));
+        FEES_WITHDRAWN
+            .with(|withdrawn| *withdrawn.borrow_mut() = state.fees_withdrawn.unwrap_or(0));
+        DUST_COLLECTED.with(|dust| *dust.borrow_mut() = state.dust_collected.unwrap_or(0));
+        PENDING_RESOLUTION_REWARDS.with(|pending| {
+            *pending.borrow_mut() = state.pending_resolution_rewards.unwrap_or_default()
+        });
+        MARKET_BOOSTS.with(|boosts| {
+            let mut boosts = boosts.borrow_mut();
+            boosts.clear();
+            let mut saved = state.market_boosts.unwrap_or_default();
+            saved.sort_by_key(|boost| boost.id);
+            for boost in saved {
+                boosts.entry(boost.market_id).or_default().push(boost);
+            }
+        });
+        NEXT_BOOST_ID.with(|id| *id.borrow_mut() = state.next_boost_id.unwrap_or(1));
+        BOOST_XP_DRAWS.with(|draws| {
+            *draws.borrow_mut() = state
+                .boost_xp_draws
+                .unwrap_or_default()
+                .into_iter()
+                .collect()
+        });
+        incentives::restore(state.incentive_programs.unwrap_or_default());
+        // The cursor did not survive, so a check that was running never finishes
+        let mut reports = state.integrity_reports.unwrap_or_default();
+        for report in reports
+            .iter_mut()
+            .filter(|r| r.status == IntegrityCheckStatus::Running)
+        {
+            report.status = IntegrityCheckStatus::Failed;
+        }
+        INTEGRITY_REPORTS.with(|stored| *stored.borrow_mut() = reports);
+        NEXT_INTEGRITY_REPORT_ID
+            .with(|id| *id.borrow_mut() = state.next_integrity_report_id.unwrap_or(1));
+        NEXT_INTEGRITY_ISSUE_ID
+            .with(|id| *id.borrow_mut() = state.next_integrity_issue_id.unwrap_or(1));
+
+        COMMENTS.with(|comments| *comments.borrow_mut() = state.comments.unwrap_or_default());
+        NEXT_COMMENT_ID.with(|id| *id.borrow_mut() = state.next_comment_id.unwrap_or(1));
+        COMMENT_HISTORY.with(|history| {
+            *history.borrow_mut() = state
+                .comment_history
+                .unwrap_or_default()
+                .into_iter()
+                .collect()
+        });
+        if let Some(insights) = state.ai_insights {
+            // The LLM call behind a generating insight did not survive
+            AI_INSIGHTS.with(|stored| {
+                *stored.borrow_mut() = insights
+                    .into_iter()
+                    .map(|mut insight| {
+                        if matches!(insight.status, InsightStatus::Generating) {
+                            insight.status =
+                                InsightStatus::Failed("Interrupted by an upgrade".to_string());
+                        }
+                        (insight.market_id, insight)
+                    })
+                    .collect()
+            });
+        }
+        MARKET_FAQS.with(|faqs| {
+            *faqs.borrow_mut() = state.market_faqs.unwrap_or_default().into_iter().collect()
+        });
+        FAQ_HISTORY.with(|history| {
+            *history.borrow_mut() = state.faq_history.unwrap_or_default().into_iter().collect()
+        });
+        if let Some(events) = state.market_events {
+            MARKET_EVENTS.with(|stored| *stored.borrow_mut() = events);
+            NEXT_EVENT_ID.with(|id| *id.borrow_mut() = state.next_event_id.unwrap_or(1));
+        }
+        if let Some(history) = state.price_history {
+            PRICE_HISTORY.with(|stored| {
+                *stored.borrow_mut() = history
+                    .into_iter()
+                    .map(|(id, snapshots)| (id, snapshots.into()))
+                    .collect()
+            });
+        }
+        if let Some(history) = state.liquidity_history {
+            LIQUIDITY_HISTORY.with(|stored| *stored.borrow_mut() = history.into_iter().collect());
+        }
+        SHARE_TRANSFERS
+            .with(|transfers| *transfers.borrow_mut() = state.share_transfers.unwrap_or_default());
+        NEXT_TRANSFER_ID.with(|id| *id.borrow_mut() = state.next_transfer_id.unwrap_or(1));
+        ACCESS_MODE.with(|mode| *mode.borrow_mut() = state.access_mode.unwrap_or_default());
+        ALLOW_LIST.with(|list| {
+            *list.borrow_mut() = state.allow_list.unwrap_or_default().into_iter().collect()
+        });
+    }
+
+    // Versions that did not keep markets come back empty; start them as a fresh install
+    if !markets_restored {
+        seed_sample_markets();
+    }
+    start_scheduler();
+}
+
+// Scheduled background tasks
+struct IntegrityCheckTask;
+
+impl Task for IntegrityCheckTask {
+    fn name(&self) -> &'static str {
+        "integrity_check"
+    }
+
+    fn priority(&self) -> u8 {
+        100
+    }
+
+    // Processes one chunk per tick while a check is running
+    fn default_interval_seconds(&self) -> u64 {
+        0
+    }
+
+    fn run(&self) -> Result<(), String> {
+        integrity_check_step();
+        Ok(())
+    }
+}
+
+struct PayoutTask;
+
+impl Task for PayoutTask {
+    fn name(&self) -> &'static str {
+        "payouts"
+    }
+
+    fn priority(&self) -> u8 {
+        10
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        0
+    }
+
+    fn run(&self) -> Result<(), String> {
+        drain_payouts(PAYOUT_BATCH_SIZE);
+        Ok(())
+    }
+}
+
+struct FlashReminderTask;
+
+impl Task for FlashReminderTask {
+    fn name(&self) -> &'static str {
+        "flash_reminders"
+    }
+
+    fn priority(&self) -> u8 {
+        50
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        60
+    }
+
+    fn run(&self) -> Result<(), String> {
+        send_flash_reminders();
+        Ok(())
+    }
+}
+
+struct QuestResetTask;
+
+impl Task for QuestResetTask {
+    fn name(&self) -> &'static str {
+        "quest_reset"
+    }
+
+    fn priority(&self) -> u8 {
+        60
+    }
+
+    // Progress is keyed by day, so a late run only delays freeing memory
+    fn default_interval_seconds(&self) -> u64 {
+        60
+    }
+
+    fn run(&self) -> Result<(), String> {
+        quests::reset_before(quest_day());
+        Ok(())
+    }
+}
+
+struct FlowPruneTask;
+
+impl Task for FlowPruneTask {
+    fn name(&self) -> &'static str {
+        "flow_prune"
+    }
+
+    fn priority(&self) -> u8 {
+        70
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        300
+    }
+
+    fn run(&self) -> Result<(), String> {
+        prune_flow(ic_cdk::api::time());
+        Ok(())
+    }
+}
+
+struct MarketCardTask;
+
+impl Task for MarketCardTask {
+    fn name(&self) -> &'static str {
+        "market_cards"
+    }
+
+    fn priority(&self) -> u8 {
+        80
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        3_600
+    }
+
+    fn run(&self) -> Result<(), String> {
+        rebuild_all_market_cards(ic_cdk::api::time());
+        Ok(())
+    }
+}
+
+struct LeaderboardBadgeTask;
+
+impl Task for LeaderboardBadgeTask {
+    fn name(&self) -> &'static str {
+        "leaderboard_badges"
+    }
+
+    fn priority(&self) -> u8 {
+        70
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        300
+    }
+
+    fn run(&self) -> Result<(), String> {
+        sync_leaderboard_badges();
+        Ok(())
+    }
+}
+
+struct CanisterHookTask;
+
+impl Task for CanisterHookTask {
+    fn name(&self) -> &'static str {
+        "canister_hooks"
+    }
+
+    fn priority(&self) -> u8 {
+        60
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        60
+    }
+
+    // Only starts the calls; their results are recorded as they come back
+    fn run(&self) -> Result<(), String> {
+        for (hook_id, canister, method, payload) in hooks::take_due() {
+            ic_cdk::spawn(async move {
+                let result = match candid::encode_one(payload) {
+                    Ok(args) => ic_cdk::api::call::call_raw(canister, &method, args, 0)
+                        .await
+                        .map(|_| ())
+                        .map_err(|(code, msg)| format!("Hook call failed: {:?} {}", code, msg)),
+                    Err(e) => Err(format!("Encoding failed: {}", e)),
+                };
+                hooks::record(hook_id, result, ic_cdk::api::time());
+            });
+        }
+        Ok(())
+    }
+}
+
+struct SurveillanceTask;
+
+impl Task for SurveillanceTask {
+    fn name(&self) -> &'static str {
+        "surveillance"
+    }
+
+    fn priority(&self) -> u8 {
+        90
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        3_600
+    }
+
+    fn run(&self) -> Result<(), String> {
+        run_surveillance(ic_cdk::api::time());
+        Ok(())
+    }
+}
+
+struct ResolverFeeTask;
+
+impl Task for ResolverFeeTask {
+    fn name(&self) -> &'static str {
+        "resolver_fees"
+    }
+
+    fn priority(&self) -> u8 {
+        40
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        600
+    }
+
+    fn run(&self) -> Result<(), String> {
+        for (market_id, resolver, fee) in resolvers::take_due_fees(ic_cdk::api::time()) {
+            pay_resolver_fee(market_id, resolver, fee);
+        }
+        Ok(())
+    }
+}
+
+struct ResolutionRewardTask;
+
+impl Task for ResolutionRewardTask {
+    fn name(&self) -> &'static str {
+        "resolution_rewards"
+    }
+
+    fn priority(&self) -> u8 {
+        40
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        600
+    }
+
+    fn run(&self) -> Result<(), String> {
+        pay_resolution_rewards(ic_cdk::api::time());
+        Ok(())
+    }
+}
+
+struct AutoArchiveTask;
+
+impl Task for AutoArchiveTask {
+    fn name(&self) -> &'static str {
+        "auto_archive"
+    }
+
+    fn priority(&self) -> u8 {
+        90
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        3_600
+    }
+
+    // Only starts the run; the archive calls finish in later messages
+    fn run(&self) -> Result<(), String> {
+        start_auto_archive();
+        Ok(())
+    }
+}
+
+struct CategoryDigestTask;
+
+impl Task for CategoryDigestTask {
+    fn name(&self) -> &'static str {
+        "category_digests"
+    }
+
+    fn priority(&self) -> u8 {
+        80
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        86_400
+    }
+
+    // Only starts the run; LLM calls finish in later messages
+    fn run(&self) -> Result<(), String> {
+        start_category_digests();
+        Ok(())
+    }
+}
+
+fn start_scheduler() {
+    scheduler::register(&PayoutTask);
+    scheduler::register(&FlashReminderTask);
+    scheduler::register(&QuestResetTask);
+    scheduler::register(&FlowPruneTask);
+    scheduler::register(&MarketCardTask);
+    scheduler::register(&LeaderboardBadgeTask);
+    scheduler::register(&AutoArchiveTask);
+    scheduler::register(&IntegrityCheckTask);
+    scheduler::register(&ResolverFeeTask);
+    scheduler::register(&ResolutionRewardTask);
+    scheduler::register(&CanisterHookTask);
+    scheduler::register(&SurveillanceTask);
+    scheduler::register(&CategoryDigestTask);
+    scheduler::start();
+}
+
+#[ic_cdk::query]
+fn get_scheduler_status() -> Vec<TaskStatus> {
+    scheduler::status()
+}
+
+#[ic_cdk::update]
+fn set_task_enabled(name: String, enabled: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage scheduled tasks".to_string());
+    }
+
+    scheduler::set_enabled(&name, enabled)
+}
+
+#[ic_cdk::update]
+fn set_task_interval(name: String, interval_seconds: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage scheduled tasks".to_string());
+    }
+
+    scheduler::set_interval(&name, interval_seconds)
+}
+
+#[ic_cdk::update]
+fn trigger_task(name: String) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage scheduled tasks".to_string());
+    }
+
+    scheduler::trigger(&name)
+}
+
+// Access functions
+fn has_access(principal: &Principal) -> bool {
+    ACCESS_MODE.with(|mode| *mode.borrow() == AccessMode::Open)
+        || is_admin(principal)
+        || ALLOW_LIST.with(|list| list.borrow().contains(principal))
+}
+
+// Guard for state-changing user actions
+fn check_access(principal: &Principal) -> Result<(), String> {
+    if has_access(principal) {
+        Ok(())
+    } else {
+        Err("Access is limited to invited users; request access to join".to_string())
+    }
+}
+
+#[ic_cdk::query]
+fn get_access_mode() -> AccessMode {
+    ACCESS_MODE.with(|mode| mode.borrow().clone())
+}
+
+// The allow-list is kept when switching to Open, so flipping back later
+// restores the same set of users
+#[ic_cdk::update]
+fn set_access_mode(mode: AccessMode) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can change the access mode".to_string());
+    }
+
+    ACCESS_MODE.with(|current| *current.borrow_mut() = mode);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_access_status() -> AccessStatus {
+    let caller = ic_cdk::caller();
+    AccessStatus {
+        mode: get_access_mode(),
+        allowed: has_access(&caller),
+        requested: ACCESS_REQUESTS.with(|requests| requests.borrow().contains_key(&caller)),
+    }
+}
+
+#[ic_cdk::update]
+fn request_access() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot request access".to_string());
+    }
+    if ALLOW_LIST.with(|list| list.borrow().contains(&caller)) {
+        return Err("Access already granted".to_string());
+    }
+
+    ACCESS_REQUESTS.with(|requests| {
+        requests
+            .borrow_mut()
+            .entry(caller)
+            .or_insert_with(|| AccessRequest {
+                principal: caller,
+                requested_at: ic_cdk::api::time(),
+            });
+    });
+    Ok(())
+}
+
+// Oldest first
+#[ic_cdk::query]
+fn get_access_requests() -> Result<Vec<AccessRequest>, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can view access requests".to_string());
+    }
+
+    let mut requests: Vec<AccessRequest> =
+        ACCESS_REQUESTS.with(|requests| requests.borrow().values().cloned().collect());
+    requests.sort_by_key(|request| request.requested_at);
+    Ok(requests)
+}
+
+// Lists every given principal that has a pending request and notifies it.
+// Returns how many were approved; principals without a request are skipped.
+#[ic_cdk::update]
+fn approve_access_requests(principals: Vec<Principal>) -> Result<u64, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can approve access requests".to_string());
+    }
+
+    let mut approved = 0;
+    for principal in principals {
+        if ACCESS_REQUESTS
+            .with(|requests| requests.borrow_mut().remove(&principal))
+            .is_none()
+        {
+            continue;
+        }
+        ALLOW_LIST.with(|list| list.borrow_mut().insert(principal));
+        notify_user(principal, UserNotificationKind::AccessGranted);
+        approved += 1;
+    }
+    Ok(approved)
+}
+
+#[ic_cdk::query]
+fn get_allow_list() -> Result<Vec<Principal>, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can view the allow-list".to_string());
+    }
+
+    Ok(ALLOW_LIST.with(|list| list.borrow().iter().copied().collect()))
+}
+
+#[ic_cdk::update]
+fn set_allow_listed(principal: Principal, listed: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage the allow-list".to_string());
+    }
+
+    ALLOW_LIST.with(|list| {
+        let mut list = list.borrow_mut();
+        if listed {
+            list.insert(principal);
+        } else {
+            list.remove(&principal);
+        }
+    });
+    if listed {
+        ACCESS_REQUESTS.with(|requests| requests.borrow_mut().remove(&principal));
+    }
+    Ok(())
+}
+
+fn notify_user(principal: Principal, kind: UserNotificationKind) {
+    // Account notices are always delivered
+    let allowed = match &kind {
+        UserNotificationKind::AccessGranted => true,
+        UserNotificationKind::MarketResolved { market_id, .. } => notification_allowed(
+            principal,
+            NotificationKind::MarketResolved,
+            Some(*market_id),
+        ),
+    };
+    if !allowed {
+        return;
+    }
+
+    USER_NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let inbox = notifications.entry(principal).or_default();
+        inbox.push_back(UserNotification {
+            kind,
+            created_at: ic_cdk::api::time(),
+        });
+        if inbox.len() > MAX_USER_NOTIFICATIONS {
+            inbox.pop_front();
+        }
+    });
+}
+
+// Oldest first
+#[ic_cdk::query]
+fn get_my_notifications() -> Vec<UserNotification> {
+    let caller = ic_cdk::caller();
+    USER_NOTIFICATIONS.with(|notifications| {
+        notifications
+            .borrow()
+            .get(&caller)
+            .map(|inbox| inbox.iter().cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+#[ic_cdk::update]
+fn clear_notifications() {
+    let caller = ic_cdk::caller();
+    USER_NOTIFICATIONS.with(|notifications| notifications.borrow_mut().remove(&caller));
+}
+
+// Checked by every emission path before anything reaches an inbox
+fn notification_allowed(
+    principal: Principal,
+    kind: NotificationKind,
+    market_id: Option<u64>,
+) -> bool {
+    let muted = market_id.is_some_and(|market_id| {
+        MUTED_MARKETS.with(|muted| {
+            muted
+                .borrow()
+                .get(&principal)
+                .is_some_and(|markets| markets.contains(&market_id))
+        })
+    });
+    if muted {
+        return false;
+    }
+
+    let setting = NOTIFICATION_PREFS.with(|prefs| {
+        prefs
+            .borrow()
+            .get(&principal)
+            .and_then(|prefs| prefs.get(kind.name()).copied())
+    });
+    setting.unwrap_or(kind.default_setting()) == NotificationSetting::Enabled
+}
+
+// Frontend preferences, stored as given; an empty blob clears them
+#[ic_cdk::update]
+fn set_settings(blob: Vec<u8>) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous users cannot save settings".to_string());
+    }
+    if blob.len() > MAX_SETTINGS_BYTES {
+        return Err(format!(
+            "Settings cannot exceed {} bytes",
+            MAX_SETTINGS_BYTES
+        ));
+    }
+
+    let now = ic_cdk::api::time();
+    let hour_ago = now.saturating_sub(to_nanos(3_600));
+    SETTINGS_WRITES.with(|writes| {
+        let mut writes = writes.borrow_mut();
+        let recent = writes.entry(caller).or_default();
+        while recent.front().is_some_and(|at| *at <= hour_ago) {
+            recent.pop_front();
+        }
+        if recent.len() >= MAX_SETTINGS_WRITES_PER_HOUR {
+            return Err("Settings were saved too often; try again later".to_string());
+        }
+        recent.push_back(now);
+        Ok(())
+    })?;
+
+    USER_SETTINGS.with(|settings| {
+        let mut settings = settings.borrow_mut();
+        if blob.is_empty() {
+            settings.remove(&caller);
+        } else {
+            settings.insert(caller, blob);
+        }
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_my_settings() -> Option<Vec<u8>> {
+    USER_SETTINGS.with(|settings| settings.borrow().get(&ic_cdk::caller()).cloned())
+}
+
+// Every known kind with its effective setting, followed by any stored
+// settings for kinds this canister does not know
+#[ic_cdk::query]
+fn get_notification_prefs() -> Vec<(String, NotificationSetting)> {
+    let caller = ic_cdk::caller();
+    let stored = NOTIFICATION_PREFS
+        .with(|prefs| prefs.borrow().get(&caller).cloned())
+        .unwrap_or_default();
+
+    let mut result: Vec<(String, NotificationSetting)> = NOTIFICATION_KINDS
+        .iter()
+        .map(|kind| {
+            let setting = stored
+                .get(kind.name())
+                .copied()
+                .unwrap_or(kind.default_setting());
+            (kind.name().to_string(), setting)
+        })
+        .collect();
+    result.extend(
+        stored
+            .into_iter()
+            .filter(|(name, _)| !NOTIFICATION_KINDS.iter().any(|kind| kind.name() == name)),
+    );
+    result
+}
+
+// Merges into the caller's preferences; kinds not listed keep their setting
+#[ic_cdk::update]
+fn set_notification_prefs(prefs: Vec<(String, NotificationSetting)>) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot set notification preferences".to_string());
+    }
+    for (name, _) in &prefs {
+        if name.is_empty() || name.chars().count() > MAX_NOTIFICATION_KIND_LEN {
+            return Err(format!(
+                "Notification kind must be between 1 and {} characters",
+                MAX_NOTIFICATION_KIND_LEN
+            ));
+        }
+    }
+
+    NOTIFICATION_PREFS.with(|all| {
+        let mut all = all.borrow_mut();
+        let mut merged = all.get(&caller).cloned().unwrap_or_default();
+        merged.extend(prefs);
+        if merged.len() > MAX_NOTIFICATION_PREFS {
+            return Err(format!(
+                "At most {} notification preferences can be stored",
+                MAX_NOTIFICATION_PREFS
+            ));
+        }
+        all.insert(caller, merged);
+        Ok(())
+    })
+}
+
+// Silences every notification kind coming from one market
+#[ic_cdk::update]
+fn mute_market(market_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot mute markets".to_string());
+    }
+    if !MARKETS.with(|markets| markets.borrow().contains_key(&market_id)) {
+        return Err("Market not found".to_string());
+    }
+
+    MUTED_MARKETS.with(|muted| {
+        let mut muted = muted.borrow_mut();
+        let markets = muted.entry(caller).or_default();
+        if !markets.contains(&market_id) && markets.len() >= MAX_MUTED_MARKETS {
+            return Err(format!(
+                "At most {} markets can be muted",
+                MAX_MUTED_MARKETS
+            ));
+        }
+        markets.insert(market_id);
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn unmute_market(market_id: u64) {
+    let caller = ic_cdk::caller();
+    MUTED_MARKETS.with(|muted| {
+        let mut muted = muted.borrow_mut();
+        if let Some(markets) = muted.get_mut(&caller) {
+            markets.remove(&market_id);
+            if markets.is_empty() {
+                muted.remove(&caller);
+            }
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn get_muted_markets() -> Vec<u64> {
+    let caller = ic_cdk::caller();
+    MUTED_MARKETS.with(|muted| {
+        muted
+            .borrow()
+            .get(&caller)
+            .map(|markets| markets.iter().copied().collect())
+            .unwrap_or_default()
+    })
+}
+
+// Market functions
+#[ic_cdk::query]
+fn get_markets() -> Vec<Market> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    // Moderated markets stay reachable by id but leave the public listing
+    MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter(|m| !matches!(m.status, MarketStatus::Rejected | MarketStatus::Archived))
+            .cloned()
+            .collect()
+    })
+}
+
+// Categories by market count, most populated first. With active_only, categories
+// without an active market are left out rather than listed with 0.
+#[ic_cdk::query]
+fn get_categories_with_counts(active_only: bool) -> Vec<(String, u64)> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    MARKETS.with(|markets| {
+        for market in markets.borrow().values() {
+            if active_only && !matches!(market.status, MarketStatus::Active) {
+                continue;
+            }
+            *counts.entry(market.category.clone()).or_insert(0) += 1;
+        }
+    });
+
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+// Display metadata for the managed categories, ordered by key
+#[ic_cdk::query]
+fn get_categories_meta() -> Vec<Category> {
+    categories::list()
+}
+
+#[ic_cdk::update]
+fn upsert_category(category: Category) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage categories".to_string());
+    }
+
+    categories::upsert(category)
+}
+
+#[ic_cdk::update]
+fn remove_category(key: String) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage categories".to_string());
+    }
+
+    categories::remove(&key)
+}
+
+#[ic_cdk::query]
+fn get_market(id: u64) -> Option<Market> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    MARKETS.with(|markets| markets.borrow().get(&id).cloned())
+}
+
+#[ic_cdk::query]
+fn get_market_by_slug(slug: String) -> Option<Market> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    let market_id = MARKET_SLUGS.with(|slugs| slugs.borrow().get(&slug).copied())?;
+    MARKETS.with(|markets| markets.borrow().get(&market_id).cloned())
+}
+
+// Gate shared by the api_v1 endpoints; see api.rs for the budget rules
+fn api_v1_meta() -> Result<api::ResponseMetaV1, api::ApiErrorV1> {
+    if !reads_allowed() {
+        return Err(api::ApiErrorV1::ReadsDisabled);
+    }
+    api::meta(
+        ic_cdk::caller(),
+        ic_cdk::api::time(),
+        ic_cdk::api::in_replicated_execution(),
+    )
+}
+
+// Listed markets by id
+#[ic_cdk::query]
+fn api_v1_list_markets(offset: u64, limit: u64) -> Result<api::MarketPageV1, api::ApiErrorV1> {
+    let meta = api_v1_meta()?;
+    let mut markets = get_markets();
+    markets.sort_by_key(|m| m.id);
+
+    let offset = offset.min(markets.len() as u64) as usize;
+    let end = offset + limit.clamp(1, api::MAX_PAGE) as usize;
+    let next_offset = (end < markets.len()).then_some(end as u64);
+    Ok(api::MarketPageV1 {
+        meta,
+        markets: markets[offset..end.min(markets.len())]
+            .iter()
+            .map(api::MarketV1::from)
+            .collect(),
+        next_offset,
+    })
+}
+
+#[ic_cdk::query]
+fn api_v1_market(market_id: u64) -> Result<api::MarketResponseV1, api::ApiErrorV1> {
+    let meta = api_v1_meta()?;
+    let market = get_market(market_id).ok_or(api::ApiErrorV1::MarketNotFound)?;
+    Ok(api::MarketResponseV1 {
+        meta,
+        market: api::MarketV1::from(&market),
+    })
+}
+
+// Oldest first, reverted trades included and flagged; `after` is a trade id
+#[ic_cdk::query]
+fn api_v1_trades(
+    market_id: u64,
+    after: Option<u64>,
+    limit: u64,
+) -> Result<api::TradePageV1, api::ApiErrorV1> {
+    let meta = api_v1_meta()?;
+    let market = get_market(market_id).ok_or(api::ApiErrorV1::MarketNotFound)?;
+    if let Some(archive) = market.archived_to {
+        return Err(api::ApiErrorV1::MarketArchived { archive });
+    }
+
+    let limit = limit.clamp(1, api::MAX_PAGE) as usize;
+    let mut trades: Vec<api::TradeV1> = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|t| t.market_id == market_id && after.is_none_or(|after| t.id > after))
+            .filter(|t| t.reverted_at.is_none())
+            .take(limit + 1)
+            .map(api::TradeV1::from)
+            .collect()
+    });
+    let next_cursor = if trades.len() > limit {
+        trades.truncate(limit);
+        trades.last().map(|t| t.id)
+    } else {
+        None
+    };
+    Ok(api::TradePageV1 {
+        meta,
+        trades,
+        next_cursor,
+    })
+}
+
+fn update_reputation(creator: Principal, f: impl FnOnce(&mut CreatorReputation)) {
+    CREATOR_REPUTATION.with(|reputations| {
+        let mut reputations = reputations.borrow_mut();
+        let reputation = reputations.entry(creator).or_default();
+        f(reputation);
+        reputation.approval_rate_bps = (reputation.markets_approved * 10_000)
+            .checked_div(reputation.markets_created)
+            .unwrap_or(0);
+    });
+}
+
+// Recently activated markets, newest first. Markets whose creator has overdue
+// resolutions sink below everyone else's.
+#[ic_cdk::query]
+fn get_new_markets(offset: u64, limit: u64) -> Vec<NewMarket> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let now = ic_cdk::api::time();
+    let markets: Vec<Market> = MARKETS.with(|markets| markets.borrow().values().cloned().collect());
+
+    let mut overdue: HashMap<Principal, u64> = HashMap::new();
+    for market in &markets {
+        if resolution_countdown(market, now).is_some_and(|left| left < 0) {
+            *overdue.entry(market.creator).or_default() += 1;
+        }
+    }
+
+    let activations: HashMap<u64, u64> = MARKET_EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .filter(|event| matches!(event.kind, MarketEventKind::Approved))
+            .map(|event| (event.market_id, event.timestamp))
+            .collect()
+    });
+
+    let mut active: Vec<(Market, u64)> = markets
+        .into_iter()
+        .filter(|market| matches!(market.status, MarketStatus::Active))
+        .map(|market| {
+            let activated_at = activations
+                .get(&market.id)
+                .copied()
+                .unwrap_or(market.created_at);
+            (market, activated_at)
+        })
+        .collect();
+    active.sort_by_key(|(market, activated_at)| {
+        (
+            overdue.contains_key(&market.creator),
+            std::cmp::Reverse(*activated_at),
+            market.id,
+        )
+    });
+
+    let page: Vec<(Market, u64)> = active
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_FEED_PAGE) as usize)
+        .collect();
+
+    // Seed liquidity is whatever the pools held before any trade added to them
+    let mut traded_in: HashMap<u64, u64> = HashMap::new();
+    TRADES.with(|trades| {
+        for trade in trades.borrow().iter().filter(|t| t.reverted_at.is_none()) {
+            if page.iter().any(|(market, _)| market.id == trade.market_id) {
+                *traded_in.entry(trade.market_id).or_default() +=
+                    trade.amount - trade.fee - trade.dust;
+            }
+        }
+    });
+
+    page.into_iter()
+        .map(|(market, activated_at)| NewMarket {
+            activated_at,
+            creator_reputation: CREATOR_REPUTATION.with(|reputations| {
+                reputations
+                    .borrow()
+                    .get(&market.creator)
+                    .cloned()
+                    .unwrap_or_default()
+            }),
+            creator_overdue_resolutions: overdue.get(&market.creator).copied().unwrap_or(0),
+            ai_insight: AI_INSIGHTS.with(|insights| {
+                insights
+                    .borrow()
+                    .get(&market.id)
+                    .map(|insight| with_stale_check(insight.clone(), ic_cdk::api::time()).status)
+            }),
+            initial_liquidity: (market.yes_liquidity + market.no_liquidity)
+                .saturating_sub(traded_in.get(&market.id).copied().unwrap_or(0)),
+            market,
+        })
+        .collect()
+}
+
+fn market_embed(market_id: u64) -> Option<EmbedCard> {
+    let market = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned())?;
+    let now = ic_cdk::api::time();
+
+    Some(EmbedCard {
+        market_id,
+        title: sanitize_text(&market.title),
+        description: embed::truncate_description(&sanitize_text(&market.description)),
+        probability: embed::format_probability(engine::implied_yes_bps(&market)),
+        volume: embed::format_volume(market.total_volume),
+        time_remaining: embed::format_time_remaining(market.close_date, now),
+        status: market.status,
+    })
+}
+
+#[ic_cdk::query]
+fn get_market_embed(market_id: u64) -> Option<EmbedCard> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    market_embed(market_id)
+}
+
+fn record_price_snapshot(market: &Market, timestamp: u64) {
+    PRICE_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let snapshots = history.entry(market.id).or_default();
+        snapshots.push_back(PriceSnapshot {
+            timestamp,
+            yes_bps: engine::implied_yes_bps(market),
+        });
+        if snapshots.len() > MAX_PRICE_SNAPSHOTS {
+            snapshots.pop_front();
+        }
+    });
+}
+
+fn record_liquidity_snapshot(market: &Market, timestamp: u64) {
+    LIQUIDITY_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let snapshots = history.entry(market.id).or_default();
+        snapshots.push(LiquiditySnapshot {
+            market_id: market.id,
+            yes_liquidity: market.yes_liquidity,
+            no_liquidity: market.no_liquidity,
+            timestamp,
+        });
+        // Halve the resolution, keeping the first and the latest snapshot
+        if snapshots.len() > MAX_LIQUIDITY_SNAPSHOTS {
+            let latest = snapshots.pop();
+            let mut index = 0;
+            snapshots.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            snapshots.extend(latest);
+        }
+    });
+}
+
+// Snapshots with from <= timestamp <= to (ns), oldest first
+#[ic_cdk::query]
+fn get_liquidity_history(market_id: u64, from: u64, to: u64) -> Vec<LiquiditySnapshot> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    LIQUIDITY_HISTORY.with(|history| {
+        history
+            .borrow()
+            .get(&market_id)
+            .map_or(vec![], |snapshots| {
+                snapshots
+                    .iter()
+                    .filter(|s| s.timestamp >= from && s.timestamp <= to)
+                    .cloned()
+                    .collect()
+            })
+    })
+}
+
+// Earliest snapshot in the window against the latest one. A single snapshot in
+// the window reports no change; none at all is insufficient history.
+#[ic_cdk::query]
+fn get_price_change(market_id: u64, window_seconds: u64) -> Option<PriceChange> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    price_change(market_id, window_seconds, ic_cdk::api::time())
+}
+
+fn price_change(market_id: u64, window_seconds: u64, now: u64) -> Option<PriceChange> {
+    let since = now.saturating_sub(to_nanos(window_seconds));
+
+    PRICE_HISTORY.with(|history| {
+        let history = history.borrow();
+        let mut in_window = history
+            .get(&market_id)?
+            .iter()
+            .filter(|snapshot| snapshot.timestamp >= since && snapshot.timestamp <= now);
+        let from = in_window.next()?;
+        let to = in_window.next_back().unwrap_or(from);
+
+        Some(PriceChange {
+            from_bps: from.yes_bps,
+            to_bps: to.yes_bps,
+            change_bps: to.yes_bps as i64 - from.yes_bps as i64,
+        })
+    })
+}
+
+// One entry per requested id, None for unknown markets
+fn probability_quotes(market_ids: &[u64], now: u64) -> Vec<Option<ProbQuote>> {
+    let market_ids = &market_ids[..market_ids.len().min(MAX_PROBABILITY_BATCH)];
+    let wanted: HashSet<u64> = market_ids.iter().copied().collect();
+
+    // Single backwards pass over the trade log for all requested markets
+    let mut last_trades: HashMap<u64, u64> = HashMap::new();
+    TRADES.with(|trades| {
+        for trade in trades.borrow().iter().rev() {
+            if last_trades.len() == wanted.len() {
+                break;
+            }
+            if wanted.contains(&trade.market_id) && trade.reverted_at.is_none() {
+                last_trades
+                    .entry(trade.market_id)
+                    .or_insert(trade.timestamp);
+            }
+        }
+    });
+
+    MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        market_ids
+            .iter()
+            .map(|id| {
+                let market = markets.get(id)?;
+                let last_trade_ts = last_trades.get(id).copied();
+                Some(ProbQuote {
+                    market_id: *id,
+                    yes_bps: engine::implied_yes_bps(market),
+                    liquidity: market.yes_liquidity.saturating_add(market.no_liquidity),
+                    last_trade_ts,
+                    stale: last_trade_ts
+                        .is_none_or(|ts| now.saturating_sub(ts) >= STALE_QUOTE_NANOS),
+                    as_of: now,
+                })
+            })
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_probabilities(market_ids: Vec<u64>) -> Vec<Option<ProbQuote>> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    probability_quotes(&market_ids, ic_cdk::api::time())
+}
+
+#[ic_cdk::query]
+fn get_odds(market_id: u64, format: OddsFormat) -> Result<OddsQuote, String> {
+    let yes_bps = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .get(&market_id)
+            .filter(|_| reads_allowed())
+            .map(engine::implied_yes_bps)
+            .ok_or_else(|| "Market not found".to_string())
+    })?;
+
+    let yes = odds::from_probability(yes_bps, format);
+    let no = odds::from_probability(10_000 - yes_bps, format);
+    Ok(OddsQuote {
+        market_id,
+        yes,
+        no,
+        yes_display: odds::format(yes),
+        no_display: odds::format(no),
+    })
+}
+
+// Implied probability (bps) of odds entered in any format
+#[ic_cdk::query]
+fn probability_from_odds(odds: Odds) -> Result<u64, String> {
+    odds::to_probability(odds)
+}
+
+// Serves /embed/{id} as an OpenGraph page, /embed/{id}.json as JSON and
+// /v1/probabilities?ids=1,2,3 as the get_probabilities payload
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let (path, query) = request
+        .url
+        .split_once('?')
+        .unwrap_or((request.url.as_str(), ""));
+    if path == "/v1/probabilities" {
+        return probabilities_response(query);
+    }
+    let Some(target) = path.strip_prefix("/embed/") else {
+        return embed::not_found();
+    };
+    let (id, as_json) = match target.strip_suffix(".json") {
+        Some(id) => (id, true),
+        None => (target, false),
+    };
+
+    let card = if reads_allowed() {
+        id.parse().ok().and_then(market_embed)
+    } else {
+        None
+    };
+    match card {
+        Some(card) if as_json => embed::render_json(&card),
+        Some(card) => embed::render_html(&card),
+        None => embed::not_found(),
+    }
+}
+
+fn probabilities_response(query: &str) -> HttpResponse {
+    if !reads_allowed() {
+        return embed::not_found();
+    }
+
+    let ids = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("ids="))
+        .unwrap_or_default();
+    let ids: Result<Vec<u64>, _> = ids
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| id.trim().parse())
+        .collect();
+    let Ok(ids) = ids else {
+        return embed::bad_request("ids must be a comma-separated list of market ids");
+    };
+    if ids.len() > MAX_PROBABILITY_BATCH {
+        return embed::bad_request(&format!(
+            "At most {} ids per request",
+            MAX_PROBABILITY_BATCH
+        ));
+    }
+
+    let now = ic_cdk::api::time();
+    embed::render_probabilities(&probability_quotes(&ids, now), now)
+}
+
+#[ic_cdk::update]
+fn create_market(
+    title: String,
+    description: String,
+    category: String,
+    close_date: u64,
+) -> Result<u64, String> {
+    create_market_with_options(
+        title,
+        description,
+        category,
+        close_date,
+        MarketOptions::default(),
+    )
+}
+
+#[ic_cdk::update]
+fn create_market_with_options(
+    title: String,
+    description: String,
+    category: String,
+    close_date: u64,
+    options: MarketOptions,
+) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot create markets".to_string());
+    }
+    check_access(&caller)?;
+
+    if title.is_empty() || description.is_empty() {
+        return Err("Title and description cannot be empty".to_string());
+    }
+
+    if let Some(schedule) = &options.trading_schedule {
+        validate_trading_schedule(schedule)?;
+    }
+    if let Some(criteria) = &options.resolution_criteria {
+        validate_resolution_criteria(criteria)?;
+    }
+    validate_structured_fields(&options, close_date)?;
+    categories::validate_key(&category)?;
+    let co_owners = options.co_owners.clone().unwrap_or_default();
+    validate_co_owners(&caller, &co_owners)?;
+    if let Some(resolver) = options.resolver {
+        if resolvers::is_listed(resolver) && !resolvers::covers(resolver, &category) {
+            return Err("The chosen resolver does not cover this category".to_string());
+        }
+    }
+
+    if !is_admin(&caller) {
+        check_active_market_capacity(active_market_count())?;
+        check_not_duplicate(&title)?;
+    }
+
+    let market_kind = options.market_kind.unwrap_or(MarketKind::Standard);
+    if market_kind == MarketKind::Flash {
+        check_flash_market(&caller, close_date)?;
+    }
+
+    let market_id = NEXT_MARKET_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    let slug = assign_slug(market_id, &title);
+    let auto_approved = market_kind == MarketKind::Flash || is_trusted_creator(&caller);
+
+    let market = Market {
+        id: market_id,
+        title,
+        slug,
+        description,
+        category,
+        creator: caller,
+        close_date,
+        status: if auto_approved {
+            MarketStatus::Active
+        } else {
+            MarketStatus::PendingValidation
         },
-        Market {
-            id: 3,
-            title: "Will Tesla stock reach $500 by Q2 2025?".to_string(),
-            description: "This market resolves to YES if Tesla (TSLA) stock price reaches or exceeds $500 USD before June 30, 2025.".to_string(),
-            category: "Finance".to_string(),
-            creator: Principal::anonymous(),
-            close_date: 1767292799,
-            status: MarketStatus::Active,
-            yes_shares: 300,
-            no_shares: 700,
-            yes_liquidity: 3000,
-            no_liquidity: 7000,
-            total_volume: 1200,
-            created_at: 1737273600,
-            resolved_outcome: None,
+        yes_shares: 500, // Initial liquidity
+        no_shares: 500,
+        yes_liquidity: NEW_MARKET_LIQUIDITY,
+        no_liquidity: NEW_MARKET_LIQUIDITY,
+        total_volume: 0,
+        seed_liquidity: 2 * NEW_MARKET_LIQUIDITY,
+        seed_volume: 0,
+        created_at: ic_cdk::api::time(),
+        resolved_outcome: None,
+        comments_locked: false,
+        trading_schedule: options.trading_schedule,
+        market_kind,
+        resolver: options.resolver,
+        resolution_criteria: options.resolution_criteria,
+        archived_to: None,
+        resolution_datetime: options.resolution_datetime,
+        primary_source_url: options.primary_source_url,
+        numeric_target: options.numeric_target,
+        co_owners: co_owners.clone(),
+        resolution_delegate: None,
+    };
+
+    record_price_snapshot(&market, market.created_at);
+    record_liquidity_snapshot(&market, market.created_at);
+    MARKETS.with(|markets| {
+        markets.borrow_mut().insert(market_id, market.clone());
+    });
+    refresh_market_card(market_id);
+    for co_owner in co_owners {
+        record_event(
+            market_id,
+            MarketEventKind::CoOwnerAdded { co_owner },
+            caller,
+        );
+    }
+    update_reputation(caller, |reputation| reputation.markets_created += 1);
+
+    if auto_approved {
+        on_market_approved(&market, caller);
+    }
+    // AI review is the only validation a flash market gets
+    if market_kind == MarketKind::Flash && !AUTO_INSIGHT_ON_APPROVE.with(|flag| *flag.borrow()) {
+        let _ = start_insight_job(market.clone());
+    }
+
+    Ok(market_id)
+}
+
+// Active markets whose titles resemble `title`, most similar first
+fn similar_markets(title: &str) -> Vec<(u64, f64)> {
+    let words = similarity::title_words(title);
+    let mut similar: Vec<(u64, f64)> = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter(|m| matches!(m.status, MarketStatus::Active))
+            .map(|m| {
+                (
+                    m.id,
+                    similarity::jaccard(&words, &similarity::title_words(&m.title)),
+                )
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect()
+    });
+    similar.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    similar
+}
+
+fn check_not_duplicate(title: &str) -> Result<(), String> {
+    let threshold_bps = SIMILARITY_THRESHOLD_BPS.with(|threshold| *threshold.borrow());
+    if threshold_bps == 0 {
+        return Ok(());
+    }
+    match similar_markets(title).first() {
+        Some((id, score)) if *score * 10_000.0 >= threshold_bps as f64 => {
+            Err(format!("A very similar market already exists: #{}", id))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Lets the UI warn before submission; scores run from 0.0 to 1.0
+#[ic_cdk::query]
+fn find_similar_markets(title: String) -> Vec<(u64, f64)> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let mut similar = similar_markets(&title);
+    similar.truncate(MAX_SIMILAR_MARKETS);
+    similar
+}
+
+#[ic_cdk::query]
+fn get_similarity_threshold_bps() -> u64 {
+    SIMILARITY_THRESHOLD_BPS.with(|threshold| *threshold.borrow())
+}
+
+#[ic_cdk::update]
+fn set_similarity_threshold_bps(threshold_bps: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the duplicate threshold".to_string());
+    }
+    if threshold_bps > 10_000 {
+        return Err("The threshold cannot exceed 10000 bps".to_string());
+    }
+
+    SIMILARITY_THRESHOLD_BPS.with(|threshold| *threshold.borrow_mut() = threshold_bps);
+    Ok(())
+}
+
+fn check_flash_market(creator: &Principal, close_date: u64) -> Result<(), String> {
+    let config = FLASH_CONFIG.with(|config| config.borrow().clone());
+
+    let xp = USER_PROFILES.with(|profiles| profiles.borrow().get(creator).map(|p| p.xp));
+    let eligible = is_admin(creator)
+        || TRUSTED_CREATORS.with(|trusted| trusted.borrow().contains(creator))
+        || xp.is_some_and(|xp| xp >= config.min_creator_xp);
+    if !eligible {
+        return Err(format!(
+            "Flash markets require a trusted creator or at least {} XP",
+            config.min_creator_xp
+        ));
+    }
+
+    let window = to_secs(close_date.saturating_sub(ic_cdk::api::time()));
+    if window < config.min_close_window_seconds || window > config.max_close_window_seconds {
+        return Err(format!(
+            "Flash markets must close between {} and {} seconds from now",
+            config.min_close_window_seconds, config.max_close_window_seconds
+        ));
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_flash_config() -> FlashConfig {
+    FLASH_CONFIG.with(|config| config.borrow().clone())
+}
+
+#[ic_cdk::update]
+fn set_flash_config(config: FlashConfig) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the flash market configuration".to_string());
+    }
+
+    if config.min_close_window_seconds == 0
+        || config.min_close_window_seconds > config.max_close_window_seconds
+    {
+        return Err("Close window must satisfy 0 < min <= max".to_string());
+    }
+    if config.reminder_interval_seconds == 0 {
+        return Err("Reminder interval must be greater than 0".to_string());
+    }
+
+    FLASH_CONFIG.with(|current| *current.borrow_mut() = config);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_markets_by_kind(kind: MarketKind) -> Vec<Market> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter(|market| market.market_kind == kind)
+            .cloned()
+            .collect()
+    })
+}
+
+// Records a resolution reminder for every closed, unresolved flash market
+// whose last reminder is older than the configured interval
+fn send_flash_reminders() {
+    let interval = FLASH_CONFIG.with(|config| config.borrow().reminder_interval_seconds);
+    let now = ic_cdk::api::time();
+
+    let due: Vec<u64> = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter(|market| market.market_kind == MarketKind::Flash)
+            .filter(|market| !matches!(market.status, MarketStatus::Resolved))
+            .filter(|market| now >= market.close_date)
+            .map(|market| market.id)
+            .collect()
+    });
+
+    for market_id in due {
+        let last = FLASH_REMINDERS.with(|reminders| reminders.borrow().get(&market_id).copied());
+        if last.is_some_and(|last| now.saturating_sub(last) < to_nanos(interval)) {
+            continue;
+        }
+        FLASH_REMINDERS.with(|reminders| reminders.borrow_mut().insert(market_id, now));
+        record_event(market_id, MarketEventKind::ResolutionReminder, ic_cdk::id());
+    }
+}
+
+fn is_trusted_creator(creator: &Principal) -> bool {
+    let policy = APPROVAL_POLICY.with(|policy| policy.borrow().clone());
+    if !policy.auto_approve {
+        return false;
+    }
+    if TRUSTED_CREATORS.with(|trusted| trusted.borrow().contains(creator)) {
+        return true;
+    }
+
+    let Some(profile) = USER_PROFILES.with(|profiles| profiles.borrow().get(creator).cloned())
+    else {
+        return false;
+    };
+    policy.min_xp.is_some_and(|min| profile.xp >= min)
+        || policy
+            .min_successful_predictions
+            .is_some_and(|min| profile.successful_predictions >= min)
+}
+
+#[ic_cdk::query]
+fn get_approval_policy() -> ApprovalPolicy {
+    APPROVAL_POLICY.with(|policy| policy.borrow().clone())
+}
+
+#[ic_cdk::update]
+fn set_approval_policy(policy: ApprovalPolicy) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can change the approval policy".to_string());
+    }
+
+    APPROVAL_POLICY.with(|current| *current.borrow_mut() = policy);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_trusted_creators() -> Vec<Principal> {
+    TRUSTED_CREATORS.with(|trusted| trusted.borrow().iter().copied().collect())
+}
+
+#[ic_cdk::update]
+fn set_creator_trusted(creator: Principal, trusted: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage trusted creators".to_string());
+    }
+
+    TRUSTED_CREATORS.with(|creators| {
+        let mut creators = creators.borrow_mut();
+        if trusted {
+            creators.insert(creator);
+        } else {
+            creators.remove(&creator);
+        }
+    });
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn approve_market(market_id: u64) -> Result<Market, String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller) {
+        return Err("Only admins can approve markets".to_string());
+    }
+
+    approve_one(market_id, caller)
+}
+
+fn approve_one(market_id: u64, caller: Principal) -> Result<Market, String> {
+    let market = MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let active = count_active(markets.values());
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        if !matches!(market.status, MarketStatus::PendingValidation) {
+            return Err("Market is not pending validation".to_string());
+        }
+        // Approval is what makes a user's market Active, so it counts against the cap
+        check_active_market_capacity(active)?;
+
+        market.status = MarketStatus::Active;
+        Ok(market.clone())
+    })?;
+
+    on_market_approved(&market, caller);
+    refresh_market_card(market_id);
+
+    Ok(market)
+}
+
+fn reject_one(market_id: u64, reason: &str, caller: Principal) -> Result<(), String> {
+    MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        if !matches!(market.status, MarketStatus::PendingValidation) {
+            return Err("Market is not pending validation".to_string());
+        }
+
+        market.status = MarketStatus::Rejected;
+        Ok(())
+    })?;
+    refresh_market_card(market_id);
+
+    let reason = reason.to_string();
+    record_event(market_id, MarketEventKind::Rejected { reason }, caller);
+    Ok(())
+}
+
+// Takedown for markets nobody has traded, so no positions are stranded
+fn archive_one(market_id: u64, reason: &str, caller: Principal) -> Result<(), String> {
+    MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        match market.status {
+            MarketStatus::Resolved | MarketStatus::Rejected | MarketStatus::Archived => {
+                return Err(format!("Market is already {:?}", market.status));
+            }
+            _ if market.total_volume > 0 => {
+                return Err("Markets with trading activity cannot be archived".to_string());
+            }
+            _ => {}
+        }
+
+        market.status = MarketStatus::Archived;
+        Ok(())
+    })?;
+    refresh_market_card(market_id);
+
+    let reason = reason.to_string();
+    record_event(market_id, MarketEventKind::Archived { reason }, caller);
+    Ok(())
+}
+
+// Runs `op` on each id independently; one failure does not stop the batch
+fn triage_batch(
+    ids: Vec<u64>,
+    reason: Option<&str>,
+    op: impl Fn(u64, Principal) -> Result<(), String>,
+) -> Result<Vec<TriageResult>, String> {
+    let caller = ic_cdk::caller();
+    if !is_admin(&caller) {
+        return Err("Only admins can moderate markets".to_string());
+    }
+    if ids.len() > MAX_TRIAGE_BATCH {
+        return Err(format!("At most {} markets per batch", MAX_TRIAGE_BATCH));
+    }
+    if reason.is_some_and(|r| r.trim().is_empty() || r.chars().count() > MAX_TRIAGE_REASON_LEN) {
+        return Err(format!(
+            "Reason must be between 1 and {} characters",
+            MAX_TRIAGE_REASON_LEN
+        ));
+    }
+
+    Ok(ids
+        .into_iter()
+        .map(|market_id| TriageResult {
+            market_id,
+            result: op(market_id, caller),
+        })
+        .collect())
+}
+
+#[ic_cdk::update]
+fn approve_markets(ids: Vec<u64>) -> Result<Vec<TriageResult>, String> {
+    triage_batch(ids, None, |market_id, caller| {
+        approve_one(market_id, caller).map(|_| ())
+    })
+}
+
+#[ic_cdk::update]
+fn reject_markets(ids: Vec<u64>, reason: String) -> Result<Vec<TriageResult>, String> {
+    let reason = reason.trim();
+    triage_batch(ids, Some(reason), |market_id, caller| {
+        reject_one(market_id, reason, caller)
+    })
+}
+
+#[ic_cdk::update]
+fn archive_markets(ids: Vec<u64>, reason: String) -> Result<Vec<TriageResult>, String> {
+    let reason = reason.trim();
+    triage_batch(ids, Some(reason), |market_id, caller| {
+        archive_one(market_id, reason, caller)
+    })
+}
+
+// Cleanup worklist for archive_markets: untraded markets that can still be
+// archived, oldest first
+#[ic_cdk::query]
+fn get_markets_without_trades() -> Vec<Market> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let mut untraded: Vec<Market> = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter(|market| market.total_volume == 0)
+            .filter(|market| {
+                !matches!(
+                    market.status,
+                    MarketStatus::Resolved | MarketStatus::Rejected | MarketStatus::Archived
+                )
+            })
+            .cloned()
+            .collect()
+    });
+    untraded.sort_by_key(|market| (market.created_at, market.id));
+    untraded
+}
+
+// Listed markets with min <= total_volume <= max, busiest first; no max means
+// no upper bound
+#[ic_cdk::query]
+fn get_markets_by_volume_range(min: u64, max: Option<u64>) -> Result<Vec<Market>, String> {
+    if max.is_some_and(|max| min > max) {
+        return Err("The minimum volume cannot exceed the maximum".to_string());
+    }
+    if !reads_allowed() {
+        return Ok(vec![]);
+    }
+
+    let mut in_range: Vec<Market> = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter(|m| !matches!(m.status, MarketStatus::Rejected | MarketStatus::Archived))
+            .filter(|m| m.total_volume >= min && max.is_none_or(|max| m.total_volume <= max))
+            .cloned()
+            .collect()
+    });
+    in_range.sort_by_key(|m| (std::cmp::Reverse(m.total_volume), m.id));
+    Ok(in_range)
+}
+
+fn on_market_approved(market: &Market, actor: Principal) {
+    record_event(market.id, MarketEventKind::Approved, actor);
+    update_reputation(market.creator, |reputation| {
+        reputation.markets_approved += 1
+    });
+
+    // Prefill the detail page instead of generating lazily on first view
+    if AUTO_INSIGHT_ON_APPROVE.with(|flag| *flag.borrow()) {
+        let _ = start_insight_job(market.clone());
+    }
+}
+
+#[ic_cdk::query]
+fn get_auto_insight_on_approve() -> bool {
+    AUTO_INSIGHT_ON_APPROVE.with(|flag| *flag.borrow())
+}
+
+#[ic_cdk::update]
+fn set_auto_insight_on_approve(enabled: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can change auto-insight settings".to_string());
+    }
+
+    AUTO_INSIGHT_ON_APPROVE.with(|flag| *flag.borrow_mut() = enabled);
+    Ok(())
+}
+
+fn validate_trading_schedule(schedule: &TradingSchedule) -> Result<(), String> {
+    match schedule {
+        TradingSchedule::Windows(windows) => {
+            if windows.is_empty() || windows.len() > MAX_TRADING_WINDOWS {
+                return Err(format!(
+                    "Trading schedule must have between 1 and {} windows",
+                    MAX_TRADING_WINDOWS
+                ));
+            }
+            if windows.iter().any(|(open, close)| open >= close) {
+                return Err("Trading windows must open before they close".to_string());
+            }
+        }
+        TradingSchedule::Daily {
+            open_second,
+            close_second,
+        } => {
+            if *open_second >= SECONDS_PER_DAY || *close_second >= SECONDS_PER_DAY {
+                return Err("Daily trading hours must be within a day".to_string());
+            }
+            if open_second == close_second {
+                return Err("Daily trading window cannot be empty".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+// None when trading is open under the market's schedule; otherwise Some with
+// the next opening time (None inside when the schedule never reopens)
+fn next_trading_open(market: &Market, now: u64) -> Option<Option<u64>> {
+    match market.trading_schedule.as_ref()? {
+        TradingSchedule::Windows(windows) => {
+            if windows
+                .iter()
+                .any(|(open, close)| *open <= now && now < *close)
+            {
+                return None;
+            }
+            Some(
+                windows
+                    .iter()
+                    .map(|(open, _)| *open)
+                    .filter(|open| *open > now)
+                    .min(),
+            )
+        }
+        TradingSchedule::Daily {
+            open_second,
+            close_second,
+        } => {
+            let now_secs = to_secs(now);
+            let second_of_day = now_secs % SECONDS_PER_DAY;
+            let is_open = if open_second < close_second {
+                *open_second <= second_of_day && second_of_day < *close_second
+            } else {
+                second_of_day >= *open_second || second_of_day < *close_second
+            };
+            if is_open {
+                return None;
+            }
+            let day_start = now_secs - second_of_day;
+            if second_of_day < *open_second {
+                Some(Some(to_nanos(day_start + open_second)))
+            } else {
+                Some(Some(to_nanos(day_start + SECONDS_PER_DAY + open_second)))
+            }
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn get_xp_config() -> XpConfig {
+    XP_CONFIG.with(|config| config.borrow().clone())
+}
+
+#[ic_cdk::update]
+fn set_xp_config(config: XpConfig) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the XP configuration".to_string());
+    }
+
+    if config.xp_per_amount_divisor == 0 {
+        return Err("XP divisor must be greater than 0".to_string());
+    }
+    if config.late_multiplier_bps < 10_000
+        || config.early_multiplier_bps < config.late_multiplier_bps
+        || config.early_multiplier_bps > MAX_TIME_MULTIPLIER_BPS
+    {
+        return Err("Time multipliers must satisfy 1x <= late <= early <= 2x".to_string());
+    }
+    if config.contrarian_bonus_bps < 10_000
+        || config.contrarian_bonus_bps > MAX_CONTRARIAN_BONUS_BPS
+    {
+        return Err("Contrarian bonus must be between 1x and 1.5x".to_string());
+    }
+
+    XP_CONFIG.with(|c| *c.borrow_mut() = config);
+    Ok(())
+}
+
+fn xp_source_rule(source: XpSource) -> XpSourceRule {
+    XP_SOURCE_RULES.with(|rules| rules.borrow().get(&source).cloned().unwrap_or_default())
+}
+
+#[ic_cdk::query]
+fn get_xp_source_rules() -> Vec<(XpSource, XpSourceRule)> {
+    XP_SOURCES
+        .iter()
+        .map(|source| (*source, xp_source_rule(*source)))
+        .collect()
+}
+
+#[ic_cdk::update]
+fn set_xp_source_rule(source: XpSource, rule: XpSourceRule) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update XP sources".to_string());
+    }
+
+    XP_SOURCE_RULES.with(|rules| rules.borrow_mut().insert(source, rule));
+    Ok(())
+}
+
+// The only place XP is added. Scales `base_amount` by the source rate, clips
+// it to what is left of the source's daily cap, and records what was granted.
+// Returns the XP actually granted.
+fn grant_xp(profile: &mut UserProfile, source: XpSource, base_amount: u64, reference: u64) -> u64 {
+    let rule = xp_source_rule(source);
+    let now = ic_cdk::api::time();
+    let day = now / to_nanos(SECONDS_PER_DAY);
+    let key = (profile.principal, source, day);
+
+    let scaled =
+        (base_amount as u128 * rule.rate_bps as u128 / 10_000).min(u64::MAX as u128) as u64;
+    let granted = XP_DAILY.with(|daily| {
+        let mut daily = daily.borrow_mut();
+        let today = daily.entry(key).or_default();
+        let granted = if rule.daily_cap == 0 {
+            scaled
+        } else {
+            scaled.min(rule.daily_cap.saturating_sub(*today))
+        };
+        *today = today.saturating_add(granted);
+        granted
+    });
+    if granted == 0 {
+        return 0;
+    }
+
+    profile.xp = profile.xp.saturating_add(granted);
+    let id = NEXT_XP_ENTRY_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+    XP_ENTRIES.with(|entries| {
+        entries.borrow_mut().push(XpEntry {
+            id,
+            principal: profile.principal,
+            source,
+            amount: granted,
+            reference,
+            timestamp: now,
+        })
+    });
+    granted
+}
+
+#[ic_cdk::update]
+fn admin_grant_xp(principal: Principal, amount: u64) -> Result<u64, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can grant XP".to_string());
+    }
+
+    USER_PROFILES.with(|profiles| {
+        let mut profiles = profiles.borrow_mut();
+        let profile = profiles
+            .get_mut(&principal)
+            .ok_or_else(|| "Profile not found".to_string())?;
+        let granted = grant_xp(profile, XpSource::Admin, amount, 0);
+        award_badges(profile);
+        Ok(granted)
+    })
+}
+
+// Newest first; entries older than `cursor` (an entry id) when given
+#[ic_cdk::query]
+fn get_my_xp_history(cursor: Option<u64>, limit: u64) -> XpHistoryPage {
+    let caller = ic_cdk::caller();
+    let limit = limit.clamp(1, MAX_XP_HISTORY_PAGE) as usize;
+
+    let mut entries: Vec<XpEntry> = XP_ENTRIES.with(|entries| {
+        entries
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|entry| entry.principal == caller)
+            .filter(|entry| cursor.is_none_or(|cursor| entry.id < cursor))
+            .take(limit + 1)
+            .cloned()
+            .collect()
+    });
+
+    let next_cursor = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|entry| entry.id)
+    } else {
+        None
+    };
+    XpHistoryPage {
+        entries,
+        next_cursor,
+    }
+}
+
+// Biggest earners from one source on one UTC day (days since the epoch), to
+// spot farming
+#[ic_cdk::query]
+fn get_top_xp_earners(
+    source: XpSource,
+    day: u64,
+    limit: u64,
+) -> Result<Vec<(Principal, u64)>, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can view XP earners".to_string());
+    }
+
+    let mut earners: Vec<(Principal, u64)> = XP_DAILY.with(|daily| {
+        daily
+            .borrow()
+            .iter()
+            .filter(|((_, s, d), _)| *s == source && *d == day)
+            .map(|((principal, _, _), xp)| (*principal, *xp))
+            .collect()
+    });
+    earners.sort_by_key(|(principal, xp)| (std::cmp::Reverse(*xp), *principal));
+    earners.truncate(limit.min(MAX_TOP_XP_EARNERS) as usize);
+    Ok(earners)
+}
+
+// Grace buffer applied after close_date before trades are rejected. It absorbs
+// clock skew between clients and the canister so last-second trades shown as
+// open in the UI still land, at the cost of letting trades in slightly after
+// the advertised close, when outcome information may already be public.
+// Keep it small.
+#[ic_cdk::query]
+fn get_close_grace_seconds() -> u64 {
+    CLOSE_GRACE_SECONDS.with(|grace| *grace.borrow())
+}
+
+#[ic_cdk::update]
+fn set_close_grace_seconds(seconds: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the close grace period".to_string());
+    }
+
+    if seconds > MAX_CLOSE_GRACE_SECONDS {
+        return Err(format!(
+            "Close grace period cannot exceed {} seconds",
+            MAX_CLOSE_GRACE_SECONDS
+        ));
+    }
+
+    CLOSE_GRACE_SECONDS.with(|grace| *grace.borrow_mut() = seconds);
+    Ok(())
+}
+
+// Shares one principal may hold on one side of a market; 0 means uncapped
+#[ic_cdk::query]
+fn get_max_position_per_market() -> u64 {
+    MAX_POSITION_PER_MARKET.with(|cap| *cap.borrow())
+}
+
+#[ic_cdk::update]
+fn set_max_position_per_market(cap: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the position cap".to_string());
+    }
+
+    MAX_POSITION_PER_MARKET.with(|current| *current.borrow_mut() = cap);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_max_xp_per_trade() -> u64 {
+    MAX_XP_PER_TRADE.with(|cap| *cap.borrow())
+}
+
+#[ic_cdk::update]
+fn set_max_xp_per_trade(cap: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the XP configuration".to_string());
+    }
+
+    MAX_XP_PER_TRADE.with(|current| *current.borrow_mut() = cap);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_leaderboard_badge_size() -> u64 {
+    LEADERBOARD_BADGE_SIZE.with(|size| *size.borrow())
+}
+
+// The old badge is taken from everyone; holders of the new one are picked at once
+#[ic_cdk::update]
+fn set_leaderboard_badge_size(size: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the XP configuration".to_string());
+    }
+    if size > LEADERBOARD_SIZE {
+        return Err(format!(
+            "The badge cannot go beyond the {} places on the leaderboard",
+            LEADERBOARD_SIZE
+        ));
+    }
+
+    let old_badge = leaderboard_badge(get_leaderboard_badge_size());
+    USER_PROFILES.with(|profiles| {
+        for profile in profiles.borrow_mut().values_mut() {
+            profile.badges.retain(|badge| *badge != old_badge);
+        }
+    });
+    LEADERBOARD_BADGE_SIZE.with(|current| *current.borrow_mut() = size);
+    sync_leaderboard_badges();
+    Ok(())
+}
+
+fn count_active<'a>(markets: impl Iterator<Item = &'a Market>) -> u64 {
+    markets
+        .filter(|m| matches!(m.status, MarketStatus::Active))
+        .count() as u64
+}
+
+fn active_market_count() -> u64 {
+    MARKETS.with(|markets| count_active(markets.borrow().values()))
+}
+
+fn check_active_market_capacity(active: u64) -> Result<(), String> {
+    let max = MAX_ACTIVE_MARKETS.with(|max| *max.borrow());
+    if max > 0 && active >= max {
+        return Err(format!(
+            "The platform is at its limit of {} active markets; try again once one resolves",
+            max
+        ));
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_active_market_limit() -> ActiveMarketLimit {
+    ActiveMarketLimit {
+        max_active_markets: MAX_ACTIVE_MARKETS.with(|max| *max.borrow()),
+        active_markets: active_market_count(),
+    }
+}
+
+#[ic_cdk::update]
+fn set_max_active_markets(max: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the active market limit".to_string());
+    }
+
+    MAX_ACTIVE_MARKETS.with(|current| *current.borrow_mut() = max);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_min_market_liquidity() -> u64 {
+    MIN_MARKET_LIQUIDITY.with(|min| *min.borrow())
+}
+
+#[ic_cdk::update]
+fn set_min_market_liquidity(min: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can set the minimum market liquidity".to_string());
+    }
+    if min > NEW_MARKET_LIQUIDITY {
+        return Err(format!(
+            "The floor cannot exceed the {} a new market starts with",
+            NEW_MARKET_LIQUIDITY
+        ));
+    }
+
+    MIN_MARKET_LIQUIDITY.with(|current| *current.borrow_mut() = min);
+    Ok(())
+}
+
+// Cap in force for a market: its override if set, otherwise the global cap
+#[ic_cdk::query]
+fn get_position_cap(market_id: u64) -> u64 {
+    position_cap(market_id)
+}
+
+// None clears the override so the market falls back to the global cap
+#[ic_cdk::update]
+fn set_market_position_cap(market_id: u64, cap: Option<u64>) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the position cap".to_string());
+    }
+    if !MARKETS.with(|markets| markets.borrow().contains_key(&market_id)) {
+        return Err("Market not found".to_string());
+    }
+
+    MARKET_POSITION_CAPS.with(|caps| {
+        let mut caps = caps.borrow_mut();
+        match cap {
+            Some(cap) => caps.insert(market_id, cap),
+            None => caps.remove(&market_id),
+        }
+    });
+    Ok(())
+}
+
+fn position_cap(market_id: u64) -> u64 {
+    MARKET_POSITION_CAPS
+        .with(|caps| caps.borrow().get(&market_id).copied())
+        .unwrap_or_else(|| MAX_POSITION_PER_MARKET.with(|cap| *cap.borrow()))
+}
+
+// Trades at the start of each user's history that are charged no fee
+#[ic_cdk::query]
+fn get_free_trades() -> u64 {
+    FREE_TRADES.with(|free| *free.borrow())
+}
+
+#[ic_cdk::update]
+fn set_free_trades(count: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the fee waiver".to_string());
+    }
+
+    FREE_TRADES.with(|free| *free.borrow_mut() = count);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_free_trades_remaining(principal: Principal) -> u64 {
+    free_trades_remaining(principal)
+}
+
+fn free_trades_remaining(principal: Principal) -> u64 {
+    let total_trades = USER_PROFILES.with(|profiles| {
+        profiles
+            .borrow()
+            .get(&principal)
+            .map_or(0, |profile| profile.total_trades)
+    });
+    FREE_TRADES
+        .with(|free| *free.borrow())
+        .saturating_sub(total_trades)
+}
+
+fn resolve_config(caller: Principal, now: u64) -> EffectiveConfig {
+    let (overrides, assignments) = experiments::overrides_for(caller, now);
+    let xp_config = XP_CONFIG.with(|config| config.borrow().clone());
+
+    // Onboarding waiver takes precedence over any fee experiment
+    let fee_bps = if free_trades_remaining(caller) > 0 {
+        0
+    } else {
+        overrides.fee_bps.unwrap_or(engine::FEE_BPS)
+    };
+
+    EffectiveConfig {
+        fee_bps,
+        xp_per_amount_divisor: overrides
+            .xp_per_amount_divisor
+            .unwrap_or(xp_config.xp_per_amount_divisor),
+        experiments: assignments,
+    }
+}
+
+#[ic_cdk::query]
+fn get_effective_config() -> EffectiveConfig {
+    resolve_config(ic_cdk::caller(), ic_cdk::api::time())
+}
+
+#[ic_cdk::query]
+fn get_config() -> PlatformConfig {
+    PlatformConfig {
+        fee_bps: engine::FEE_BPS,
+        free_trades: get_free_trades(),
+        close_grace_seconds: get_close_grace_seconds(),
+        max_position_per_market: get_max_position_per_market(),
+        max_xp_per_trade: get_max_xp_per_trade(),
+        leaderboard_badge_size: get_leaderboard_badge_size(),
+        max_active_markets: MAX_ACTIVE_MARKETS.with(|max| *max.borrow()),
+        min_market_liquidity: get_min_market_liquidity(),
+        max_comments_per_market: get_max_comments_per_market(),
+        similarity_threshold_bps: get_similarity_threshold_bps(),
+        insight_refresh_threshold_bps: get_insight_refresh_threshold_bps(),
+        resolution_reward_bps: get_resolution_reward_bps(),
+        link_domains: get_link_domains(),
+        auto_insight_on_approve: get_auto_insight_on_approve(),
+        llm_enabled: get_llm_enabled(),
+        llm_daily_calls: get_llm_daily_calls(),
+        access_mode: get_access_mode(),
+        anonymous_reads_allowed: get_anonymous_reads_allowed(),
+        approval_policy: get_approval_policy(),
+        treasury_floor: get_treasury_floor(),
+        ledger_canister: LEDGER_ID.with(|id| *id.borrow()),
+        archive_canister: get_archive_canister(),
+        auto_archive: get_auto_archive_config(),
+        flash: get_flash_config(),
+        xp: get_xp_config(),
+    }
+}
+
+// Experiment functions
+#[ic_cdk::update]
+fn create_experiment(
+    name: String,
+    overrides: ExperimentOverrides,
+    traffic_bps: u64,
+    starts_at: u64,
+    ends_at: u64,
+) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    if !is_admin(&caller) {
+        return Err("Only admins can create experiments".to_string());
+    }
+
+    experiments::create(
+        name,
+        overrides,
+        traffic_bps,
+        starts_at,
+        ends_at,
+        caller,
+        ic_cdk::api::time(),
+    )
+}
+
+#[ic_cdk::update]
+fn stop_experiment(experiment_id: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can stop experiments".to_string());
+    }
+
+    experiments::stop(experiment_id)
+}
+
+#[ic_cdk::query]
+fn get_experiments() -> Vec<Experiment> {
+    experiments::list()
+}
+
+#[ic_cdk::query]
+fn get_experiment_results(experiment_id: u64) -> Result<ExperimentResults, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can view experiment results".to_string());
+    }
+
+    experiments::results(experiment_id).ok_or_else(|| "Experiment not found".to_string())
+}
+
+// Timestamps, ic_cdk::api::time() included, are nanoseconds; configured
+// durations are whole seconds
+fn to_nanos(secs: u64) -> u64 {
+    secs.saturating_mul(1_000_000_000)
+}
+
+fn to_secs(nanos: u64) -> u64 {
+    nanos / 1_000_000_000
+}
+
+// Quest functions
+// UTC day quests are tracked under, in days since the epoch
+fn quest_day() -> u64 {
+    ic_cdk::api::time() / to_nanos(SECONDS_PER_DAY)
+}
+
+#[ic_cdk::query]
+fn get_quests() -> Vec<Quest> {
+    quests::list()
+}
+
+#[ic_cdk::update]
+fn set_quests(quests: Vec<Quest>) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update quests".to_string());
+    }
+
+    quests::set(quests)
+}
+
+// Today's quests with the caller's progress; completed, unclaimed ones are claimable
+#[ic_cdk::query]
+fn get_my_quests() -> Vec<QuestProgress> {
+    quests::for_principal(ic_cdk::caller(), quest_day())
+}
+
+// A day with a trade or a comment counts; days run midnight to midnight UTC
+fn activity_streak(principal: Principal, now: u64) -> u64 {
+    let day_nanos = to_nanos(SECONDS_PER_DAY);
+    let mut days: BTreeSet<u64> = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|t| t.trader == principal && t.reverted_at.is_none())
+            .map(|t| t.timestamp / day_nanos)
+            .collect()
+    });
+    COMMENTS.with(|comments| {
+        days.extend(
+            comments
+                .borrow()
+                .iter()
+                .filter(|c| c.author == principal)
+                .map(|c| c.timestamp / day_nanos),
+        )
+    });
+    quests::activity_streak(&days, now / day_nanos)
+}
+
+#[ic_cdk::query]
+fn get_activity_streak(principal: Principal) -> u64 {
+    if !reads_allowed() {
+        return 0;
+    }
+
+    activity_streak(principal, ic_cdk::api::time())
+}
+
+// Returns the XP granted, which the daily Quest cap may reduce
+#[ic_cdk::update]
+fn claim_quest_reward(quest_id: u64) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot claim quest rewards".to_string());
+    }
+
+    let (reward, streak) = quests::claim(caller, quest_id, quest_day())?;
+
+    USER_PROFILES.with(|profiles| {
+        let mut profiles = profiles.borrow_mut();
+        let profile = profiles
+            .entry(caller)
+            .or_insert_with(|| new_profile(caller, default_username(&caller)));
+        let granted = grant_xp(profile, XpSource::Quest, reward, quest_id);
+        if streak >= QUEST_STREAK_DAYS && !profile.badges.iter().any(|b| b == QUEST_STREAK_BADGE) {
+            profile.badges.push(QUEST_STREAK_BADGE.to_string());
+        }
+        award_badges(profile);
+        Ok(granted)
+    })
+}
+
+// Draw functions
+// Orders tied candidates with a fresh raw_rand seed; the first gets the higher
+// slot. The whole draw is kept for get_draw_audit.
+#[ic_cdk::update]
+async fn draw_tie_break(purpose: String, candidates: Vec<Principal>) -> Result<Draw, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can run draws".to_string());
+    }
+    run_draw(purpose, candidates).await
+}
+
+async fn run_draw(purpose: String, candidates: Vec<Principal>) -> Result<Draw, String> {
+    randomness::validate(&purpose, &candidates)?;
+
+    let (seed,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(code, msg)| format!("Randomness unavailable: {:?} {}", code, msg))?;
+    Ok(randomness::record(
+        purpose,
+        seed,
+        candidates,
+        ic_cdk::api::time(),
+    ))
+}
+
+// Public so anyone can recompute the order from the seed
+#[ic_cdk::query]
+fn get_draw_audit(draw_id: u64) -> Option<Draw> {
+    randomness::get(draw_id)
+}
+
+#[ic_cdk::query]
+fn get_participation_split(market_id: u64) -> Option<ParticipationSplit> {
+    if !reads_allowed() || !MARKETS.with(|markets| markets.borrow().contains_key(&market_id)) {
+        return None;
+    }
+
+    Some(
+        participation_splits(Some(market_id))
+            .remove(&market_id)
+            .unwrap_or_default(),
+    )
+}
+
+// Splits for one market, or for every traded market in a single pass
+fn participation_splits(market_id: Option<u64>) -> HashMap<u64, ParticipationSplit> {
+    let mut sides: HashMap<(u64, Principal), (bool, bool)> = HashMap::new();
+    TRADES.with(|trades| {
+        for trade in trades
+            .borrow()
+            .iter()
+            .filter(|t| market_id.is_none_or(|id| t.market_id == id) && t.reverted_at.is_none())
+        {
+            let (yes, no) = sides.entry((trade.market_id, trade.trader)).or_default();
+            if trade.is_yes {
+                *yes = true;
+            } else {
+                *no = true;
+            }
+        }
+    });
+
+    let mut splits: HashMap<u64, ParticipationSplit> = HashMap::new();
+    for ((market_id, _), (yes, no)) in sides {
+        let split = splits.entry(market_id).or_default();
+        split.yes_traders += yes as u64;
+        split.no_traders += no as u64;
+        split.both += (yes && no) as u64;
+    }
+    splits
+}
+
+#[ic_cdk::update]
+fn buy_shares(market_id: u64, is_yes: bool, amount: u64) -> Result<Trade, String> {
+    place_buy(ic_cdk::caller(), market_id, is_yes, amount)
+}
+
+// Places (market_id, is_yes, amount) orders one after another, so each sees
+// the price impact of the ones before it. A failed order does not stop the
+// rest; orders past the batch limit are not attempted.
+#[ic_cdk::update]
+fn buy_shares_batch(orders: Vec<(u64, bool, u64)>) -> Vec<Result<Trade, String>> {
+    let caller = ic_cdk::caller();
+    orders
+        .into_iter()
+        .enumerate()
+        .map(|(index, (market_id, is_yes, amount))| {
+            if index >= MAX_BATCH_ORDERS {
+                return Err(format!(
+                    "Batches are limited to {} orders",
+                    MAX_BATCH_ORDERS
+                ));
+            }
+            place_buy(caller, market_id, is_yes, amount)
+        })
+        .collect()
+}
+
+fn place_buy(
+    caller: Principal,
+    market_id: u64,
+    is_yes: bool,
+    amount: u64,
+) -> Result<Trade, String> {
+    check_access(&caller)?;
+
+    if amount == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+    limits::check_trade(caller, amount, ic_cdk::api::time()).map_err(|breach| breach.message())?;
+
+    let trade_id = NEXT_TRADE_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    let now = ic_cdk::api::time();
+    let config = resolve_config(caller, now);
+    let xp_config = XpConfig {
+        xp_per_amount_divisor: config.xp_per_amount_divisor,
+        ..XP_CONFIG.with(|xp_config| xp_config.borrow().clone())
+    };
+
+    let (outcome, xp_multiplier_bps, yes_bps_before, yes_bps_after) = MARKETS.with(|markets| {
+        let mut markets_map = markets.borrow_mut();
+        if let Some(market) = markets_map.get_mut(&market_id) {
+            if !matches!(market.status, MarketStatus::Active) {
+                return Err("Market is not active".to_string());
+            }
+
+            let grace = CLOSE_GRACE_SECONDS.with(|grace| *grace.borrow());
+            if now >= market.close_date.saturating_add(to_nanos(grace)) {
+                return Err("Market is closed".to_string());
+            }
+
+            // A scheduled pause, not the terminal close
+            if let Some(next_open) = next_trading_open(market, now) {
+                return Err(match next_open {
+                    Some(ts) => format!("TradingWindowClosed: next open at {}", ts),
+                    None => "TradingWindowClosed: no upcoming trading window".to_string(),
+                });
+            }
+
+            let cap = position_cap(market_id);
+            if cap > 0 {
+                let held = POSITIONS.with(|positions| {
+                    positions.borrow().get(&(caller, market_id)).map_or(0, |p| {
+                        if is_yes {
+                            p.yes_shares
+                        } else {
+                            p.no_shares
+                        }
+                    })
+                });
+                let minted =
+                    engine::shares_for(market.yes_shares, market.no_shares, is_yes, amount);
+                if held.saturating_add(minted) > cap {
+                    return Err("Position limit reached".to_string());
+                }
+            }
+
+            // Weighted against the pre-trade state so the majority is the one being traded against
+            let xp_multiplier_bps =
+                engine::trade_xp_multiplier_bps(&xp_config, market, is_yes, now);
+            // Checked before the pool moves; the debit below then cannot fail
+            if get_balance(caller) < amount {
+                return Err("Insufficient balance".to_string());
+            }
+            let yes_bps_before = engine::implied_yes_bps(market);
+            let outcome = engine::apply_buy(market, is_yes, amount, config.fee_bps)?;
+            debit_balance(caller, amount, BalanceReason::TradeDebit, trade_id)?;
+            record_price_snapshot(market, now);
+            record_liquidity_snapshot(market, now);
+
+            TREASURY.with(|treasury| {
+                *treasury.borrow_mut() += outcome.fee + outcome.dust;
+            });
+            record_treasury_inflow(outcome.fee + outcome.dust, market_id, now);
+            record_flow(market_id, is_yes, amount, now);
+            DUST_COLLECTED.with(|dust| *dust.borrow_mut() += outcome.dust);
+
+            Ok((
+                outcome,
+                xp_multiplier_bps,
+                yes_bps_before,
+                engine::implied_yes_bps(market),
+            ))
+        } else {
+            Err("Market not found".to_string())
+        }
+    })?;
+
+    // Checked before the trade is logged, for the new-category quest
+    let new_category = !traded_in_category(caller, market_id);
+    let incentive_rebate = incentives::rebate(
+        market_id,
+        caller,
+        is_yes,
+        yes_bps_before,
+        yes_bps_after,
+        outcome.fee,
+        now,
+    );
+
+    let trade = Trade {
+        id: trade_id,
+        market_id,
+        trader: caller,
+        is_yes,
+        amount,
+        shares: outcome.shares,
+        price: outcome.price,
+        timestamp: now,
+        xp_multiplier_bps,
+        fee: outcome.fee,
+        dust: outcome.dust,
+        experiments: config.experiments,
+        incentive_rebate: Some(incentive_rebate),
+        reverted_at: None,
+    };
+
+    TRADES.with(|trades| {
+        trades.borrow_mut().push(trade.clone());
+    });
+    refresh_market_card(market_id);
+    if incentive_rebate > 0 {
+        credit_balance(
+            caller,
+            incentive_rebate,
+            BalanceReason::IncentiveRebate,
+            trade_id,
+        );
+    }
+    experiments::record_trade(caller, &trade.experiments, amount, now);
+    limits::record_spend(caller, amount, now);
+
+    POSITIONS.with(|positions| {
+        let mut positions = positions.borrow_mut();
+        let position = positions
+            .entry((caller, market_id))
+            .or_insert_with(|| Position {
+                market_id,
+                owner: caller,
+                yes_shares: 0,
+                no_shares: 0,
+                yes_cost: 0,
+                no_cost: 0,
+                claimed: false,
+            });
+        engine::apply_position_buy(position, is_yes, outcome.shares, amount)
+    })?;
+
+    let rebate = drawn_total(&draw_boost(market_id, caller, |kind| match kind {
+        BoostKind::VolumeRebate { rebate_bps } => outcome.fee * rebate_bps / 10_000,
+        _ => 0,
+    }));
+    if rebate > 0 {
+        credit_balance(caller, rebate, BalanceReason::BoostPayout, trade_id);
+    }
+
+    let streak = activity_streak(caller, now);
+
+    // Update user profile XP
+    let bonus_draws = USER_PROFILES.with(|profiles| {
+        let mut profiles_map = profiles.borrow_mut();
+        let profile = profiles_map
+            .entry(caller)
+            .or_insert_with(|| new_profile(caller, default_username(&caller)));
+
+        profile.total_trades += 1;
+        if streak >= ACTIVITY_STREAK_DAYS
+            && !profile.badges.iter().any(|b| b == ACTIVITY_STREAK_BADGE)
+        {
+            profile.badges.push(ACTIVITY_STREAK_BADGE.to_string());
+        }
+        // Gain XP for trading, weighted by timing and conviction
+        let xp = amount as u128 * xp_multiplier_bps as u128
+            / (xp_config.xp_per_amount_divisor as u128 * 10_000);
+        let xp = xp.min(u64::MAX as u128) as u64;
+        // The cap bounds the multiplied XP; boosts only draw what fits under it
+        let cap = match MAX_XP_PER_TRADE.with(|cap| *cap.borrow()) {
+            0 => u64::MAX,
+            cap => cap,
+        };
+        let headroom = cap.saturating_sub(xp);
+        let xp = xp.min(cap);
+        let bonus_draws = draw_boost(market_id, caller, |kind| match kind {
+            BoostKind::XpMultiplier { multiplier_bps } => {
+                (xp.saturating_mul(multiplier_bps.saturating_sub(10_000)) / 10_000).min(headroom)
+            }
+            _ => 0,
+        });
+        grant_xp(
+            profile,
+            XpSource::Trade,
+            xp.saturating_add(drawn_total(&bonus_draws)),
+            trade_id,
+        );
+
+        award_badges(profile);
+        bonus_draws
+    });
+    record_boost_xp_draws(trade_id, now, bonus_draws);
+
+    quests::record(
+        caller,
+        QuestEvent::Trade {
+            amount,
+            new_category,
         },
-    ];
+        quest_day(),
+    );
+
+    refresh_insight_on_move(market_id);
+
+    Ok(trade)
+}
+
+// Sells fraction_bps of the caller's shares on one side back to the pool. The
+// share count rounds down, except that 10000 sells everything. The sale is
+// refused if it would pay less than min_proceeds.
+#[ic_cdk::update]
+fn close_position(
+    market_id: u64,
+    is_yes: bool,
+    fraction_bps: u16,
+    min_proceeds: u64,
+) -> Result<ClosedPosition, String> {
+    if fraction_bps == 0 || fraction_bps > 10_000 {
+        return Err("Fraction must be between 1 and 10000 bps".to_string());
+    }
+    let caller = ic_cdk::caller();
+    let held = POSITIONS.with(|positions| {
+        positions.borrow().get(&(caller, market_id)).map_or(0, |p| {
+            if is_yes {
+                p.yes_shares
+            } else {
+                p.no_shares
+            }
+        })
+    });
+    if held == 0 {
+        return Err("NoPosition: no shares held on this side".to_string());
+    }
+    let shares = engine::shares_for_fraction(held, fraction_bps);
+    if shares == 0 {
+        return Err("Fraction is too small to sell any shares".to_string());
+    }
+    place_sell(caller, market_id, is_yes, shares, min_proceeds)
+}
+
+fn place_sell(
+    caller: Principal,
+    market_id: u64,
+    is_yes: bool,
+    shares: u64,
+    min_proceeds: u64,
+) -> Result<ClosedPosition, String> {
+    check_access(&caller)?;
+    let now = ic_cdk::api::time();
+    let config = resolve_config(caller, now);
+
+    // Both sides are worked on copies and committed together
+    let mut position = POSITIONS
+        .with(|positions| positions.borrow().get(&(caller, market_id)).cloned())
+        .ok_or_else(|| "NoPosition: no shares held on this side".to_string())?;
+    let sold_cost = engine::apply_position_sell(&mut position, is_yes, shares)?;
+
+    let outcome = MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+        if !matches!(market.status, MarketStatus::Active) {
+            return Err("Market is not active".to_string());
+        }
+        let grace = CLOSE_GRACE_SECONDS.with(|grace| *grace.borrow());
+        if now >= market.close_date.saturating_add(to_nanos(grace)) {
+            return Err("Market is closed".to_string());
+        }
+        if let Some(next_open) = next_trading_open(market, now) {
+            return Err(match next_open {
+                Some(ts) => format!("TradingWindowClosed: next open at {}", ts),
+                None => "TradingWindowClosed: no upcoming trading window".to_string(),
+            });
+        }
+
+        let mut sold = market.clone();
+        let outcome = engine::apply_sell(&mut sold, is_yes, shares, sold_cost, config.fee_bps)?;
+        if outcome.proceeds < min_proceeds {
+            return Err(format!(
+                "Slippage: sale pays {} below the minimum {}",
+                outcome.proceeds, min_proceeds
+            ));
+        }
+        // Only the side sold from moves, so a side already under the floor
+        // does not block sales on the other
+        let left = if is_yes {
+            sold.yes_liquidity
+        } else {
+            sold.no_liquidity
+        };
+        if left < MIN_MARKET_LIQUIDITY.with(|min| *min.borrow()) {
+            return Err("Would breach minimum market liquidity".to_string());
+        }
+        *market = sold;
+        record_price_snapshot(market, now);
+        record_liquidity_snapshot(market, now);
+        Ok(outcome)
+    })?;
+
+    let sale_id = NEXT_TRADE_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+    POSITIONS.with(|positions| {
+        positions
+            .borrow_mut()
+            .insert((caller, market_id), position.clone());
+    });
+    credit_balance(
+        caller,
+        outcome.proceeds,
+        BalanceReason::SaleProceeds,
+        sale_id,
+    );
+
+    let sale = Sale {
+        id: sale_id,
+        market_id,
+        trader: caller,
+        is_yes,
+        shares,
+        proceeds: outcome.proceeds,
+        price: outcome.price,
+        fee: outcome.fee,
+        timestamp: now,
+    };
+    SALES.with(|sales| sales.borrow_mut().push(sale.clone()));
+    refresh_market_card(market_id);
+    refresh_insight_on_move(market_id);
+
+    Ok(ClosedPosition {
+        sale,
+        remaining: position,
+    })
+}
+
+#[ic_cdk::query]
+fn get_trade_undo(market_id: u64) -> bool {
+    UNDO_MARKETS.with(|markets| markets.borrow().contains(&market_id))
+}
+
+#[ic_cdk::update]
+fn set_trade_undo(market_id: u64, enabled: bool) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let owned = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .get(&market_id)
+            .map(|m| is_creator(m, caller))
+    });
+    let Some(owned) = owned else {
+        return Err("Market not found".to_string());
+    };
+    if !owned && !is_admin(&caller) {
+        return Err("Only the creator or an admin can change the undo window".to_string());
+    }
+
+    UNDO_MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        if enabled {
+            markets.insert(market_id);
+        } else {
+            markets.remove(&market_id);
+        }
+    });
+    Ok(())
+}
+
+// Undoes a fat-fingered buy. The trade must be the caller's, at most
+// REVERT_WINDOW_SECONDS old and still the latest on its market, so no one else
+// traded against the price it set. Pool, position, volume, XP and price history
+// go back to how they were. The treasury gives back the trade's fee and dust and
+// keeps REVERT_PENALTY_BPS of the amount instead; the trader gets the rest.
+#[ic_cdk::update]
+fn revert_trade(trade_id: u64) -> Result<Trade, String> {
+    let caller = ic_cdk::caller();
+    check_access(&caller)?;
+    let now = ic_cdk::api::time();
+
+    let trade = TRADES
+        .with(|trades| trades.borrow().iter().find(|t| t.id == trade_id).cloned())
+        .ok_or_else(|| "Trade not found".to_string())?;
+    if trade.trader != caller {
+        return Err("Only the trader can revert this trade".to_string());
+    }
+    if trade.reverted_at.is_some() {
+        return Err("Trade is already reverted".to_string());
+    }
+    if !get_trade_undo(trade.market_id) {
+        return Err("This market has no undo window".to_string());
+    }
+    if now.saturating_sub(trade.timestamp) > to_nanos(REVERT_WINDOW_SECONDS) {
+        return Err("The undo window for this trade has passed".to_string());
+    }
+    let traded_since = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .any(|t| t.market_id == trade.market_id && t.id > trade.id && t.reverted_at.is_none())
+    });
+    let sold_since = SALES.with(|sales| {
+        sales
+            .borrow()
+            .iter()
+            .any(|s| s.market_id == trade.market_id && s.id > trade.id)
+    });
+    if traded_since || sold_since {
+        return Err("Another trade has executed on this market since".to_string());
+    }
+    // Rebates may already be spent, and clawing them back is not worth it
+    let boosted = BALANCE_ENTRIES.with(|entries| {
+        entries
+            .borrow()
+            .iter()
+            .rev()
+            .any(|e| matches!(e.reason, BalanceReason::BoostPayout) && e.reference == trade_id)
+    });
+    if trade.incentive_rebate.unwrap_or(0) > 0 || boosted {
+        return Err("Trades that earned a rebate cannot be reverted".to_string());
+    }
+    // Withdrawals are limited to collected fees, so these may already be gone
+    let charged = trade.fee + trade.dust;
+    let penalty = revert_penalty(trade.amount);
+    let collected = FEES_COLLECTED.with(|collected| *collected.borrow());
+    let withdrawn = FEES_WITHDRAWN.with(|withdrawn| *withdrawn.borrow());
+    if collected.saturating_sub(withdrawn) + penalty < charged {
+        return Err("The fees from this trade have already been withdrawn".to_string());
+    }
+    let key = (caller, trade.market_id, now / to_nanos(SECONDS_PER_DAY));
+    if TRADE_REVERTS.with(|reverts| reverts.borrow().contains(&key)) {
+        return Err("Only one trade per market can be reverted each day".to_string());
+    }
+
+    MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&trade.market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+        if !matches!(market.status, MarketStatus::Active) {
+            return Err("Market is not active".to_string());
+        }
+        let grace = CLOSE_GRACE_SECONDS.with(|grace| *grace.borrow());
+        if now >= market.close_date.saturating_add(to_nanos(grace)) {
+            return Err("Market is closed".to_string());
+        }
+        if market.market_kind == MarketKind::Flash
+            && now.saturating_add(to_nanos(FLASH_REVERT_CUTOFF_SECONDS)) >= market.close_date
+        {
+            return Err("Flash markets take no reverts this close to their close".to_string());
+        }
+
+        // Checked on a copy so a failure leaves the market untouched
+        let mut reverted = market.clone();
+        let outcome = engine::BuyOutcome {
+            price: trade.price,
+            shares: trade.shares,
+            fee: trade.fee,
+            dust: trade.dust,
+        };
+        engine::revert_buy(&mut reverted, trade.is_yes, trade.amount, &outcome)?;
+        let mut position = POSITIONS
+            .with(|positions| positions.borrow().get(&(caller, trade.market_id)).cloned())
+            .ok_or_else(|| "The shares from this trade are no longer held".to_string())?;
+        engine::revert_position_buy(&mut position, trade.is_yes, trade.shares, trade.amount)?;
+        uncollect_trade_fees(&trade, penalty, now)?;
+        POSITIONS.with(|positions| {
+            positions
+                .borrow_mut()
+                .insert((caller, trade.market_id), position)
+        });
+        *market = reverted;
+        Ok(())
+    })?;
+
+    drop_trade_snapshots(&trade);
+    FLOW_BUCKETS.with(|buckets| {
+        let mut buckets = buckets.borrow_mut();
+        let hour = flow_hour(trade.timestamp);
+        if let Some(bucket) = buckets
+            .get_mut(&trade.market_id)
+            .and_then(|b| b.iter_mut().find(|bucket| bucket.hour == hour))
+        {
+            if trade.is_yes {
+                bucket.yes_volume = bucket.yes_volume.saturating_sub(trade.amount);
+            } else {
+                bucket.no_volume = bucket.no_volume.saturating_sub(trade.amount);
+            }
+        }
+    });
+    revoke_trade_xp(caller, trade_id);
+    return_boost_xp_draws(trade.market_id, trade_id);
+    limits::release_spend(caller, trade.amount, trade.timestamp);
+    credit_balance(
+        caller,
+        trade.amount - penalty,
+        BalanceReason::TradeRevert,
+        trade_id,
+    );
+    TRADE_REVERTS.with(|reverts| reverts.borrow_mut().insert(key));
+
+    let reverted = TRADES.with(|trades| {
+        let mut trades = trades.borrow_mut();
+        let trade = trades
+            .iter_mut()
+            .find(|t| t.id == trade_id)
+            .expect("trade was found above");
+        trade.reverted_at = Some(now);
+        trade.clone()
+    });
+    refresh_market_card(trade.market_id);
+    refresh_insight_on_move(trade.market_id);
+    Ok(reverted)
+}
+
+fn revert_penalty(amount: u64) -> u64 {
+    (amount as u128 * REVERT_PENALTY_BPS as u128).div_ceil(10_000) as u64
+}
+
+// Takes a reverted trade's fee and dust back out of the treasury and its
+// revenue records, as if they had never been charged, and collects the revert
+// penalty instead. Nothing changes if the treasury no longer holds them.
+fn uncollect_trade_fees(trade: &Trade, penalty: u64, now: u64) -> Result<(), String> {
+    let charged = trade.fee + trade.dust;
+    let gone = || "The fees from this trade are no longer in the treasury".to_string();
+    let treasury = TREASURY
+        .with(|treasury| (*treasury.borrow()).checked_add(penalty))
+        .and_then(|treasury| treasury.checked_sub(charged))
+        .ok_or_else(gone)?;
+    let collected = FEES_COLLECTED
+        .with(|collected| (*collected.borrow()).checked_add(penalty))
+        .and_then(|collected| collected.checked_sub(charged))
+        .ok_or_else(gone)?;
+    let dust = DUST_COLLECTED
+        .with(|dust| (*dust.borrow()).checked_sub(trade.dust))
+        .ok_or_else(gone)?;
+
+    TREASURY.with(|stored| *stored.borrow_mut() = treasury);
+    record_treasury_inflow(penalty, trade.market_id, now);
+    FEES_COLLECTED.with(|stored| *stored.borrow_mut() = collected);
+    DUST_COLLECTED.with(|stored| *stored.borrow_mut() = dust);
+    if charged > 0 {
+        TREASURY_INFLOWS.with(|inflows| {
+            let mut inflows = inflows.borrow_mut();
+            if let Some(index) = inflows.iter().rposition(|inflow| {
+                inflow.market_id == trade.market_id
+                    && inflow.timestamp == trade.timestamp
+                    && inflow.amount == charged
+            }) {
+                inflows.remove(index);
+            }
+        });
+    }
+    Ok(())
+}
+
+// The trade was the market's latest, so its snapshots are the newest ones
+fn drop_trade_snapshots(trade: &Trade) {
+    PRICE_HISTORY.with(|history| {
+        if let Some(snapshots) = history.borrow_mut().get_mut(&trade.market_id) {
+            if snapshots
+                .back()
+                .is_some_and(|s| s.timestamp == trade.timestamp)
+            {
+                snapshots.pop_back();
+            }
+        }
+    });
+    LIQUIDITY_HISTORY.with(|history| {
+        if let Some(snapshots) = history.borrow_mut().get_mut(&trade.market_id) {
+            if snapshots
+                .last()
+                .is_some_and(|s| s.timestamp == trade.timestamp)
+            {
+                snapshots.pop();
+            }
+        }
+    });
+}
+
+fn revoke_trade_xp(principal: Principal, trade_id: u64) {
+    let is_trade_xp = |entry: &XpEntry| {
+        entry.principal == principal
+            && entry.source == XpSource::Trade
+            && entry.reference == trade_id
+    };
+    let revoked: Vec<XpEntry> = XP_ENTRIES.with(|entries| {
+        let mut entries = entries.borrow_mut();
+        let revoked = entries.iter().filter(|e| is_trade_xp(e)).cloned().collect();
+        entries.retain(|e| !is_trade_xp(e));
+        revoked
+    });
+
+    let mut total = 0u64;
+    XP_DAILY.with(|daily| {
+        let mut daily = daily.borrow_mut();
+        for entry in &revoked {
+            let day = entry.timestamp / to_nanos(SECONDS_PER_DAY);
+            if let Some(granted) = daily.get_mut(&(principal, XpSource::Trade, day)) {
+                *granted = granted.saturating_sub(entry.amount);
+            }
+            total = total.saturating_add(entry.amount);
+        }
+    });
+    USER_PROFILES.with(|profiles| {
+        if let Some(profile) = profiles.borrow_mut().get_mut(&principal) {
+            profile.xp = profile.xp.saturating_sub(total);
+            profile.total_trades = profile.total_trades.saturating_sub(1);
+        }
+    });
+}
+
+// Whether the principal has traded any market in this market's category
+fn traded_in_category(principal: Principal, market_id: u64) -> bool {
+    MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        let Some(category) = markets.get(&market_id).map(|m| &m.category) else {
+            return false;
+        };
+        TRADES.with(|trades| {
+            trades.borrow().iter().any(|trade| {
+                trade.trader == principal
+                    && markets
+                        .get(&trade.market_id)
+                        .is_some_and(|m| &m.category == category)
+            })
+        })
+    })
+}
+
+// Boost functions
+#[ic_cdk::update]
+fn sponsor_market(market_id: u64, amount: u64, boost: BoostKind) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot sponsor markets".to_string());
+    }
+    if amount == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+
+    match boost {
+        BoostKind::VolumeRebate { rebate_bps } if rebate_bps == 0 || rebate_bps > 10_000 => {
+            return Err("Rebate must be between 1 and 10000 bps".to_string());
+        }
+        BoostKind::XpMultiplier { multiplier_bps }
+            if multiplier_bps <= 10_000 || multiplier_bps > MAX_BOOST_XP_MULTIPLIER_BPS =>
+        {
+            return Err(format!(
+                "XP multiplier must be above 10000 and at most {} bps",
+                MAX_BOOST_XP_MULTIPLIER_BPS
+            ));
+        }
+        _ => {}
+    }
+
+    MARKETS.with(|markets| match markets.borrow().get(&market_id) {
+        None => Err("Market not found".to_string()),
+        Some(market)
+            if !matches!(
+                market.status,
+                MarketStatus::PendingValidation | MarketStatus::Active
+            ) =>
+        {
+            Err("Only pending or active markets can be sponsored".to_string())
+        }
+        Some(_) => Ok(()),
+    })?;
+
+    debit_balance(caller, amount, BalanceReason::BoostFunding, market_id)?;
+
+    let boost_id = NEXT_BOOST_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    MARKET_BOOSTS.with(|boosts| {
+        boosts
+            .borrow_mut()
+            .entry(market_id)
+            .or_default()
+            .push(MarketBoost {
+                id: boost_id,
+                market_id,
+                sponsor: caller,
+                kind: boost,
+                funded: amount,
+                remaining: amount,
+                created_at: ic_cdk::api::time(),
+            });
+    });
+
+    record_event(market_id, MarketEventKind::Sponsored, caller);
+
+    Ok(boost_id)
+}
+
+#[ic_cdk::query]
+fn get_market_boosts(market_id: u64) -> Vec<MarketBoost> {
+    MARKET_BOOSTS.with(|boosts| boosts.borrow().get(&market_id).cloned().unwrap_or_default())
+}
+
+// Draws the payout computed by `payout` from each of the market's boost pools,
+// capped by what remains, and returns what came from each as (boost id, amount).
+// Sponsors never receive payouts from their own boosts.
+fn draw_boost(
+    market_id: u64,
+    recipient: Principal,
+    payout: impl Fn(&BoostKind) -> u64,
+) -> Vec<(u64, u64)> {
+    MARKET_BOOSTS.with(|boosts| {
+        let mut boosts = boosts.borrow_mut();
+        let Some(market_boosts) = boosts.get_mut(&market_id) else {
+            return Vec::new();
+        };
+
+        let mut draws = Vec::new();
+        for boost in market_boosts.iter_mut() {
+            if boost.sponsor == recipient {
+                continue;
+            }
+            let drawn = payout(&boost.kind).min(boost.remaining);
+            if drawn > 0 {
+                boost.remaining -= drawn;
+                draws.push((boost.id, drawn));
+            }
+        }
+        draws
+    })
+}
+
+fn drawn_total(draws: &[(u64, u64)]) -> u64 {
+    draws
+        .iter()
+        .fold(0u64, |total, (_, drawn)| total.saturating_add(*drawn))
+}
+
+// Keeps a trade's bonus XP draws while the trade can still be reverted
+fn record_boost_xp_draws(trade_id: u64, timestamp: u64, draws: Vec<(u64, u64)>) {
+    BOOST_XP_DRAWS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        let window = to_nanos(REVERT_WINDOW_SECONDS);
+        stored.retain(|_, (drawn_at, _)| timestamp.saturating_sub(*drawn_at) <= window);
+        if !draws.is_empty() {
+            stored.insert(trade_id, (timestamp, draws));
+        }
+    });
+}
+
+// Puts a reverted trade's bonus XP back into the boost pools it was drawn from
+fn return_boost_xp_draws(market_id: u64, trade_id: u64) {
+    let Some((_, draws)) = BOOST_XP_DRAWS.with(|stored| stored.borrow_mut().remove(&trade_id))
+    else {
+        return;
+    };
+    MARKET_BOOSTS.with(|boosts| {
+        let mut boosts = boosts.borrow_mut();
+        let Some(market_boosts) = boosts.get_mut(&market_id) else {
+            return;
+        };
+        for (boost_id, drawn) in draws {
+            if let Some(boost) = market_boosts.iter_mut().find(|b| b.id == boost_id) {
+                boost.remaining = boost.remaining.saturating_add(drawn).min(boost.funded);
+            }
+        }
+    });
+}
+
+// Maker incentive functions
+#[ic_cdk::query]
+fn get_incentives(market_id: u64) -> Option<IncentiveInfo> {
+    incentives::info(market_id, ic_cdk::caller(), ic_cdk::api::time())
+}
+
+#[ic_cdk::update]
+fn enable_incentives(market_id: u64, config: IncentiveConfig) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage incentive programs".to_string());
+    }
+    MARKETS.with(|markets| match markets.borrow().get(&market_id) {
+        None => Err("Market not found".to_string()),
+        Some(market) if !matches!(market.status, MarketStatus::Active) => {
+            Err("Incentives can only run on active markets".to_string())
+        }
+        Some(_) => Ok(()),
+    })?;
+
+    incentives::enable(market_id, config)
+}
+
+// Moves treasury funds into the pool, keeping the treasury above its floor
+#[ic_cdk::update]
+fn fund_incentives(market_id: u64, amount: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage incentive programs".to_string());
+    }
+    if amount == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+    incentives::check_funding(market_id, amount)?;
+
+    TREASURY.with(|treasury| {
+        let mut treasury = treasury.borrow_mut();
+        let floor = TREASURY_FLOOR.with(|floor| *floor.borrow());
+        if treasury
+            .checked_sub(amount)
+            .is_none_or(|remaining| remaining < floor)
+        {
+            return Err("Would breach treasury reserve floor".to_string());
+        }
+        *treasury -= amount;
+        Ok(())
+    })?;
+    incentives::fund(market_id, None, amount);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn sponsor_incentives(market_id: u64, amount: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot sponsor markets".to_string());
+    }
+    if amount == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+    incentives::check_funding(market_id, amount)?;
+
+    debit_balance(caller, amount, BalanceReason::IncentiveFunding, market_id)?;
+    incentives::fund(market_id, Some(caller), amount);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn end_incentives(market_id: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage incentive programs".to_string());
+    }
+    if incentives::info(market_id, Principal::anonymous(), 0).is_none() {
+        return Err("No incentive program for this market".to_string());
+    }
+
+    refund_incentives(market_id);
+    Ok(())
+}
+
+// Ends the market's program and returns the unspent pool to its funders
+fn refund_incentives(market_id: u64) {
+    for (funder, amount) in incentives::close(market_id) {
+        match funder {
+            Some(sponsor) => credit_balance(sponsor, amount, BalanceReason::Refund, market_id),
+            None => TREASURY.with(|treasury| *treasury.borrow_mut() += amount),
+        }
+    }
+}
+
+// Splits ResolutionBonus pools among correct predictors pro rata to their
+// winning-side volume, then refunds whatever is left in every pool to its sponsor
+fn settle_boosts(market_id: u64, winners: &HashMap<Principal, u64>) {
+    let Some(market_boosts) = MARKET_BOOSTS.with(|boosts| boosts.borrow_mut().remove(&market_id))
+    else {
+        return;
+    };
+
+    let mut credits: Vec<(Principal, u64, BalanceReason)> = vec![];
+    let mut settled = vec![];
+
+    for mut boost in market_boosts {
+        if matches!(boost.kind, BoostKind::ResolutionBonus) {
+            let eligible: Vec<(&Principal, &u64)> = winners
+                .iter()
+                .filter(|(winner, _)| **winner != boost.sponsor)
+                .collect();
+            let total_shares: u128 = eligible.iter().map(|(_, shares)| **shares as u128).sum();
+            let pool = boost.remaining as u128;
+            for (winner, shares) in eligible {
+                if let Some(share) = (pool * *shares as u128).checked_div(total_shares) {
+                    boost.remaining -= share as u64;
+                    credits.push((*winner, share as u64, BalanceReason::BoostPayout));
+                }
+            }
+        }
+
+        credits.push((boost.sponsor, boost.remaining, BalanceReason::Refund));
+        boost.remaining = 0;
+        settled.push(boost);
+    }
+
+    for (principal, amount, reason) in credits.into_iter().filter(|(_, amount, _)| *amount > 0) {
+        let kind = PayoutKind::BalanceCredit {
+            amount,
+            reason: Some(reason),
+        };
+        enqueue_payout(market_id, principal, kind);
+    }
+
+    MARKET_BOOSTS.with(|boosts| boosts.borrow_mut().insert(market_id, settled));
+}
+
+// Payout queue
+fn enqueue_payout(market_id: u64, recipient: Principal, kind: PayoutKind) {
+    let id = NEXT_PAYOUT_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    PAYOUT_QUEUE.with(|queue| {
+        queue.borrow_mut().push_back(PayoutItem {
+            id,
+            market_id,
+            recipient,
+            kind,
+            enqueued_at: ic_cdk::api::time(),
+        });
+    });
+}
+
+// Applies up to `limit` queued payouts. Each item leaves the queue in the same
+// message that applies it, so it is either pending or paid, never both.
+fn drain_payouts(limit: usize) {
+    for _ in 0..limit {
+        let Some(item) = PAYOUT_QUEUE.with(|queue| queue.borrow_mut().pop_front()) else {
+            return;
+        };
+
+        match item.kind {
+            PayoutKind::SuccessfulPrediction => USER_PROFILES.with(|profiles| {
+                if let Some(profile) = profiles.borrow_mut().get_mut(&item.recipient) {
+                    profile.successful_predictions += 1;
+                    award_badges(profile);
+                }
+            }),
+            PayoutKind::BalanceCredit { amount, reason } => {
+                let reason = reason.unwrap_or(BalanceReason::BoostPayout);
+                credit_balance(item.recipient, amount, reason, item.market_id);
+            }
+        }
+
+        PAYOUTS_COMPLETED.with(|count| *count.borrow_mut() += 1);
+    }
+}
+
+#[ic_cdk::query]
+fn get_payout_queue_status() -> PayoutQueueStatus {
+    PayoutQueueStatus {
+        pending: PAYOUT_QUEUE.with(|queue| queue.borrow().len() as u64),
+        completed: PAYOUTS_COMPLETED.with(|count| *count.borrow()),
+    }
+}
+
+// Claim functions
+fn user_positions(owner: Principal) -> Vec<Position> {
+    POSITIONS.with(|positions| {
+        positions
+            .borrow()
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|(_, position)| position.clone())
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_my_positions() -> Vec<Position> {
+    user_positions(ic_cdk::caller())
+}
+
+#[ic_cdk::query]
+fn get_my_portfolio() -> Vec<PositionValue> {
+    let caller = ic_cdk::caller();
+    let positions = user_positions(caller);
+    let mut fees: HashMap<u64, u64> = HashMap::new();
+    TRADES.with(|trades| {
+        for trade in trades.borrow().iter().filter(|t| t.trader == caller) {
+            *fees.entry(trade.market_id).or_default() += trade.fee;
+        }
+    });
+    MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        positions
+            .into_iter()
+            .filter_map(|position| {
+                let market = markets.get(&position.market_id)?;
+                Some(PositionValue {
+                    yes_pool_share_bps: engine::pool_share_bps(
+                        position.yes_shares,
+                        market.yes_shares,
+                    ),
+                    no_pool_share_bps: engine::pool_share_bps(position.no_shares, market.no_shares),
+                    payout_if_yes: engine::payout_if(market, &position, Outcome::Yes),
+                    payout_if_no: engine::payout_if(market, &position, Outcome::No),
+                    fees_paid: fees.get(&position.market_id).copied().unwrap_or(0),
+                    position,
+                })
+            })
+            .collect()
+    })
+}
+
+// Trading fees the principal has paid, from the fee recorded on each trade
+#[ic_cdk::query]
+fn get_fees_paid(principal: Principal) -> u64 {
+    if !reads_allowed() {
+        return 0;
+    }
+
+    TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|trade| trade.trader == principal)
+            .map(|trade| trade.fee)
+            .sum()
+    })
+}
+
+#[ic_cdk::update]
+fn transfer_shares(market_id: u64, to: Principal, is_yes: bool, amount: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    check_access(&caller)?;
+
+    if to == Principal::anonymous() {
+        return Err("Cannot transfer shares to the anonymous principal".to_string());
+    }
+    if to == caller {
+        return Ok(());
+    }
+    if !has_access(&to) {
+        return Err("The recipient has not been given access yet".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+    let market = MARKETS
+        .with(|markets| markets.borrow().get(&market_id).cloned())
+        .ok_or_else(|| "Market not found".to_string())?;
+    let grace = CLOSE_GRACE_SECONDS.with(|grace| *grace.borrow());
+    if !matches!(market.status, MarketStatus::Active)
+        || now >= market.close_date.saturating_add(to_nanos(grace))
+    {
+        return Err("Shares can only be transferred while the market is open".to_string());
+    }
+
+    // Caps wash transfers aimed at badge and XP criteria
+    let day_ago = now.saturating_sub(to_nanos(SECONDS_PER_DAY));
+    let sent_today = SHARE_TRANSFERS.with(|transfers| {
+        transfers
+            .borrow()
+            .iter()
+            .filter(|t| t.from == caller && t.timestamp > day_ago)
+            .count()
+    });
+    if sent_today >= MAX_SHARE_TRANSFERS_PER_DAY {
+        return Err(format!(
+            "Daily transfer limit of {} reached",
+            MAX_SHARE_TRANSFERS_PER_DAY
+        ));
+    }
+
+    let cost_basis = POSITIONS.with(|positions| {
+        let mut positions = positions.borrow_mut();
+        let mut sender = positions
+            .get(&(caller, market_id))
+            .cloned()
+            .ok_or_else(|| "No position in this market".to_string())?;
+        let mut recipient = positions
+            .get(&(to, market_id))
+            .cloned()
+            .unwrap_or(Position {
+                market_id,
+                owner: to,
+                yes_shares: 0,
+                no_shares: 0,
+                yes_cost: 0,
+                no_cost: 0,
+                claimed: false,
+            });
+
+        let cost_basis =
+            engine::transfer_position_shares(&mut sender, &mut recipient, is_yes, amount)?;
+        let held = if is_yes {
+            recipient.yes_shares
+        } else {
+            recipient.no_shares
+        };
+        let cap = position_cap(market_id);
+        if cap > 0 && held > cap {
+            return Err("The recipient's position limit would be exceeded".to_string());
+        }
+        positions.insert((caller, market_id), sender);
+        positions.insert((to, market_id), recipient);
+        Ok::<_, String>(cost_basis)
+    })?;
+
+    let id = NEXT_TRANSFER_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+    SHARE_TRANSFERS.with(|transfers| {
+        transfers.borrow_mut().push(TransferRecord {
+            id,
+            market_id,
+            from: caller,
+            to,
+            is_yes,
+            shares: amount,
+            cost_basis,
+            timestamp: now,
+        });
+    });
+
+    Ok(())
+}
+
+// Transfers the caller sent or received, oldest first
+#[ic_cdk::query]
+fn get_my_share_transfers() -> Vec<TransferRecord> {
+    let caller = ic_cdk::caller();
+    SHARE_TRANSFERS.with(|transfers| {
+        transfers
+            .borrow()
+            .iter()
+            .filter(|t| t.from == caller || t.to == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+fn settle_claim(owner: Principal, market_id: u64) -> Result<u64, String> {
+    let market = MARKETS
+        .with(|markets| markets.borrow().get(&market_id).cloned())
+        .ok_or_else(|| "Market not found".to_string())?;
+
+    let payout = POSITIONS.with(|positions| {
+        let mut positions = positions.borrow_mut();
+        let position = positions
+            .get_mut(&(owner, market_id))
+            .ok_or_else(|| "No position in this market".to_string())?;
+        engine::settle_position(&market, position)
+    })?;
+
+    credit_balance(owner, payout, BalanceReason::WinningsCredit, market_id);
+
+    Ok(payout)
+}
+
+#[ic_cdk::update]
+fn claim_winnings(market_id: u64) -> Result<u64, String> {
+    settle_claim(ic_cdk::caller(), market_id)
+}
+
+// Settles up to `limit` resolved, unclaimed winning positions in one call.
+// Failures are reported per market without aborting the rest; callers loop
+// while `remaining` is non-zero.
+#[ic_cdk::update]
+fn claim_all_winnings(limit: u32) -> ClaimAllResult {
+    let caller = ic_cdk::caller();
+    let limit = limit.min(MAX_CLAIMS_PER_CALL) as usize;
+
+    let claimable: Vec<u64> = MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        user_positions(caller)
+            .into_iter()
+            .filter(|position| !position.claimed)
+            .filter(|position| {
+                markets.get(&position.market_id).is_some_and(|market| {
+                    matches!(market.status, MarketStatus::Resolved)
+                        && engine::winnings_for(market, position) > 0
+                })
+            })
+            .map(|position| position.market_id)
+            .collect()
+    });
+
+    let mut total_claimed = 0u64;
+    let results: Vec<ClaimResult> = claimable
+        .iter()
+        .take(limit)
+        .map(|market_id| {
+            let result = settle_claim(caller, *market_id);
+            if let Ok(payout) = result {
+                total_claimed = total_claimed.saturating_add(payout);
+            }
+            ClaimResult {
+                market_id: *market_id,
+                result,
+            }
+        })
+        .collect();
+
+    ClaimAllResult {
+        remaining: claimable.len().saturating_sub(results.len()) as u32,
+        results,
+        total_claimed,
+    }
+}
+
+fn validate_resolution_criteria(criteria: &ResolutionCriteria) -> Result<(), String> {
+    if criteria.statements.is_empty() || criteria.statements.len() > MAX_RESOLUTION_CRITERIA {
+        return Err(format!(
+            "Markets can define between 1 and {} resolution criteria",
+            MAX_RESOLUTION_CRITERIA
+        ));
+    }
+    if criteria
+        .statements
+        .iter()
+        .any(|s| s.trim().is_empty() || s.chars().count() > MAX_CRITERION_LEN)
+    {
+        return Err(format!(
+            "Each criterion must be between 1 and {} characters",
+            MAX_CRITERION_LEN
+        ));
+    }
+    if criteria.verdicts.is_some() {
+        return Err("Verdicts are given at resolution, not creation".to_string());
+    }
+    Ok(())
+}
+
+// The policy for user-supplied links: https only, bounded length, no
+// whitespace or control characters
+fn is_valid_https_url(url: &str) -> bool {
+    !url.strip_prefix("https://").unwrap_or_default().is_empty()
+        && url.chars().count() <= MAX_SOURCE_URL_LEN
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+// Lowercased host of a link that passes the URL policy. Links that put
+// credentials before the host are refused, since they disguise where they go.
+fn link_host(url: &str) -> Option<String> {
+    if !is_valid_https_url(url) {
+        return None;
+    }
+    let authority = url["https://".len()..]
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    if authority.contains('@') {
+        return None;
+    }
+    let host = authority.split(':').next().unwrap_or_default();
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+fn validate_structured_fields(options: &MarketOptions, close_date: u64) -> Result<(), String> {
+    if options
+        .resolution_datetime
+        .is_some_and(|deadline| deadline < close_date)
+    {
+        return Err("Resolution date cannot be before the close date".to_string());
+    }
+    if let Some(url) = &options.primary_source_url {
+        if !is_valid_https_url(url) {
+            return Err(format!(
+                "Source URL must be an https URL of at most {} characters",
+                MAX_SOURCE_URL_LEN
+            ));
+        }
+    }
+    if let Some((metric, value)) = &options.numeric_target {
+        if metric.trim().is_empty() || metric.chars().count() > MAX_TARGET_METRIC_LEN {
+            return Err(format!(
+                "Target metric must be between 1 and {} characters",
+                MAX_TARGET_METRIC_LEN
+            ));
+        }
+        if *value == 0 || *value > MAX_NUMERIC_TARGET {
+            return Err(format!(
+                "Target value must be between 1 and {}",
+                MAX_NUMERIC_TARGET
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Delegates resolution to an oracle principal; None revokes the delegation
+#[ic_cdk::update]
+fn set_market_resolver(market_id: u64, resolver: Option<Principal>) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        if !is_creator(market, caller) && !is_admin(&caller) {
+            return Err("Only the creator or an admin can set the resolver".to_string());
+        }
+        if market.resolved_outcome.is_some() {
+            return Err("Market is already resolved".to_string());
+        }
+
+        market.resolver = resolver;
+        Ok(())
+    })
+}
+
+// The anonymous principal never counts as a creator, whatever a market records
+fn is_creator(market: &Market, principal: Principal) -> bool {
+    principal != Principal::anonymous() && market.creator == principal
+}
+
+fn is_market_owner(market: &Market, principal: Principal) -> bool {
+    is_creator(market, principal) || market.co_owners.contains(&principal)
+}
+
+// Co-owners only resolve through an explicit delegation from the creator
+fn may_resolve(market: &Market, principal: Principal) -> bool {
+    if principal == Principal::anonymous() {
+        return false;
+    }
+    is_creator(market, principal)
+        || market.resolver == Some(principal)
+        || market.resolution_delegate == Some(principal)
+        || is_admin(&principal)
+}
+
+fn validate_co_owners(creator: &Principal, co_owners: &[Principal]) -> Result<(), String> {
+    if co_owners.len() > MAX_CO_OWNERS {
+        return Err(format!(
+            "A market can have at most {} co-owners",
+            MAX_CO_OWNERS
+        ));
+    }
+    for (i, co_owner) in co_owners.iter().enumerate() {
+        if co_owner == creator || *co_owner == Principal::anonymous() {
+            return Err("Co-owners must be other, non-anonymous principals".to_string());
+        }
+        if co_owners[..i].contains(co_owner) {
+            return Err("Co-owners must be distinct".to_string());
+        }
+    }
+    Ok(())
+}
+
+// Runs a creator-only ownership change on a pending or active market and logs it
+fn change_ownership(
+    market_id: u64,
+    change: impl FnOnce(&mut Market) -> Result<MarketEventKind, String>,
+) -> Result<Market, String> {
+    let caller = ic_cdk::caller();
+    let (market, event) = MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        if !is_creator(market, caller) {
+            return Err("Only the market creator can change its owners".to_string());
+        }
+        if !matches!(
+            market.status,
+            MarketStatus::PendingValidation | MarketStatus::Active
+        ) {
+            return Err("Owners can only change while the market is pending or active".to_string());
+        }
+
+        let event = change(market)?;
+        Ok((market.clone(), event))
+    })?;
+
+    record_event(market_id, event, caller);
+    Ok(market)
+}
+
+#[ic_cdk::update]
+fn add_co_owner(market_id: u64, co_owner: Principal) -> Result<Market, String> {
+    change_ownership(market_id, |market| {
+        let mut co_owners = market.co_owners.clone();
+        co_owners.push(co_owner);
+        validate_co_owners(&market.creator, &co_owners)?;
+        market.co_owners = co_owners;
+        Ok(MarketEventKind::CoOwnerAdded { co_owner })
+    })
+}
+
+// Also withdraws a resolution delegation held by the removed co-owner
+#[ic_cdk::update]
+fn remove_co_owner(market_id: u64, co_owner: Principal) -> Result<Market, String> {
+    change_ownership(market_id, |market| {
+        if !market.co_owners.contains(&co_owner) {
+            return Err("Not a co-owner of this market".to_string());
+        }
+        market.co_owners.retain(|p| *p != co_owner);
+        if market.resolution_delegate == Some(co_owner) {
+            market.resolution_delegate = None;
+        }
+        Ok(MarketEventKind::CoOwnerRemoved { co_owner })
+    })
+}
+
+// Lets a co-owner resolve the market; None takes resolution back
+#[ic_cdk::update]
+fn delegate_resolution(market_id: u64, to: Option<Principal>) -> Result<Market, String> {
+    change_ownership(market_id, |market| {
+        if let Some(delegate) = to {
+            if !market.co_owners.contains(&delegate) {
+                return Err("Resolution can only be delegated to a co-owner".to_string());
+            }
+        }
+        market.resolution_delegate = to;
+        Ok(MarketEventKind::ResolutionDelegated { to })
+    })
+}
+
+fn ownership_history(market_id: u64) -> Vec<MarketEvent> {
+    MARKET_EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .filter(|e| {
+                e.market_id == market_id
+                    && matches!(
+                        e.kind,
+                        MarketEventKind::CoOwnerAdded { .. }
+                            | MarketEventKind::CoOwnerRemoved { .. }
+                            | MarketEventKind::ResolutionDelegated { .. }
+                    )
+            })
+            .cloned()
+            .collect()
+    })
+}
+
+// Resolver registry functions
+#[ic_cdk::update]
+fn register_resolver(
+    stake: u64,
+    fee: u64,
+    categories: Vec<String>,
+) -> Result<ResolverProfile, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot register as resolvers".to_string());
+    }
+    check_access(&caller)?;
+    resolvers::check_registration(caller, stake, fee, &categories)?;
+
+    if stake > 0 {
+        debit_balance(caller, stake, BalanceReason::ResolverStake, 0)?;
+    }
+    Ok(resolvers::register(
+        caller,
+        stake,
+        fee,
+        &categories,
+        ic_cdk::api::time(),
+    ))
+}
+
+#[ic_cdk::query]
+fn list_resolvers(category: Option<String>) -> Vec<ResolverProfile> {
+    resolvers::list(category.as_deref())
+}
+
+#[ic_cdk::query]
+fn get_resolver(principal: Principal) -> Option<ResolverProfile> {
+    resolvers::get(principal)
+}
+
+// Only once no open market names the caller as resolver and no fee or
+// dispute is still pending
+#[ic_cdk::update]
+fn withdraw_resolver_stake() -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    let assigned = MARKETS.with(|markets| {
+        markets.borrow().values().any(|market| {
+            market.resolver == Some(caller)
+                && matches!(
+                    market.status,
+                    MarketStatus::PendingValidation | MarketStatus::Active | MarketStatus::Closed
+                )
+        })
+    });
+    if assigned || resolvers::has_open_assignment(caller) {
+        return Err("Resolver has pending assignments".to_string());
+    }
+
+    let stake = resolvers::withdraw(caller)?;
+    credit_balance(caller, stake, BalanceReason::ResolverStake, 0);
+    Ok(stake)
+}
+
+#[ic_cdk::query]
+fn get_resolver_assignment(market_id: u64) -> Option<ResolverAssignment> {
+    resolvers::assignment(market_id)
+}
+
+// Holders may dispute a listed resolver's outcome inside the dispute window
+#[ic_cdk::update]
+fn dispute_resolution(market_id: u64, reason: String) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let holds = POSITIONS.with(|positions| positions.borrow().contains_key(&(caller, market_id)));
+    if !holds {
+        return Err("Only traders in this market can dispute its resolution".to_string());
+    }
+
+    resolvers::open_dispute(market_id, caller, &reason, ic_cdk::api::time())
+}
+
+// Admin verdict on a dispute. The outcome itself is not reversed here.
+#[ic_cdk::update]
+fn settle_resolution_dispute(market_id: u64, upheld: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can settle disputes".to_string());
+    }
+
+    match resolvers::settle_dispute(market_id, upheld)? {
+        DisputeSettlement::PayFee { resolver, fee } => pay_resolver_fee(market_id, resolver, fee),
+        DisputeSettlement::Slash {
+            disputer,
+            to_disputer,
+            to_treasury,
+        } => {
+            credit_balance(
+                disputer,
+                to_disputer,
+                BalanceReason::ResolverSlash,
+                market_id,
+            );
+            TREASURY.with(|treasury| *treasury.borrow_mut() += to_treasury);
+        }
+    }
+    Ok(())
+}
+
+// Paid out of the treasury, but never more than the market's own trading fees
+fn pay_resolver_fee(market_id: u64, resolver: Principal, fee: u64) {
+    let market_fees: u64 = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|trade| trade.market_id == market_id && trade.reverted_at.is_none())
+            .map(|trade| trade.fee)
+            .sum()
+    });
+    let paid = TREASURY.with(|treasury| {
+        let mut treasury = treasury.borrow_mut();
+        let paid = fee.min(market_fees).min(*treasury);
+        *treasury -= paid;
+        paid
+    });
+    if paid > 0 {
+        credit_balance(resolver, paid, BalanceReason::ResolverFee, market_id);
+        resolvers::record_fee_paid(resolver, paid);
+    }
+}
+
+// Only the listed resolver assigned to the market is rewarded, as only their
+// outcome can be disputed; creators and admins resolving as operators are not.
+// The reward and the resolver's fee together stay within the market's fees.
+fn queue_resolution_reward(market: &Market, resolver: Principal) {
+    let market_id = market.id;
+    let reward_bps = RESOLUTION_REWARD_BPS.with(|bps| *bps.borrow());
+    if reward_bps == 0 || is_creator(market, resolver) || is_admin(&resolver) {
+        return;
+    }
+    let Some(assignment) =
+        resolvers::assignment(market_id).filter(|assignment| assignment.resolver == resolver)
+    else {
+        return;
+    };
+    let fees: u128 = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|trade| trade.market_id == market_id && trade.reverted_at.is_none())
+            .map(|trade| trade.fee as u128)
+            .sum()
+    });
+    let amount = (fees * reward_bps as u128 / 10_000)
+        .min(fees.saturating_sub(assignment.fee as u128)) as u64;
+    if amount == 0 {
+        return;
+    }
+
+    PENDING_RESOLUTION_REWARDS.with(|pending| {
+        pending.borrow_mut().push(PendingResolutionReward {
+            market_id,
+            resolver,
+            amount,
+            due_at: ic_cdk::api::time().saturating_add(resolvers::DISPUTE_WINDOW_NANOS),
+        })
+    });
+}
+
+// Pays rewards whose dispute window has passed. A reward waits while its
+// resolution is disputed and is dropped if the dispute is upheld or the
+// market is no longer resolved.
+fn pay_resolution_rewards(now: u64) {
+    let due: Vec<PendingResolutionReward> = PENDING_RESOLUTION_REWARDS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let (due, waiting): (Vec<_>, Vec<_>) = pending.drain(..).partition(|reward| {
+            reward.due_at <= now
+                && resolvers::assignment(reward.market_id).is_none_or(|assignment| {
+                    assignment.status != resolvers::AssignmentStatus::Disputed
+                })
+        });
+        *pending = waiting;
+        due
+    });
+
+    for reward in due {
+        let upheld = resolvers::assignment(reward.market_id)
+            .is_some_and(|assignment| assignment.status == resolvers::AssignmentStatus::Slashed);
+        let resolved = MARKETS.with(|markets| {
+            markets
+                .borrow()
+                .get(&reward.market_id)
+                .is_some_and(|market| matches!(market.status, MarketStatus::Resolved))
+        });
+        if upheld || !resolved {
+            continue;
+        }
+
+        let paid = TREASURY.with(|treasury| {
+            let mut treasury = treasury.borrow_mut();
+            let paid = reward.amount.min(*treasury);
+            *treasury -= paid;
+            paid
+        });
+        if paid > 0 {
+            credit_balance(
+                reward.resolver,
+                paid,
+                BalanceReason::ResolutionReward,
+                reward.market_id,
+            );
+            RESOLUTION_REWARDS_EARNED.with(|earned| {
+                *earned.borrow_mut().entry(reward.resolver).or_default() += paid;
+            });
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn get_resolution_rewards_earned(principal: Principal) -> u64 {
+    RESOLUTION_REWARDS_EARNED.with(|earned| earned.borrow().get(&principal).copied().unwrap_or(0))
+}
+
+#[ic_cdk::query]
+fn get_resolution_reward_bps() -> u64 {
+    RESOLUTION_REWARD_BPS.with(|bps| *bps.borrow())
+}
+
+#[ic_cdk::update]
+fn set_resolution_reward_bps(reward_bps: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can set the resolution reward".to_string());
+    }
+    if reward_bps > 10_000 {
+        return Err("The reward cannot exceed 10000 bps".to_string());
+    }
+
+    RESOLUTION_REWARD_BPS.with(|bps| *bps.borrow_mut() = reward_bps);
+    Ok(())
+}
+
+// Resolution hooks: the canister's method is called with a ResolutionPayload
+// by the canister_hooks task once the market resolves
+#[ic_cdk::update]
+fn register_canister_hook(
+    market_id: u64,
+    canister: Principal,
+    method: String,
+) -> Result<u64, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage canister hooks".to_string());
+    }
+    MARKETS.with(|markets| match markets.borrow().get(&market_id) {
+        None => Err("Market not found".to_string()),
+        Some(market) if market.resolved_outcome.is_some() => {
+            Err("Market is already resolved".to_string())
+        }
+        Some(_) => Ok(()),
+    })?;
+
+    hooks::register(market_id, canister, method)
+}
+
+#[ic_cdk::update]
+fn remove_canister_hook(hook_id: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage canister hooks".to_string());
+    }
+
+    hooks::remove(hook_id)
+}
+
+// Delivery status included
+#[ic_cdk::query]
+fn get_canister_hooks(market_id: u64) -> Vec<CanisterHook> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    hooks::list(market_id)
+}
+
+// Free-form resolution, for markets created without criteria
+#[ic_cdk::update]
+fn resolve_market(market_id: u64, outcome: Outcome) -> Result<Market, String> {
+    settle_market(ic_cdk::caller(), market_id, outcome, None)
+}
+
+// What each winner would be paid if the market resolved to `outcome` now,
+// largest first; for Invalid, each holder's refund. Only those who may resolve
+// the market can preview it.
+#[ic_cdk::query]
+fn preview_resolution(market_id: u64, outcome: Outcome) -> Vec<(Principal, u64)> {
+    let caller = ic_cdk::caller();
+    let Some(market) = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned()) else {
+        return vec![];
+    };
+    if !may_resolve(&market, caller) {
+        return vec![];
+    }
+
+    let mut payouts: Vec<(Principal, u64)> = POSITIONS.with(|positions| {
+        positions
+            .borrow()
+            .iter()
+            .filter(|((_, id), position)| *id == market_id && !position.claimed)
+            .map(|((owner, _), position)| (*owner, engine::payout_if(&market, position, outcome)))
+            .filter(|(_, payout)| *payout > 0)
+            .collect()
+    });
+    payouts.sort_by_key(|(owner, payout)| (std::cmp::Reverse(*payout), *owner));
+    payouts
+}
+
+// Resolution of a market with criteria: one verdict per statement, in order
+#[ic_cdk::update]
+fn resolve_market_with_verdicts(
+    market_id: u64,
+    verdicts: Vec<bool>,
+    outcome: bool,
+) -> Result<Market, String> {
+    settle_market(ic_cdk::caller(), market_id, outcome.into(), Some(verdicts))
+}
+
+fn settle_market(
+    caller: Principal,
+    market_id: u64,
+    outcome: Outcome,
+    verdicts: Option<Vec<bool>>,
+) -> Result<Market, String> {
+    let market = MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        if !may_resolve(market, caller) {
+            return Err(
+                "Only the creator, the designated resolver or an admin can resolve this market"
+                    .to_string(),
+            );
+        }
+        let now = ic_cdk::api::time();
+        if !matches!(market.status, MarketStatus::Closed) && now < market.close_date {
+            return Err("Market cannot be resolved before it closes".to_string());
+        }
+
+        // Criteria only decide between YES and NO; Invalid sets them aside
+        match (verdicts, outcome.winning_side()) {
+            (Some(verdicts), Some(side)) => engine::apply_verdicts(market, verdicts, side)?,
+            (Some(_), None) => {
+                return Err("Invalid resolutions take no verdicts".to_string());
+            }
+            (None, Some(_)) if market.resolution_criteria.is_some() => {
+                return Err(
+                    "This market has resolution criteria; submit a verdict for each".to_string(),
+                );
+            }
+            _ => {}
+        }
+        engine::resolve(market, outcome)?;
+        Ok(market.clone())
+    })?;
+
+    let deadline = market
+        .close_date
+        .saturating_add(to_nanos(RESOLUTION_WINDOW_SECONDS));
+    let on_time = ic_cdk::api::time() <= deadline;
+    update_reputation(market.creator, |reputation| {
+        if on_time {
+            reputation.resolved_on_time += 1;
+        } else {
+            reputation.resolved_late += 1;
+        }
+    });
+
+    // Winning-side volume per trader
+    let mut winners: HashMap<Principal, u64> = HashMap::new();
+    TRADES.with(|trades| {
+        for trade in trades.borrow().iter() {
+            if trade.market_id == market_id
+                && outcome.winning_side() == Some(trade.is_yes)
+                && trade.reverted_at.is_none()
+            {
+                *winners.entry(trade.trader).or_default() += trade.amount;
+            }
+        }
+    });
+
+    let mut recipients: Vec<Principal> = winners.keys().copied().collect();
+    recipients.sort();
+    for winner in recipients {
+        enqueue_payout(market_id, winner, PayoutKind::SuccessfulPrediction);
+        quests::record(winner, QuestEvent::CorrectPrediction, quest_day());
+    }
+
+    // Traders whose first correct trade landed at the same instant are told
+    // apart by a recorded draw
+    let earliest = earliest_correct_traders(market_id, outcome);
+    match earliest.as_slice() {
+        [] => {}
+        [trader] => award_earliest_correct(market_id, *trader, None),
+        _ => ic_cdk::spawn(draw_earliest_correct(market_id, earliest)),
+    }
+
+    let traders: BTreeSet<Principal> = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|trade| trade.market_id == market_id)
+            .map(|trade| trade.trader)
+            .collect()
+    });
+    for trader in traders {
+        notify_user(
+            trader,
+            UserNotificationKind::MarketResolved { market_id, outcome },
+        );
+    }
+
+    settle_boosts(market_id, &winners);
+    refund_incentives(market_id);
+    refresh_market_card(market_id);
+    hooks::queue(ResolutionPayload {
+        market_id,
+        outcome,
+        resolved_at: ic_cdk::api::time(),
+    });
+    record_event(market_id, MarketEventKind::Resolved, caller);
+    if market.resolver == Some(caller) && resolvers::is_listed(caller) {
+        resolvers::assign(market_id, caller, ic_cdk::api::time());
+    }
+    queue_resolution_reward(&market, caller);
+
+    Ok(market)
+}
+
+fn earliest_correct_traders(market_id: u64, outcome: Outcome) -> Vec<Principal> {
+    TRADES.with(|trades| {
+        let trades = trades.borrow();
+        let correct: Vec<&Trade> = trades
+            .iter()
+            .filter(|trade| {
+                trade.market_id == market_id
+                    && outcome.winning_side() == Some(trade.is_yes)
+                    && trade.reverted_at.is_none()
+            })
+            .collect();
+        let Some(first) = correct.iter().map(|trade| trade.timestamp).min() else {
+            return vec![];
+        };
+        let tied: BTreeSet<Principal> = correct
+            .iter()
+            .filter(|trade| trade.timestamp == first)
+            .map(|trade| trade.trader)
+            .collect();
+        tied.into_iter().collect()
+    })
+}
+
+async fn draw_earliest_correct(market_id: u64, candidates: Vec<Principal>) {
+    let purpose = format!("Earliest correct trader on market {}", market_id);
+    match run_draw(purpose, candidates).await {
+        Ok(draw) => award_earliest_correct(market_id, draw.order[0], Some(draw.id)),
+        Err(e) => ic_cdk::println!(
+            "Earliest correct draw for market {} failed: {}",
+            market_id,
+            e
+        ),
+    }
+}
+
+fn award_earliest_correct(market_id: u64, trader: Principal, draw_id: Option<u64>) {
+    USER_PROFILES.with(|profiles| {
+        if let Some(profile) = profiles.borrow_mut().get_mut(&trader) {
+            grant_xp(
+                profile,
+                XpSource::Resolution,
+                EARLIEST_CORRECT_XP,
+                market_id,
+            );
+            award_badges(profile);
+        }
+    });
+    record_event(
+        market_id,
+        MarketEventKind::EarliestCorrect { trader, draw_id },
+        ic_cdk::id(),
+    );
+}
+
+// Surveillance functions
+fn run_surveillance(now: u64) {
+    let since = now.saturating_sub(to_nanos(surveillance::REPORT_WINDOW_DAYS * SECONDS_PER_DAY));
+    let config = surveillance::config();
+
+    let markets: HashMap<u64, Market> = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .iter()
+            .map(|(id, m)| (*id, m.clone()))
+            .collect()
+    });
+    let trades: Vec<surveillance::TradeFact> = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|t| t.timestamp >= since && t.reverted_at.is_none())
+            .filter_map(|t| {
+                Some(surveillance::TradeFact {
+                    id: t.id,
+                    trader: t.trader,
+                    market_id: t.market_id,
+                    is_yes: t.is_yes,
+                    timestamp: t.timestamp,
+                    market_creator: markets.get(&t.market_id)?.creator,
+                })
+            })
+            .collect()
+    });
+    let accounts: HashMap<Principal, u64> = USER_PROFILES.with(|profiles| {
+        profiles
+            .borrow()
+            .values()
+            .map(|p| (p.principal, p.created_at))
+            .collect()
+    });
+
+    let mut trade_ids: HashMap<(Principal, u64), Vec<u64>> = HashMap::new();
+    for trade in &trades {
+        trade_ids
+            .entry((trade.trader, trade.market_id))
+            .or_default()
+            .push(trade.id);
+    }
+
+    // PnL is only realized once a market resolves
+    let mut pnl: BTreeMap<Principal, surveillance::PnlFact> = BTreeMap::new();
+    POSITIONS.with(|positions| {
+        for ((owner, market_id), position) in positions.borrow().iter() {
+            let Some(market) = markets
+                .get(market_id)
+                .filter(|m| m.resolved_outcome.is_some())
+            else {
+                continue;
+            };
+            let cost = position.yes_cost.saturating_add(position.no_cost);
+            let fact = pnl.entry(*owner).or_insert(surveillance::PnlFact {
+                principal: *owner,
+                xp: 0,
+                realized_pnl: 0,
+                trade_ids: vec![],
+            });
+            fact.realized_pnl += engine::winnings_for(market, position) as i64 - cost as i64;
+            fact.trade_ids
+                .extend(trade_ids.remove(&(*owner, *market_id)).unwrap_or_default());
+        }
+    });
+    XP_ENTRIES.with(|entries| {
+        for entry in entries.borrow().iter().filter(|e| e.timestamp >= since) {
+            if let Some(fact) = pnl.get_mut(&entry.principal) {
+                fact.xp = fact.xp.saturating_add(entry.amount);
+            }
+        }
+    });
+    let pnl: Vec<surveillance::PnlFact> = pnl.into_values().collect();
+
+    let mut findings = surveillance::self_market_trading(&trades, &config);
+    findings.extend(surveillance::opposite_side_pairs(&trades, &config));
+    findings.extend(surveillance::burst_accounts(&trades, &accounts, &config));
+    findings.extend(surveillance::xp_pnl_outliers(&pnl, &config));
+    surveillance::record(findings, now);
+}
+
+// Flags from the latest surveillance runs that were still detected within the
+// last window_days, at most the report window
+#[ic_cdk::query]
+fn get_suspicious_activity(window_days: u64) -> Result<Vec<SuspiciousFlag>, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can review suspicious activity".to_string());
+    }
+
+    let days = window_days.min(surveillance::REPORT_WINDOW_DAYS);
+    let since = ic_cdk::api::time().saturating_sub(to_nanos(days * SECONDS_PER_DAY));
+    Ok(surveillance::flags_since(since))
+}
+
+#[ic_cdk::update]
+fn review_suspicious_flag(flag_id: u64, status: FlagStatus) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can review suspicious activity".to_string());
+    }
+
+    surveillance::review(flag_id, status)
+}
+
+#[ic_cdk::query]
+fn get_surveillance_config() -> SurveillanceConfig {
+    surveillance::config()
+}
+
+#[ic_cdk::update]
+fn set_surveillance_config(config: SurveillanceConfig) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can review suspicious activity".to_string());
+    }
+
+    surveillance::set_config(config)
+}
+
+// Badge functions
+fn badge_metric_value(profile: &UserProfile, metric: BadgeMetric) -> u64 {
+    match metric {
+        BadgeMetric::Trades => profile.total_trades,
+        BadgeMetric::Xp => profile.xp,
+        BadgeMetric::SuccessfulPredictions => profile.successful_predictions,
+    }
+}
+
+// Adds every milestone badge the profile has reached but not yet been awarded
+fn award_badges(profile: &mut UserProfile) {
+    for (name, metric, target) in BADGE_MILESTONES {
+        if badge_metric_value(profile, *metric) >= *target
+            && !profile.badges.iter().any(|badge| badge == name)
+        {
+            profile.badges.push(name.to_string());
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn get_badge_progress(principal: Principal) -> Vec<BadgeProgress> {
+    let profile = USER_PROFILES.with(|profiles| profiles.borrow().get(&principal).cloned());
+
+    BADGE_MILESTONES
+        .iter()
+        .map(|(name, metric, target)| {
+            let current = profile
+                .as_ref()
+                .map(|p| badge_metric_value(p, *metric))
+                .unwrap_or(0);
+            let earned = profile
+                .as_ref()
+                .is_some_and(|p| p.badges.iter().any(|badge| badge == name));
+            BadgeProgress {
+                badge_name: name.to_string(),
+                current,
+                target: *target,
+                earned: earned || current >= *target,
+            }
+        })
+        .collect()
+}
+
+// Archived markets are read back from their archive canister
+#[ic_cdk::query(composite = true)]
+async fn get_market_trades(market_id: u64) -> Vec<Trade> {
+    if !reads_allowed() {
+        return vec![];
+    }
+    if let Some(archive) = archived_to(market_id) {
+        return fetch_archive(archive, market_id)
+            .await
+            .map(|archived| archived.trades)
+            .unwrap_or_default();
+    }
+
+    TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|trade| trade.market_id == market_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// Biggest trades by shares minted over the last `window_seconds`, across all markets
+#[ic_cdk::query]
+fn get_largest_trades(window_seconds: u64, limit: u64) -> Vec<LargeTrade> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let limit = limit.min(MAX_LARGEST_TRADES) as usize;
+    let since = ic_cdk::api::time().saturating_sub(to_nanos(window_seconds));
+
+    // The trade log is in execution order, so the window is a suffix of it
+    let mut recent: Vec<Trade> = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .rev()
+            .take_while(|trade| trade.timestamp >= since)
+            .filter(|trade| trade.reverted_at.is_none())
+            .cloned()
+            .collect()
+    });
+    recent.sort_by(|a, b| b.shares.cmp(&a.shares).then(b.id.cmp(&a.id)));
+    recent.truncate(limit);
+
+    MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        recent
+            .into_iter()
+            .map(|trade| LargeTrade {
+                market_title: markets
+                    .get(&trade.market_id)
+                    .map(|market| market.title.clone())
+                    .unwrap_or_default(),
+                trade,
+            })
+            .collect()
+    })
+}
+
+// Depth chart for a market; `steps` levels per side, at most MAX_DEPTH_STEPS
+#[ic_cdk::query]
+fn get_depth(market_id: u64, steps: u8) -> Option<DepthChart> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    let market = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned())?;
+    let steps = steps.clamp(1, MAX_DEPTH_STEPS) as u32;
+    let fee_bps = resolve_config(ic_cdk::caller(), ic_cdk::api::time()).fee_bps;
+    Some(DepthChart {
+        market_id,
+        yes_shares: market.yes_shares,
+        no_shares: market.no_shares,
+        yes_liquidity: market.yes_liquidity,
+        no_liquidity: market.no_liquidity,
+        base_depth: engine::BASE_DEPTH,
+        max_price_impact: engine::MAX_PRICE_IMPACT,
+        fee_bps,
+        yes_bps: engine::implied_yes_bps(&market),
+        yes_levels: engine::depth_ladder(&market, true, steps, fee_bps),
+        no_levels: engine::depth_ladder(&market, false, steps, fee_bps),
+    })
+}
+
+// Histogram of executed prices for one side of a market. Trade prices range
+// over 0-1000 (0.0-1.0); each entry is (bucket midpoint in bps, trade count).
+#[ic_cdk::query]
+fn get_price_histogram(market_id: u64, is_yes: bool, buckets: u64) -> Vec<(u64, u64)> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let buckets = buckets.clamp(1, MAX_HISTOGRAM_BUCKETS);
+
+    let prices: Vec<u64> = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|trade| {
+                trade.market_id == market_id
+                    && trade.is_yes == is_yes
+                    && trade.reverted_at.is_none()
+            })
+            .map(|trade| trade.price.min(1000))
+            .collect()
+    });
+
+    if prices.is_empty() {
+        return vec![];
+    }
+
+    let mut counts = vec![0u64; buckets as usize];
+    for price in prices {
+        // A price of exactly 1000 falls into the last bucket
+        let bucket = (price * buckets / 1000).min(buckets - 1);
+        counts[bucket as usize] += 1;
+    }
+
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| {
+            let midpoint_bps = (2 * i as u64 + 1) * 10_000 / (2 * buckets);
+            (midpoint_bps, count)
+        })
+        .collect()
+}
+
+fn new_profile(principal: Principal, username: String) -> UserProfile {
+    UserProfile {
+        principal,
+        username,
+        xp: 0,
+        total_trades: 0,
+        successful_predictions: 0,
+        badges: vec![],
+        created_at: ic_cdk::api::time(),
+    }
+}
+
+fn default_username(principal: &Principal) -> String {
+    format!(
+        "User{}",
+        principal.to_text().chars().take(8).collect::<String>()
+    )
+}
+
+// 3-20 ASCII letters, digits or underscores, unique ignoring case
+fn validate_username(username: &str, owner: &Principal) -> Result<(), String> {
+    let len = username.chars().count();
+    if !(MIN_USERNAME_LEN..=MAX_USERNAME_LEN).contains(&len) {
+        return Err(format!(
+            "Username must be between {} and {} characters",
+            MIN_USERNAME_LEN, MAX_USERNAME_LEN
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err("Username may only contain letters, digits and underscores".to_string());
+    }
+
+    let taken = USER_PROFILES.with(|profiles| {
+        profiles.borrow().values().any(|profile| {
+            profile.principal != *owner && profile.username.eq_ignore_ascii_case(username)
+        })
+    });
+    if taken {
+        return Err("Username is already taken".to_string());
+    }
+    Ok(())
+}
+
+// Creates the caller's profile without requiring a trade. Repeat calls return
+// the existing profile unchanged, whatever username they pass.
+#[ic_cdk::update]
+fn create_profile(username: Option<String>) -> Result<UserProfile, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot create a profile".to_string());
+    }
+
+    if let Some(existing) = USER_PROFILES.with(|profiles| profiles.borrow().get(&caller).cloned()) {
+        return Ok(existing);
+    }
+
+    let username = match username {
+        Some(username) => {
+            let username = username.trim().to_string();
+            validate_username(&username, &caller)?;
+            username
+        }
+        None => default_username(&caller),
+    };
+
+    let profile = new_profile(caller, username);
+    USER_PROFILES.with(|profiles| profiles.borrow_mut().insert(caller, profile.clone()));
+    Ok(profile)
+}
+
+#[ic_cdk::query]
+fn get_user_profile(principal: Principal) -> Option<RankedProfile> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    let profile = USER_PROFILES.with(|profiles| profiles.borrow().get(&principal).cloned())?;
+    let (level, rank_name) = levels::xp_to_level(profile.xp);
+    Some(RankedProfile {
+        profile,
+        level,
+        rank_name,
+    })
+}
+
+// (minimum XP, rank name) per level, lowest first
+#[ic_cdk::query]
+fn get_level_thresholds() -> Vec<(u64, String)> {
+    levels::thresholds()
+}
+
+#[ic_cdk::update]
+fn set_level_thresholds(thresholds: Vec<(u64, String)>) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update level thresholds".to_string());
+    }
+
+    levels::set_thresholds(thresholds)
+}
+
+// Responsible trading controls. Only the caller can change their own limits;
+// admins can repair the spend counters but not the limits themselves.
+#[ic_cdk::query]
+fn get_trading_limits() -> TradingLimitsStatus {
+    limits::status(ic_cdk::caller(), ic_cdk::api::time())
+}
+
+#[ic_cdk::update]
+fn set_trading_limits(requested: TradingLimits) -> Result<TradingLimitsStatus, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous users cannot set trading limits".to_string());
+    }
+
+    limits::set_limits(caller, requested, ic_cdk::api::time())
+}
+
+// Blocks the caller's trading until the returned time (ns)
+#[ic_cdk::update]
+fn self_exclude(duration_seconds: u64) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous users cannot self-exclude".to_string());
+    }
+
+    limits::self_exclude(caller, duration_seconds, ic_cdk::api::time())
+}
+
+// Recomputes every trader's rolling spend from the trade log
+#[ic_cdk::update]
+fn repair_spend_counters() -> Result<u64, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can repair spend counters".to_string());
+    }
+
+    let trades = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|trade| trade.reverted_at.is_none())
+            .map(|trade| (trade.trader, trade.timestamp, trade.amount))
+            .collect::<Vec<_>>()
+    });
+    Ok(limits::rebuild_spend(
+        trades.into_iter(),
+        ic_cdk::api::time(),
+    ))
+}
+
+// Profiles for a batch of principals, in input order, skipping unknown ones
+#[ic_cdk::query]
+fn get_profiles(principals: Vec<Principal>) -> Vec<UserProfile> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    USER_PROFILES.with(|profiles| {
+        let profiles = profiles.borrow();
+        principals
+            .iter()
+            .take(MAX_PROFILE_BATCH)
+            .filter_map(|principal| profiles.get(principal).cloned())
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_leaderboard() -> Vec<UserProfile> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    USER_PROFILES.with(|profiles| {
+        let mut users: Vec<_> = profiles.borrow().values().cloned().collect();
+        users.sort_by_key(|u| (std::cmp::Reverse(u.xp), u.principal));
+        users.into_iter().take(LEADERBOARD_SIZE as usize).collect()
+    })
+}
+
+fn leaderboard_badge(size: u64) -> String {
+    format!("Top{}", size)
+}
+
+// Gives the standing badge to the current top of the leaderboard and takes it
+// from whoever dropped out. Users without XP are not ranked.
+fn sync_leaderboard_badges() {
+    let size = get_leaderboard_badge_size();
+    if size == 0 {
+        return;
+    }
+    let badge = leaderboard_badge(size);
+
+    USER_PROFILES.with(|profiles| {
+        let mut profiles = profiles.borrow_mut();
+        let mut ranked: Vec<(u64, Principal)> = profiles
+            .values()
+            .filter(|p| p.xp > 0)
+            .map(|p| (p.xp, p.principal))
+            .collect();
+        ranked.sort_by_key(|(xp, principal)| (std::cmp::Reverse(*xp), *principal));
+        let top: HashSet<Principal> = ranked
+            .into_iter()
+            .take(size as usize)
+            .map(|(_, principal)| principal)
+            .collect();
+
+        for profile in profiles.values_mut() {
+            let holds = profile.badges.contains(&badge);
+            match (top.contains(&profile.principal), holds) {
+                (true, false) => profile.badges.push(badge.clone()),
+                (false, true) => profile.badges.retain(|b| *b != badge),
+                _ => {}
+            }
+        }
+    });
+}
+
+// Insight generation that outlived the job timeout is reported as failed
+fn with_stale_check(mut insight: AIInsight, now: u64) -> AIInsight {
+    if matches!(insight.status, InsightStatus::Generating)
+        && now.saturating_sub(insight.generated_at) >= INSIGHT_JOB_TIMEOUT_NANOS
+    {
+        insight.status = InsightStatus::Failed("Insight generation timed out".to_string());
+    }
+    insight
+}
+
+#[ic_cdk::query(composite = true)]
+async fn get_ai_insight(market_id: u64) -> Option<AIInsight> {
+    if !reads_allowed() {
+        return None;
+    }
+    if let Some(archive) = archived_to(market_id) {
+        return fetch_archive(archive, market_id)
+            .await
+            .ok()
+            .and_then(|archived| archived.insight);
+    }
+
+    AI_INSIGHTS
+        .with(|insights| insights.borrow().get(&market_id).cloned())
+        .map(|insight| with_stale_check(insight, ic_cdk::api::time()))
+}
+
+// Poll functions
+fn poll_closed(market: &Market, now: u64) -> bool {
+    !matches!(
+        market.status,
+        MarketStatus::Active | MarketStatus::PendingValidation
+    ) || now >= market.close_date
+}
+
+fn poll_view(market_id: u64, caller: Principal, closed: bool) -> PollView {
+    MARKET_POLLS.with(|polls| {
+        let polls = polls.borrow();
+        let poll = polls.get(&market_id);
+        let has_voted = poll.is_some_and(|poll| poll.voters.contains(&caller));
+        let tally = poll.map(|poll| poll.tally.clone()).unwrap_or_default();
+        PollView {
+            market_id,
+            total_votes: tally.yes + tally.no + tally.unsure,
+            has_voted,
+            closed,
+            tally: (has_voted || closed).then_some(tally),
+        }
+    })
+}
+
+#[ic_cdk::update]
+fn vote_poll(market_id: u64, choice: PollChoice) -> Result<PollView, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot vote".to_string());
+    }
+    check_access(&caller)?;
+
+    let market = MARKETS
+        .with(|markets| markets.borrow().get(&market_id).cloned())
+        .ok_or_else(|| "Market not found".to_string())?;
+    if poll_closed(&market, ic_cdk::api::time()) {
+        return Err("The poll closed with the market".to_string());
+    }
+
+    MARKET_POLLS.with(|polls| {
+        let mut polls = polls.borrow_mut();
+        let poll = polls.entry(market_id).or_default();
+        if !poll.voters.insert(caller) {
+            return Err("You already voted in this poll".to_string());
+        }
+        match choice {
+            PollChoice::Yes => poll.tally.yes += 1,
+            PollChoice::No => poll.tally.no += 1,
+            PollChoice::Unsure => poll.tally.unsure += 1,
+        }
+        Ok(())
+    })?;
+
+    Ok(poll_view(market_id, caller, false))
+}
+
+#[ic_cdk::query]
+fn get_poll(market_id: u64) -> Option<PollView> {
+    let market = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned())?;
+    let closed = poll_closed(&market, ic_cdk::api::time());
+    Some(poll_view(market_id, ic_cdk::caller(), closed))
+}
+
+fn lean(yes: u64, no: u64) -> Option<bool> {
+    match yes.cmp(&no) {
+        std::cmp::Ordering::Greater => Some(true),
+        std::cmp::Ordering::Less => Some(false),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+// Crowd vs market vs AI on a resolved market
+#[ic_cdk::query]
+fn get_resolution_comparison(market_id: u64) -> Result<ResolutionComparison, String> {
+    let market = MARKETS
+        .with(|markets| markets.borrow().get(&market_id).cloned())
+        .ok_or_else(|| "Market not found".to_string())?;
+    let outcome = market
+        .resolved_outcome
+        .ok_or_else(|| "Market is not resolved".to_string())?;
+
+    let crowd = MARKET_POLLS.with(|polls| {
+        polls
+            .borrow()
+            .get(&market_id)
+            .map(|poll| poll.tally.clone())
+    });
+    let market_yes_bps = engine::implied_yes_bps(&market);
+    let ai_lean = AI_INSIGHTS.with(|insights| {
+        insights
+            .borrow()
+            .get(&market_id)
+            .and_then(|insight| insight.prediction_lean)
+    });
+
+    Ok(ResolutionComparison {
+        market_id,
+        outcome,
+        crowd_lean: crowd.as_ref().and_then(|tally| lean(tally.yes, tally.no)),
+        crowd,
+        market_yes_bps,
+        market_lean: lean(market_yes_bps, 10_000 - market_yes_bps),
+        ai_correct: ai_lean
+            .zip(outcome.winning_side())
+            .map(|(lean, side)| lean == side),
+        ai_lean,
+    })
+}
+
+// Queues a fresh insight for the market and returns the job id. The result is
+// written back when generation finishes; poll get_ai_insight until it carries
+// the job id. Each job spends the shared LLM budget, so only the creator or an
+// admin may ask, and creators only once per cooldown.
+#[ic_cdk::update]
+fn refresh_ai_insight(market_id: u64) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    let market = MARKETS
+        .with(|markets| markets.borrow().get(&market_id).cloned())
+        .ok_or_else(|| "Market not found".to_string())?;
+
+    if is_admin(&caller) {
+        return start_insight_job(market);
+    }
+    if !is_creator(&market, caller) {
+        return Err("Only the creator or an admin can refresh this insight".to_string());
+    }
+    let now = ic_cdk::api::time();
+    let last = INSIGHT_REFRESHED_AT.with(|refreshed| refreshed.borrow().get(&caller).copied());
+    if last.is_some_and(|last| now.saturating_sub(last) < INSIGHT_REFRESH_COOLDOWN_NANOS) {
+        return Err("Insights can only be refreshed once every 10 minutes".to_string());
+    }
+
+    let job_id = start_insight_job(market)?;
+    INSIGHT_REFRESHED_AT.with(|refreshed| refreshed.borrow_mut().insert(caller, now));
+    Ok(job_id)
+}
+
+// Regenerates a ready insight once the price has moved past the threshold
+// since it was written; insights without a recorded price are left alone
+fn refresh_insight_on_move(market_id: u64) {
+    let threshold_bps = INSIGHT_REFRESH_MOVE_BPS.with(|threshold| *threshold.borrow());
+    if threshold_bps == 0 {
+        return;
+    }
+    let Some(market) = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned()) else {
+        return;
+    };
+    let generated_yes_bps = AI_INSIGHTS.with(|insights| {
+        insights
+            .borrow()
+            .get(&market_id)
+            .filter(|insight| matches!(insight.status, InsightStatus::Ready))
+            .and_then(|insight| insight.generated_yes_bps)
+    });
+    if let Some(generated_yes_bps) = generated_yes_bps {
+        if engine::implied_yes_bps(&market).abs_diff(generated_yes_bps) >= threshold_bps {
+            // The trade stands whether or not the job could be queued
+            let _ = start_insight_job(market);
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn get_insight_refresh_threshold_bps() -> u64 {
+    INSIGHT_REFRESH_MOVE_BPS.with(|threshold| *threshold.borrow())
+}
+
+#[ic_cdk::update]
+fn set_insight_refresh_threshold_bps(threshold_bps: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the insight refresh threshold".to_string());
+    }
+    if threshold_bps > 10_000 {
+        return Err("The threshold cannot exceed 10000 bps".to_string());
+    }
+
+    INSIGHT_REFRESH_MOVE_BPS.with(|threshold| *threshold.borrow_mut() = threshold_bps);
+    Ok(())
+}
+
+fn start_insight_job(market: Market) -> Result<u64, String> {
+    let market_id = market.id;
+    let now = ic_cdk::api::time();
+
+    let current = AI_INSIGHTS.with(|insights| {
+        insights
+            .borrow()
+            .get(&market_id)
+            .map(|insight| with_stale_check(insight.clone(), now))
+    });
+    let refreshing = INSIGHT_REFRESHES.with(|refreshes| {
+        refreshes
+            .borrow()
+            .get(&market_id)
+            .filter(|(_, started_at)| now.saturating_sub(*started_at) < INSIGHT_JOB_TIMEOUT_NANOS)
+            .map(|(job_id, _)| *job_id)
+    });
+    let in_flight = refreshing.or_else(|| {
+        current
+            .as_ref()
+            .filter(|insight| matches!(insight.status, InsightStatus::Generating))
+            .and_then(|insight| insight.job_id)
+    });
+    if let Some(job_id) = in_flight {
+        return Err(format!(
+            "An insight is already being generated for this market (job {})",
+            job_id
+        ));
+    }
+
+    let job_id = NEXT_INSIGHT_JOB_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    // A ready insight stays up until its replacement completes; anything else
+    // gives way to the placeholder
+    let ready = current.is_some_and(|insight| matches!(insight.status, InsightStatus::Ready));
+    if ready {
+        INSIGHT_REFRESHES.with(|refreshes| refreshes.borrow_mut().insert(market_id, (job_id, now)));
+    } else {
+        INSIGHT_REFRESHES.with(|refreshes| refreshes.borrow_mut().remove(&market_id));
+        AI_INSIGHTS.with(|insights| {
+            insights.borrow_mut().insert(
+                market_id,
+                AIInsight {
+                    market_id,
+                    summary: String::new(),
+                    confidence: 0.0,
+                    risks: vec![],
+                    prediction_lean: None,
+                    generated_at: now,
+                    status: InsightStatus::Generating,
+                    job_id: Some(job_id),
+                    generated_yes_bps: Some(engine::implied_yes_bps(&market)),
+                },
+            );
+        });
+    }
+
+    ic_cdk::spawn(async move {
+        let result = generate_ai_insight(market).await;
+        cache_insight(market_id, job_id, result);
+    });
+
+    Ok(job_id)
+}
+
+fn cache_insight(market_id: u64, job_id: u64, result: Result<AIInsight, String>) {
+    let refreshing = INSIGHT_REFRESHES.with(|refreshes| {
+        refreshes
+            .borrow()
+            .get(&market_id)
+            .map(|(job_id, _)| *job_id)
+    });
+    AI_INSIGHTS.with(|insights| {
+        let mut insights = insights.borrow_mut();
+
+        // A newer job took over after this one timed out, or the market is gone
+        let current =
+            refreshing.or_else(|| insights.get(&market_id).and_then(|insight| insight.job_id));
+        if current != Some(job_id) {
+            return;
+        }
+        if refreshing.is_some() {
+            INSIGHT_REFRESHES.with(|refreshes| refreshes.borrow_mut().remove(&market_id));
+        }
+
+        let insight = match result {
+            Ok(insight) => AIInsight {
+                status: InsightStatus::Ready,
+                job_id: Some(job_id),
+                ..insight
+            },
+            Err(error) => AIInsight {
+                market_id,
+                summary: String::new(),
+                confidence: 0.0,
+                risks: vec![],
+                prediction_lean: None,
+                generated_at: ic_cdk::api::time(),
+                status: InsightStatus::Failed(error),
+                job_id: Some(job_id),
+                generated_yes_bps: None,
+            },
+        };
+        insights.insert(market_id, insight);
+    });
+}
+
+// Generates a fresh insight for the market
+async fn generate_ai_insight(market: Market) -> Result<AIInsight, String> {
+    let llm_enabled = LLM_ENABLED.with(|enabled| *enabled.borrow());
+    match insight::select_backend(llm_enabled, LLM_CANISTER_ID)? {
+        insight::Backend::Mock => Ok(insight::mock_insight(&market, ic_cdk::api::time())),
+        insight::Backend::Llm(llm) => {
+            let request = insight::build_chat_request(insight::build_prompt(&market));
+            let response = call_llm(llm, request).await?;
+            insight::parse_response(&response, &market, ic_cdk::api::time())
+        }
+    }
+}
+
+async fn call_llm(llm: Principal, request: ChatRequestV0) -> Result<String, String> {
+    take_llm_call()?;
+    let result: Result<(String,), _> = ic_cdk::call(llm, "v0_chat", (request,)).await;
+    result.map(|(response,)| response).map_err(|(code, msg)| {
+        format!(
+            "AI analysis call failed: {:?} {}. The LLM canister may be offline or unreachable.",
+            code, msg
+        )
+    })
+}
+
+fn fresh_sentiment(market_id: u64, now: u64) -> Option<CommentSentiment> {
+    COMMENT_SENTIMENT.with(|cache| {
+        cache
+            .borrow()
+            .get(&market_id)
+            .filter(|sentiment| now.saturating_sub(sentiment.analyzed_at) < SENTIMENT_TTL_NANOS)
+            .cloned()
+    })
+}
+
+// Returns the cached analysis while it is fresh, otherwise analyzes the most
+// recent comments with the same backend as insights
+#[ic_cdk::update]
+async fn analyze_comment_sentiment(market_id: u64) -> Result<CommentSentiment, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can analyze comment sentiment".to_string());
+    }
+    let market = MARKETS
+        .with(|markets| markets.borrow().get(&market_id).cloned())
+        .ok_or_else(|| "Market not found".to_string())?;
+    if let Some(cached) = fresh_sentiment(market_id, ic_cdk::api::time()) {
+        return Ok(cached);
+    }
+
+    let mut comments: Vec<String> = COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|comment| comment.market_id == market_id)
+            .take(MAX_SENTIMENT_COMMENTS)
+            .map(|comment| comment.content.clone())
+            .collect()
+    });
+    if comments.is_empty() {
+        return Err("This market has no comments to analyze".to_string());
+    }
+    comments.reverse();
+
+    let llm_enabled = LLM_ENABLED.with(|enabled| *enabled.borrow());
+    let sentiment = match insight::select_backend(llm_enabled, LLM_CANISTER_ID)? {
+        insight::Backend::Mock => insight::mock_sentiment(&comments, ic_cdk::api::time()),
+        insight::Backend::Llm(llm) => {
+            let prompt = insight::build_sentiment_prompt(&market, &comments);
+            let response = call_llm(llm, insight::build_chat_request(prompt)).await?;
+            insight::parse_sentiment(&response, ic_cdk::api::time())?
+        }
+    };
+
+    COMMENT_SENTIMENT.with(|cache| cache.borrow_mut().insert(market_id, sentiment.clone()));
+    Ok(sentiment)
+}
+
+#[ic_cdk::query]
+fn get_comment_sentiment(market_id: u64) -> Option<CommentSentiment> {
+    fresh_sentiment(market_id, ic_cdk::api::time())
+}
+
+// Counts one LLM call against today's budget, or refuses it
+fn take_llm_call() -> Result<(), String> {
+    let day = quest_day();
+    let budget = get_llm_daily_calls();
+    LLM_CALLS_USED.with(|used| {
+        let mut used = used.borrow_mut();
+        if used.0 != day {
+            *used = (day, 0);
+        }
+        if used.1 >= budget {
+            return Err("The daily AI budget is exhausted; try again tomorrow".to_string());
+        }
+        used.1 += 1;
+        Ok(())
+    })
+}
+
+fn llm_calls_remaining() -> u64 {
+    let day = quest_day();
+    let used = LLM_CALLS_USED.with(|used| {
+        let used = used.borrow();
+        if used.0 == day {
+            used.1
+        } else {
+            0
+        }
+    });
+    get_llm_daily_calls().saturating_sub(used)
+}
+
+#[ic_cdk::query]
+fn get_llm_daily_calls() -> u64 {
+    LLM_DAILY_CALLS.with(|calls| *calls.borrow())
+}
+
+#[ic_cdk::update]
+fn set_llm_daily_calls(calls: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can configure AI insights".to_string());
+    }
+
+    LLM_DAILY_CALLS.with(|current| *current.borrow_mut() = calls);
+    Ok(())
+}
+
+// Writes today's digest for each active category that lacks one. Categories
+// are done one after another and the run stops quietly once the LLM budget
+// runs out; the rest are picked up on the next run.
+fn start_category_digests() {
+    let now = ic_cdk::api::time();
+    let today = quest_day();
+    let inputs: Vec<insight::DigestInput> = compile_digest_inputs(now)
+        .into_iter()
+        .filter(|input| latest_digest(&input.category).is_none_or(|d| d.date < today))
+        .collect();
+    if inputs.is_empty() || DIGESTS_IN_FLIGHT.with(|in_flight| in_flight.replace(true)) {
+        return;
+    }
+
+    ic_cdk::spawn(async move {
+        for input in inputs {
+            let llm_enabled = LLM_ENABLED.with(|enabled| *enabled.borrow());
+            let text = match insight::select_backend(llm_enabled, LLM_CANISTER_ID) {
+                Ok(insight::Backend::Mock) => Ok(insight::mock_digest(&input)),
+                Ok(insight::Backend::Llm(llm)) => {
+                    if llm_calls_remaining() == 0 {
+                        break;
+                    }
+                    let request = insight::build_chat_request(insight::build_digest_prompt(&input));
+                    match call_llm(llm, request).await {
+                        Ok(response) => insight::parse_digest(&response),
+                        Err(error) => Err(error),
+                    }
+                }
+                Err(error) => Err(error),
+            };
+            // A failed category is retried on the next run
+            if let Ok(text) = text {
+                store_digest(CategoryDigest {
+                    market_ids: input.market_ids(),
+                    category: input.category,
+                    date: today,
+                    text,
+                    generated_at: ic_cdk::api::time(),
+                });
+            }
+        }
+        DIGESTS_IN_FLIGHT.with(|in_flight| *in_flight.borrow_mut() = false);
+    });
+}
+
+// Digest material for every category with enough activity in the last 24h
+fn compile_digest_inputs(now: u64) -> Vec<insight::DigestInput> {
+    let since = now.saturating_sub(to_nanos(SECONDS_PER_DAY));
+
+    // The trade log is in execution order, so the window is a suffix of it
+    let mut volumes: HashMap<u64, (u64, u64)> = HashMap::new(); // market -> (volume, trades)
+    TRADES.with(|trades| {
+        for trade in trades.borrow().iter().rev() {
+            if trade.timestamp < since {
+                break;
+            }
+            if trade.reverted_at.is_some() {
+                continue;
+            }
+            let entry = volumes.entry(trade.market_id).or_default();
+            entry.0 = entry.0.saturating_add(trade.amount);
+            entry.1 += 1;
+        }
+    });
+    let resolutions: Vec<u64> = MARKET_EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .filter(|e| e.timestamp >= since && matches!(e.kind, MarketEventKind::Resolved))
+            .map(|e| e.market_id)
+            .collect()
+    });
+
+    let mut by_category: BTreeMap<String, insight::DigestInput> = BTreeMap::new();
+    let mut activity: HashMap<String, u64> = HashMap::new();
+    MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        let mut market_ids: Vec<&u64> = markets.keys().collect();
+        market_ids.sort();
+        for id in market_ids {
+            let market = &markets[id];
+            let input = by_category
+                .entry(market.category.clone())
+                .or_insert_with(|| insight::DigestInput {
+                    category: market.category.clone(),
+                    movers: vec![],
+                    top_volume: vec![],
+                    resolved: vec![],
+                });
+            if let Some((volume, trades)) = volumes.get(id) {
+                input.top_volume.push((*id, market.title.clone(), *volume));
+                *activity.entry(market.category.clone()).or_default() += trades;
+            }
+            if let Some(change) = get_price_change(*id, SECONDS_PER_DAY) {
+                if change.change_bps != 0 {
+                    input
+                        .movers
+                        .push((*id, market.title.clone(), change.change_bps));
+                }
+            }
+            if let (true, Some(outcome)) = (resolutions.contains(id), market.resolved_outcome) {
+                input.resolved.push((*id, market.title.clone(), outcome));
+                *activity.entry(market.category.clone()).or_default() += 1;
+            }
+        }
+    });
+
+    by_category
+        .into_values()
+        .filter(|input| activity.get(&input.category).copied().unwrap_or(0) >= MIN_DIGEST_ACTIVITY)
+        .map(|mut input| {
+            input
+                .movers
+                .sort_by_key(|(_, _, change)| std::cmp::Reverse(change.abs()));
+            input.movers.truncate(DIGEST_TOP_MARKETS);
+            input
+                .top_volume
+                .sort_by_key(|(_, _, volume)| std::cmp::Reverse(*volume));
+            input.top_volume.truncate(DIGEST_TOP_MARKETS);
+            input.resolved.truncate(DIGEST_TOP_MARKETS);
+            input
+        })
+        .collect()
+}
+
+fn store_digest(digest: CategoryDigest) {
+    CATEGORY_DIGESTS.with(|digests| {
+        let mut digests = digests.borrow_mut();
+        let history = digests.entry(digest.category.clone()).or_default();
+        history.push_back(digest);
+        if history.len() > MAX_DIGEST_HISTORY {
+            history.pop_front();
+        }
+    });
+}
+
+fn latest_digest(category: &str) -> Option<CategoryDigest> {
+    CATEGORY_DIGESTS.with(|digests| digests.borrow().get(category)?.back().cloned())
+}
+
+fn digest_view(digest: CategoryDigest) -> DigestView {
+    let markets = MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        digest
+            .market_ids
+            .iter()
+            .filter_map(|id| markets.get(id).map(market_summary))
+            .collect()
+    });
+    DigestView { digest, markets }
+}
+
+#[ic_cdk::query]
+fn get_latest_digest(category: String) -> Option<DigestView> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    latest_digest(&category).map(digest_view)
+}
+
+// Newest first
+#[ic_cdk::query]
+fn get_digest_history(category: String, limit: u64) -> Vec<DigestView> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let history: Vec<CategoryDigest> = CATEGORY_DIGESTS.with(|digests| {
+        digests.borrow().get(&category).map_or(vec![], |history| {
+            history
+                .iter()
+                .rev()
+                .take(limit.min(MAX_DIGEST_HISTORY as u64) as usize)
+                .cloned()
+                .collect()
+        })
+    });
+    history.into_iter().map(digest_view).collect()
+}
+
+#[ic_cdk::query]
+fn get_llm_enabled() -> bool {
+    LLM_ENABLED.with(|enabled| *enabled.borrow())
+}
+
+// Switches insight generation between the LLM canister and the built-in mock
+#[ic_cdk::update]
+fn set_llm_enabled(enabled: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can configure AI insights".to_string());
+    }
+
+    LLM_ENABLED.with(|flag| *flag.borrow_mut() = enabled);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn add_comment(market_id: u64, content: String) -> Result<u64, String> {
+    post_comment(ic_cdk::caller(), market_id, content, None)
+}
+
+#[ic_cdk::update]
+fn add_comment_with_attachment(
+    market_id: u64,
+    content: String,
+    attachment: CommentAttachment,
+) -> Result<u64, String> {
+    post_comment(ic_cdk::caller(), market_id, content, Some(attachment))
+}
+
+fn validate_attachment(attachment: &CommentAttachment, now: u64) -> Result<(), String> {
+    match attachment {
+        CommentAttachment::PriceChartRef {
+            market_id,
+            from_ts,
+            to_ts,
+        } => {
+            let created_at = MARKETS
+                .with(|markets| markets.borrow().get(market_id).map(|m| m.created_at))
+                .ok_or_else(|| "Charted market not found".to_string())?;
+            if from_ts >= to_ts || *from_ts < created_at || *to_ts > now {
+                return Err(
+                    "Chart range must run forward between the market's creation and now"
+                        .to_string(),
+                );
+            }
+            Ok(())
+        }
+        CommentAttachment::ExternalLink { url } => {
+            let host = link_host(url).ok_or_else(|| {
+                format!(
+                    "Links must be https URLs of at most {} characters",
+                    MAX_SOURCE_URL_LEN
+                )
+            })?;
+            let allowed = LINK_DOMAINS.with(|domains| {
+                domains
+                    .borrow()
+                    .iter()
+                    .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+            });
+            if !allowed {
+                return Err(format!("Links to {} are not allowed", host));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn get_link_domains() -> Vec<String> {
+    LINK_DOMAINS.with(|domains| domains.borrow().iter().cloned().collect())
+}
+
+// Replaces the allow-list for external link attachments
+#[ic_cdk::update]
+fn set_link_domains(domains: Vec<String>) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can manage link domains".to_string());
+    }
+    if domains.len() > MAX_LINK_DOMAINS {
+        return Err(format!(
+            "At most {} link domains can be allowed",
+            MAX_LINK_DOMAINS
+        ));
+    }
+    let domains: BTreeSet<String> = domains
+        .iter()
+        .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+        .collect();
+    if domains.iter().any(|domain| {
+        domain.is_empty()
+            || !domain
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+    }) {
+        return Err("Domains must be plain host names like example.com".to_string());
+    }
+
+    LINK_DOMAINS.with(|current| *current.borrow_mut() = domains);
+    Ok(())
+}
+
+fn post_comment(
+    caller: Principal,
+    market_id: u64,
+    content: String,
+    attachment: Option<CommentAttachment>,
+) -> Result<u64, String> {
+    check_access(&caller)?;
+
+    if content.is_empty() || content.len() > 500 {
+        return Err("Comment must be between 1 and 500 characters".to_string());
+    }
+
+    MARKETS.with(|markets| match markets.borrow().get(&market_id) {
+        None => Err("Market not found".to_string()),
+        Some(market) if market.comments_locked => {
+            Err("Comments are locked for this market".to_string())
+        }
+        Some(market) if market.archived_to.is_some() => Err("Market is archived".to_string()),
+        Some(_) => Ok(()),
+    })?;
+    if ARCHIVING.with(|archiving| archiving.borrow().contains(&market_id)) {
+        return Err("Market is being archived".to_string());
+    }
+    if let Some(attachment) = &attachment {
+        validate_attachment(attachment, ic_cdk::api::time())?;
+    }
+
+    let comment_id = NEXT_COMMENT_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    let market_refs = market_refs_in(&content, market_id);
+    let comment = MarketComment {
+        id: comment_id,
+        market_id,
+        author: caller,
+        content,
+        timestamp: ic_cdk::api::time(),
+        edited_at: None,
+        market_refs,
+        attachment,
+    };
+
+    let timestamp = comment.timestamp;
+    count_mentions(&comment.market_refs);
+    COMMENTS.with(|comments| {
+        comments.borrow_mut().push(comment);
+    });
+    prune_market_comments(market_id);
+    refresh_market_card(market_id);
+
+    let holds_position = POSITIONS.with(|positions| {
+        positions
+            .borrow()
+            .get(&(caller, market_id))
+            .is_some_and(|p| p.yes_shares > 0 || p.no_shares > 0)
+    });
+    if holds_position {
+        quests::record(caller, QuestEvent::CommentOnHeldMarket, quest_day());
+    }
+
+    notify_comment_subscribers(market_id, comment_id, caller, timestamp);
+    let auto_subscribe = caller != Principal::anonymous()
+        && !COMMENT_AUTO_SUBSCRIBE_DISABLED.with(|disabled| disabled.borrow().contains(&caller));
+    if auto_subscribe {
+        // Best effort: a full subscriber list does not block commenting
+        let _ = subscribe_to_comments(market_id, caller);
+    }
+
+    Ok(comment_id)
+}
+
+// Comments kept per market. This is spam control, not moderation: once a market
+// is over the cap its oldest comments are deleted for good, edit history included.
+#[ic_cdk::query]
+fn get_max_comments_per_market() -> u64 {
+    MAX_COMMENTS_PER_MARKET.with(|cap| *cap.borrow())
+}
+
+#[ic_cdk::update]
+fn set_max_comments_per_market(cap: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can update the comment cap".to_string());
+    }
+    if cap == 0 {
+        return Err("Comment cap must be greater than 0".to_string());
+    }
+
+    MAX_COMMENTS_PER_MARKET.with(|current| *current.borrow_mut() = cap);
+    Ok(())
+}
+
+// Existing markets referenced in the content, other than the comment's own
+fn market_refs_in(content: &str, market_id: u64) -> Vec<u64> {
+    MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        references::parse_market_refs(content)
+            .into_iter()
+            .filter(|id| *id != market_id && markets.contains_key(id))
+            .take(MAX_COMMENT_MARKET_REFS)
+            .collect()
+    })
+}
+
+fn count_mentions(market_ids: &[u64]) {
+    MARKET_MENTIONS.with(|mentions| {
+        let mut mentions = mentions.borrow_mut();
+        for market_id in market_ids {
+            *mentions.entry(*market_id).or_default() += 1;
+        }
+    });
+}
+
+fn market_summary(market: &Market) -> MarketSummary {
+    MarketSummary {
+        id: market.id,
+        title: market.title.clone(),
+        slug: market.slug.clone(),
+        status: market.status.clone(),
+        yes_bps: engine::implied_yes_bps(market),
+        total_volume: market.total_volume,
+    }
+}
+
+#[ic_cdk::query]
+fn get_market_stats(market_id: u64) -> Option<MarketStats> {
+    let total_volume = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .get(&market_id)
+            .map(|market| market.total_volume)
+    })?;
+    let (trade_count, unique_traders) = TRADES.with(|trades| {
+        let trades = trades.borrow();
+        let market_trades: Vec<&Trade> = trades
+            .iter()
+            .filter(|t| t.market_id == market_id && t.reverted_at.is_none())
+            .collect();
+        let traders: HashSet<Principal> = market_trades.iter().map(|t| t.trader).collect();
+        (market_trades.len() as u64, traders.len() as u64)
+    });
+    let comment_count = COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .filter(|c| c.market_id == market_id)
+            .count() as u64
+    });
+
+    Some(MarketStats {
+        market_id,
+        total_volume,
+        trade_count,
+        unique_traders,
+        comment_count,
+        discussion_mentions: MARKET_MENTIONS
+            .with(|mentions| mentions.borrow().get(&market_id).copied().unwrap_or(0)),
+    })
+}
+
+// Drops the market's oldest comments until it is back at the cap
+fn prune_market_comments(market_id: u64) {
+    let cap = get_max_comments_per_market() as usize;
+    let pruned: Vec<u64> = COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        let ids: Vec<u64> = comments
+            .iter()
+            .filter(|c| c.market_id == market_id)
+            .map(|c| c.id)
+            .collect();
+        let excess = ids.len().saturating_sub(cap);
+        let pruned = ids[..excess].to_vec();
+        comments.retain(|c| !pruned.contains(&c.id));
+        pruned
+    });
+
+    COMMENT_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        for id in &pruned {
+            history.remove(id);
+        }
+    });
+}
+
+fn notify_comment_subscribers(market_id: u64, comment_id: u64, author: Principal, now: u64) {
+    let subscribers: Vec<Principal> = COMMENT_SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .get(&market_id)
+            .map(|set| set.iter().filter(|p| **p != author).copied().collect())
+            .unwrap_or_default()
+    });
+    // New comments in a followed discussion count as replies
+    let subscribers: Vec<Principal> = subscribers
+        .into_iter()
+        .filter(|subscriber| {
+            notification_allowed(*subscriber, NotificationKind::CommentReply, Some(market_id))
+        })
+        .collect();
+
+    COMMENT_NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        for subscriber in subscribers {
+            let inbox = notifications.entry(subscriber).or_default();
+            // Fold into the open window for this market if there is one
+            let open = inbox.iter_mut().rev().find(|n| {
+                n.market_id == market_id
+                    && now.saturating_sub(n.first_at) < COMMENT_NOTIFICATION_WINDOW_NANOS
+            });
+            match open {
+                Some(notification) => {
+                    notification.new_comments += 1;
+                    notification.latest_comment_id = comment_id;
+                    notification.last_at = now;
+                }
+                None => {
+                    inbox.push_back(CommentNotification {
+                        market_id,
+                        new_comments: 1,
+                        latest_comment_id: comment_id,
+                        first_at: now,
+                        last_at: now,
+                    });
+                    if inbox.len() > MAX_COMMENT_NOTIFICATIONS {
+                        inbox.pop_front();
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn subscribe_to_comments(market_id: u64, subscriber: Principal) -> Result<(), String> {
+    COMMENT_SUBSCRIBERS.with(|subscribers| {
+        let mut subscribers = subscribers.borrow_mut();
+        let set = subscribers.entry(market_id).or_default();
+        if !set.contains(&subscriber) && set.len() >= MAX_COMMENT_SUBSCRIBERS {
+            return Err("Subscriber limit reached for this market".to_string());
+        }
+        set.insert(subscriber);
+        Ok(())
+    })
+}
+
+// Follow a market's discussion without trading or watching it
+#[ic_cdk::update]
+fn subscribe_comments(market_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot subscribe".to_string());
+    }
+    if !MARKETS.with(|markets| markets.borrow().contains_key(&market_id)) {
+        return Err("Market not found".to_string());
+    }
+
+    subscribe_to_comments(market_id, caller)
+}
+
+#[ic_cdk::update]
+fn unsubscribe_comments(market_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    COMMENT_SUBSCRIBERS.with(|subscribers| {
+        let mut subscribers = subscribers.borrow_mut();
+        if let Some(set) = subscribers.get_mut(&market_id) {
+            set.remove(&caller);
+            if set.is_empty() {
+                subscribers.remove(&market_id);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_my_comment_subscriptions() -> Vec<u64> {
+    let caller = ic_cdk::caller();
+    let mut market_ids: Vec<u64> = COMMENT_SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .iter()
+            .filter(|(_, set)| set.contains(&caller))
+            .map(|(market_id, _)| *market_id)
+            .collect()
+    });
+    market_ids.sort_unstable();
+    market_ids
+}
+
+// Oldest first
+#[ic_cdk::query]
+fn get_my_comment_notifications() -> Vec<CommentNotification> {
+    let caller = ic_cdk::caller();
+    COMMENT_NOTIFICATIONS.with(|notifications| {
+        notifications
+            .borrow()
+            .get(&caller)
+            .map(|inbox| inbox.iter().cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+#[ic_cdk::update]
+fn clear_comment_notifications() {
+    let caller = ic_cdk::caller();
+    COMMENT_NOTIFICATIONS.with(|notifications| notifications.borrow_mut().remove(&caller));
+}
+
+// Whether commenting subscribes the caller to the market's discussion (default on)
+#[ic_cdk::query]
+fn get_comment_auto_subscribe() -> bool {
+    let caller = ic_cdk::caller();
+    !COMMENT_AUTO_SUBSCRIBE_DISABLED.with(|disabled| disabled.borrow().contains(&caller))
+}
+
+#[ic_cdk::update]
+fn set_comment_auto_subscribe(enabled: bool) {
+    let caller = ic_cdk::caller();
+    COMMENT_AUTO_SUBSCRIBE_DISABLED.with(|disabled| {
+        let mut disabled = disabled.borrow_mut();
+        if enabled {
+            disabled.remove(&caller);
+        } else {
+            disabled.insert(caller);
+        }
+    });
+}
+
+fn set_comments_locked(market_id: u64, locked: bool) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        if !is_creator(market, caller) && !is_admin(&caller) {
+            return Err("Only the creator or an admin can lock comments".to_string());
+        }
+
+        market.comments_locked = locked;
+        Ok(())
+    })?;
+
+    let kind = if locked {
+        MarketEventKind::CommentsLocked
+    } else {
+        MarketEventKind::CommentsUnlocked
+    };
+    record_event(market_id, kind, caller);
+
+    Ok(())
+}
+
+// Existing comments stay visible while a market's comments are locked
+#[ic_cdk::update]
+fn lock_comments(market_id: u64) -> Result<(), String> {
+    set_comments_locked(market_id, true)
+}
+
+#[ic_cdk::update]
+fn unlock_comments(market_id: u64) -> Result<(), String> {
+    set_comments_locked(market_id, false)
+}
+
+#[ic_cdk::query]
+fn get_market_events(market_id: u64) -> Vec<MarketEvent> {
+    MARKET_EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .filter(|event| event.market_id == market_id)
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::query(composite = true)]
+async fn get_market_comments(market_id: u64) -> Vec<MarketComment> {
+    if !reads_allowed() {
+        return vec![];
+    }
+    if let Some(archive) = archived_to(market_id) {
+        return fetch_archive(archive, market_id)
+            .await
+            .map(|archived| archived.comments.into_iter().rev().collect())
+            .unwrap_or_default();
+    }
+
+    // Newest first
+    COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|comment| comment.market_id == market_id)
+            .cloned()
+            .collect()
+    })
+}
+
+fn position_bucket(market_id: u64, owner: Principal) -> PositionBucket {
+    let net = POSITIONS.with(|positions| {
+        positions
+            .borrow()
+            .get(&(owner, market_id))
+            .map_or(0, |p| p.yes_shares.abs_diff(p.no_shares))
+    });
+    match net {
+        0 => PositionBucket::None,
+        n if n < MEDIUM_POSITION_SHARES => PositionBucket::Small,
+        n if n < LARGE_POSITION_SHARES => PositionBucket::Medium,
+        _ => PositionBucket::Large,
+    }
+}
+
+// Ties in every order but Oldest fall back to newest first
+fn sorted_comments(market_id: u64, sort: CommentSort) -> Vec<RankedComment> {
+    let mut ranked: Vec<RankedComment> = COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|comment| comment.market_id == market_id)
+            .map(|comment| RankedComment {
+                author_position: position_bucket(market_id, comment.author),
+                comment: comment.clone(),
+            })
+            .collect()
+    });
+
+    match sort {
+        CommentSort::Newest | CommentSort::MostLiked => {}
+        CommentSort::Oldest => ranked.reverse(),
+        // Stable, so newest-first order is kept within a bucket
+        CommentSort::StakeWeighted => {
+            ranked.sort_by_key(|ranked| std::cmp::Reverse(ranked.author_position))
+        }
+    }
+    ranked
+}
+
+#[ic_cdk::query]
+fn get_market_comments_page(
+    market_id: u64,
+    sort: CommentSort,
+    offset: u64,
+    limit: u64,
+) -> CommentPage {
+    if !reads_allowed() {
+        return CommentPage {
+            comments: vec![],
+            referenced_markets: vec![],
+        };
+    }
+
+    let comments: Vec<RankedComment> = sorted_comments(market_id, sort)
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_COMMENT_PAGE) as usize)
+        .collect();
+    let referenced: BTreeSet<u64> = comments
+        .iter()
+        .flat_map(|ranked| ranked.comment.market_refs.iter().copied())
+        .collect();
+    let referenced_markets = MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        referenced
+            .iter()
+            .filter_map(|id| markets.get(id).map(market_summary))
+            .collect()
+    });
+
+    CommentPage {
+        comments,
+        referenced_markets,
+    }
+}
+
+// Plain comments, without the author's position bucket
+#[ic_cdk::query]
+fn get_market_comments_sorted(
+    market_id: u64,
+    sort: CommentSort,
+    offset: u64,
+    limit: u64,
+) -> Vec<MarketComment> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    sorted_comments(market_id, sort)
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_COMMENT_PAGE) as usize)
+        .map(|ranked| ranked.comment)
+        .collect()
+}
+
+#[ic_cdk::query]
+fn get_comment_stats(user: Principal) -> CommentStats {
+    if !reads_allowed() {
+        return CommentStats {
+            total_comments: 0,
+            total_likes_received: 0,
+            markets_commented: 0,
+        };
+    }
+
+    COMMENTS.with(|comments| {
+        let comments = comments.borrow();
+        let authored: Vec<_> = comments.iter().filter(|c| c.author == user).collect();
+        let markets: HashSet<u64> = authored.iter().map(|c| c.market_id).collect();
+
+        CommentStats {
+            total_comments: authored.len() as u64,
+            total_likes_received: 0,
+            markets_commented: markets.len() as u64,
+        }
+    })
+}
+
+// Trades plus comments per UTC day for the last `days` days including today,
+// oldest first, as (day start in seconds, count). Idle days are included.
+#[ic_cdk::query]
+fn get_activity_heatmap(principal: Principal, days: u64) -> Vec<(u64, u64)> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let days = days.min(MAX_HEATMAP_DAYS);
+    let today = to_secs(ic_cdk::api::time()) / SECONDS_PER_DAY;
+    let first = (today + 1).saturating_sub(days);
+
+    let mut heatmap: Vec<(u64, u64)> = (first..=today)
+        .map(|day| (day * SECONDS_PER_DAY, 0))
+        .collect();
+    let mut count = |timestamp: u64| {
+        let day = to_secs(timestamp) / SECONDS_PER_DAY;
+        if day >= first && day <= today {
+            heatmap[(day - first) as usize].1 += 1;
+        }
+    };
+    TRADES.with(|trades| {
+        for trade in trades
+            .borrow()
+            .iter()
+            .filter(|t| t.trader == principal && t.reverted_at.is_none())
+        {
+            count(trade.timestamp);
+        }
+    });
+    COMMENTS.with(|comments| {
+        for comment in comments.borrow().iter().filter(|c| c.author == principal) {
+            count(comment.timestamp);
+        }
+    });
+    heatmap
+}
+
+#[ic_cdk::update]
+fn edit_comment(comment_id: u64, content: String) -> Result<MarketComment, String> {
+    let caller = ic_cdk::caller();
+
+    if content.is_empty() || content.len() > 500 {
+        return Err("Comment must be between 1 and 500 characters".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+
+    let (updated, previous) = COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        let comment = comments
+            .iter_mut()
+            .find(|c| c.id == comment_id)
+            .ok_or_else(|| "Comment not found".to_string())?;
+
+        if comment.author != caller {
+            return Err("Only the author can edit this comment".to_string());
+        }
+
+        // Only references the edit adds count as new mentions
+        let market_refs = market_refs_in(&content, comment.market_id);
+        let added: Vec<u64> = market_refs
+            .iter()
+            .filter(|id| !comment.market_refs.contains(id))
+            .copied()
+            .collect();
+        count_mentions(&added);
+        comment.market_refs = market_refs;
+
+        let previous = std::mem::replace(&mut comment.content, content);
+        comment.edited_at = Some(now);
+        Ok((comment.clone(), previous))
+    })?;
+
+    COMMENT_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let revisions = history.entry(comment_id).or_default();
+        let version = revisions.last().map(|r| r.version + 1).unwrap_or(1);
+        revisions.push(CommentRevision {
+            version,
+            content: previous,
+            editor: caller,
+            edited_at: now,
+        });
+        // Keep only the most recent revisions
+        if revisions.len() > MAX_REVISIONS {
+            revisions.remove(0);
+        }
+    });
+
+    Ok(updated)
+}
+
+#[ic_cdk::update]
+fn delete_comment(comment_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    let market_id = COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        let index = comments
+            .iter()
+            .position(|c| c.id == comment_id)
+            .ok_or_else(|| "Comment not found".to_string())?;
+
+        if comments[index].author != caller && !is_admin(&caller) {
+            return Err("Only the author or an admin can delete this comment".to_string());
+        }
+
+        Ok(comments.remove(index).market_id)
+    })?;
+
+    // Purging a comment purges its edit history
+    COMMENT_HISTORY.with(|history| history.borrow_mut().remove(&comment_id));
+    refresh_market_card(market_id);
+
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_comment_history(comment_id: u64) -> Result<Vec<CommentRevision>, String> {
+    let caller = ic_cdk::caller();
+
+    let author = COMMENTS
+        .with(|comments| {
+            comments
+                .borrow()
+                .iter()
+                .find(|c| c.id == comment_id)
+                .map(|c| c.author)
+        })
+        .ok_or_else(|| "Comment not found".to_string())?;
+
+    if author != caller && !is_admin(&caller) {
+        return Err("Only the author or an admin can view comment history".to_string());
+    }
+
+    Ok(COMMENT_HISTORY.with(|history| {
+        history
+            .borrow()
+            .get(&comment_id)
+            .cloned()
+            .unwrap_or_default()
+    }))
+}
+
+#[ic_cdk::update]
+fn update_market(
+    market_id: u64,
+    title: String,
+    description: String,
+    category: String,
+    close_date: u64,
+) -> Result<Market, String> {
+    let caller = ic_cdk::caller();
+
+    if title.is_empty() || description.is_empty() {
+        return Err("Title and description cannot be empty".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+
+    let (updated, previous) = MARKETS.with(|markets| {
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get_mut(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        // Co-owners share editing only until the market is approved
+        let co_owner_edit = market.co_owners.contains(&caller)
+            && matches!(market.status, MarketStatus::PendingValidation);
+        if !is_creator(market, caller) && !co_owner_edit && !is_admin(&caller) {
+            return Err("Only the creator or an admin can edit this market".to_string());
+        }
+
+        if !matches!(
+            market.status,
+            MarketStatus::PendingValidation | MarketStatus::Active
+        ) {
+            return Err("Only pending or active markets can be edited".to_string());
+        }
+        // A market may keep a category that was removed since
+        if category != market.category {
+            categories::validate_key(&category)?;
+        }
+
+        let previous = market.clone();
+        market.title = title;
+        market.description = description;
+        market.category = category;
+        market.close_date = close_date;
+        Ok((market.clone(), previous))
+    })?;
+    refresh_market_card(market_id);
+
+    // Long descriptions are truncated in old revisions to bound state growth
+    let description_truncated = previous.description.chars().count() > MAX_REVISION_DESCRIPTION_LEN;
+    let description = previous
+        .description
+        .chars()
+        .take(MAX_REVISION_DESCRIPTION_LEN)
+        .collect();
+
+    MARKET_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let revisions = history.entry(market_id).or_default();
+        let version = revisions.last().map(|r| r.version + 1).unwrap_or(1);
+        revisions.push(MarketRevision {
+            version,
+            title: previous.title,
+            description,
+            description_truncated,
+            category: previous.category,
+            close_date: previous.close_date,
+            editor: caller,
+            edited_at: now,
+        });
+        if revisions.len() > MAX_REVISIONS {
+            revisions.remove(0);
+        }
+    });
+
+    Ok(updated)
+}
+
+// Market edit history is public since resolution criteria changes matter to traders
+#[ic_cdk::query]
+fn get_market_edit_history(market_id: u64) -> Vec<MarketRevision> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    MARKET_HISTORY.with(|history| {
+        history
+            .borrow()
+            .get(&market_id)
+            .cloned()
+            .unwrap_or_default()
+    })
+}
+
+// FAQ functions
+// The FAQ freezes once the market stops trading so resolution criteria can't be
+// reinterpreted after the fact
+fn is_faq_frozen(market: &Market, faq: Option<&MarketFaq>) -> bool {
+    let trading = matches!(
+        market.status,
+        MarketStatus::PendingValidation | MarketStatus::Active
+    );
+    let past_close = ic_cdk::api::time() >= market.close_date;
+    !trading || past_close || faq.is_some_and(|f| f.force_frozen)
+}
+
+fn validate_faq_entry(question: &str, answer: &str) -> Result<(String, String), String> {
+    let question = sanitize_text(question);
+    let answer = sanitize_text(answer);
+
+    if question.is_empty() || question.chars().count() > MAX_FAQ_QUESTION_LEN {
+        return Err(format!(
+            "Question must be between 1 and {} characters",
+            MAX_FAQ_QUESTION_LEN
+        ));
+    }
+    if answer.is_empty() || answer.chars().count() > MAX_FAQ_ANSWER_LEN {
+        return Err(format!(
+            "Answer must be between 1 and {} characters",
+            MAX_FAQ_ANSWER_LEN
+        ));
+    }
+
+    Ok((question, answer))
+}
+
+// Checks that the caller may edit the market's FAQ and returns whether the market is Active
+fn check_faq_editable(market_id: u64, caller: Principal) -> Result<bool, String> {
+    let market = MARKETS
+        .with(|markets| markets.borrow().get(&market_id).cloned())
+        .ok_or_else(|| "Market not found".to_string())?;
+
+    if !is_market_owner(&market, caller) {
+        return Err("Only the market creator or a co-owner can manage the FAQ".to_string());
+    }
+
+    let frozen = MARKET_FAQS.with(|faqs| is_faq_frozen(&market, faqs.borrow().get(&market_id)));
+    if frozen {
+        return Err("The FAQ for this market is frozen".to_string());
+    }
+
+    Ok(matches!(market.status, MarketStatus::Active))
+}
+
+// FAQ edits on Active markets amend resolution criteria, so they are version-tracked.
+// Each entry keeps up to MAX_REVISIONS revisions. Past that its oldest edits
+// are dropped, but never the one that added it or the one holding its original
+// wording, so a busy entry cannot push out how it first read. Once the market
+// as a whole is over its bound, entries that were removed lose theirs first.
+fn record_faq_revision(market_id: u64, revision: FaqRevision) {
+    let faq_id = revision.faq_id;
+    FAQ_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let revisions = history.entry(market_id).or_default();
+        let version = revisions.last().map(|r| r.version + 1).unwrap_or(1);
+        revisions.push(FaqRevision {
+            version,
+            ..revision
+        });
+
+        let entry: Vec<usize> = (0..revisions.len())
+            .filter(|i| revisions[*i].faq_id == faq_id)
+            .collect();
+        if entry.len() > MAX_REVISIONS {
+            let kept = match revisions[entry[0]].change {
+                FaqChange::Added => 2,
+                _ => 1,
+            };
+            revisions.remove(entry[kept]);
+        }
+
+        if revisions.len() > MAX_FAQ_ENTRIES * MAX_REVISIONS {
+            let removed: HashSet<u64> = revisions
+                .iter()
+                .filter(|r| matches!(r.change, FaqChange::Removed))
+                .map(|r| r.faq_id)
+                .collect();
+            if let Some(index) = revisions.iter().position(|r| removed.contains(&r.faq_id)) {
+                revisions.remove(index);
+            }
+        }
+    });
+}
+
+#[ic_cdk::update]
+fn add_market_faq(market_id: u64, question: String, answer: String) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    let is_active = check_faq_editable(market_id, caller)?;
+    let (question, answer) = validate_faq_entry(&question, &answer)?;
+    let now = ic_cdk::api::time();
+
+    let faq_id = MARKET_FAQS.with(|faqs| {
+        let mut faqs = faqs.borrow_mut();
+        let faq = faqs.entry(market_id).or_default();
+
+        if faq.entries.len() >= MAX_FAQ_ENTRIES {
+            return Err(format!(
+                "A market can have at most {} FAQ entries",
+                MAX_FAQ_ENTRIES
+            ));
+        }
+
+        faq.next_id += 1;
+        let faq_id = faq.next_id;
+        faq.entries.push(FaqEntry {
+            id: faq_id,
+            question,
+            answer,
+            created_at: now,
+            updated_at: now,
+        });
+        Ok(faq_id)
+    })?;
+
+    if is_active {
+        record_faq_revision(
+            market_id,
+            FaqRevision {
+                version: 0,
+                faq_id,
+                change: FaqChange::Added,
+                question: String::new(),
+                answer: String::new(),
+                editor: caller,
+                edited_at: now,
+            },
+        );
+    }
+
+    Ok(faq_id)
+}
+
+#[ic_cdk::update]
+fn update_market_faq(
+    market_id: u64,
+    faq_id: u64,
+    question: String,
+    answer: String,
+) -> Result<FaqEntry, String> {
+    let caller = ic_cdk::caller();
+    let is_active = check_faq_editable(market_id, caller)?;
+    let (question, answer) = validate_faq_entry(&question, &answer)?;
+    let now = ic_cdk::api::time();
+
+    let (updated, previous) = MARKET_FAQS.with(|faqs| {
+        let mut faqs = faqs.borrow_mut();
+        let entry = faqs
+            .get_mut(&market_id)
+            .and_then(|faq| faq.entries.iter_mut().find(|e| e.id == faq_id))
+            .ok_or_else(|| "FAQ entry not found".to_string())?;
+
+        let previous = entry.clone();
+        entry.question = question;
+        entry.answer = answer;
+        entry.updated_at = now;
+        Ok::<_, String>((entry.clone(), previous))
+    })?;
+
+    if is_active {
+        record_faq_revision(
+            market_id,
+            FaqRevision {
+                version: 0,
+                faq_id,
+                change: FaqChange::Updated,
+                question: previous.question,
+                answer: previous.answer,
+                editor: caller,
+                edited_at: now,
+            },
+        );
+    }
+
+    Ok(updated)
+}
+
+#[ic_cdk::update]
+fn remove_market_faq(market_id: u64, faq_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let is_active = check_faq_editable(market_id, caller)?;
+
+    let removed = MARKET_FAQS.with(|faqs| {
+        let mut faqs = faqs.borrow_mut();
+        let faq = faqs
+            .get_mut(&market_id)
+            .ok_or_else(|| "FAQ entry not found".to_string())?;
+        let index = faq
+            .entries
+            .iter()
+            .position(|e| e.id == faq_id)
+            .ok_or_else(|| "FAQ entry not found".to_string())?;
+        Ok::<_, String>(faq.entries.remove(index))
+    })?;
+
+    if is_active {
+        record_faq_revision(
+            market_id,
+            FaqRevision {
+                version: 0,
+                faq_id,
+                change: FaqChange::Removed,
+                question: removed.question,
+                answer: removed.answer,
+                editor: caller,
+                edited_at: ic_cdk::api::time(),
+            },
+        );
+    }
+
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn freeze_market_faq(market_id: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can freeze a market FAQ".to_string());
+    }
+
+    if MARKETS.with(|markets| !markets.borrow().contains_key(&market_id)) {
+        return Err("Market not found".to_string());
+    }
+
+    MARKET_FAQS.with(|faqs| {
+        faqs.borrow_mut().entry(market_id).or_default().force_frozen = true;
+    });
+
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_market_faq_history(market_id: u64) -> Vec<FaqRevision> {
+    FAQ_HISTORY.with(|history| {
+        history
+            .borrow()
+            .get(&market_id)
+            .cloned()
+            .unwrap_or_default()
+    })
+}
+
+#[ic_cdk::query]
+fn get_market_detail(market_id: u64) -> Option<MarketDetail> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    let market = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned())?;
+    let now = ic_cdk::api::time();
+
+    MARKET_FAQS.with(|faqs| {
+        let faqs = faqs.borrow();
+        let faq = faqs.get(&market_id);
+        Some(MarketDetail {
+            faq: faq.map(|f| f.entries.clone()).unwrap_or_default(),
+            faq_frozen: is_faq_frozen(&market, faq),
+            trading_open: next_trading_open(&market, now).is_none(),
+            next_open: next_trading_open(&market, now).flatten(),
+            boosts: get_market_boosts(market_id),
+            resolution_countdown: resolution_countdown(&market, now),
+            flow: flow_info(market_id, now),
+            ownership_history: ownership_history(market_id),
+            resolution_timeline: resolution_timeline(&market, ic_cdk::caller(), now),
+            market,
+        })
+    })
+}
+
+fn resolution_timeline(market: &Market, caller: Principal, now: u64) -> Option<ResolutionTimeline> {
+    let closed = match market.status {
+        MarketStatus::Active => now >= market.close_date,
+        MarketStatus::Closed | MarketStatus::Resolved => true,
+        _ => false,
+    };
+    if !closed {
+        return None;
+    }
+
+    let resolved = matches!(market.status, MarketStatus::Resolved);
+    let assignment = resolvers::assignment(market.id).filter(|_| resolved);
+    let dispute_window_remaining = assignment
+        .as_ref()
+        .filter(|assignment| assignment.status == resolvers::AssignmentStatus::Pending)
+        .map(|assignment| {
+            let elapsed = now.saturating_sub(assignment.resolved_at);
+            to_secs(resolvers::DISPUTE_WINDOW_NANOS.saturating_sub(elapsed))
+        })
+        .filter(|remaining| *remaining > 0);
+    let phase = if !resolved {
+        ResolutionPhase::AwaitingResolution
+    } else if dispute_window_remaining.is_some() {
+        ResolutionPhase::DisputeWindow
+    } else if assignment
+        .is_some_and(|assignment| assignment.status == resolvers::AssignmentStatus::Disputed)
+    {
+        ResolutionPhase::Disputed
+    } else {
+        ResolutionPhase::Final
+    };
+
+    let claimable = POSITIONS.with(|positions| {
+        positions
+            .borrow()
+            .get(&(caller, market.id))
+            .filter(|position| !position.claimed)
+            .map_or(0, |position| engine::winnings_for(market, position))
+    });
+
+    Some(ResolutionTimeline {
+        phase,
+        resolution_deadline: market
+            .close_date
+            .saturating_add(to_nanos(RESOLUTION_WINDOW_SECONDS)),
+        resolved_at: resolved.then(|| resolved_at(market.id)).flatten(),
+        dispute_window_remaining,
+        claimable,
+        evidence_submitted: market
+            .resolution_criteria
+            .as_ref()
+            .is_some_and(|criteria| criteria.verdicts.is_some()),
+    })
+}
+
+// Seconds until the resolution deadline, negative if overdue. None while the
+// market is still open or once it has been resolved.
+fn resolution_countdown(market: &Market, now: u64) -> Option<i64> {
+    let closed = matches!(market.status, MarketStatus::Closed) || now >= market.close_date;
+    if !closed || matches!(market.status, MarketStatus::Resolved) {
+        return None;
+    }
+
+    let deadline = market
+        .close_date
+        .saturating_add(to_nanos(RESOLUTION_WINDOW_SECONDS));
+    Some((deadline as i64 - now as i64).div_euclid(1_000_000_000))
+}
+
+#[ic_cdk::query]
+fn get_resolution_countdown(market_id: u64) -> Option<i64> {
+    if !reads_allowed() {
+        return None;
+    }
+
+    let market = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned())?;
+    resolution_countdown(&market, ic_cdk::api::time())
+}
+
+// Archive functions
+// Resolved markets past a retention age have their trades, comments, insight
+// and price history moved to a separate archive canister. The Market record
+// and positions stay here, so claims keep working; reads of the moved data are
+// proxied to the archive.
+#[ic_cdk::query]
+fn get_archive_canister() -> Option<Principal> {
+    ARCHIVE_CANISTER.with(|archive| *archive.borrow())
+}
+
+#[ic_cdk::update]
+fn set_archive_canister(archive: Option<Principal>) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can set the archive canister".to_string());
+    }
+
+    ARCHIVE_CANISTER.with(|current| *current.borrow_mut() = archive);
+    Ok(())
+}
+
+fn archived_to(market_id: u64) -> Option<Principal> {
+    MARKETS.with(|markets| markets.borrow().get(&market_id)?.archived_to)
+}
+
+// When the market was resolved, from the event log
+fn resolved_at(market_id: u64) -> Option<u64> {
+    MARKET_EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .rev()
+            .find(|e| e.market_id == market_id && matches!(e.kind, MarketEventKind::Resolved))
+            .map(|e| e.timestamp)
+    })
+}
+
+// Resolved markets, most recently resolved first
+#[ic_cdk::query]
+fn get_recent_resolutions(limit: u64) -> Vec<ResolvedMarketView> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let resolved_times: HashMap<u64, u64> = MARKET_EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .filter(|e| matches!(e.kind, MarketEventKind::Resolved))
+            .map(|e| (e.market_id, e.timestamp))
+            .collect()
+    });
+
+    let mut winners: HashMap<u64, u64> = HashMap::new();
+    MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        POSITIONS.with(|positions| {
+            for position in positions.borrow().values() {
+                let Some(outcome) = markets
+                    .get(&position.market_id)
+                    .and_then(|m| m.resolved_outcome)
+                else {
+                    continue;
+                };
+                let held = match outcome {
+                    Outcome::Yes => position.yes_shares,
+                    Outcome::No => position.no_shares,
+                    Outcome::Invalid => 0, // refunds, not winnings
+                };
+                if held > 0 {
+                    *winners.entry(position.market_id).or_default() += 1;
+                }
+            }
+        });
+    });
+
+    let mut resolved: Vec<ResolvedMarketView> = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter_map(|market| {
+                Some(ResolvedMarketView {
+                    market_id: market.id,
+                    title: market.title.clone(),
+                    resolved_outcome: market.resolved_outcome?,
+                    resolved_at: *resolved_times.get(&market.id)?,
+                    total_volume: market.total_volume,
+                    winners: winners.get(&market.id).copied().unwrap_or(0),
+                })
+            })
+            .collect()
+    });
+    resolved.sort_by(|a, b| {
+        b.resolved_at
+            .cmp(&a.resolved_at)
+            .then(b.market_id.cmp(&a.market_id))
+    });
+    resolved.truncate(limit.min(MAX_RECENT_RESOLUTIONS) as usize);
+    resolved
+}
+
+// Archives up to `limit` markets resolved more than `older_than_days` ago,
+// oldest market id first. Each market succeeds or fails on its own.
+#[ic_cdk::update]
+async fn archive_resolved_markets(
+    older_than_days: u64,
+    limit: u32,
+) -> Result<Vec<ArchiveOutcome>, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can archive markets".to_string());
+    }
+    let archive = get_archive_canister().ok_or_else(|| "No archive canister set".to_string())?;
+
+    Ok(archive_batch(archive, older_than_days, limit as usize).await)
+}
+
+async fn archive_batch(
+    archive: Principal,
+    older_than_days: u64,
+    limit: usize,
+) -> Vec<ArchiveOutcome> {
+    let cutoff = ic_cdk::api::time()
+        .saturating_sub(to_nanos(older_than_days.saturating_mul(SECONDS_PER_DAY)));
+    let mut candidates: Vec<u64> = MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .filter(|m| matches!(m.status, MarketStatus::Resolved) && m.archived_to.is_none())
+            .map(|m| m.id)
+            .collect()
+    });
+    candidates.sort_unstable();
+    candidates.retain(|id| {
+        resolved_at(*id).is_some_and(|at| at <= cutoff)
+            && !ARCHIVING.with(|archiving| archiving.borrow().contains(id))
+    });
+    candidates.truncate(limit);
+
+    let mut outcomes = vec![];
+    for market_id in candidates {
+        ARCHIVING.with(|archiving| archiving.borrow_mut().insert(market_id));
+        let result = archive_market_data(archive, market_id).await;
+        ARCHIVING.with(|archiving| archiving.borrow_mut().remove(&market_id));
+        if result.is_err() {
+            // Best effort; a stale pending upload is also replaced on retry
+            let _: ic_cdk::api::call::CallResult<(Result<(), String>,)> =
+                ic_cdk::call(archive, "abort", (market_id,)).await;
+        }
+        outcomes.push(ArchiveOutcome { market_id, result });
+    }
+    outcomes
+}
+
+#[ic_cdk::query]
+fn get_auto_archive_config() -> AutoArchiveConfig {
+    AUTO_ARCHIVE_CONFIG.with(|config| config.borrow().clone())
+}
+
+#[ic_cdk::update]
+fn set_auto_archive_enabled(enabled: bool) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can configure auto-archival".to_string());
+    }
+
+    AUTO_ARCHIVE_CONFIG.with(|config| config.borrow_mut().enabled = enabled);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_auto_archive_age_days(older_than_days: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can configure auto-archival".to_string());
+    }
+
+    AUTO_ARCHIVE_CONFIG.with(|config| config.borrow_mut().older_than_days = older_than_days);
+    Ok(())
+}
+
+// Most recent runs last
+#[ic_cdk::query]
+fn get_auto_archive_runs() -> Vec<AutoArchiveRun> {
+    AUTO_ARCHIVE_RUNS.with(|runs| runs.borrow().iter().cloned().collect())
+}
+
+// One scheduled pass; at most one runs at a time since archiving spans calls
+fn start_auto_archive() {
+    let config = get_auto_archive_config();
+    let Some(archive) = get_archive_canister() else {
+        return;
+    };
+    if !config.enabled || AUTO_ARCHIVE_IN_FLIGHT.with(|in_flight| in_flight.replace(true)) {
+        return;
+    }
+
+    ic_cdk::spawn(async move {
+        let started_at = ic_cdk::api::time();
+        let outcomes = archive_batch(archive, config.older_than_days, AUTO_ARCHIVE_BATCH).await;
+        let archived = outcomes.iter().filter(|o| o.result.is_ok()).count() as u64;
+
+        AUTO_ARCHIVE_RUNS.with(|runs| {
+            let mut runs = runs.borrow_mut();
+            runs.push_back(AutoArchiveRun {
+                started_at,
+                archived,
+                failed: outcomes.len() as u64 - archived,
+            });
+            if runs.len() > MAX_AUTO_ARCHIVE_RUNS {
+                runs.pop_front();
+            }
+        });
+        AUTO_ARCHIVE_IN_FLIGHT.with(|in_flight| *in_flight.borrow_mut() = false);
+    });
+}
+
+async fn archive_market_data(archive: Principal, market_id: u64) -> Result<u64, String> {
+    let trades: Vec<Trade> = TRADES.with(|trades| {
+        trades
+            .borrow()
+            .iter()
+            .filter(|t| t.market_id == market_id)
+            .cloned()
+            .collect()
+    });
+    let comments: Vec<MarketComment> = COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .filter(|c| c.market_id == market_id)
+            .cloned()
+            .collect()
+    });
+    let comment_history = COMMENT_HISTORY.with(|history| {
+        let history = history.borrow();
+        comments
+            .iter()
+            .filter_map(|c| Some((c.id, history.get(&c.id)?.clone())))
+            .collect()
+    });
+    let snapshot = MarketArchive {
+        market_id,
+        trades,
+        comments,
+        comment_history,
+        insight: AI_INSIGHTS.with(|insights| insights.borrow().get(&market_id).cloned()),
+        price_history: PRICE_HISTORY.with(|history| {
+            history
+                .borrow()
+                .get(&market_id)
+                .map(|snapshots| snapshots.iter().cloned().collect())
+                .unwrap_or_default()
+        }),
+        liquidity_history: LIQUIDITY_HISTORY
+            .with(|history| history.borrow().get(&market_id).cloned()),
+    };
+
+    let blob = candid::encode_one(&snapshot).map_err(|e| format!("Encoding failed: {}", e))?;
+    let expected = Sha256::digest(&blob).to_vec();
+
+    for (i, chunk) in blob.chunks(ARCHIVE_CHUNK_SIZE).enumerate() {
+        let offset = (i * ARCHIVE_CHUNK_SIZE) as u64;
+        let (result,): (Result<(), String>,) =
+            ic_cdk::call(archive, "append_chunk", (market_id, offset, chunk.to_vec()))
+                .await
+                .map_err(|(code, msg)| format!("Archive call failed: {:?} {}", code, msg))?;
+        result?;
+    }
+    let (hash,): (Result<Vec<u8>, String>,) =
+        ic_cdk::call(archive, "commit", (market_id,))
+            .await
+            .map_err(|(code, msg)| format!("Archive call failed: {:?} {}", code, msg))?;
+    if hash? != expected {
+        return Err("Archive hash mismatch; local data kept".to_string());
+    }
+
+    // Prune exactly what was archived
+    let trade_ids: HashSet<u64> = snapshot.trades.iter().map(|t| t.id).collect();
+    let comment_ids: HashSet<u64> = snapshot.comments.iter().map(|c| c.id).collect();
+    TRADES.with(|trades| trades.borrow_mut().retain(|t| !trade_ids.contains(&t.id)));
+    COMMENTS.with(|comments| {
+        comments
+            .borrow_mut()
+            .retain(|c| !comment_ids.contains(&c.id))
+    });
+    COMMENT_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        for id in &comment_ids {
+            history.remove(id);
+        }
+    });
+    AI_INSIGHTS.with(|insights| insights.borrow_mut().remove(&market_id));
+    INSIGHT_REFRESHES.with(|refreshes| refreshes.borrow_mut().remove(&market_id));
+    PRICE_HISTORY.with(|history| history.borrow_mut().remove(&market_id));
+    LIQUIDITY_HISTORY.with(|history| history.borrow_mut().remove(&market_id));
+    MARKETS.with(|markets| {
+        if let Some(market) = markets.borrow_mut().get_mut(&market_id) {
+            market.archived_to = Some(archive);
+        }
+    });
+    refresh_market_card(market_id);
+
+    Ok(blob.len() as u64)
+}
 
-    let sample_insights = vec![
-        AIInsight {
-            market_id: 1,
-            summary: "Bitcoin has shown strong institutional adoption and macroeconomic factors favor crypto. However, regulatory uncertainty remains a risk.".to_string(),
-            confidence: 0.72,
-            risks: vec!["Regulatory crackdowns".to_string(), "Market volatility".to_string(), "Macro economic shifts".to_string()],
-            prediction_lean: Some(true),
-            generated_at: 1767292799,
-        },
-        AIInsight {
-            market_id: 2,
-            summary: "OpenAI is likely to continue their rapid development cycle. GPT-5 announcement is probable given competitive pressure from other AI companies.".to_string(),
-            confidence: 0.65,
-            risks: vec!["Technical setbacks".to_string(), "Compute resource limitations".to_string(), "Safety concerns".to_string()],
-            prediction_lean: Some(true),
-            generated_at: 1767292799,
-        },
-        AIInsight {
-            market_id: 3,
-            summary: "Tesla faces production challenges and increased EV competition. Stock price target seems ambitious given current market conditions.".to_string(),
-            confidence: 0.58,
-            risks: vec!["Production delays".to_string(), "Increased competition".to_string(), "Economic recession".to_string()],
-            prediction_lean: Some(false),
-            generated_at: 1737273600,
-        },
-    ];
+async fn fetch_archive(archive: Principal, market_id: u64) -> Result<MarketArchive, String> {
+    let call_failed = |(code, msg): (ic_cdk::api::call::RejectionCode, String)| {
+        format!("Archive call failed: {:?} {}", code, msg)
+    };
+
+    let (size,): (Option<u64>,) = ic_cdk::call(archive, "get_archive_size", (market_id,))
+        .await
+        .map_err(call_failed)?;
+    let size = size.ok_or_else(|| "Market not found in archive".to_string())?;
+
+    let mut blob = Vec::with_capacity(size as usize);
+    while (blob.len() as u64) < size {
+        let (chunk,): (Option<Vec<u8>>,) = ic_cdk::call(
+            archive,
+            "get_archive_chunk",
+            (market_id, blob.len() as u64, ARCHIVE_CHUNK_SIZE as u64),
+        )
+        .await
+        .map_err(call_failed)?;
+        match chunk {
+            Some(chunk) if !chunk.is_empty() => blob.extend_from_slice(&chunk),
+            _ => return Err("Archive blob is truncated".to_string()),
+        }
+    }
+
+    candid::decode_one(&blob).map_err(|e| format!("Decoding failed: {}", e))
+}
+
+#[ic_cdk::update]
+fn delete_market(market_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller) {
+        return Err("Only admins can delete markets".to_string());
+    }
 
     MARKETS.with(|markets| {
-        let mut m = markets.borrow_mut();
-        for market in sample_markets {
-            m.insert(market.id, market);
+        let mut markets = markets.borrow_mut();
+        let market = markets
+            .get(&market_id)
+            .ok_or_else(|| "Market not found".to_string())?;
+
+        if market.total_volume > 0 {
+            return Err("Markets with trading activity cannot be deleted".to_string());
         }
+
+        let slug = market.slug.clone();
+        markets.remove(&market_id);
+        MARKET_SLUGS.with(|slugs| slugs.borrow_mut().remove(&slug));
+        Ok(())
+    })?;
+
+    // Purge the market's comments together with their histories
+    let removed_comments: Vec<u64> = COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        let removed = comments
+            .iter()
+            .filter(|c| c.market_id == market_id)
+            .map(|c| c.id)
+            .collect();
+        comments.retain(|c| c.market_id != market_id);
+        removed
     });
 
-    AI_INSIGHTS.with(|insights| {
-        let mut ai = insights.borrow_mut();
-        for insight in sample_insights {
-            ai.insert(insight.market_id, insight);
+    COMMENT_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        for comment_id in removed_comments {
+            history.remove(&comment_id);
         }
     });
 
-    NEXT_MARKET_ID.with(|id| *id.borrow_mut() = 4);
+    MARKET_HISTORY.with(|history| history.borrow_mut().remove(&market_id));
+    MARKET_CARDS.with(|cards| cards.borrow_mut().remove(&market_id));
+    hooks::clear(market_id);
+    MARKET_FAQS.with(|faqs| faqs.borrow_mut().remove(&market_id));
+    FAQ_HISTORY.with(|history| history.borrow_mut().remove(&market_id));
+    AI_INSIGHTS.with(|insights| insights.borrow_mut().remove(&market_id));
+    INSIGHT_REFRESHES.with(|refreshes| refreshes.borrow_mut().remove(&market_id));
+    PRICE_HISTORY.with(|history| history.borrow_mut().remove(&market_id));
+    LIQUIDITY_HISTORY.with(|history| history.borrow_mut().remove(&market_id));
+    COMMENT_SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().remove(&market_id));
+    COMMENT_NOTIFICATIONS.with(|notifications| {
+        for inbox in notifications.borrow_mut().values_mut() {
+            inbox.retain(|n| n.market_id != market_id);
+        }
+    });
+    MUTED_MARKETS.with(|muted| {
+        for markets in muted.borrow_mut().values_mut() {
+            markets.remove(&market_id);
+        }
+    });
+    MARKET_POLLS.with(|polls| polls.borrow_mut().remove(&market_id));
+    COMMENT_SENTIMENT.with(|cache| cache.borrow_mut().remove(&market_id));
+
+    Ok(())
+}
+
+// Order flow functions
+fn flow_hour(now: u64) -> u64 {
+    now / to_nanos(3_600)
+}
+
+fn record_flow(market_id: u64, is_yes: bool, amount: u64, now: u64) {
+    let hour = flow_hour(now);
+    FLOW_BUCKETS.with(|buckets| {
+        let mut buckets = buckets.borrow_mut();
+        let market_buckets = buckets.entry(market_id).or_default();
+        if market_buckets
+            .back()
+            .is_none_or(|bucket| bucket.hour != hour)
+        {
+            market_buckets.push_back(FlowBucket {
+                hour,
+                yes_volume: 0,
+                no_volume: 0,
+            });
+        }
+        if let Some(bucket) = market_buckets.back_mut() {
+            if is_yes {
+                bucket.yes_volume = bucket.yes_volume.saturating_add(amount);
+            } else {
+                bucket.no_volume = bucket.no_volume.saturating_add(amount);
+            }
+        }
+    });
+}
+
+// Drops buckets that have left the 24h window, and markets with none left
+fn prune_flow(now: u64) {
+    let oldest = (flow_hour(now) + 1).saturating_sub(FLOW_WINDOW_HOURS);
+    FLOW_BUCKETS.with(|buckets| {
+        buckets.borrow_mut().retain(|_, market_buckets| {
+            while market_buckets
+                .front()
+                .is_some_and(|bucket| bucket.hour < oldest)
+            {
+                market_buckets.pop_front();
+            }
+            !market_buckets.is_empty()
+        })
+    });
+}
+
+fn flow_info(market_id: u64, now: u64) -> FlowInfo {
+    let hour = flow_hour(now);
+    let oldest = (hour + 1).saturating_sub(FLOW_WINDOW_HOURS);
+    let mut info = FlowInfo::default();
+
+    FLOW_BUCKETS.with(|buckets| {
+        let buckets = buckets.borrow();
+        for bucket in buckets.get(&market_id).into_iter().flatten() {
+            // Buckets past the window may linger until the next prune
+            if bucket.hour < oldest {
+                continue;
+            }
+            info.yes_vol_24h = info.yes_vol_24h.saturating_add(bucket.yes_volume);
+            info.no_vol_24h = info.no_vol_24h.saturating_add(bucket.no_volume);
+            if bucket.hour == hour {
+                info.yes_vol_1h = bucket.yes_volume;
+                info.no_vol_1h = bucket.no_volume;
+            }
+        }
+    });
+
+    let total = info.yes_vol_24h as i128 + info.no_vol_24h as i128;
+    if total > 0 {
+        info.net_flow_bps =
+            ((info.yes_vol_24h as i128 - info.no_vol_24h as i128) * 10_000 / total) as i64;
+    }
+    info
 }
 
-// Market functions
 #[ic_cdk::query]
-fn get_markets() -> Vec<Market> {
-    MARKETS.with(|markets| markets.borrow().values().cloned().collect())
+fn get_flow(market_id: u64) -> FlowInfo {
+    flow_info(market_id, ic_cdk::api::time())
+}
+
+fn trending_score(flow: &FlowInfo) -> u64 {
+    let volume = flow.yes_vol_24h as u128 + flow.no_vol_24h as u128;
+    let score = volume * (10_000 + flow.net_flow_bps.unsigned_abs() as u128) / 10_000;
+    score.min(u64::MAX as u128) as u64
 }
 
+// Active markets by 24h buy volume, boosted by up to 2x for one-sided flow so
+// strongly directional markets surface even at moderate volume
 #[ic_cdk::query]
-fn get_market(id: u64) -> Option<Market> {
-    MARKETS.with(|markets| markets.borrow().get(&id).cloned())
+fn get_trending_markets(limit: u64) -> Vec<TrendingMarket> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let now = ic_cdk::api::time();
+    let market_ids: Vec<u64> =
+        FLOW_BUCKETS.with(|buckets| buckets.borrow().keys().copied().collect());
+
+    let mut trending: Vec<TrendingMarket> = MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        market_ids
+            .into_iter()
+            .filter_map(|market_id| {
+                let market = markets.get(&market_id)?;
+                if !matches!(market.status, MarketStatus::Active) {
+                    return None;
+                }
+                let flow = flow_info(market_id, now);
+                let score = trending_score(&flow);
+                if score == 0 {
+                    return None;
+                }
+                Some(TrendingMarket {
+                    market: market.clone(),
+                    flow,
+                    score,
+                })
+            })
+            .collect()
+    });
+
+    trending.sort_by_key(|t| (std::cmp::Reverse(t.score), t.market.id));
+    trending.truncate(limit.min(MAX_TRENDING_MARKETS) as usize);
+    trending
+}
+
+fn market_card(
+    market: &Market,
+    participation: ParticipationSplit,
+    comments: u64,
+    now: u64,
+) -> MarketCard {
+    let trending_score = if matches!(market.status, MarketStatus::Active) {
+        trending_score(&flow_info(market.id, now))
+    } else {
+        0
+    };
+    MarketCard {
+        market_id: market.id,
+        title: market.title.clone(),
+        slug: market.slug.clone(),
+        category: market.category.clone(),
+        status: market.status.clone(),
+        close_date: market.close_date,
+        yes_bps: engine::implied_yes_bps(market),
+        change_24h_bps: price_change(market.id, 86_400, now).map_or(0, |change| change.change_bps),
+        total_volume: market.total_volume,
+        participation,
+        comments,
+        trending_score,
+        refreshed_at: now,
+    }
+}
+
+fn comment_counts(market_id: Option<u64>) -> HashMap<u64, u64> {
+    let mut counts: HashMap<u64, u64> = HashMap::new();
+    COMMENTS.with(|comments| {
+        for comment in comments
+            .borrow()
+            .iter()
+            .filter(|c| market_id.is_none_or(|id| c.market_id == id))
+        {
+            *counts.entry(comment.market_id).or_default() += 1;
+        }
+    });
+    counts
+}
+
+// Cards for every market from scratch, with the windowed stats of each card
+// taken at `at(market_id)`
+fn build_market_cards(at: impl Fn(u64) -> u64) -> BTreeMap<u64, MarketCard> {
+    let mut participation = participation_splits(None);
+    let comments = comment_counts(None);
+    MARKETS.with(|markets| {
+        markets
+            .borrow()
+            .values()
+            .map(|market| {
+                let card = market_card(
+                    market,
+                    participation.remove(&market.id).unwrap_or_default(),
+                    comments.get(&market.id).copied().unwrap_or(0),
+                    at(market.id),
+                );
+                (market.id, card)
+            })
+            .collect()
+    })
+}
+
+// Called after anything that changes what a market's card shows
+fn refresh_market_card(market_id: u64) {
+    let card = MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        let market = markets.get(&market_id)?;
+        Some(market_card(
+            market,
+            participation_splits(Some(market_id))
+                .remove(&market_id)
+                .unwrap_or_default(),
+            comment_counts(Some(market_id))
+                .get(&market_id)
+                .copied()
+                .unwrap_or(0),
+            ic_cdk::api::time(),
+        ))
+    });
+    MARKET_CARDS.with(|cards| {
+        let mut cards = cards.borrow_mut();
+        match card {
+            Some(card) => cards.insert(market_id, card),
+            None => cards.remove(&market_id),
+        };
+    });
+}
+
+fn rebuild_all_market_cards(now: u64) -> u64 {
+    let cards = build_market_cards(|_| now);
+    let count = cards.len() as u64;
+    MARKET_CARDS.with(|cached| *cached.borrow_mut() = cards);
+    count
+}
+
+// Listed markets by id, as cached cards
+#[ic_cdk::query]
+fn get_market_cards(offset: u64, limit: u64) -> Vec<MarketCard> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    MARKET_CARDS.with(|cards| {
+        cards
+            .borrow()
+            .values()
+            .filter(|c| !matches!(c.status, MarketStatus::Rejected | MarketStatus::Archived))
+            .skip(offset as usize)
+            .take(limit.min(MAX_MARKET_CARDS_PAGE) as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+// Same ranking as get_trending_markets, as of the last card refresh
+#[ic_cdk::query]
+fn get_trending_market_cards(limit: u64) -> Vec<MarketCard> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let mut trending: Vec<MarketCard> = MARKET_CARDS.with(|cards| {
+        cards
+            .borrow()
+            .values()
+            .filter(|c| c.trending_score > 0)
+            .cloned()
+            .collect()
+    });
+    trending.sort_by_key(|c| (std::cmp::Reverse(c.trending_score), c.market_id));
+    trending.truncate(limit.min(MAX_TRENDING_MARKETS) as usize);
+    trending
+}
+
+// Case-insensitive match on title or category
+#[ic_cdk::query]
+fn search_market_cards(query: String, limit: u64) -> Vec<MarketCard> {
+    if !reads_allowed() {
+        return vec![];
+    }
+
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return vec![];
+    }
+    MARKET_CARDS.with(|cards| {
+        cards
+            .borrow()
+            .values()
+            .filter(|c| !matches!(c.status, MarketStatus::Rejected | MarketStatus::Archived))
+            .filter(|c| {
+                c.title.to_lowercase().contains(&query)
+                    || c.category.to_lowercase().contains(&query)
+            })
+            .take(limit.min(MAX_MARKET_CARDS_PAGE) as usize)
+            .cloned()
+            .collect()
+    })
 }
 
+// Escape hatch should a code path ever forget to refresh a card
 #[ic_cdk::update]
-fn create_market(
-    title: String,
-    description: String,
-    category: String,
-    close_date: u64,
-) -> Result<u64, String> {
+fn rebuild_market_cards() -> Result<u64, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can rebuild market cards".to_string());
+    }
+
+    Ok(rebuild_all_market_cards(ic_cdk::api::time()))
+}
+
+// Ids whose cached card differs from one rebuilt from scratch as of the same
+// refresh time; empty when the incremental updates are consistent
+#[ic_cdk::query]
+fn get_stale_market_cards() -> Result<Vec<u64>, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can rebuild market cards".to_string());
+    }
+
+    let cached = MARKET_CARDS.with(|cards| cards.borrow().clone());
+    let rebuilt = build_market_cards(|market_id| {
+        cached
+            .get(&market_id)
+            .map_or(ic_cdk::api::time(), |card| card.refreshed_at)
+    });
+    let ids: BTreeSet<u64> = cached.keys().chain(rebuilt.keys()).copied().collect();
+    Ok(ids
+        .into_iter()
+        .filter(|id| cached.get(id) != rebuilt.get(id))
+        .collect())
+}
+
+// Social trending: markets gaining discussion momentum, comparing the most
+// recent window of comments against the window right before it
+#[ic_cdk::query]
+fn get_markets_by_comment_velocity(window_seconds: u64, limit: u64) -> Vec<CommentVelocity> {
+    if !reads_allowed() || window_seconds == 0 || limit == 0 {
+        return vec![];
+    }
+
+    let now = ic_cdk::api::time();
+    let window = to_nanos(window_seconds);
+    let recent_start = now.saturating_sub(window);
+    let prior_start = recent_start.saturating_sub(window);
+
+    let mut counts: HashMap<u64, (u64, u64)> = HashMap::new();
+    COMMENTS.with(|comments| {
+        for comment in comments.borrow().iter() {
+            if comment.timestamp >= recent_start {
+                counts.entry(comment.market_id).or_default().0 += 1;
+            } else if comment.timestamp >= prior_start {
+                counts.entry(comment.market_id).or_default().1 += 1;
+            }
+        }
+    });
+
+    let window_hours = window_seconds as f64 / 3600.0;
+
+    let mut ranked: Vec<CommentVelocity> = MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        counts
+            .into_iter()
+            .filter(|(_, (recent, _))| *recent > 0)
+            .filter_map(|(market_id, (recent, prior))| {
+                let market = markets.get(&market_id)?.clone();
+                Some(CommentVelocity {
+                    market,
+                    recent_comments: recent,
+                    prior_comments: prior,
+                    recent_per_hour: recent as f64 / window_hours,
+                    prior_per_hour: prior as f64 / window_hours,
+                    // A silent prior window counts as one comment to avoid dividing by zero
+                    acceleration: (recent as f64 - prior as f64) / prior.max(1) as f64,
+                })
+            })
+            .collect()
+    });
+
+    ranked.sort_by(|a, b| {
+        b.acceleration
+            .total_cmp(&a.acceleration)
+            .then(b.recent_comments.cmp(&a.recent_comments))
+            .then(a.market.id.cmp(&b.market.id))
+    });
+    ranked.truncate(limit.min(100) as usize);
+    ranked
+}
+
+// Deposit functions
+// Each user deposits to their own subaccount of the canister: the principal's
+// length followed by its bytes, zero padded to 32 bytes
+fn deposit_subaccount(principal: &Principal) -> Vec<u8> {
+    let bytes = principal.as_slice();
+    let mut subaccount = vec![0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..=bytes.len()].copy_from_slice(bytes);
+    subaccount
+}
+
+fn nat_to_u64(value: Nat) -> Result<u64, String> {
+    u64::try_from(value.0).map_err(|_| "Ledger amount does not fit in u64".to_string())
+}
+
+#[ic_cdk::query]
+fn get_deposit_account() -> Account {
+    Account {
+        owner: ic_cdk::api::id(),
+        subaccount: Some(deposit_subaccount(&ic_cdk::caller())),
+    }
+}
+
+#[ic_cdk::query]
+fn get_balance(principal: Principal) -> u64 {
+    BALANCES.with(|balances| balances.borrow().get(&principal).copied().unwrap_or(0))
+}
+
+fn credit_balance(principal: Principal, amount: u64, reason: BalanceReason, reference: u64) {
+    let balance_after = BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let balance = balances.entry(principal).or_insert(0);
+        *balance += amount;
+        *balance
+    });
+    record_balance_entry(principal, amount as i128, balance_after, reason, reference);
+}
+
+fn debit_balance(
+    principal: Principal,
+    amount: u64,
+    reason: BalanceReason,
+    reference: u64,
+) -> Result<(), String> {
+    let balance_after = BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let balance = balances.entry(principal).or_insert(0);
+        if *balance < amount {
+            return Err("Insufficient balance".to_string());
+        }
+        *balance -= amount;
+        Ok(*balance)
+    })?;
+    record_balance_entry(
+        principal,
+        -(amount as i128),
+        balance_after,
+        reason,
+        reference,
+    );
+    Ok(())
+}
+
+fn record_balance_entry(
+    principal: Principal,
+    delta: i128,
+    balance_after: u64,
+    reason: BalanceReason,
+    reference: u64,
+) {
+    let id = NEXT_BALANCE_ENTRY_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+
+    BALANCE_ENTRIES.with(|entries| {
+        entries.borrow_mut().push(BalanceEntry {
+            id,
+            principal,
+            delta,
+            balance_after,
+            reason,
+            reference,
+            timestamp: ic_cdk::api::time(),
+        });
+    });
+}
+
+// Newest first; entries older than `cursor` (an entry id) when given
+fn balance_history(principal: Principal, cursor: Option<u64>, limit: u64) -> BalanceHistoryPage {
+    let limit = limit.clamp(1, MAX_BALANCE_HISTORY_PAGE) as usize;
+
+    let mut entries: Vec<BalanceEntry> = BALANCE_ENTRIES.with(|entries| {
+        entries
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|entry| entry.principal == principal)
+            .filter(|entry| cursor.is_none_or(|cursor| entry.id < cursor))
+            .take(limit + 1)
+            .cloned()
+            .collect()
+    });
+
+    let next_cursor = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|entry| entry.id)
+    } else {
+        None
+    };
+    BalanceHistoryPage {
+        entries,
+        next_cursor,
+    }
+}
+
+#[ic_cdk::query]
+fn get_my_balance_history(cursor: Option<u64>, limit: u64) -> BalanceHistoryPage {
+    balance_history(ic_cdk::caller(), cursor, limit)
+}
+
+#[ic_cdk::query]
+fn get_balance_history(
+    principal: Principal,
+    cursor: Option<u64>,
+    limit: u64,
+) -> Result<BalanceHistoryPage, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can view other users' balance history".to_string());
+    }
+
+    Ok(balance_history(principal, cursor, limit))
+}
+
+#[ic_cdk::query]
+fn get_my_deposits() -> Vec<DepositSweep> {
+    let caller = ic_cdk::caller();
+    DEPOSIT_SWEEPS.with(|sweeps| {
+        sweeps
+            .borrow()
+            .iter()
+            .filter(|sweep| sweep.principal == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+fn set_ledger_canister(ledger: Principal) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can set the ledger canister".to_string());
+    }
+
+    LEDGER_ID.with(|id| *id.borrow_mut() = ledger);
+    Ok(())
+}
+
+// Sweeps the caller's deposit subaccount into the canister's main account and
+// credits the swept amount (net of the ledger fee) to their internal balance.
+// Only what is observed in the subaccount is swept, so funds arriving mid-sweep
+// are picked up by the next notify. Repeated notifies are idempotent: an empty
+// subaccount credits nothing and concurrent notifies for the same user are rejected.
+#[ic_cdk::update]
+async fn notify_deposit() -> Result<u64, String> {
     let caller = ic_cdk::caller();
 
-    if title.is_empty() || description.is_empty() {
-        return Err("Title and description cannot be empty".to_string());
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot deposit".to_string());
+    }
+
+    let already_sweeping = SWEEPS_IN_FLIGHT.with(|in_flight| {
+        let mut in_flight = in_flight.borrow_mut();
+        if in_flight.contains(&caller) {
+            true
+        } else {
+            in_flight.push(caller);
+            false
+        }
+    });
+    if already_sweeping {
+        return Err("A deposit sweep is already in progress".to_string());
+    }
+
+    let result = sweep_deposit(caller).await;
+
+    SWEEPS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().retain(|p| *p != caller));
+
+    result
+}
+
+async fn ledger_fee(ledger: Principal) -> Result<u64, String> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, msg)| format!("Ledger fee query failed: {:?} {}", code, msg))?;
+    nat_to_u64(fee)
+}
+
+async fn sweep_deposit(principal: Principal) -> Result<u64, String> {
+    let ledger = LEDGER_ID.with(|id| *id.borrow());
+    let subaccount = deposit_subaccount(&principal);
+    let deposit_account = Account {
+        owner: ic_cdk::api::id(),
+        subaccount: Some(subaccount.clone()),
+    };
+
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (deposit_account,))
+        .await
+        .map_err(|(code, msg)| format!("Ledger balance query failed: {:?} {}", code, msg))?;
+    let fee = ledger_fee(ledger).await?;
+    let balance = nat_to_u64(balance)?;
+
+    // Nothing worth sweeping once the fee is paid
+    if balance <= fee {
+        return Ok(0);
     }
+    let amount = balance - fee;
 
-    let market_id = NEXT_MARKET_ID.with(|id| {
+    let transfer = TransferArg {
+        from_subaccount: Some(subaccount),
+        to: Account {
+            owner: ic_cdk::api::id(),
+            subaccount: None,
+        },
+        fee: Some(Nat::from(fee)),
+        memo: None,
+        created_at_time: None,
+        amount: Nat::from(amount),
+    };
+
+    let (result,): (Result<Nat, TransferError>,) =
+        ic_cdk::call(ledger, "icrc1_transfer", (transfer,))
+            .await
+            .map_err(|(code, msg)| format!("Ledger transfer failed: {:?} {}", code, msg))?;
+    let block_index = result.map_err(|e| format!("Ledger transfer rejected: {:?}", e))?;
+    let block_index = nat_to_u64(block_index)?;
+
+    let id = NEXT_SWEEP_ID.with(|id| {
         let current_id = *id.borrow();
         *id.borrow_mut() = current_id + 1;
         current_id
     });
-
-    let market = Market {
-        id: market_id,
-        title,
-        description,
-        category,
-        creator: caller,
-        close_date,
-        status: MarketStatus::PendingValidation,
-        yes_shares: 500, // Initial liquidity
-        no_shares: 500,
-        yes_liquidity: 5000,
-        no_liquidity: 5000,
-        total_volume: 0,
-        created_at: ic_cdk::api::time(),
-        resolved_outcome: None,
-    };
-
-    MARKETS.with(|markets| {
-        markets.borrow_mut().insert(market_id, market);
+    credit_balance(principal, amount, BalanceReason::Deposit, id);
+    DEPOSIT_SWEEPS.with(|sweeps| {
+        sweeps.borrow_mut().push(DepositSweep {
+            id,
+            principal,
+            amount,
+            fee,
+            block_index,
+            timestamp: ic_cdk::api::time(),
+        });
     });
 
-    Ok(market_id)
+    Ok(amount)
 }
 
-// AMM pricing function using LMSR (simplified)
-fn calculate_price(yes_shares: u64, no_shares: u64, buy_yes: bool, amount: u64) -> u64 {
-    let base_liquidity = 1000u64;
-
-    if buy_yes {
-        let price_impact = (amount * 1000) / (base_liquidity + yes_shares);
-        500 + price_impact.min(450) // Price between 50-950 (0.05-0.95 in decimal)
-    } else {
-        let price_impact = (amount * 1000) / (base_liquidity + no_shares);
-        500 - price_impact.min(450)
-    }
+// Ledger memo naming the kind of withdrawal and its sequence number
+fn withdrawal_memo(kind: &[u8], sequence: u64) -> Vec<u8> {
+    let mut memo = kind.to_vec();
+    memo.extend_from_slice(&sequence.to_be_bytes());
+    memo
 }
 
+// Sends `amount` from the caller's internal balance to a ledger account. The
+// ledger fee is charged on top, and the balance is debited before the transfer
+// so concurrent withdrawals cannot spend it twice; a failed transfer puts it
+// back. Returns the ledger block index.
 #[ic_cdk::update]
-fn buy_shares(market_id: u64, is_yes: bool, amount: u64) -> Result<Trade, String> {
+async fn withdraw(amount: u64, to: Account) -> Result<u64, String> {
     let caller = ic_cdk::caller();
 
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot withdraw".to_string());
+    }
     if amount == 0 {
         return Err("Amount must be greater than 0".to_string());
     }
 
-    let trade_id = NEXT_TRADE_ID.with(|id| {
+    let ledger = LEDGER_ID.with(|id| *id.borrow());
+    let fee = ledger_fee(ledger).await?;
+    let total = amount
+        .checked_add(fee)
+        .ok_or_else(|| "Amount too large".to_string())?;
+
+    let id = NEXT_WITHDRAWAL_ID.with(|id| {
         let current_id = *id.borrow();
         *id.borrow_mut() = current_id + 1;
         current_id
     });
+    debit_balance(caller, total, BalanceReason::Withdraw, id)?;
 
-    let price = MARKETS.with(|markets| {
-        let mut markets_map = markets.borrow_mut();
-        if let Some(market) = markets_map.get_mut(&market_id) {
-            if !matches!(market.status, MarketStatus::Active) {
-                return Err("Market is not active".to_string());
-            }
-
-            let price = calculate_price(market.yes_shares, market.no_shares, is_yes, amount);
+    // Tagged so the ledger rejects a retried transfer as a duplicate
+    let transfer = TransferArg {
+        from_subaccount: None,
+        to,
+        fee: Some(Nat::from(fee)),
+        memo: Some(withdrawal_memo(b"withdraw", id)),
+        created_at_time: Some(ic_cdk::api::time()),
+        amount: Nat::from(amount),
+    };
 
-            // Update market state - liquidity should directly reflect the amount bet
-            if is_yes {
-                market.yes_shares += amount;
-                market.yes_liquidity += amount; // Direct 1:1 relationship
-            } else {
-                market.no_shares += amount;
-                market.no_liquidity += amount; // Direct 1:1 relationship
-            }
+    let result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(ledger, "icrc1_transfer", (transfer,)).await;
+    let block_index = match result {
+        Ok((Ok(block_index),)) => Ok(block_index),
+        Ok((Err(e),)) => Err(format!("Ledger transfer rejected: {:?}", e)),
+        Err((code, msg)) => Err(format!("Ledger transfer failed: {:?} {}", code, msg)),
+    };
 
-            market.total_volume += amount;
+    if block_index.is_err() {
+        credit_balance(caller, total, BalanceReason::Withdraw, id);
+    }
 
-            // Collect 2% fee on the amount bet
-            let fee = (amount * 2) / 100;
-            TREASURY.with(|treasury| {
-                *treasury.borrow_mut() += fee;
-            });
+    nat_to_u64(block_index?)
+}
 
-            Ok(price)
-        } else {
-            Err("Market not found".to_string())
-        }
-    })?;
+// Integrity check functions
+// The check walks trades and comments in bounded chunks, one chunk per
+// scheduler tick, so it never exhausts the instruction limit of a single message
+#[ic_cdk::update]
+fn run_integrity_check() -> Result<IntegrityReport, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can run integrity checks".to_string());
+    }
 
-    let trade = Trade {
-        id: trade_id,
-        market_id,
-        trader: caller,
-        is_yes,
-        shares: amount,
-        price,
-        timestamp: ic_cdk::api::time(),
-    };
+    if INTEGRITY_CURSOR.with(|cursor| cursor.borrow().is_some()) {
+        return Err("An integrity check is already running".to_string());
+    }
 
-    TRADES.with(|trades| {
-        trades.borrow_mut().push(trade.clone());
+    let id = NEXT_INTEGRITY_REPORT_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
     });
 
-    // Update user profile XP
-    USER_PROFILES.with(|profiles| {
-        let mut profiles_map = profiles.borrow_mut();
-        let profile = profiles_map.entry(caller).or_insert(UserProfile {
-            principal: caller,
-            username: format!(
-                "User{}",
-                caller.to_text().chars().take(8).collect::<String>()
-            ),
-            xp: 0,
-            total_trades: 0,
-            successful_predictions: 0,
-            badges: vec![],
-            created_at: ic_cdk::api::time(),
-        });
+    let report = IntegrityReport {
+        id,
+        status: IntegrityCheckStatus::Running,
+        started_at: ic_cdk::api::time(),
+        completed_at: None,
+        trades_checked: 0,
+        comments_checked: 0,
+        issues: vec![],
+    };
 
-        profile.total_trades += 1;
-        profile.xp += amount / 10; // Gain XP for trading
+    INTEGRITY_REPORTS.with(|reports| {
+        let mut reports = reports.borrow_mut();
+        reports.push(report.clone());
+        if reports.len() > MAX_INTEGRITY_REPORTS {
+            reports.remove(0);
+        }
+    });
+    let inflow_cutoff = TREASURY_INFLOWS.with(|inflows| {
+        let inflows = inflows.borrow();
+        match inflows.front() {
+            Some(oldest) if inflows.len() >= MAX_TREASURY_INFLOWS => oldest.timestamp + 1,
+            _ => 0,
+        }
+    });
+    INTEGRITY_CURSOR.with(|cursor| {
+        *cursor.borrow_mut() = Some(IntegrityCursor {
+            inflow_cutoff,
+            ..IntegrityCursor::default()
+        })
     });
 
-    Ok(trade)
+    Ok(report)
 }
 
-#[ic_cdk::query]
-fn get_market_trades(market_id: u64) -> Vec<Trade> {
-    TRADES.with(|trades| {
-        trades
-            .borrow()
-            .iter()
-            .filter(|trade| trade.market_id == market_id)
-            .cloned()
-            .collect()
-    })
-}
+fn new_integrity_issue(kind: IntegrityIssueKind, entity_id: u64, detail: String) -> IntegrityIssue {
+    let id = NEXT_INTEGRITY_ISSUE_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
 
-#[ic_cdk::query]
-fn get_user_profile(principal: Principal) -> Option<UserProfile> {
-    USER_PROFILES.with(|profiles| profiles.borrow().get(&principal).cloned())
+    IntegrityIssue {
+        id,
+        kind,
+        entity_id,
+        detail,
+        repaired: None,
+    }
 }
 
-#[ic_cdk::query]
-fn get_leaderboard() -> Vec<UserProfile> {
-    USER_PROFILES.with(|profiles| {
-        let mut users: Vec<_> = profiles.borrow().values().cloned().collect();
-        users.sort_by(|a, b| b.xp.cmp(&a.xp));
-        users.into_iter().take(20).collect()
-    })
-}
+fn integrity_check_step() {
+    let Some(mut cursor) = INTEGRITY_CURSOR.with(|cursor| cursor.borrow_mut().take()) else {
+        return;
+    };
 
-#[ic_cdk::update]
-async fn get_ai_insight(market_id: u64) -> Option<AIInsight> {
-    // First check if we have a cached insight
-    let cached = AI_INSIGHTS.with(|insights| insights.borrow().get(&market_id).cloned());
+    let mut issues = vec![];
+    let mut trades_checked = 0;
+    let mut comments_checked = 0;
 
-    // If we have a recent cached insight (less than 1 hour old), return it
-    if let Some(insight) = cached {
-        let current_time = ic_cdk::api::time();
-        let one_hour = 3600 * 1_000_000_000; // 1 hour in nanoseconds
+    let total_trades = TRADES.with(|trades| trades.borrow().len());
+    let total_sales = SALES.with(|sales| sales.borrow().len());
+    let total_comments = COMMENTS.with(|comments| comments.borrow().len());
 
-        if current_time - insight.generated_at < one_hour {
-            return Some(insight);
-        }
+    if cursor.trade_index < total_trades {
+        let end = (cursor.trade_index + INTEGRITY_CHUNK_SIZE).min(total_trades);
+        TRADES.with(|trades| {
+            MARKETS.with(|markets| {
+                let markets = markets.borrow();
+                for trade in &trades.borrow()[cursor.trade_index..end] {
+                    cursor.max_trade_id = cursor.max_trade_id.max(trade.id);
+                    if trade.timestamp >= cursor.inflow_cutoff {
+                        // A revert swaps the fee and dust for the penalty
+                        *cursor.market_fees.entry(trade.market_id).or_default() +=
+                            match trade.reverted_at {
+                                Some(_) => revert_penalty(trade.amount),
+                                None => trade.fee + trade.dust,
+                            };
+                    }
+                    if markets.contains_key(&trade.market_id) {
+                        if trade.reverted_at.is_none() {
+                            *cursor
+                                .market_trade_volume
+                                .entry(trade.market_id)
+                                .or_default() += trade.amount;
+                        }
+                    } else {
+                        issues.push(new_integrity_issue(
+                            IntegrityIssueKind::OrphanTrade,
+                            trade.id,
+                            format!("Trade references missing market {}", trade.market_id),
+                        ));
+                    }
+                }
+            })
+        });
+        trades_checked = (end - cursor.trade_index) as u64;
+        cursor.trade_index = end;
+    } else if cursor.sale_index < total_sales {
+        let end = (cursor.sale_index + INTEGRITY_CHUNK_SIZE).min(total_sales);
+        SALES.with(|sales| {
+            for sale in &sales.borrow()[cursor.sale_index..end] {
+                cursor.max_trade_id = cursor.max_trade_id.max(sale.id);
+            }
+        });
+        cursor.sale_index = end;
+    } else if cursor.comment_index < total_comments {
+        let end = (cursor.comment_index + INTEGRITY_CHUNK_SIZE).min(total_comments);
+        COMMENTS.with(|comments| {
+            MARKETS.with(|markets| {
+                let markets = markets.borrow();
+                for comment in &comments.borrow()[cursor.comment_index..end] {
+                    cursor.max_comment_id = cursor.max_comment_id.max(comment.id);
+                    if !markets.contains_key(&comment.market_id) {
+                        issues.push(new_integrity_issue(
+                            IntegrityIssueKind::OrphanComment,
+                            comment.id,
+                            format!("Comment references missing market {}", comment.market_id),
+                        ));
+                    }
+                }
+            })
+        });
+        comments_checked = (end - cursor.comment_index) as u64;
+        cursor.comment_index = end;
+    } else {
+        issues.extend(check_accounting_invariants(&cursor));
+        finish_integrity_step(issues, trades_checked, comments_checked, true);
+        return;
     }
 
-    // Get market data
-    let market = MARKETS.with(|markets| markets.borrow().get(&market_id).cloned())?;
+    finish_integrity_step(issues, trades_checked, comments_checked, false);
+    INTEGRITY_CURSOR.with(|c| *c.borrow_mut() = Some(cursor));
+}
 
-    // Create prompt for the AI agent
-    let prompt = format!(
-        "Analyze this prediction market and provide insights:
-        
-        Title: {}
-        Description: {}
-        Category: {}
-        
-        Current state:
-        - Yes liquidity: {} ICP
-        - No liquidity: {} ICP  
-        - Total volume: {} ICP
-        - Status: {:?}
-        
-        Please provide:
-        1. A brief analysis summary (2-3 sentences)
-        2. Your prediction (YES/NO) with confidence level (0-1)
-        3. Key risk factors (list 2-3 main risks)
-        
-        Format your response as JSON with keys: summary, prediction, confidence, risks",
-        market.title,
-        market.description,
-        market.category,
-        market.yes_liquidity as f64 / 100_000_000.0,
-        market.no_liquidity as f64 / 100_000_000.0,
-        market.total_volume as f64 / 100_000_000.0,
-        market.status
-    );
+// Final pass over markets and counters once every trade and comment was visited
+fn check_accounting_invariants(cursor: &IntegrityCursor) -> Vec<IntegrityIssue> {
+    let mut issues = vec![];
 
-    // Create chat request
-    let _chat_request = ChatRequestV0 {
-        model: "gpt-4o-mini".to_string(),
-        messages: vec![
-            ChatMessageV0 {
-                role: ChatRole::System,
-                content: "You are an expert financial analyst specializing in prediction markets. Provide clear, objective analysis based on market data.".to_string(),
-            },
-            ChatMessageV0 {
-                role: ChatRole::User,
-                content: prompt,
+    let mut held: HashMap<u64, (u64, u64)> = HashMap::new();
+    POSITIONS.with(|positions| {
+        for position in positions.borrow().values() {
+            let shares = held.entry(position.market_id).or_default();
+            shares.0 += position.yes_shares;
+            shares.1 += position.no_shares;
+        }
+    });
+
+    let max_market_id = MARKETS.with(|markets| {
+        let markets = markets.borrow();
+        for market in markets.values() {
+            // The market's counters also carry the seed shares nobody holds
+            let (yes, no) = held.get(&market.id).copied().unwrap_or_default();
+            if yes > market.yes_shares || no > market.no_shares {
+                issues.push(new_integrity_issue(
+                    IntegrityIssueKind::PositionsExceedShares,
+                    market.id,
+                    format!(
+                        "Positions hold {}/{} YES/NO shares but the market issued {}/{}",
+                        yes, no, market.yes_shares, market.no_shares
+                    ),
+                ));
+            }
+            let indexed = MARKET_SLUGS.with(|slugs| slugs.borrow().get(&market.slug).copied());
+            if indexed != Some(market.id) {
+                issues.push(new_integrity_issue(
+                    IntegrityIssueKind::SlugIndexMismatch,
+                    market.id,
+                    format!("Slug {:?} does not resolve to this market", market.slug),
+                ));
             }
-        ],
-    };
 
-    // For testing purposes, let's create a mock AI response first
-    // TODO: Remove this when the LLM canister is properly accessible
-    let market_title = MARKETS.with(|markets| {
-        markets
-            .borrow()
-            .get(&market_id)
-            .map(|m| m.title.clone())
-            .unwrap_or_default()
+            let traded = cursor
+                .market_trade_volume
+                .get(&market.id)
+                .copied()
+                .unwrap_or(0);
+            if market.total_volume < traded {
+                issues.push(new_integrity_issue(
+                    IntegrityIssueKind::VolumeBelowTrades,
+                    market.id,
+                    format!(
+                        "Market volume {} is below traded volume {}",
+                        market.total_volume, traded
+                    ),
+                ));
+            }
+        }
+        markets.keys().max().copied().unwrap_or(0)
     });
 
-    let mock_insight = AIInsight {
-        market_id,
-        summary: format!(
-            "🤖 AI Analysis for '{}': Based on current market trends and sentiment analysis, this prediction market shows interesting dynamics. The market sentiment appears to be driven by recent news and social media discussions. Consider both bullish and bearish scenarios before making investment decisions.",
-            market_title
+    let counters = [
+        (
+            IntegrityIssueKind::MarketCounterBehind,
+            NEXT_MARKET_ID.with(|id| *id.borrow()),
+            max_market_id,
         ),
-        confidence: 0.75,
-        risks: vec![
-            "Market volatility due to external events".to_string(),
-            "Limited trading volume may affect price discovery".to_string(),
-            "Information asymmetry between participants".to_string(),
-        ],
-        prediction_lean: Some(true), // Slightly bullish
-        generated_at: ic_cdk::api::time(),
-    };
-
-    // Cache the mock insight
-    AI_INSIGHTS.with(|insights| {
-        insights
-            .borrow_mut()
-            .insert(market_id, mock_insight.clone());
-    });
-
-    Some(mock_insight)
-
-    // TODO: Uncomment this when ready to use the real LLM canister
-    /*
-    // Call the LLM canister
-    match Principal::from_text(LLM_CANISTER_ID) {
-        Ok(llm_principal) => {
-            let response: Result<(String,), _> =
-                call(llm_principal, "v0_chat", (_chat_request,)).await;
-
-            match response {
-                Ok((ai_response,)) => {
-                    // Parse the AI response and create AIInsight
-                    let insight = parse_ai_response(&ai_response, market_id);
-
-                    // Cache the insight
-                    if let Some(ref insight_to_cache) = insight {
-                        AI_INSIGHTS.with(|insights| {
-                            insights
-                                .borrow_mut()
-                                .insert(market_id, insight_to_cache.clone());
-                        });
-                    }
+        (
+            IntegrityIssueKind::TradeCounterBehind,
+            NEXT_TRADE_ID.with(|id| *id.borrow()),
+            cursor.max_trade_id,
+        ),
+        (
+            IntegrityIssueKind::CommentCounterBehind,
+            NEXT_COMMENT_ID.with(|id| *id.borrow()),
+            cursor.max_comment_id,
+        ),
+    ];
+    for (kind, next_id, max_id) in counters {
+        if next_id <= max_id {
+            issues.push(new_integrity_issue(
+                kind,
+                max_id,
+                format!(
+                    "Next id {} does not exceed max existing id {}",
+                    next_id, max_id
+                ),
+            ));
+        }
+    }
 
-                    insight
-                }
-                Err(e) => {
-                    // Fallback to a default insight if AI call fails
-                    Some(AIInsight {
-                        market_id,
-                        summary: format!("AI analysis call failed: {:?}. Your Python agent may be offline or unreachable.", e),
-                        confidence: 0.3,
-                        risks: vec!["AI analysis temporarily unavailable".to_string(), "Check Python agent status".to_string()],
-                        prediction_lean: None,
-                        generated_at: ic_cdk::api::time(),
-                    })
-                }
-            }
+    // Archived markets no longer have their trades here, and if the log lost
+    // inflows the check started with, there is nothing reliable to compare
+    let mut inflows: HashMap<u64, u64> = HashMap::new();
+    let truncated = TREASURY_INFLOWS.with(|log| {
+        let log = log.borrow();
+        for inflow in log.iter().filter(|i| i.timestamp >= cursor.inflow_cutoff) {
+            *inflows.entry(inflow.market_id).or_default() += inflow.amount;
         }
-        Err(_) => {
-            // Invalid canister ID
-            Some(AIInsight {
-                market_id,
-                summary: "Invalid LLM canister ID configuration. Please check the setup."
-                    .to_string(),
-                confidence: 0.1,
-                risks: vec!["Configuration error".to_string()],
-                prediction_lean: None,
-                generated_at: ic_cdk::api::time(),
+        log.len() >= MAX_TREASURY_INFLOWS
+            && log
+                .front()
+                .is_some_and(|oldest| oldest.timestamp >= cursor.inflow_cutoff)
+    });
+    if !truncated {
+        let mut market_ids: Vec<u64> = inflows
+            .keys()
+            .chain(cursor.market_fees.keys())
+            .copied()
+            .collect::<BTreeSet<u64>>()
+            .into_iter()
+            .collect();
+        market_ids.retain(|id| {
+            MARKETS.with(|markets| {
+                markets
+                    .borrow()
+                    .get(id)
+                    .is_some_and(|m| m.archived_to.is_none())
             })
+        });
+        for market_id in market_ids {
+            let fees = cursor.market_fees.get(&market_id).copied().unwrap_or(0);
+            let inflow = inflows.get(&market_id).copied().unwrap_or(0);
+            if fees != inflow {
+                issues.push(new_integrity_issue(
+                    IntegrityIssueKind::TreasuryFeeMismatch,
+                    market_id,
+                    format!(
+                        "Fees and dust charged {} differ from treasury inflows {}",
+                        fees, inflow
+                    ),
+                ));
+            }
         }
     }
-    */
+
+    issues
 }
 
-// Helper function to parse AI response
-// TODO: Uncomment when using real LLM canister
-/*
-fn parse_ai_response(response: &str, market_id: u64) -> Option<AIInsight> {
-    // Try to parse JSON response from AI
-    // This is a simplified parser - you might want to use a proper JSON library
+fn finish_integrity_step(
+    issues: Vec<IntegrityIssue>,
+    trades_checked: u64,
+    comments_checked: u64,
+    completed: bool,
+) {
+    INTEGRITY_REPORTS.with(|reports| {
+        if let Some(report) = reports.borrow_mut().last_mut() {
+            report.issues.extend(issues);
+            report.trades_checked += trades_checked;
+            report.comments_checked += comments_checked;
+            if completed {
+                report.status = IntegrityCheckStatus::Completed;
+                report.completed_at = Some(ic_cdk::api::time());
+            }
+        }
+    });
+}
 
-    // For now, create a basic insight with the raw response
-    // You can enhance this to properly parse JSON
-    Some(AIInsight {
-        market_id,
-        summary: response.to_string(),
-        confidence: 0.7, // Default confidence
-        risks: vec![
-            "Market volatility".to_string(),
-            "Unexpected events".to_string(),
-        ],
-        prediction_lean: None, // Parse from response
-        generated_at: ic_cdk::api::time(),
-    })
+#[ic_cdk::query]
+fn get_integrity_reports() -> Result<Vec<IntegrityReport>, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can view integrity reports".to_string());
+    }
+
+    Ok(INTEGRITY_REPORTS.with(|reports| reports.borrow().clone()))
 }
-*/
 
 #[ic_cdk::update]
-fn add_comment(market_id: u64, content: String) -> Result<u64, String> {
-    let caller = ic_cdk::caller();
+fn repair(issue_id: u64, strategy: RepairStrategy) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can repair integrity issues".to_string());
+    }
 
-    if content.is_empty() || content.len() > 500 {
-        return Err("Comment must be between 1 and 500 characters".to_string());
+    let issue = INTEGRITY_REPORTS
+        .with(|reports| {
+            reports
+                .borrow()
+                .iter()
+                .flat_map(|report| report.issues.iter())
+                .find(|issue| issue.id == issue_id)
+                .cloned()
+        })
+        .ok_or_else(|| "Integrity issue not found".to_string())?;
+
+    if issue.repaired.is_some() {
+        return Err("Integrity issue was already repaired".to_string());
     }
 
-    let comment_id = NEXT_COMMENT_ID.with(|id| {
-        let current_id = *id.borrow();
-        *id.borrow_mut() = current_id + 1;
-        current_id
+    match (&issue.kind, &strategy) {
+        (IntegrityIssueKind::MarketCounterBehind, RepairStrategy::RecomputeCounter) => {
+            let max_id =
+                MARKETS.with(|markets| markets.borrow().keys().max().copied().unwrap_or(0));
+            NEXT_MARKET_ID.with(|id| *id.borrow_mut() = max_id + 1);
+        }
+        (IntegrityIssueKind::TradeCounterBehind, RepairStrategy::RecomputeCounter) => {
+            let max_id =
+                TRADES.with(|trades| trades.borrow().iter().map(|t| t.id).max().unwrap_or(0));
+            let max_id =
+                SALES.with(|sales| sales.borrow().iter().map(|s| s.id).fold(max_id, u64::max));
+            NEXT_TRADE_ID.with(|id| *id.borrow_mut() = max_id + 1);
+        }
+        (IntegrityIssueKind::CommentCounterBehind, RepairStrategy::RecomputeCounter) => {
+            let max_id =
+                COMMENTS.with(|comments| comments.borrow().iter().map(|c| c.id).max().unwrap_or(0));
+            NEXT_COMMENT_ID.with(|id| *id.borrow_mut() = max_id + 1);
+        }
+        (IntegrityIssueKind::OrphanTrade, RepairStrategy::PurgeOrphan) => {
+            let purged = TRADES.with(|trades| {
+                let mut trades = trades.borrow_mut();
+                let index = trades.iter().position(|t| t.id == issue.entity_id)?;
+                Some(trades.remove(index))
+            });
+            // Take back what the trade added to its position. Shares since
+            // sold or transferred are gone already, so this cannot underflow.
+            if let Some(trade) = purged.filter(|t| t.reverted_at.is_none()) {
+                POSITIONS.with(|positions| {
+                    let mut positions = positions.borrow_mut();
+                    let key = (trade.trader, trade.market_id);
+                    if let Some(position) = positions.get_mut(&key) {
+                        let (held, paid) = if trade.is_yes {
+                            (&mut position.yes_shares, &mut position.yes_cost)
+                        } else {
+                            (&mut position.no_shares, &mut position.no_cost)
+                        };
+                        *held = held.saturating_sub(trade.shares);
+                        *paid = paid.saturating_sub(trade.amount);
+                        if position.yes_shares == 0 && position.no_shares == 0 {
+                            positions.remove(&key);
+                        }
+                    }
+                });
+            }
+        }
+        (IntegrityIssueKind::SlugIndexMismatch, RepairStrategy::RebuildIndexes) => {
+            MARKETS.with(|markets| rebuild_slug_index(&markets.borrow()));
+            rebuild_all_market_cards(ic_cdk::api::time());
+        }
+        (IntegrityIssueKind::OrphanComment, RepairStrategy::PurgeOrphan) => {
+            COMMENTS.with(|comments| comments.borrow_mut().retain(|c| c.id != issue.entity_id));
+            COMMENT_HISTORY.with(|history| history.borrow_mut().remove(&issue.entity_id));
+        }
+        _ => {
+            return Err(format!(
+                "Strategy {:?} cannot repair {:?}",
+                strategy, issue.kind
+            ))
+        }
+    }
+
+    INTEGRITY_REPORTS.with(|reports| {
+        for report in reports.borrow_mut().iter_mut() {
+            for reported in report.issues.iter_mut() {
+                if reported.id == issue_id {
+                    reported.repaired = Some(strategy.clone());
+                }
+            }
+        }
     });
 
-    let comment = MarketComment {
-        id: comment_id,
-        market_id,
-        author: caller,
-        content,
-        timestamp: ic_cdk::api::time(),
-    };
+    Ok(())
+}
 
-    COMMENTS.with(|comments| {
-        comments.borrow_mut().push(comment);
-    });
+#[ic_cdk::query]
+fn get_treasury_balance() -> u64 {
+    TREASURY.with(|treasury| *treasury.borrow())
+}
 
-    Ok(comment_id)
+fn record_treasury_inflow(amount: u64, market_id: u64, timestamp: u64) {
+    if amount == 0 {
+        return;
+    }
+    FEES_COLLECTED.with(|collected| *collected.borrow_mut() += amount);
+
+    TREASURY_INFLOWS.with(|inflows| {
+        let mut inflows = inflows.borrow_mut();
+        inflows.push_back(TreasuryInflow {
+            amount,
+            market_id,
+            timestamp,
+        });
+        if inflows.len() > MAX_TREASURY_INFLOWS {
+            inflows.pop_front();
+        }
+    });
 }
 
+// Inflows with from <= timestamp < to, oldest first
 #[ic_cdk::query]
-fn get_market_comments(market_id: u64) -> Vec<MarketComment> {
-    COMMENTS.with(|comments| {
-        comments
+fn get_treasury_inflows(from: u64, to: u64) -> Vec<TreasuryInflow> {
+    TREASURY_INFLOWS.with(|inflows| {
+        inflows
             .borrow()
             .iter()
-            .filter(|comment| comment.market_id == market_id)
+            .filter(|inflow| inflow.timestamp >= from && inflow.timestamp < to)
             .cloned()
             .collect()
     })
 }
 
+// Revenue per UTC day for the last `days` days including today, oldest first,
+// as (day start in seconds, amount). Days before the retained log read as 0.
 #[ic_cdk::query]
-fn get_treasury_balance() -> u64 {
-    TREASURY.with(|treasury| *treasury.borrow())
+fn get_daily_revenue(days: u64) -> Vec<(u64, u64)> {
+    let days = days.min(MAX_REVENUE_DAYS);
+    let today = to_secs(ic_cdk::api::time()) / SECONDS_PER_DAY;
+    let first = (today + 1).saturating_sub(days);
+
+    let mut revenue: Vec<(u64, u64)> = (first..=today)
+        .map(|day| (day * SECONDS_PER_DAY, 0))
+        .collect();
+    TREASURY_INFLOWS.with(|inflows| {
+        for inflow in inflows.borrow().iter() {
+            let day = to_secs(inflow.timestamp) / SECONDS_PER_DAY;
+            if day >= first && day <= today {
+                let bucket = &mut revenue[(day - first) as usize].1;
+                *bucket = bucket.saturating_add(inflow.amount);
+            }
+        }
+    });
+    revenue
+}
+
+// Cash from floored share fractions credited to the treasury, included in its balance
+#[ic_cdk::query]
+fn get_dust_collected() -> u64 {
+    DUST_COLLECTED.with(|dust| *dust.borrow())
+}
+
+// Withdrawals may never take the treasury below this operational reserve
+#[ic_cdk::query]
+fn get_treasury_floor() -> u64 {
+    TREASURY_FLOOR.with(|floor| *floor.borrow())
+}
+
+#[ic_cdk::update]
+fn set_treasury_floor(floor: u64) -> Result<(), String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can set the treasury floor".to_string());
+    }
+
+    TREASURY_FLOOR.with(|f| *f.borrow_mut() = floor);
+    Ok(())
+}
+
+// Transfers treasury funds out of the canister's main ledger account. The
+// main account also holds swept user deposits, so only trading fees the
+// treasury actually collected can leave, and the ledger fee is paid from them.
+#[ic_cdk::update]
+async fn withdraw_treasury(amount: u64, to: Account) -> Result<u64, String> {
+    if !is_admin(&ic_cdk::caller()) {
+        return Err("Only admins can withdraw from the treasury".to_string());
+    }
+
+    if amount == 0 {
+        return Err("Amount must be greater than 0".to_string());
+    }
+
+    let ledger = LEDGER_ID.with(|id| *id.borrow());
+    let fee = ledger_fee(ledger).await?;
+    let total = amount
+        .checked_add(fee)
+        .ok_or_else(|| "Amount too large".to_string())?;
+
+    // Debit before the ledger call so concurrent withdrawals can't both pass the floor check
+    let collected = FEES_COLLECTED.with(|collected| *collected.borrow());
+    let withdrawn_before = FEES_WITHDRAWN.with(|withdrawn| {
+        let mut withdrawn = withdrawn.borrow_mut();
+        if withdrawn.saturating_add(total) > collected {
+            return Err("Withdrawal exceeds the fees collected".to_string());
+        }
+        TREASURY.with(|treasury| {
+            let mut treasury = treasury.borrow_mut();
+            let floor = TREASURY_FLOOR.with(|floor| *floor.borrow());
+            if treasury
+                .checked_sub(total)
+                .is_none_or(|remaining| remaining < floor)
+            {
+                return Err("Would breach treasury reserve floor".to_string());
+            }
+            *treasury -= total;
+            Ok(())
+        })?;
+        let before = *withdrawn;
+        *withdrawn += total;
+        Ok(before)
+    })?;
+
+    // Tagged so the ledger rejects a retried transfer as a duplicate
+    let transfer = TransferArg {
+        from_subaccount: None,
+        to,
+        fee: Some(Nat::from(fee)),
+        memo: Some(withdrawal_memo(b"treasury", withdrawn_before)),
+        created_at_time: Some(ic_cdk::api::time()),
+        amount: Nat::from(amount),
+    };
+
+    let result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(ledger, "icrc1_transfer", (transfer,)).await;
+    let block_index = match result {
+        Ok((Ok(block_index),)) => Ok(block_index),
+        Ok((Err(e),)) => Err(format!("Ledger transfer rejected: {:?}", e)),
+        Err((code, msg)) => Err(format!("Ledger transfer failed: {:?} {}", code, msg)),
+    };
+
+    // Restore the treasury if the transfer did not go through
+    if block_index.is_err() {
+        TREASURY.with(|treasury| *treasury.borrow_mut() += total);
+        FEES_WITHDRAWN.with(|withdrawn| *withdrawn.borrow_mut() -= total);
+    }
+
+    nat_to_u64(block_index?)
 }
 
 export_candid!();
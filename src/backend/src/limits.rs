@@ -0,0 +1,444 @@
+// Responsible trading limits
+//
+// Users can cap what they trade over a rolling day and week, and exclude
+// themselves from trading for a chosen period. Tightening takes effect at once;
+// loosening (raising or removing a cap) is held back for a cool-down so it
+// cannot be done on impulse. A self-exclusion can be extended but never cut
+// short, by the user or anyone else. Spend counters are kept for every trader
+// so limits set later still see the trailing week.
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+pub const DAY_NANOS: u64 = 86_400 * NANOS_PER_SECOND;
+pub const WEEK_NANOS: u64 = 7 * DAY_NANOS;
+pub const LOOSEN_COOL_DOWN_NANOS: u64 = DAY_NANOS;
+pub const MIN_EXCLUSION_SECONDS: u64 = 86_400;
+const MAX_EXCLUSION_SECONDS: u64 = 5 * 365 * 86_400;
+
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub struct TradingLimits {
+    pub daily_limit: Option<u64>, // None means uncapped
+    pub weekly_limit: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct UserLimits {
+    pub limits: TradingLimits,
+    pub pending: Option<TradingLimits>, // a loosening waiting out the cool-down
+    pub pending_effective_at: Option<u64>,
+    pub excluded_until: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TradingLimitsStatus {
+    pub limits: TradingLimits,
+    pub pending: Option<TradingLimits>,
+    pub pending_effective_at: Option<u64>,
+    pub excluded_until: Option<u64>,
+    pub spent_last_day: u64,
+    pub spent_last_week: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LimitBreach {
+    SelfExcluded { until: u64 },
+    DailyLimit { limit: u64, resets_at: Option<u64> }, // None: the amount alone is over the cap
+    WeeklyLimit { limit: u64, resets_at: Option<u64> },
+}
+
+impl LimitBreach {
+    // Rendered with a stable prefix so clients can branch on the kind
+    pub fn message(&self) -> String {
+        match self {
+            LimitBreach::SelfExcluded { until } => format!("SelfExcluded: until {}", until),
+            LimitBreach::DailyLimit { limit, resets_at } => {
+                breach_message("DailyLimitExceeded", *limit, *resets_at)
+            }
+            LimitBreach::WeeklyLimit { limit, resets_at } => {
+                breach_message("WeeklyLimitExceeded", *limit, *resets_at)
+            }
+        }
+    }
+}
+
+fn breach_message(kind: &str, limit: u64, resets_at: Option<u64>) -> String {
+    match resets_at {
+        Some(at) => format!("{}: limit {}, resets at {}", kind, limit, at),
+        None => format!("{}: amount is above the limit of {}", kind, limit),
+    }
+}
+
+// Per trader, as persisted across upgrades
+pub type SpendLog = Vec<(Principal, Vec<(u64, u64)>)>;
+
+thread_local! {
+    static USER_LIMITS: RefCell<BTreeMap<Principal, UserLimits>> = const { RefCell::new(BTreeMap::new()) };
+    // (timestamp, amount) per trade over the trailing week, oldest first
+    static SPEND_LOG: RefCell<BTreeMap<Principal, VecDeque<(u64, u64)>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn snapshot() -> (Vec<(Principal, UserLimits)>, SpendLog) {
+    let limits = USER_LIMITS.with(|limits| {
+        limits
+            .borrow()
+            .iter()
+            .map(|(p, l)| (*p, l.clone()))
+            .collect()
+    });
+    let spends = SPEND_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .map(|(p, spends)| (*p, spends.iter().copied().collect()))
+            .collect()
+    });
+    (limits, spends)
+}
+
+pub fn restore(limits: Vec<(Principal, UserLimits)>, spends: SpendLog) {
+    USER_LIMITS.with(|current| *current.borrow_mut() = limits.into_iter().collect());
+    SPEND_LOG.with(|log| {
+        *log.borrow_mut() = spends
+            .into_iter()
+            .map(|(p, spends)| (p, spends.into()))
+            .collect()
+    });
+}
+
+// The user's limits with any matured loosening applied
+fn current(principal: Principal, now: u64) -> UserLimits {
+    USER_LIMITS.with(|limits| {
+        let mut limits = limits.borrow_mut();
+        let Some(user) = limits.get_mut(&principal) else {
+            return UserLimits::default();
+        };
+        if user.pending_effective_at.is_some_and(|at| at <= now) {
+            if let Some(pending) = user.pending.take() {
+                user.limits = pending;
+            }
+            user.pending_effective_at = None;
+        }
+        user.clone()
+    })
+}
+
+fn spent_since(principal: Principal, since: u64) -> u64 {
+    SPEND_LOG.with(|log| {
+        log.borrow().get(&principal).map_or(0, |spends| {
+            spends
+                .iter()
+                .filter(|(at, _)| *at > since)
+                .map(|(_, amount)| *amount)
+                .fold(0u64, |total, amount| total.saturating_add(amount))
+        })
+    })
+}
+
+// When enough of the window's spend has rolled off for `amount` to fit under
+// `limit`; None if the amount alone exceeds it
+fn resets_at(principal: Principal, window: u64, limit: u64, amount: u64, now: u64) -> Option<u64> {
+    if amount > limit {
+        return None;
+    }
+    let since = now.saturating_sub(window);
+    SPEND_LOG.with(|log| {
+        let log = log.borrow();
+        let spends = log.get(&principal)?;
+        let mut spent = spent_since(principal, since);
+        for (at, spend) in spends.iter().filter(|(at, _)| *at > since) {
+            spent = spent.saturating_sub(*spend);
+            if spent.saturating_add(amount) <= limit {
+                return Some(at + window);
+            }
+        }
+        None
+    })
+}
+
+pub fn check_trade(principal: Principal, amount: u64, now: u64) -> Result<(), LimitBreach> {
+    let user = current(principal, now);
+    if let Some(until) = user.excluded_until.filter(|until| *until > now) {
+        return Err(LimitBreach::SelfExcluded { until });
+    }
+
+    if let Some(limit) = user.limits.daily_limit {
+        let spent = spent_since(principal, now.saturating_sub(DAY_NANOS));
+        if spent.saturating_add(amount) > limit {
+            let resets_at = resets_at(principal, DAY_NANOS, limit, amount, now);
+            return Err(LimitBreach::DailyLimit { limit, resets_at });
+        }
+    }
+    if let Some(limit) = user.limits.weekly_limit {
+        let spent = spent_since(principal, now.saturating_sub(WEEK_NANOS));
+        if spent.saturating_add(amount) > limit {
+            let resets_at = resets_at(principal, WEEK_NANOS, limit, amount, now);
+            return Err(LimitBreach::WeeklyLimit { limit, resets_at });
+        }
+    }
+    Ok(())
+}
+
+pub fn record_spend(principal: Principal, amount: u64, now: u64) {
+    SPEND_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let spends = log.entry(principal).or_default();
+        while spends
+            .front()
+            .is_some_and(|(at, _)| *at <= now.saturating_sub(WEEK_NANOS))
+        {
+            spends.pop_front();
+        }
+        spends.push_back((now, amount));
+    });
+}
+
+// Drops a spend recorded by record_spend, for a trade that was undone
+pub fn release_spend(principal: Principal, amount: u64, at: u64) {
+    SPEND_LOG.with(|log| {
+        if let Some(spends) = log.borrow_mut().get_mut(&principal) {
+            if let Some(index) = spends.iter().rposition(|spend| *spend == (at, amount)) {
+                spends.remove(index);
+            }
+        }
+    });
+}
+
+// A cap is looser when it is removed or raised
+fn loosens(current: Option<u64>, requested: Option<u64>) -> bool {
+    match (current, requested) {
+        (Some(_), None) => true,
+        (Some(current), Some(requested)) => requested > current,
+        (None, _) => false,
+    }
+}
+
+fn tightest(current: Option<u64>, requested: Option<u64>) -> Option<u64> {
+    match (current, requested) {
+        (Some(current), Some(requested)) => Some(current.min(requested)),
+        (current, requested) => current.or(requested),
+    }
+}
+
+// Applies the tightening parts of `requested` now. If anything loosens, the
+// full request replaces any earlier pending change and matures after the
+// cool-down; a request that only tightens cancels a pending loosening.
+pub fn set_limits(
+    principal: Principal,
+    requested: TradingLimits,
+    now: u64,
+) -> Result<TradingLimitsStatus, String> {
+    if requested.daily_limit == Some(0) || requested.weekly_limit == Some(0) {
+        return Err("Limits must be greater than 0; self-exclusion blocks trading".to_string());
+    }
+    if let (Some(daily), Some(weekly)) = (requested.daily_limit, requested.weekly_limit) {
+        if daily > weekly {
+            return Err("The daily limit cannot exceed the weekly limit".to_string());
+        }
+    }
+
+    let mut user = current(principal, now);
+    let loosening = loosens(user.limits.daily_limit, requested.daily_limit)
+        || loosens(user.limits.weekly_limit, requested.weekly_limit);
+    user.limits = TradingLimits {
+        daily_limit: tightest(user.limits.daily_limit, requested.daily_limit),
+        weekly_limit: tightest(user.limits.weekly_limit, requested.weekly_limit),
+    };
+    if loosening {
+        user.pending = Some(requested);
+        user.pending_effective_at = Some(now.saturating_add(LOOSEN_COOL_DOWN_NANOS));
+    } else {
+        user.pending = None;
+        user.pending_effective_at = None;
+    }
+
+    USER_LIMITS.with(|limits| limits.borrow_mut().insert(principal, user));
+    Ok(status(principal, now))
+}
+
+// Starts or extends a self-exclusion; an active one is never shortened
+pub fn self_exclude(principal: Principal, duration_seconds: u64, now: u64) -> Result<u64, String> {
+    if !(MIN_EXCLUSION_SECONDS..=MAX_EXCLUSION_SECONDS).contains(&duration_seconds) {
+        return Err(format!(
+            "Self-exclusion must last between {} and {} seconds",
+            MIN_EXCLUSION_SECONDS, MAX_EXCLUSION_SECONDS
+        ));
+    }
+
+    let mut user = current(principal, now);
+    let requested = now.saturating_add(duration_seconds * NANOS_PER_SECOND);
+    let until = user
+        .excluded_until
+        .filter(|until| *until > now)
+        .map_or(requested, |until| until.max(requested));
+    user.excluded_until = Some(until);
+
+    USER_LIMITS.with(|limits| limits.borrow_mut().insert(principal, user));
+    Ok(until)
+}
+
+pub fn status(principal: Principal, now: u64) -> TradingLimitsStatus {
+    let user = current(principal, now);
+    TradingLimitsStatus {
+        limits: user.limits,
+        pending: user.pending,
+        pending_effective_at: user.pending_effective_at,
+        excluded_until: user.excluded_until.filter(|until| *until > now),
+        spent_last_day: spent_since(principal, now.saturating_sub(DAY_NANOS)),
+        spent_last_week: spent_since(principal, now.saturating_sub(WEEK_NANOS)),
+    }
+}
+
+// Rebuilds the spend counters from the trade log; limits are left alone.
+// Returns how many traders have spend in the trailing week.
+pub fn rebuild_spend(trades: impl Iterator<Item = (Principal, u64, u64)>, now: u64) -> u64 {
+    let since = now.saturating_sub(WEEK_NANOS);
+    let mut rebuilt: BTreeMap<Principal, Vec<(u64, u64)>> = BTreeMap::new();
+    for (principal, at, amount) in trades.filter(|(_, at, _)| *at > since) {
+        rebuilt.entry(principal).or_default().push((at, amount));
+    }
+    let traders = rebuilt.len() as u64;
+    SPEND_LOG.with(|log| {
+        *log.borrow_mut() = rebuilt
+            .into_iter()
+            .map(|(principal, mut spends)| {
+                spends.sort_unstable();
+                (principal, spends.into())
+            })
+            .collect()
+    });
+    traders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3_600 * NANOS_PER_SECOND;
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    #[test]
+    fn released_spend_frees_the_cap() {
+        let bob = user(9);
+        let start = 20 * DAY_NANOS;
+        set_limits(
+            bob,
+            TradingLimits {
+                daily_limit: Some(100),
+                weekly_limit: None,
+            },
+            start,
+        )
+        .unwrap();
+
+        record_spend(bob, 80, start);
+        assert!(check_trade(bob, 50, start + HOUR).is_err());
+        release_spend(bob, 80, start);
+        assert_eq!(check_trade(bob, 50, start + HOUR), Ok(()));
+    }
+
+    #[test]
+    fn rolling_caps_report_when_spend_rolls_off() {
+        let alice = user(1);
+        let start = 10 * DAY_NANOS;
+        set_limits(
+            alice,
+            TradingLimits {
+                daily_limit: Some(100),
+                weekly_limit: Some(150),
+            },
+            start,
+        )
+        .unwrap();
+
+        record_spend(alice, 60, start);
+        record_spend(alice, 30, start + HOUR);
+        assert_eq!(
+            check_trade(alice, 20, start + 2 * HOUR),
+            Err(LimitBreach::DailyLimit {
+                limit: 100,
+                resets_at: Some(start + DAY_NANOS),
+            })
+        );
+        assert_eq!(
+            check_trade(alice, 101, start + 2 * HOUR),
+            Err(LimitBreach::DailyLimit {
+                limit: 100,
+                resets_at: None,
+            })
+        );
+        assert!(check_trade(alice, 10, start + 2 * HOUR).is_ok());
+
+        // The day rolled over but the week still holds the earlier spend
+        let next_day = start + DAY_NANOS + 2 * HOUR;
+        assert_eq!(
+            check_trade(alice, 70, next_day),
+            Err(LimitBreach::WeeklyLimit {
+                limit: 150,
+                resets_at: Some(start + WEEK_NANOS),
+            })
+        );
+        assert!(check_trade(alice, 60, next_day).is_ok());
+    }
+
+    #[test]
+    fn loosening_waits_for_the_cool_down() {
+        let bob = user(2);
+        let now = DAY_NANOS;
+        let tight = TradingLimits {
+            daily_limit: Some(50),
+            weekly_limit: None,
+        };
+        set_limits(bob, tight.clone(), now).unwrap();
+
+        let loose = TradingLimits {
+            daily_limit: Some(500),
+            weekly_limit: None,
+        };
+        let view = set_limits(bob, loose.clone(), now + HOUR).unwrap();
+        assert_eq!(view.limits, tight);
+        assert_eq!(view.pending, Some(loose.clone()));
+        assert!(check_trade(bob, 100, now + 2 * HOUR).is_err());
+
+        let matured = now + HOUR + LOOSEN_COOL_DOWN_NANOS;
+        assert_eq!(status(bob, matured).limits, loose);
+        assert!(check_trade(bob, 100, matured).is_ok());
+
+        // Tightening again applies at once
+        let view = set_limits(bob, tight.clone(), matured).unwrap();
+        assert_eq!(view.limits, tight);
+        assert_eq!(view.pending, None);
+    }
+
+    #[test]
+    fn self_exclusion_only_extends() {
+        let carol = user(3);
+        let now = DAY_NANOS;
+        assert!(self_exclude(carol, 3_600, now).is_err());
+
+        let until = self_exclude(carol, 2 * 86_400, now).unwrap();
+        assert_eq!(self_exclude(carol, 86_400, now + HOUR).unwrap(), until);
+        assert_eq!(
+            check_trade(carol, 1, now + HOUR),
+            Err(LimitBreach::SelfExcluded { until })
+        );
+        assert!(check_trade(carol, 1, until).is_ok());
+    }
+
+    #[test]
+    fn counters_rebuild_from_the_trade_log() {
+        let dave = user(4);
+        let now = 30 * DAY_NANOS;
+        record_spend(dave, 1_000, now - HOUR);
+        let trades = vec![
+            (dave, now - 2 * HOUR, 40),
+            (dave, now - HOUR, 10),
+            (dave, now - 8 * DAY_NANOS, 999),
+        ];
+        assert_eq!(rebuild_spend(trades.into_iter(), now), 1);
+        assert_eq!(status(dave, now).spent_last_week, 50);
+    }
+}
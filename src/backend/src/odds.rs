@@ -0,0 +1,197 @@
+// Odds formats
+//
+// Every format is derived from the implied YES probability in bps, clamped to
+// 0.01%..99.99% first so even the most lopsided pool quotes finite odds.
+// Rounding rules, all to nearest with halves away from zero:
+// - Decimal odds are European odds in hundredths (2.50 is 250)
+// - American odds are whole numbers: +100 and up for probabilities up to 50%,
+//   -100 and down above it
+// - Fractional odds are the decimal odds' profit over the stake, reduced, so
+//   they agree with the rounded decimal figure (1.33 reads 33/100)
+// Going back to a probability rounds to the nearest bps.
+use candid::{CandidType, Deserialize};
+
+const MIN_BPS: u64 = 1;
+const MAX_BPS: u64 = 9_999;
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum OddsFormat {
+    Probability,
+    Decimal,
+    American,
+    Fractional,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum Odds {
+    Probability(u64), // bps
+    Decimal(u64),     // hundredths
+    American(i32),
+    Fractional { num: u64, den: u64 }, // profit / stake
+}
+
+fn div_round(numerator: u64, denominator: u64) -> u64 {
+    (numerator + denominator / 2) / denominator
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+pub fn from_probability(yes_bps: u64, format: OddsFormat) -> Odds {
+    let bps = yes_bps.clamp(MIN_BPS, MAX_BPS);
+    match format {
+        OddsFormat::Probability => Odds::Probability(yes_bps.min(10_000)),
+        OddsFormat::Decimal => Odds::Decimal(div_round(1_000_000, bps)),
+        OddsFormat::American if bps <= 5_000 => {
+            Odds::American(div_round(100 * (10_000 - bps), bps) as i32)
+        }
+        OddsFormat::American => Odds::American(-(div_round(100 * bps, 10_000 - bps) as i32)),
+        OddsFormat::Fractional => {
+            let profit = div_round(1_000_000, bps) - 100;
+            let divisor = gcd(profit, 100);
+            Odds::Fractional {
+                num: profit / divisor,
+                den: 100 / divisor,
+            }
+        }
+    }
+}
+
+pub fn to_probability(odds: Odds) -> Result<u64, String> {
+    match odds {
+        Odds::Probability(bps) if bps > 10_000 => {
+            Err("Probabilities cannot exceed 10000 bps".to_string())
+        }
+        Odds::Probability(bps) => Ok(bps),
+        Odds::Decimal(hundredths) if hundredths < 100 => {
+            Err("Decimal odds start at 1.00".to_string())
+        }
+        Odds::Decimal(hundredths) => Ok(div_round(1_000_000, hundredths)),
+        Odds::American(american) if american >= 100 => {
+            Ok(div_round(1_000_000, american as u64 + 100))
+        }
+        Odds::American(american) if american <= -100 => {
+            let stake = american.unsigned_abs() as u64;
+            Ok(div_round(10_000 * stake, stake + 100))
+        }
+        Odds::American(_) => Err("American odds are at least +100 or at most -100".to_string()),
+        Odds::Fractional { den: 0, .. } => {
+            Err("Fractional odds need a non-zero denominator".to_string())
+        }
+        Odds::Fractional { num, den } => {
+            let total = num as u128 + den as u128;
+            Ok(((10_000 * den as u128 + total / 2) / total) as u64)
+        }
+    }
+}
+
+// Locale-neutral strings: "66.67%", "2.50", "+150", "3/2"
+pub fn format(odds: Odds) -> String {
+    match odds {
+        Odds::Probability(bps) if bps % 100 == 0 => format!("{}%", bps / 100),
+        Odds::Probability(bps) => format!("{}.{:02}%", bps / 100, bps % 100),
+        Odds::Decimal(hundredths) => format!("{}.{:02}", hundredths / 100, hundredths % 100),
+        Odds::American(american) if american > 0 => format!("+{}", american),
+        Odds::American(american) => american.to_string(),
+        Odds::Fractional { num, den } => format!("{}/{}", num, den),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all(bps: u64) -> (Odds, Odds, Odds) {
+        (
+            from_probability(bps, OddsFormat::Decimal),
+            from_probability(bps, OddsFormat::American),
+            from_probability(bps, OddsFormat::Fractional),
+        )
+    }
+
+    #[test]
+    fn standard_conversion_table() {
+        let table = [
+            (5_000, 200, 100, (1, 1)),
+            (4_000, 250, 150, (3, 2)),
+            (2_500, 400, 300, (3, 1)),
+            (2_000, 500, 400, (4, 1)),
+            (6_667, 150, -200, (1, 2)),
+            (7_500, 133, -300, (33, 100)),
+            (8_000, 125, -400, (1, 4)),
+        ];
+        for (bps, decimal, american, (num, den)) in table {
+            assert_eq!(
+                all(bps),
+                (
+                    Odds::Decimal(decimal),
+                    Odds::American(american),
+                    Odds::Fractional { num, den }
+                ),
+                "{} bps",
+                bps
+            );
+        }
+    }
+
+    #[test]
+    fn extremes_are_clamped_to_finite_odds() {
+        let longshot = (
+            Odds::Decimal(1_000_000),
+            Odds::American(999_900),
+            Odds::Fractional { num: 9_999, den: 1 },
+        );
+        assert_eq!(all(0), longshot);
+        assert_eq!(all(1), longshot);
+
+        let lock = (
+            Odds::Decimal(100),
+            Odds::American(-999_900),
+            Odds::Fractional { num: 0, den: 1 },
+        );
+        assert_eq!(all(10_000), lock);
+        assert_eq!(all(9_999), lock);
+        assert_eq!(
+            from_probability(10_000, OddsFormat::Probability),
+            Odds::Probability(10_000)
+        );
+    }
+
+    #[test]
+    fn odds_convert_back_to_probability() {
+        assert_eq!(to_probability(Odds::Decimal(250)), Ok(4_000));
+        assert_eq!(to_probability(Odds::American(150)), Ok(4_000));
+        assert_eq!(to_probability(Odds::American(-200)), Ok(6_667));
+        assert_eq!(
+            to_probability(Odds::Fractional { num: 3, den: 2 }),
+            Ok(4_000)
+        );
+        assert_eq!(to_probability(Odds::Decimal(100)), Ok(10_000));
+        assert_eq!(to_probability(Odds::American(100)), Ok(5_000));
+        assert_eq!(to_probability(Odds::American(-100)), Ok(5_000));
+        assert!(to_probability(Odds::American(99)).is_err());
+        assert!(to_probability(Odds::Decimal(99)).is_err());
+        assert!(to_probability(Odds::Fractional { num: 1, den: 0 }).is_err());
+        assert!(to_probability(Odds::Probability(10_001)).is_err());
+
+        for bps in [1, 2_000, 4_000, 5_000, 8_000] {
+            let american = from_probability(bps, OddsFormat::American);
+            assert_eq!(to_probability(american), Ok(bps), "{} bps", bps);
+        }
+    }
+
+    #[test]
+    fn odds_format_for_display() {
+        assert_eq!(format(Odds::Probability(6_667)), "66.67%");
+        assert_eq!(format(Odds::Probability(4_000)), "40%");
+        assert_eq!(format(Odds::Decimal(250)), "2.50");
+        assert_eq!(format(Odds::American(150)), "+150");
+        assert_eq!(format(Odds::American(-200)), "-200");
+        assert_eq!(format(Odds::Fractional { num: 3, den: 2 }), "3/2");
+    }
+}
@@ -0,0 +1,321 @@
+// Daily quests
+//
+// A small admin-editable set of quests is offered to everyone each UTC day.
+// Progress is tracked per (day, principal, quest) as trades, comments and
+// resolutions happen; the scheduler drops earlier days' progress after
+// midnight. Rewards are claimed explicitly, and claiming on consecutive days
+// builds a streak.
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+const MAX_QUESTS: usize = 10;
+const MAX_QUEST_TITLE_LEN: usize = 100;
+const MAX_QUEST_TARGET: u64 = 100;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum QuestGoal {
+    Trade { min_amount: u64 },
+    CommentOnHeldMarket,
+    CorrectPrediction, // on a market resolved today
+    NewCategory,       // first trade in a category the principal never traded
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Quest {
+    pub id: u64,
+    pub title: String,
+    pub goal: QuestGoal,
+    pub target: u64, // times the goal must be met
+    pub xp_reward: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct QuestProgress {
+    pub quest: Quest,
+    pub progress: u64,
+    pub completed: bool,
+    pub claimed: bool,
+}
+
+pub enum QuestEvent {
+    Trade { amount: u64, new_category: bool },
+    CommentOnHeldMarket,
+    CorrectPrediction,
+}
+
+#[derive(Default)]
+struct Entry {
+    progress: u64,
+    claimed: bool,
+}
+
+thread_local! {
+    static QUESTS: RefCell<Vec<Quest>> = RefCell::new(default_quests());
+    static PROGRESS: RefCell<BTreeMap<(u64, Principal, u64), Entry>> = const { RefCell::new(BTreeMap::new()) };
+    // principal -> (last day a reward was claimed, consecutive days)
+    static STREAKS: RefCell<HashMap<Principal, (u64, u64)>> = RefCell::new(HashMap::new());
+}
+
+fn default_quests() -> Vec<Quest> {
+    vec![
+        Quest {
+            id: 1,
+            title: "Make a trade of 100 or more".to_string(),
+            goal: QuestGoal::Trade { min_amount: 100 },
+            target: 1,
+            xp_reward: 20,
+        },
+        Quest {
+            id: 2,
+            title: "Comment on a market you hold".to_string(),
+            goal: QuestGoal::CommentOnHeldMarket,
+            target: 1,
+            xp_reward: 10,
+        },
+        Quest {
+            id: 3,
+            title: "Get a prediction right on a market resolved today".to_string(),
+            goal: QuestGoal::CorrectPrediction,
+            target: 1,
+            xp_reward: 30,
+        },
+        Quest {
+            id: 4,
+            title: "Trade in a category you have never traded".to_string(),
+            goal: QuestGoal::NewCategory,
+            target: 1,
+            xp_reward: 15,
+        },
+    ]
+}
+
+pub fn list() -> Vec<Quest> {
+    QUESTS.with(|quests| quests.borrow().clone())
+}
+
+// Replaces the quest set. Progress on quests that keep their id is kept.
+pub fn set(quests: Vec<Quest>) -> Result<(), String> {
+    if quests.len() > MAX_QUESTS {
+        return Err(format!("At most {} quests can be active", MAX_QUESTS));
+    }
+    for (i, quest) in quests.iter().enumerate() {
+        if quests[..i].iter().any(|other| other.id == quest.id) {
+            return Err(format!("Duplicate quest id {}", quest.id));
+        }
+        let title_len = quest.title.trim().chars().count();
+        if title_len == 0 || title_len > MAX_QUEST_TITLE_LEN {
+            return Err(format!(
+                "Quest title must be between 1 and {} characters",
+                MAX_QUEST_TITLE_LEN
+            ));
+        }
+        if quest.target == 0 || quest.target > MAX_QUEST_TARGET {
+            return Err(format!(
+                "Quest target must be between 1 and {}",
+                MAX_QUEST_TARGET
+            ));
+        }
+    }
+
+    QUESTS.with(|current| *current.borrow_mut() = quests);
+    Ok(())
+}
+
+fn counts(goal: &QuestGoal, event: &QuestEvent) -> bool {
+    match (goal, event) {
+        (QuestGoal::Trade { min_amount }, QuestEvent::Trade { amount, .. }) => amount >= min_amount,
+        (QuestGoal::NewCategory, QuestEvent::Trade { new_category, .. }) => *new_category,
+        (QuestGoal::CommentOnHeldMarket, QuestEvent::CommentOnHeldMarket) => true,
+        (QuestGoal::CorrectPrediction, QuestEvent::CorrectPrediction) => true,
+        _ => false,
+    }
+}
+
+pub fn record(principal: Principal, event: QuestEvent, day: u64) {
+    let matching: Vec<(u64, u64)> = QUESTS.with(|quests| {
+        quests
+            .borrow()
+            .iter()
+            .filter(|quest| counts(&quest.goal, &event))
+            .map(|quest| (quest.id, quest.target))
+            .collect()
+    });
+
+    PROGRESS.with(|progress| {
+        let mut progress = progress.borrow_mut();
+        for (quest_id, target) in matching {
+            let entry = progress.entry((day, principal, quest_id)).or_default();
+            entry.progress = (entry.progress + 1).min(target);
+        }
+    });
+}
+
+pub fn for_principal(principal: Principal, day: u64) -> Vec<QuestProgress> {
+    PROGRESS.with(|progress| {
+        let progress = progress.borrow();
+        list()
+            .into_iter()
+            .map(|quest| {
+                let entry = progress.get(&(day, principal, quest.id));
+                let count = entry.map_or(0, |e| e.progress);
+                QuestProgress {
+                    completed: count >= quest.target,
+                    claimed: entry.is_some_and(|e| e.claimed),
+                    progress: count,
+                    quest,
+                }
+            })
+            .collect()
+    })
+}
+
+// Marks today's reward claimed and extends the claim streak. Returns the XP
+// reward and the streak length in days.
+pub fn claim(principal: Principal, quest_id: u64, day: u64) -> Result<(u64, u64), String> {
+    let quest = list()
+        .into_iter()
+        .find(|quest| quest.id == quest_id)
+        .ok_or_else(|| "Quest not found".to_string())?;
+
+    PROGRESS.with(|progress| {
+        let mut progress = progress.borrow_mut();
+        let entry = progress
+            .get_mut(&(day, principal, quest_id))
+            .filter(|entry| entry.progress >= quest.target)
+            .ok_or_else(|| "Quest not completed today".to_string())?;
+        if entry.claimed {
+            return Err("Quest reward already claimed".to_string());
+        }
+        entry.claimed = true;
+        Ok(())
+    })?;
+
+    let streak = STREAKS.with(|streaks| {
+        let mut streaks = streaks.borrow_mut();
+        let (last_day, streak) = streaks.entry(principal).or_insert((day, 0));
+        *streak = match day.checked_sub(*last_day) {
+            Some(0) if *streak > 0 => *streak,
+            Some(1) => *streak + 1,
+            _ => 1,
+        };
+        *last_day = day;
+        *streak
+    });
+
+    Ok((quest.xp_reward, streak))
+}
+
+// Consecutive active days up to the latest one. Today counts as soon as it has
+// activity but does not break the streak until it is over; a whole missed day
+// resets it to 0.
+pub fn activity_streak(active_days: &BTreeSet<u64>, today: u64) -> u64 {
+    let mut days = active_days.range(..=today).rev();
+    let Some(&latest) = days.next() else {
+        return 0;
+    };
+    if today - latest > 1 {
+        return 0;
+    }
+
+    let mut streak = 1;
+    let mut previous = latest;
+    for &day in days {
+        if day + 1 != previous {
+            break;
+        }
+        streak += 1;
+        previous = day;
+    }
+    streak
+}
+
+// Drops progress from days before `day`
+pub fn reset_before(day: u64) {
+    PROGRESS.with(|progress| progress.borrow_mut().retain(|(d, _, _), _| *d >= day));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    #[test]
+    fn progress_counts_matching_events_up_to_the_target() {
+        let alice = user(1);
+        record(
+            alice,
+            QuestEvent::Trade {
+                amount: 50,
+                new_category: true,
+            },
+            10,
+        );
+        let quests = for_principal(alice, 10);
+        assert_eq!(quests[0].progress, 0); // trade below the minimum
+        assert!(quests[3].completed); // new category
+        assert!(!quests[1].completed);
+
+        record(
+            alice,
+            QuestEvent::Trade {
+                amount: 500,
+                new_category: false,
+            },
+            10,
+        );
+        record(
+            alice,
+            QuestEvent::Trade {
+                amount: 500,
+                new_category: false,
+            },
+            10,
+        );
+        assert_eq!(for_principal(alice, 10)[0].progress, 1);
+        assert_eq!(for_principal(alice, 11)[0].progress, 0);
+    }
+
+    #[test]
+    fn rewards_are_claimed_once_and_build_a_streak() {
+        let bob = user(2);
+        assert!(claim(bob, 2, 20).is_err());
+
+        record(bob, QuestEvent::CommentOnHeldMarket, 20);
+        assert_eq!(claim(bob, 2, 20), Ok((10, 1)));
+        assert!(claim(bob, 2, 20).is_err());
+
+        record(bob, QuestEvent::CorrectPrediction, 20);
+        assert_eq!(claim(bob, 3, 20), Ok((30, 1)));
+
+        record(bob, QuestEvent::CommentOnHeldMarket, 21);
+        assert_eq!(claim(bob, 2, 21), Ok((10, 2)));
+
+        record(bob, QuestEvent::CommentOnHeldMarket, 23);
+        assert_eq!(claim(bob, 2, 23), Ok((10, 1)));
+    }
+
+    #[test]
+    fn activity_streaks_survive_today_but_not_a_missed_day() {
+        let days: BTreeSet<u64> = [3, 5, 6, 7].into_iter().collect();
+        assert_eq!(activity_streak(&days, 7), 3);
+        assert_eq!(activity_streak(&days, 8), 3);
+        assert_eq!(activity_streak(&days, 9), 0);
+        assert_eq!(activity_streak(&days, 5), 1);
+        assert_eq!(activity_streak(&days, 4), 1);
+        assert_eq!(activity_streak(&BTreeSet::new(), 0), 0);
+    }
+
+    #[test]
+    fn reset_drops_earlier_days_only() {
+        let carol = user(3);
+        record(carol, QuestEvent::CommentOnHeldMarket, 30);
+        record(carol, QuestEvent::CommentOnHeldMarket, 31);
+        reset_before(31);
+        assert!(!for_principal(carol, 30)[1].completed);
+        assert!(for_principal(carol, 31)[1].completed);
+    }
+}
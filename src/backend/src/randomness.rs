@@ -0,0 +1,143 @@
+// Verifiable draws
+//
+// Any ordering that must not look rigged (tie-breaks, raffles, prize splits)
+// is drawn from a fresh raw_rand seed fetched at the moment of need. The seed,
+// the candidates in submission order and the resulting order are stored
+// together, so anyone can recompute the result from the audit record:
+// candidates are sorted by sha256(seed || principal bytes), ascending.
+use candid::{CandidType, Deserialize, Principal};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+pub const ALGORITHM: &str = "sort ascending by sha256(seed || principal bytes)";
+const MAX_DRAW_PURPOSE_LEN: usize = 100;
+const MAX_DRAW_CANDIDATES: usize = 1_000;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Draw {
+    pub id: u64,
+    pub purpose: String,
+    pub seed: Vec<u8>,
+    pub candidates: Vec<Principal>, // as submitted, before ordering
+    pub order: Vec<Principal>,      // first = highest slot
+    pub algorithm: String,
+    pub drawn_at: u64,
+}
+
+thread_local! {
+    static DRAWS: RefCell<BTreeMap<u64, Draw>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_DRAW_ID: RefCell<u64> = const { RefCell::new(1) };
+}
+
+pub fn validate(purpose: &str, candidates: &[Principal]) -> Result<(), String> {
+    if purpose.trim().is_empty() || purpose.chars().count() > MAX_DRAW_PURPOSE_LEN {
+        return Err(format!(
+            "Draw purpose must be between 1 and {} characters",
+            MAX_DRAW_PURPOSE_LEN
+        ));
+    }
+    if candidates.len() < 2 || candidates.len() > MAX_DRAW_CANDIDATES {
+        return Err(format!(
+            "A draw needs between 2 and {} candidates",
+            MAX_DRAW_CANDIDATES
+        ));
+    }
+    let mut unique = candidates.to_vec();
+    unique.sort();
+    unique.dedup();
+    if unique.len() != candidates.len() {
+        return Err("Draw candidates must be distinct".to_string());
+    }
+    Ok(())
+}
+
+pub fn order(seed: &[u8], candidates: &[Principal]) -> Vec<Principal> {
+    let mut keyed: Vec<([u8; 32], Principal)> = candidates
+        .iter()
+        .map(|candidate| {
+            let mut hasher = Sha256::new();
+            hasher.update(seed);
+            hasher.update(candidate.as_slice());
+            (hasher.finalize().into(), *candidate)
+        })
+        .collect();
+    keyed.sort();
+    keyed.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+pub fn record(purpose: String, seed: Vec<u8>, candidates: Vec<Principal>, now: u64) -> Draw {
+    let id = NEXT_DRAW_ID.with(|id| {
+        let current_id = *id.borrow();
+        *id.borrow_mut() = current_id + 1;
+        current_id
+    });
+    let draw = Draw {
+        id,
+        purpose: purpose.trim().to_string(),
+        order: order(&seed, &candidates),
+        seed,
+        candidates,
+        algorithm: ALGORITHM.to_string(),
+        drawn_at: now,
+    };
+    DRAWS.with(|draws| draws.borrow_mut().insert(id, draw.clone()));
+    draw
+}
+
+pub fn get(id: u64) -> Option<Draw> {
+    DRAWS.with(|draws| draws.borrow().get(&id).cloned())
+}
+
+pub fn snapshot() -> Vec<Draw> {
+    DRAWS.with(|draws| draws.borrow().values().cloned().collect())
+}
+
+pub fn restore(draws: Vec<Draw>) {
+    let next_id = draws.iter().map(|d| d.id + 1).max().unwrap_or(1);
+    DRAWS.with(|current| *current.borrow_mut() = draws.into_iter().map(|d| (d.id, d)).collect());
+    NEXT_DRAW_ID.with(|next| *next.borrow_mut() = next_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principals(n: u8) -> Vec<Principal> {
+        (1..=n).map(|i| Principal::from_slice(&[i])).collect()
+    }
+
+    #[test]
+    fn order_is_reproducible_from_the_seed() {
+        let candidates = principals(5);
+        let draw = record("tie".to_string(), vec![7; 32], candidates.clone(), 0);
+
+        assert_eq!(draw.order, order(&draw.seed, &draw.candidates));
+        assert_eq!(get(draw.id).unwrap().order, draw.order);
+
+        let mut sorted = draw.order.clone();
+        sorted.sort();
+        assert_eq!(sorted, candidates);
+    }
+
+    #[test]
+    fn order_ignores_submission_order_but_not_the_seed() {
+        let candidates = principals(8);
+        let mut reversed = candidates.clone();
+        reversed.reverse();
+        assert_eq!(order(&[1; 32], &candidates), order(&[1; 32], &reversed));
+
+        let differs =
+            (0..10u8).any(|b| order(&[b; 32], &candidates) != order(&[1; 32], &candidates));
+        assert!(differs);
+    }
+
+    #[test]
+    fn candidates_must_be_distinct() {
+        let p = Principal::from_slice(&[1]);
+        assert!(validate("tie", &[p, p]).is_err());
+        assert!(validate("tie", &[p]).is_err());
+        assert!(validate(" ", &principals(2)).is_err());
+        assert!(validate("tie", &principals(2)).is_ok());
+    }
+}
@@ -0,0 +1,84 @@
+// Market references in comment text
+//
+// Comments point at other markets either as "#12" or spelled out as
+// "market 12" / "market:12" / "market-12" (any case). Ids are returned in order
+// of first appearance, without duplicates; whether they exist is for the
+// caller to check.
+
+pub fn parse_market_refs(content: &str) -> Vec<u64> {
+    let lower = content.to_lowercase();
+    let mut refs: Vec<u64> = vec![];
+    let mut push = |digits: &str| {
+        if let Ok(id) = digits.parse::<u64>() {
+            if !refs.contains(&id) {
+                refs.push(id);
+            }
+        }
+    };
+
+    let bytes = lower.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = if bytes[i] == b'#' {
+            Some(i + 1)
+        } else if bytes[i..].starts_with(b"market") && (i == 0 || !is_word(bytes[i - 1])) {
+            let after = i + "market".len();
+            match bytes.get(after) {
+                Some(b' ' | b':' | b'-') => Some(after + 1),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        if let Some(start) = start {
+            let end = start
+                + bytes[start..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_digit())
+                    .count();
+            // "#12abc" is a tag, not a reference
+            if end > start && bytes.get(end).is_none_or(|b| !is_word(*b)) {
+                push(&lower[start..end]);
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    refs
+}
+
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_hash_and_spelled_out_references() {
+        assert_eq!(
+            parse_market_refs("See #12 and Market 7, also market:3 (not #12 again)"),
+            vec![12, 7, 3]
+        );
+        assert_eq!(parse_market_refs("market-42."), vec![42]);
+        assert_eq!(
+            parse_market_refs("#1st supermarket 5 #x market"),
+            Vec::<u64>::new()
+        );
+        assert_eq!(
+            parse_market_refs("Prices in 2025 went up"),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn handles_non_ascii_text() {
+        assert_eq!(
+            parse_market_refs("Très bien, voir #9 — marché #10"),
+            vec![9, 10]
+        );
+    }
+}
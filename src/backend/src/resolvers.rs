@@ -0,0 +1,447 @@
+// Resolver registry
+//
+// Third-party resolvers stake a bond to be listed. When a listed resolver
+// settles a market it was designated for, an assignment records the fee it is
+// owed. The fee is paid once the dispute window passes quietly, or when an
+// admin rejects the dispute; an upheld dispute slashes part of the bond
+// instead, split between the disputer and the treasury. Track records survive
+// a withdrawal, so re-registering restores them.
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+pub const MIN_RESOLVER_STAKE: u64 = 1_000;
+pub const DISPUTE_WINDOW_NANOS: u64 = 2 * 86_400 * 1_000_000_000;
+const SLASH_BPS: u64 = 5_000; // of the bond, per upheld dispute
+const MAX_RESOLVER_FEE: u64 = 1_000_000;
+const MAX_RESOLVER_CATEGORIES: usize = 10;
+const MAX_CATEGORY_LEN: usize = 50;
+const MAX_DISPUTE_REASON_LEN: usize = 500;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ResolverProfile {
+    pub principal: Principal,
+    pub stake: u64, // listed while at least MIN_RESOLVER_STAKE
+    pub fee_per_resolution: u64,
+    pub categories: Vec<String>,
+    pub resolutions: u64,
+    pub disputes_upheld: u64,
+    pub disputes_rejected: u64,
+    pub fees_earned: u64,
+    pub slashed: u64,
+    pub registered_at: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum AssignmentStatus {
+    Pending, // inside the dispute window
+    Disputed,
+    Paid,
+    Slashed,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ResolverDispute {
+    pub disputer: Principal,
+    pub reason: String,
+    pub opened_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ResolverAssignment {
+    pub market_id: u64,
+    pub resolver: Principal,
+    pub fee: u64, // fee agreed at registration time, before any cap
+    pub resolved_at: u64,
+    pub status: AssignmentStatus,
+    pub dispute: Option<ResolverDispute>,
+}
+
+pub enum DisputeSettlement {
+    PayFee {
+        resolver: Principal,
+        fee: u64,
+    },
+    Slash {
+        disputer: Principal,
+        to_disputer: u64,
+        to_treasury: u64,
+    },
+}
+
+thread_local! {
+    static RESOLVERS: RefCell<BTreeMap<Principal, ResolverProfile>> = const { RefCell::new(BTreeMap::new()) };
+    // One assignment per market, keyed by market id
+    static ASSIGNMENTS: RefCell<BTreeMap<u64, ResolverAssignment>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+fn normalize_categories(categories: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = categories
+        .iter()
+        .map(|category| category.trim().to_string())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    if normalized.is_empty() || normalized.len() > MAX_RESOLVER_CATEGORIES {
+        return Err(format!(
+            "Between 1 and {} categories are required",
+            MAX_RESOLVER_CATEGORIES
+        ));
+    }
+    if normalized
+        .iter()
+        .any(|category| category.is_empty() || category.chars().count() > MAX_CATEGORY_LEN)
+    {
+        return Err(format!(
+            "Categories must be between 1 and {} characters",
+            MAX_CATEGORY_LEN
+        ));
+    }
+    Ok(normalized)
+}
+
+// Checks a registration or top-up before any funds move
+pub fn check_registration(
+    principal: Principal,
+    stake: u64,
+    fee: u64,
+    categories: &[String],
+) -> Result<(), String> {
+    normalize_categories(categories)?;
+    if fee > MAX_RESOLVER_FEE {
+        return Err(format!("Resolution fee cannot exceed {}", MAX_RESOLVER_FEE));
+    }
+    let current = get(principal).map_or(0, |profile| profile.stake);
+    if current.saturating_add(stake) < MIN_RESOLVER_STAKE {
+        return Err(format!(
+            "Resolvers must stake at least {}",
+            MIN_RESOLVER_STAKE
+        ));
+    }
+    Ok(())
+}
+
+// Adds the stake and replaces the fee and categories. Call check_registration first.
+pub fn register(
+    principal: Principal,
+    stake: u64,
+    fee: u64,
+    categories: &[String],
+    now: u64,
+) -> ResolverProfile {
+    let categories = normalize_categories(categories).unwrap_or_default();
+    RESOLVERS.with(|resolvers| {
+        let mut resolvers = resolvers.borrow_mut();
+        let profile = resolvers
+            .entry(principal)
+            .or_insert_with(|| ResolverProfile {
+                principal,
+                stake: 0,
+                fee_per_resolution: 0,
+                categories: vec![],
+                resolutions: 0,
+                disputes_upheld: 0,
+                disputes_rejected: 0,
+                fees_earned: 0,
+                slashed: 0,
+                registered_at: now,
+            });
+        profile.stake = profile.stake.saturating_add(stake);
+        profile.fee_per_resolution = fee;
+        profile.categories = categories;
+        profile.clone()
+    })
+}
+
+pub fn get(principal: Principal) -> Option<ResolverProfile> {
+    RESOLVERS.with(|resolvers| resolvers.borrow().get(&principal).cloned())
+}
+
+pub fn is_listed(principal: Principal) -> bool {
+    get(principal).is_some_and(|profile| profile.stake >= MIN_RESOLVER_STAKE)
+}
+
+pub fn covers(principal: Principal, category: &str) -> bool {
+    get(principal).is_some_and(|profile| {
+        profile
+            .categories
+            .iter()
+            .any(|covered| covered.eq_ignore_ascii_case(category.trim()))
+    })
+}
+
+// Listed resolvers, optionally only those covering a category, busiest first
+pub fn list(category: Option<&str>) -> Vec<ResolverProfile> {
+    let mut listed: Vec<ResolverProfile> = RESOLVERS.with(|resolvers| {
+        resolvers
+            .borrow()
+            .values()
+            .filter(|profile| profile.stake >= MIN_RESOLVER_STAKE)
+            .filter(|profile| category.is_none_or(|category| covers(profile.principal, category)))
+            .cloned()
+            .collect()
+    });
+    listed.sort_by_key(|profile| (std::cmp::Reverse(profile.resolutions), profile.principal));
+    listed
+}
+
+pub fn has_open_assignment(principal: Principal) -> bool {
+    ASSIGNMENTS.with(|assignments| {
+        assignments.borrow().values().any(|assignment| {
+            assignment.resolver == principal
+                && matches!(
+                    assignment.status,
+                    AssignmentStatus::Pending | AssignmentStatus::Disputed
+                )
+        })
+    })
+}
+
+// Returns the whole stake and delists the resolver
+pub fn withdraw(principal: Principal) -> Result<u64, String> {
+    RESOLVERS.with(|resolvers| {
+        let mut resolvers = resolvers.borrow_mut();
+        let profile = resolvers
+            .get_mut(&principal)
+            .filter(|profile| profile.stake > 0)
+            .ok_or_else(|| "No resolver stake to withdraw".to_string())?;
+        let stake = profile.stake;
+        profile.stake = 0;
+        Ok(stake)
+    })
+}
+
+// Records a resolution by a listed resolver and counts it on its track record
+pub fn assign(market_id: u64, resolver: Principal, now: u64) {
+    let Some(fee) = RESOLVERS.with(|resolvers| {
+        let mut resolvers = resolvers.borrow_mut();
+        let profile = resolvers.get_mut(&resolver)?;
+        profile.resolutions += 1;
+        Some(profile.fee_per_resolution)
+    }) else {
+        return;
+    };
+
+    ASSIGNMENTS.with(|assignments| {
+        assignments.borrow_mut().insert(
+            market_id,
+            ResolverAssignment {
+                market_id,
+                resolver,
+                fee,
+                resolved_at: now,
+                status: AssignmentStatus::Pending,
+                dispute: None,
+            },
+        )
+    });
+}
+
+pub fn assignment(market_id: u64) -> Option<ResolverAssignment> {
+    ASSIGNMENTS.with(|assignments| assignments.borrow().get(&market_id).cloned())
+}
+
+pub fn open_dispute(
+    market_id: u64,
+    disputer: Principal,
+    reason: &str,
+    now: u64,
+) -> Result<(), String> {
+    let reason = reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_DISPUTE_REASON_LEN {
+        return Err(format!(
+            "Dispute reason must be between 1 and {} characters",
+            MAX_DISPUTE_REASON_LEN
+        ));
+    }
+
+    ASSIGNMENTS.with(|assignments| {
+        let mut assignments = assignments.borrow_mut();
+        let assignment = assignments
+            .get_mut(&market_id)
+            .ok_or_else(|| "This market was not settled by a listed resolver".to_string())?;
+        if assignment.status != AssignmentStatus::Pending
+            || now.saturating_sub(assignment.resolved_at) >= DISPUTE_WINDOW_NANOS
+        {
+            return Err("The dispute window for this resolution is closed".to_string());
+        }
+        if assignment.resolver == disputer {
+            return Err("Resolvers cannot dispute their own resolution".to_string());
+        }
+
+        assignment.status = AssignmentStatus::Disputed;
+        assignment.dispute = Some(ResolverDispute {
+            disputer,
+            reason: reason.to_string(),
+            opened_at: now,
+        });
+        Ok(())
+    })
+}
+
+// Decides an open dispute. Upholding it slashes the bond; half goes to the
+// disputer and the rest to the treasury. Rejecting it releases the fee.
+pub fn settle_dispute(market_id: u64, upheld: bool) -> Result<DisputeSettlement, String> {
+    let (resolver, fee, disputer) = ASSIGNMENTS.with(|assignments| {
+        let mut assignments = assignments.borrow_mut();
+        let assignment = assignments
+            .get_mut(&market_id)
+            .filter(|assignment| assignment.status == AssignmentStatus::Disputed)
+            .ok_or_else(|| "No open dispute for this market".to_string())?;
+        assignment.status = if upheld {
+            AssignmentStatus::Slashed
+        } else {
+            AssignmentStatus::Paid
+        };
+        let disputer = assignment
+            .dispute
+            .as_ref()
+            .map(|dispute| dispute.disputer)
+            .ok_or_else(|| "No open dispute for this market".to_string())?;
+        Ok::<_, String>((assignment.resolver, assignment.fee, disputer))
+    })?;
+
+    RESOLVERS.with(|resolvers| {
+        let mut resolvers = resolvers.borrow_mut();
+        let profile = resolvers
+            .get_mut(&resolver)
+            .ok_or_else(|| "Resolver not found".to_string())?;
+        if !upheld {
+            profile.disputes_rejected += 1;
+            return Ok(DisputeSettlement::PayFee { resolver, fee });
+        }
+
+        let slash = (profile.stake as u128 * SLASH_BPS as u128 / 10_000) as u64;
+        profile.stake -= slash;
+        profile.slashed += slash;
+        profile.disputes_upheld += 1;
+        let to_disputer = slash / 2;
+        Ok(DisputeSettlement::Slash {
+            disputer,
+            to_disputer,
+            to_treasury: slash - to_disputer,
+        })
+    })
+}
+
+// Marks undisputed assignments past their window as paid and returns them
+pub fn take_due_fees(now: u64) -> Vec<(u64, Principal, u64)> {
+    ASSIGNMENTS.with(|assignments| {
+        assignments
+            .borrow_mut()
+            .values_mut()
+            .filter(|assignment| {
+                assignment.status == AssignmentStatus::Pending
+                    && now.saturating_sub(assignment.resolved_at) >= DISPUTE_WINDOW_NANOS
+            })
+            .map(|assignment| {
+                assignment.status = AssignmentStatus::Paid;
+                (assignment.market_id, assignment.resolver, assignment.fee)
+            })
+            .collect()
+    })
+}
+
+pub fn record_fee_paid(resolver: Principal, amount: u64) {
+    RESOLVERS.with(|resolvers| {
+        if let Some(profile) = resolvers.borrow_mut().get_mut(&resolver) {
+            profile.fees_earned += amount;
+        }
+    });
+}
+
+pub fn snapshot() -> (Vec<ResolverProfile>, Vec<ResolverAssignment>) {
+    (
+        RESOLVERS.with(|resolvers| resolvers.borrow().values().cloned().collect()),
+        ASSIGNMENTS.with(|assignments| assignments.borrow().values().cloned().collect()),
+    )
+}
+
+pub fn restore(profiles: Vec<ResolverProfile>, assignments: Vec<ResolverAssignment>) {
+    RESOLVERS.with(|current| {
+        *current.borrow_mut() = profiles.into_iter().map(|p| (p.principal, p)).collect()
+    });
+    ASSIGNMENTS.with(|current| {
+        *current.borrow_mut() = assignments.into_iter().map(|a| (a.market_id, a)).collect()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn categories(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn registration_requires_the_minimum_stake() {
+        let alice = user(1);
+        let sports = categories(&["Sports"]);
+        assert!(check_registration(alice, MIN_RESOLVER_STAKE - 1, 10, &sports).is_err());
+        assert!(check_registration(alice, MIN_RESOLVER_STAKE, 10, &[]).is_err());
+        assert!(check_registration(alice, MIN_RESOLVER_STAKE, 10, &sports).is_ok());
+
+        register(alice, MIN_RESOLVER_STAKE, 10, &sports, 0);
+        assert!(is_listed(alice));
+        assert!(covers(alice, "sports"));
+        assert_eq!(list(Some("Crypto")).len(), 0);
+        assert_eq!(list(None).len(), 1);
+
+        // Top-ups only need to keep the total above the minimum
+        assert!(check_registration(alice, 1, 20, &sports).is_ok());
+        assert_eq!(withdraw(alice), Ok(MIN_RESOLVER_STAKE));
+        assert!(!is_listed(alice));
+        assert!(withdraw(alice).is_err());
+    }
+
+    #[test]
+    fn undisputed_fees_fall_due_after_the_window() {
+        let bob = user(2);
+        register(bob, 2_000, 25, &categories(&["Crypto"]), 0);
+        assign(7, bob, 100);
+        assert!(has_open_assignment(bob));
+
+        assert!(take_due_fees(100 + DISPUTE_WINDOW_NANOS - 1).is_empty());
+        assert_eq!(
+            take_due_fees(100 + DISPUTE_WINDOW_NANOS),
+            vec![(7, bob, 25)]
+        );
+        assert!(take_due_fees(u64::MAX).is_empty());
+        assert!(!has_open_assignment(bob));
+        assert!(open_dispute(7, user(9), "Wrong", u64::MAX).is_err());
+        assert_eq!(get(bob).unwrap().resolutions, 1);
+    }
+
+    #[test]
+    fn upheld_disputes_slash_the_bond() {
+        let carol = user(3);
+        let disputer = user(4);
+        register(carol, 2_000, 25, &categories(&["Crypto"]), 0);
+        assign(8, carol, 0);
+
+        assert!(open_dispute(8, carol, "Mine", 1).is_err());
+        assert!(open_dispute(8, disputer, " ", 1).is_err());
+        open_dispute(8, disputer, "Source said NO", 1).unwrap();
+        assert!(take_due_fees(u64::MAX).is_empty());
+
+        match settle_dispute(8, true).unwrap() {
+            DisputeSettlement::Slash {
+                disputer: paid_to,
+                to_disputer,
+                to_treasury,
+            } => {
+                assert_eq!(paid_to, disputer);
+                assert_eq!((to_disputer, to_treasury), (500, 500));
+            }
+            DisputeSettlement::PayFee { .. } => panic!("dispute was upheld"),
+        }
+        let profile = get(carol).unwrap();
+        assert_eq!((profile.stake, profile.disputes_upheld), (1_000, 1));
+        assert!(settle_dispute(8, false).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+// Background task scheduler
+//
+// Every recurring job implements `Task` and is registered once at init and
+// post_upgrade. A single master timer wakes up every TICK_SECONDS and runs the
+// due tasks in priority order until the per-tick instruction budget is spent;
+// whatever is left over runs on the next tick.
+use candid::{CandidType, Deserialize};
+use ic_cdk_timers::TimerId;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const TICK_SECONDS: u64 = 5;
+const TICK_INSTRUCTION_BUDGET: u64 = 2_000_000_000;
+
+pub trait Task {
+    fn name(&self) -> &'static str;
+    // Lower values run first
+    fn priority(&self) -> u8;
+    // 0 runs the task on every tick
+    fn default_interval_seconds(&self) -> u64;
+    fn run(&self) -> Result<(), String>;
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub priority: u8,
+    pub interval_seconds: u64,
+    pub enabled: bool,
+    pub last_run: Option<u64>,
+    pub error_count: u64,
+    pub last_error: Option<String>,
+}
+
+thread_local! {
+    static TASKS: RefCell<Vec<&'static dyn Task>> = const { RefCell::new(Vec::new()) };
+    static REGISTRY: RefCell<BTreeMap<String, TaskStatus>> = const { RefCell::new(BTreeMap::new()) };
+    static MASTER_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+// Registers a task, keeping any persisted settings restored for it
+pub fn register(task: &'static dyn Task) {
+    TASKS.with(|tasks| tasks.borrow_mut().push(task));
+    REGISTRY.with(|registry| {
+        registry
+            .borrow_mut()
+            .entry(task.name().to_string())
+            .or_insert_with(|| TaskStatus {
+                name: task.name().to_string(),
+                priority: task.priority(),
+                interval_seconds: task.default_interval_seconds(),
+                enabled: true,
+                last_run: None,
+                error_count: 0,
+                last_error: None,
+            });
+    });
+}
+
+// Loads registry state saved before an upgrade. Must run before `register`.
+pub fn restore(statuses: Vec<TaskStatus>) {
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        for status in statuses {
+            registry.insert(status.name.clone(), status);
+        }
+    });
+}
+
+// Registry state to persist across upgrades
+pub fn snapshot() -> Vec<TaskStatus> {
+    REGISTRY.with(|registry| registry.borrow().values().cloned().collect())
+}
+
+pub fn start() {
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(TICK_SECONDS), tick);
+    MASTER_TIMER.with(|timer| {
+        if let Some(previous) = timer.borrow_mut().replace(timer_id) {
+            ic_cdk_timers::clear_timer(previous);
+        }
+    });
+}
+
+pub fn status() -> Vec<TaskStatus> {
+    let mut statuses = snapshot();
+    statuses.sort_by(|a, b| a.priority.cmp(&b.priority).then(a.name.cmp(&b.name)));
+    statuses
+}
+
+pub fn set_enabled(name: &str, enabled: bool) -> Result<(), String> {
+    update_status(name, |status| status.enabled = enabled)
+}
+
+pub fn set_interval(name: &str, interval_seconds: u64) -> Result<(), String> {
+    update_status(name, |status| status.interval_seconds = interval_seconds)
+}
+
+// Runs a task immediately, whether or not it is due or enabled
+pub fn trigger(name: &str) -> Result<(), String> {
+    let task = find_task(name).ok_or_else(|| format!("Unknown task: {}", name))?;
+    run_task(task);
+    Ok(())
+}
+
+fn update_status(name: &str, f: impl FnOnce(&mut TaskStatus)) -> Result<(), String> {
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let status = registry
+            .get_mut(name)
+            .ok_or_else(|| format!("Unknown task: {}", name))?;
+        f(status);
+        Ok(())
+    })
+}
+
+fn find_task(name: &str) -> Option<&'static dyn Task> {
+    TASKS.with(|tasks| tasks.borrow().iter().find(|t| t.name() == name).copied())
+}
+
+fn run_task(task: &'static dyn Task) {
+    let result = task.run();
+    let now = ic_cdk::api::time();
+    let _ = update_status(task.name(), |status| {
+        status.last_run = Some(now);
+        if let Err(error) = result {
+            status.error_count += 1;
+            status.last_error = Some(error);
+        }
+    });
+}
+
+fn tick() {
+    let now = ic_cdk::api::time();
+
+    let mut due: Vec<TaskStatus> = REGISTRY.with(|registry| {
+        registry
+            .borrow()
+            .values()
+            .filter(|status| status.enabled)
+            .filter(|status| match status.last_run {
+                None => true,
+                Some(last_run) => {
+                    now.saturating_sub(last_run) >= crate::to_nanos(status.interval_seconds)
+                }
+            })
+            .cloned()
+            .collect()
+    });
+    due.sort_by_key(|status| status.priority);
+
+    for status in due {
+        if ic_cdk::api::instruction_counter() >= TICK_INSTRUCTION_BUDGET {
+            break;
+        }
+        if let Some(task) = find_task(&status.name) {
+            run_task(task);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// Title similarity for duplicate detection
+//
+// Titles are compared as sets of normalized words: lowercased alphanumeric
+// runs, minus filler words every market question shares. Similarity is the
+// Jaccard index of the two sets, from 0.0 (disjoint) to 1.0 (same words).
+use std::collections::BTreeSet;
+
+const FILLER_WORDS: [&str; 14] = [
+    "a", "an", "and", "at", "be", "by", "in", "is", "of", "on", "or", "the", "to", "will",
+];
+
+pub fn title_words(title: &str) -> BTreeSet<String> {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !FILLER_WORDS.contains(word))
+        .map(|word| word.to_string())
+        .collect()
+}
+
+pub fn jaccard(a: &BTreeSet<String>, b: &BTreeSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewordings_score_high_and_new_questions_low() {
+        let original = title_words("Will Bitcoin reach $150,000 by end of 2025?");
+        assert_eq!(
+            jaccard(
+                &original,
+                &title_words("bitcoin REACH 150,000 by the end of 2025")
+            ),
+            1.0
+        );
+        assert!(
+            jaccard(
+                &original,
+                &title_words("Will Bitcoin reach $200,000 by end of 2025?")
+            ) > 0.6
+        );
+        assert!(jaccard(&original, &title_words("Will Ethereum flip Bitcoin?")) < 0.2);
+        assert_eq!(jaccard(&title_words("The?"), &title_words("Will")), 0.0);
+    }
+}
@@ -0,0 +1,489 @@
+// Trade surveillance
+//
+// Detectors for patterns that farm rewards and leaderboards rather than
+// express a view: trading only on one's own markets, pairs of accounts taking
+// opposite sides of each other within minutes, accounts opened in a burst that
+// all trade a single market, and XP far out of line with realized PnL. The
+// scheduler feeds them the recent trade log and records what they find as
+// flags with the trade ids as evidence. A flag found again keeps the review an
+// admin gave it, so cleared patterns do not resurface as new work.
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+pub const REPORT_WINDOW_DAYS: u64 = 30;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct SurveillanceConfig {
+    pub min_self_trades: u64,
+    pub pair_window_seconds: u64, // how close opposite trades must be to match
+    pub min_pair_matches: u64,
+    pub burst_window_seconds: u64, // span within which accounts count as one burst
+    pub min_burst_accounts: u64,
+    pub xp_pnl_outlier_multiple: u64, // times the median XP per unit of PnL
+}
+
+impl Default for SurveillanceConfig {
+    fn default() -> Self {
+        SurveillanceConfig {
+            min_self_trades: 3,
+            pair_window_seconds: 300,
+            min_pair_matches: 3,
+            burst_window_seconds: 3_600,
+            min_burst_accounts: 3,
+            xp_pnl_outlier_multiple: 5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize)]
+pub enum FlagKind {
+    SelfMarketTrading,
+    OppositeSidePair,
+    BurstAccounts,
+    XpPnlOutlier,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Deserialize)]
+pub enum FlagStatus {
+    Open,
+    Reviewed,      // confirmed and handled
+    FalsePositive, // a signal the thresholds should not raise
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SuspiciousFlag {
+    pub id: u64,
+    pub kind: FlagKind,
+    pub principals: Vec<Principal>,
+    pub market_id: Option<u64>, // when the pattern is confined to one market
+    pub evidence: Vec<u64>,     // trade ids
+    pub detail: String,
+    pub first_detected: u64,
+    pub last_detected: u64,
+    pub status: FlagStatus,
+}
+
+// What the detectors see of a trade
+pub struct TradeFact {
+    pub id: u64,
+    pub trader: Principal,
+    pub market_id: u64,
+    pub is_yes: bool,
+    pub timestamp: u64,
+    pub market_creator: Principal,
+}
+
+// XP earned against PnL realized on resolved markets
+pub struct PnlFact {
+    pub principal: Principal,
+    pub xp: u64,
+    pub realized_pnl: i64,
+    pub trade_ids: Vec<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub kind: FlagKind,
+    pub principals: Vec<Principal>,
+    pub market_id: Option<u64>,
+    pub evidence: Vec<u64>,
+    pub detail: String,
+}
+
+thread_local! {
+    static CONFIG: RefCell<SurveillanceConfig> = RefCell::new(SurveillanceConfig::default());
+    static FLAGS: RefCell<BTreeMap<u64, SuspiciousFlag>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_FLAG_ID: RefCell<u64> = const { RefCell::new(1) };
+}
+
+fn single_market(trades: &[&TradeFact]) -> Option<u64> {
+    let first = trades.first()?.market_id;
+    trades.iter().all(|t| t.market_id == first).then_some(first)
+}
+
+// Traders whose every trade is on a market they created
+pub fn self_market_trading(trades: &[TradeFact], config: &SurveillanceConfig) -> Vec<Finding> {
+    let mut by_trader: BTreeMap<Principal, Vec<&TradeFact>> = BTreeMap::new();
+    for trade in trades {
+        by_trader.entry(trade.trader).or_default().push(trade);
+    }
+
+    by_trader
+        .into_iter()
+        .filter(|(trader, own)| {
+            own.len() as u64 >= config.min_self_trades
+                && own.iter().all(|t| t.market_creator == *trader)
+        })
+        .map(|(trader, own)| Finding {
+            kind: FlagKind::SelfMarketTrading,
+            principals: vec![trader],
+            market_id: single_market(&own),
+            evidence: own.iter().map(|t| t.id).collect(),
+            detail: format!("{} trades, all on their own markets", own.len()),
+        })
+        .collect()
+}
+
+// Pairs who repeatedly take the other side of each other's trades within the
+// pair window, making up at least half of the quieter account's trading
+pub fn opposite_side_pairs(trades: &[TradeFact], config: &SurveillanceConfig) -> Vec<Finding> {
+    let window = crate::to_nanos(config.pair_window_seconds);
+    let mut by_market: BTreeMap<u64, Vec<&TradeFact>> = BTreeMap::new();
+    let mut trade_counts: HashMap<Principal, u64> = HashMap::new();
+    for trade in trades {
+        by_market.entry(trade.market_id).or_default().push(trade);
+        *trade_counts.entry(trade.trader).or_default() += 1;
+    }
+
+    // Each trade matches at most one later opposite trade by someone else
+    let mut matches: BTreeMap<(Principal, Principal), Vec<&TradeFact>> = BTreeMap::new();
+    for market_trades in by_market.values_mut() {
+        market_trades.sort_by_key(|t| (t.timestamp, t.id));
+        let mut matched: BTreeSet<u64> = BTreeSet::new();
+        for (i, trade) in market_trades.iter().enumerate() {
+            if matched.contains(&trade.id) {
+                continue;
+            }
+            let counter = market_trades[i + 1..]
+                .iter()
+                .take_while(|t| t.timestamp - trade.timestamp <= window)
+                .find(|t| {
+                    t.trader != trade.trader && t.is_yes != trade.is_yes && !matched.contains(&t.id)
+                });
+            if let Some(counter) = counter {
+                matched.insert(trade.id);
+                matched.insert(counter.id);
+                let pair = if trade.trader < counter.trader {
+                    (trade.trader, counter.trader)
+                } else {
+                    (counter.trader, trade.trader)
+                };
+                matches.entry(pair).or_default().extend([*trade, *counter]);
+            }
+        }
+    }
+
+    matches
+        .into_iter()
+        .filter_map(|((a, b), evidence)| {
+            let count = evidence.len() as u64 / 2;
+            let quieter = trade_counts[&a].min(trade_counts[&b]);
+            (count >= config.min_pair_matches && count * 2 >= quieter).then(|| Finding {
+                kind: FlagKind::OppositeSidePair,
+                principals: vec![a, b],
+                market_id: single_market(&evidence),
+                evidence: evidence.iter().map(|t| t.id).collect(),
+                detail: format!("{} opposite-side trades within the pair window", count),
+            })
+        })
+        .collect()
+}
+
+// Accounts opened within one burst window whose trading is all on the same
+// single market; `accounts` maps principals to their creation time
+pub fn burst_accounts(
+    trades: &[TradeFact],
+    accounts: &HashMap<Principal, u64>,
+    config: &SurveillanceConfig,
+) -> Vec<Finding> {
+    let window = crate::to_nanos(config.burst_window_seconds);
+    let mut by_trader: BTreeMap<Principal, Vec<&TradeFact>> = BTreeMap::new();
+    for trade in trades {
+        by_trader.entry(trade.trader).or_default().push(trade);
+    }
+
+    let mut by_market: BTreeMap<u64, Vec<(u64, Principal)>> = BTreeMap::new();
+    for (trader, own) in &by_trader {
+        if let (Some(market_id), Some(created_at)) = (single_market(own), accounts.get(trader)) {
+            by_market
+                .entry(market_id)
+                .or_default()
+                .push((*created_at, *trader));
+        }
+    }
+
+    let mut findings = vec![];
+    for (market_id, mut opened) in by_market {
+        opened.sort();
+        // Largest group opened within one window
+        let (mut best, mut start) = ((0, 0), 0);
+        for end in 0..opened.len() {
+            while opened[end].0 - opened[start].0 > window {
+                start += 1;
+            }
+            if end + 1 - start > best.1 - best.0 {
+                best = (start, end + 1);
+            }
+        }
+        let burst = &opened[best.0..best.1];
+        if (burst.len() as u64) < config.min_burst_accounts {
+            continue;
+        }
+        let principals: Vec<Principal> = burst.iter().map(|(_, p)| *p).collect();
+        findings.push(Finding {
+            kind: FlagKind::BurstAccounts,
+            evidence: principals
+                .iter()
+                .flat_map(|p| by_trader[p].iter().map(|t| t.id))
+                .collect(),
+            detail: format!(
+                "{} accounts opened together, trading only this market",
+                principals.len()
+            ),
+            principals,
+            market_id: Some(market_id),
+        });
+    }
+    findings
+}
+
+// XP per unit of realized PnL far above the median. Needs a few accounts to
+// have a median worth comparing against.
+pub fn xp_pnl_outliers(facts: &[PnlFact], config: &SurveillanceConfig) -> Vec<Finding> {
+    let ratio = |fact: &PnlFact| fact.xp / fact.realized_pnl.unsigned_abs().max(1);
+    let mut ratios: Vec<u64> = facts.iter().filter(|f| f.xp > 0).map(ratio).collect();
+    if ratios.len() < 3 {
+        return vec![];
+    }
+    ratios.sort_unstable();
+    let median = ratios[ratios.len() / 2].max(1);
+
+    facts
+        .iter()
+        .filter(|fact| ratio(fact) > median.saturating_mul(config.xp_pnl_outlier_multiple))
+        .map(|fact| Finding {
+            kind: FlagKind::XpPnlOutlier,
+            principals: vec![fact.principal],
+            market_id: None,
+            evidence: fact.trade_ids.clone(),
+            detail: format!(
+                "{} XP for {} realized PnL, against a median of {} XP per unit",
+                fact.xp, fact.realized_pnl, median
+            ),
+        })
+        .collect()
+}
+
+fn same_subject(flag: &SuspiciousFlag, finding: &Finding) -> bool {
+    flag.kind == finding.kind
+        && flag.principals == finding.principals
+        && flag.market_id == finding.market_id
+}
+
+// Merges a run's findings into the report. Known flags get fresh evidence and
+// keep their review; new ones open.
+pub fn record(findings: Vec<Finding>, now: u64) {
+    FLAGS.with(|flags| {
+        let mut flags = flags.borrow_mut();
+        for finding in findings {
+            if let Some(flag) = flags.values_mut().find(|f| same_subject(f, &finding)) {
+                flag.evidence = finding.evidence;
+                flag.detail = finding.detail;
+                flag.last_detected = now;
+                continue;
+            }
+            let id = NEXT_FLAG_ID.with(|next| next.replace_with(|id| *id + 1));
+            flags.insert(
+                id,
+                SuspiciousFlag {
+                    id,
+                    kind: finding.kind,
+                    principals: finding.principals,
+                    market_id: finding.market_id,
+                    evidence: finding.evidence,
+                    detail: finding.detail,
+                    first_detected: now,
+                    last_detected: now,
+                    status: FlagStatus::Open,
+                },
+            );
+        }
+    });
+}
+
+// Flags detected again since `since`
+pub fn flags_since(since: u64) -> Vec<SuspiciousFlag> {
+    FLAGS.with(|flags| {
+        flags
+            .borrow()
+            .values()
+            .filter(|f| f.last_detected >= since)
+            .cloned()
+            .collect()
+    })
+}
+
+pub fn review(flag_id: u64, status: FlagStatus) -> Result<(), String> {
+    FLAGS.with(|flags| {
+        let mut flags = flags.borrow_mut();
+        let flag = flags
+            .get_mut(&flag_id)
+            .ok_or_else(|| "Flag not found".to_string())?;
+        flag.status = status;
+        Ok(())
+    })
+}
+
+pub fn config() -> SurveillanceConfig {
+    CONFIG.with(|config| config.borrow().clone())
+}
+
+pub fn set_config(config: SurveillanceConfig) -> Result<(), String> {
+    if config.pair_window_seconds == 0 || config.burst_window_seconds == 0 {
+        return Err("Windows must be greater than 0".to_string());
+    }
+    if config.min_self_trades == 0
+        || config.min_pair_matches == 0
+        || config.min_burst_accounts < 2
+        || config.xp_pnl_outlier_multiple == 0
+    {
+        return Err("Thresholds must be at least 1, and bursts at least 2 accounts".to_string());
+    }
+    CONFIG.with(|current| *current.borrow_mut() = config);
+    Ok(())
+}
+
+pub fn snapshot() -> (Vec<SuspiciousFlag>, SurveillanceConfig) {
+    (
+        FLAGS.with(|flags| flags.borrow().values().cloned().collect()),
+        config(),
+    )
+}
+
+pub fn restore(flags: Vec<SuspiciousFlag>, config: SurveillanceConfig) {
+    let next_id = flags.iter().map(|f| f.id + 1).max().unwrap_or(1);
+    FLAGS.with(|current| *current.borrow_mut() = flags.into_iter().map(|f| (f.id, f)).collect());
+    NEXT_FLAG_ID.with(|next| *next.borrow_mut() = next_id);
+    CONFIG.with(|current| *current.borrow_mut() = config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    fn trade(id: u64, trader: u8, market_id: u64, is_yes: bool, secs: u64) -> TradeFact {
+        TradeFact {
+            id,
+            trader: user(trader),
+            market_id,
+            is_yes,
+            timestamp: secs * SECOND,
+            market_creator: user(100 + market_id as u8),
+        }
+    }
+
+    #[test]
+    fn own_market_traders_are_flagged() {
+        let config = SurveillanceConfig::default();
+        let mut trades: Vec<TradeFact> = (1..=3).map(|id| trade(id, 1, 1, true, id)).collect();
+        for t in &mut trades {
+            t.market_creator = user(1);
+        }
+        trades.push(trade(4, 2, 1, true, 4));
+
+        let findings = self_market_trading(&trades, &config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].principals, vec![user(1)]);
+        assert_eq!(findings[0].evidence, vec![1, 2, 3]);
+        assert_eq!(findings[0].market_id, Some(1));
+    }
+
+    #[test]
+    fn matched_opposite_trades_flag_the_pair() {
+        let config = SurveillanceConfig::default();
+        let mut trades = vec![];
+        for round in 0..3 {
+            let at = round * 1_000;
+            trades.push(trade(round * 2 + 1, 1, 1, true, at));
+            trades.push(trade(round * 2 + 2, 2, 1, false, at + 60));
+        }
+        // Too far apart to match, and a third party trading normally
+        trades.push(trade(20, 3, 1, false, 10_000));
+        trades.push(trade(21, 1, 1, true, 20_000));
+
+        let findings = opposite_side_pairs(&trades, &config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].principals, vec![user(1), user(2)]);
+        assert_eq!(findings[0].evidence, vec![1, 2, 3, 4, 5, 6]);
+
+        let strict = SurveillanceConfig {
+            min_pair_matches: 4,
+            ..config
+        };
+        assert!(opposite_side_pairs(&trades, &strict).is_empty());
+    }
+
+    #[test]
+    fn single_market_accounts_opened_together_are_flagged() {
+        let config = SurveillanceConfig::default();
+        let trades: Vec<TradeFact> = (1..=4)
+            .map(|n| trade(n as u64, n, 7, true, 5_000))
+            .chain([trade(5, 4, 8, true, 5_000)])
+            .collect();
+        let accounts: HashMap<Principal, u64> = [(1, 0), (2, 600), (3, 1_200), (4, 1_300)]
+            .into_iter()
+            .map(|(n, secs)| (user(n), secs * SECOND))
+            .collect();
+
+        // Account 4 also traded market 8, so only three remain
+        let findings = burst_accounts(&trades, &accounts, &config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].principals, vec![user(1), user(2), user(3)]);
+        assert_eq!(findings[0].market_id, Some(7));
+
+        let spread = SurveillanceConfig {
+            burst_window_seconds: 700,
+            ..config
+        };
+        assert!(burst_accounts(&trades, &accounts, &spread).is_empty());
+    }
+
+    #[test]
+    fn xp_far_above_realized_pnl_is_an_outlier() {
+        let config = SurveillanceConfig::default();
+        let fact = |n, xp, realized_pnl| PnlFact {
+            principal: user(n),
+            xp,
+            realized_pnl,
+            trade_ids: vec![n as u64],
+        };
+        let facts = [
+            fact(1, 100, 100),
+            fact(2, 200, -100),
+            fact(3, 150, 100),
+            fact(4, 900, 0),
+        ];
+        let findings = xp_pnl_outliers(&facts, &config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].principals, vec![user(4)]);
+        assert!(xp_pnl_outliers(&facts[..2], &config).is_empty());
+    }
+
+    #[test]
+    fn findings_keep_their_review_when_found_again() {
+        let finding = || Finding {
+            kind: FlagKind::XpPnlOutlier,
+            principals: vec![user(9)],
+            market_id: None,
+            evidence: vec![1],
+            detail: "detail".to_string(),
+        };
+        record(vec![finding()], 10);
+        let id = flags_since(0)[0].id;
+        review(id, FlagStatus::FalsePositive).unwrap();
+
+        record(vec![finding()], 20);
+        let flags = flags_since(15);
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].status, FlagStatus::FalsePositive);
+        assert_eq!(flags[0].first_detected, 10);
+        assert!(review(id + 1, FlagStatus::Reviewed).is_err());
+    }
+}
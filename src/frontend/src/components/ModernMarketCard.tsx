@@ -33,7 +33,7 @@ export function ModernMarketCard({
   const noPercentage = 100 - yesPercentage;
 
   const formatDate = (timestamp: bigint) => {
-    const date = new Date(Number(timestamp) * 1000);
+    const date = new Date(Number(timestamp) / 1000000);
     return date.toLocaleDateString("en-US", {
       month: "short",
       day: "numeric",
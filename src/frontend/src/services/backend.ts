@@ -7,6 +7,7 @@ import type {
   AIInsight,
   MarketComment,
   MarketStatus,
+  PriceChange,
 } from "../../../declarations/backend/backend.did";
 import { Principal } from "@dfinity/principal";
 import { AuthService } from "./auth";
@@ -136,6 +137,22 @@ export class BackendService {
     }
   }
 
+  // Probability move over the last `windowSeconds`, null without history
+  static async getPriceChange(
+    marketId: bigint,
+    windowSeconds: bigint,
+  ): Promise<PriceChange | null> {
+    try {
+      const result = await backend.get_price_change(marketId, windowSeconds);
+      return Array.isArray(result) && result.length > 0
+        ? result[0] || null
+        : null;
+    } catch (error) {
+      console.error("Failed to fetch price change:", error);
+      throw error;
+    }
+  }
+
   static async getMarketTrades(marketId: bigint): Promise<Trade[]> {
     try {
       return await backend.get_market_trades(marketId);
@@ -152,7 +169,7 @@ export class BackendService {
     try {
       const result = await backend.get_user_profile(principal as any);
       return Array.isArray(result) && result.length > 0
-        ? result[0] || null
+        ? result[0]?.profile || null
         : null;
     } catch (error) {
       console.error("Failed to fetch user profile:", error);
@@ -182,6 +199,21 @@ export class BackendService {
     }
   }
 
+  // Starts a background generation job and returns its id
+  static async refreshAIInsight(marketId: bigint): Promise<bigint> {
+    try {
+      const result = await backend.refresh_ai_insight(marketId);
+      if ("Ok" in result) {
+        return result.Ok;
+      } else {
+        throw new Error(result.Err);
+      }
+    } catch (error) {
+      console.error("Failed to refresh AI insight:", error);
+      throw error;
+    }
+  }
+
   // Comments
   static async addComment(marketId: bigint, content: string): Promise<bigint> {
     try {
@@ -222,6 +254,8 @@ export class BackendService {
     if ("Closed" in status) return "Closed";
     if ("Resolved" in status) return "Resolved";
     if ("PendingValidation" in status) return "Pending";
+    if ("Rejected" in status) return "Rejected";
+    if ("Archived" in status) return "Archived";
     return "Unknown";
   }
 
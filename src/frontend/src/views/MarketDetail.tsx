@@ -183,6 +183,67 @@ const MarketDetail: React.FC<MarketDetailProps> = ({ marketId, onBack }) => {
           </div>
           <p className="mb-4 text-gray-600">{market.description}</p>
 
+          {/* Structured Fields */}
+          {(market.numeric_target.length > 0 ||
+            market.resolution_datetime.length > 0 ||
+            market.primary_source_url.length > 0) && (
+            <div className="mb-4 flex flex-wrap items-center gap-x-2 text-sm text-gray-700">
+              {market.numeric_target[0] && (
+                <span>
+                  Target: {market.numeric_target[0][0]}{" "}
+                  {market.numeric_target[0][1].toLocaleString()}
+                </span>
+              )}
+              {market.resolution_datetime[0] !== undefined && (
+                <span>
+                  · Deadline:{" "}
+                  {new Date(
+                    Number(market.resolution_datetime[0]) / 1000000,
+                  ).toLocaleDateString(undefined, {
+                    month: "short",
+                    day: "numeric",
+                    year: "numeric",
+                  })}
+                </span>
+              )}
+              {market.primary_source_url[0] && (
+                <a
+                  href={market.primary_source_url[0]}
+                  target="_blank"
+                  rel="noopener noreferrer"
+                  className="text-primary-purple hover:underline"
+                >
+                  · Source
+                </a>
+              )}
+            </div>
+          )}
+
+          {/* Resolution Criteria */}
+          {market.resolution_criteria.length > 0 &&
+            (() => {
+              const criteria = market.resolution_criteria[0]!;
+              const verdicts = criteria.verdicts[0];
+              return (
+                <div className="mb-6 rounded-lg border border-gray-200 p-4">
+                  <h3 className="mb-2 text-sm font-semibold text-gray-900">
+                    Resolves YES if{" "}
+                    {"All" in criteria.rule ? "all" : "any"} of these hold
+                  </h3>
+                  <ul className="space-y-1 text-sm text-gray-700">
+                    {criteria.statements.map((statement, i) => (
+                      <li key={i} className="flex items-start space-x-2">
+                        <span>
+                          {verdicts ? (verdicts[i] ? "✅" : "❌") : "•"}
+                        </span>
+                        <span>{statement}</span>
+                      </li>
+                    ))}
+                  </ul>
+                </div>
+              );
+            })()}
+
           {/* AI Insights Section */}
           {showInsight && (
             <div className="border-primary-purple/20 from-primary-purple/5 mb-6 rounded-lg border bg-gradient-to-r to-purple-100/30 p-6">
@@ -9,6 +9,9 @@ import type {
   MarketComment,
 } from "../../../declarations/backend/backend.did";
 
+// How often to poll while an AI insight is being generated
+const INSIGHT_POLL_MS = 2000;
+
 interface MarketViewProps {
   marketId: bigint;
   onBack: () => void;
@@ -59,6 +62,11 @@ export function MarketView({ marketId, onBack }: MarketViewProps) {
       setTrades(tradesData);
       setAIInsight(insightData);
       setComments(commentsData);
+
+      if (!insightData) {
+        await BackendService.refreshAIInsight(marketId).catch(() => undefined);
+        setAIInsight(await BackendService.getAIInsight(marketId));
+      }
     } catch (err) {
       setError(
         err instanceof Error ? err.message : "Failed to load market data",
@@ -68,6 +76,16 @@ export function MarketView({ marketId, onBack }: MarketViewProps) {
     }
   };
 
+  // Poll until a pending insight is ready or has failed
+  useEffect(() => {
+    if (!aiInsight || !("Generating" in aiInsight.status)) return;
+
+    const timer = setTimeout(async () => {
+      setAIInsight(await BackendService.getAIInsight(marketId));
+    }, INSIGHT_POLL_MS);
+    return () => clearTimeout(timer);
+  }, [aiInsight, marketId]);
+
   const handleTrade = async () => {
     if (!authState.isAuthenticated || !market || !tradeAmount) return;
 
@@ -250,7 +268,13 @@ export function MarketView({ marketId, onBack }: MarketViewProps) {
           </div>
 
           {/* AI Insight */}
-          {aiInsight && (
+          {aiInsight && "Generating" in aiInsight.status && (
+            <div className="rounded-xl border border-purple-200 bg-gradient-to-r from-purple-50 to-blue-50 p-6 text-purple-700">
+              Generating AI insight...
+            </div>
+          )}
+
+          {aiInsight && "Ready" in aiInsight.status && (
             <div className="rounded-xl border border-purple-200 bg-gradient-to-r from-purple-50 to-blue-50 p-6">
               <div className="mb-4 flex items-center space-x-2">
                 <div className="h-3 w-3 animate-pulse rounded-full bg-purple-500"></div>
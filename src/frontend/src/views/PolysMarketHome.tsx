@@ -1,6 +1,9 @@
 import React, { useState, useEffect } from "react";
 import { BackendService } from "../services/backend";
-import type { Market } from "../../../declarations/backend/backend.did";
+import type {
+  Market,
+  PriceChange,
+} from "../../../declarations/backend/backend.did";
 import MarketDetail from "./MarketDetail";
 
 interface PolysMarketHomeProps {
@@ -9,6 +12,9 @@ interface PolysMarketHomeProps {
 
 const PolysMarketHome: React.FC<PolysMarketHomeProps> = () => {
   const [markets, setMarkets] = useState<Market[]>([]);
+  const [priceChanges, setPriceChanges] = useState<
+    Record<string, PriceChange | null>
+  >({});
   const [loading, setLoading] = useState(true);
   const [error, setError] = useState<string | null>(null);
   const [activeTab, setActiveTab] = useState<"all" | "trending" | "new">("all");
@@ -24,6 +30,20 @@ const PolysMarketHome: React.FC<PolysMarketHomeProps> = () => {
       const fetchedMarkets = await BackendService.getMarkets();
       setMarkets(fetchedMarkets);
       setError(null);
+
+      // 24h moves are decoration; a failure here should not hide the list
+      const changes = await Promise.all(
+        fetchedMarkets.map((market) =>
+          BackendService.getPriceChange(market.id, BigInt(86_400)).catch(
+            () => null,
+          ),
+        ),
+      );
+      setPriceChanges(
+        Object.fromEntries(
+          fetchedMarkets.map((market, i) => [market.id.toString(), changes[i]]),
+        ),
+      );
     } catch (err) {
       console.error("Failed to load markets:", err);
       setError("Failed to load markets. Please try again.");
@@ -310,6 +330,23 @@ const PolysMarketHome: React.FC<PolysMarketHomeProps> = () => {
                             return `${percentage}¢`;
                           })()}
                         </div>
+                        {(() => {
+                          const change = priceChanges[market.id.toString()];
+                          if (!change || change.change_bps === BigInt(0)) {
+                            return null;
+                          }
+                          const points = Number(change.change_bps) / 100;
+                          return (
+                            <div
+                              className={`text-xs font-medium ${
+                                points > 0 ? "text-green-600" : "text-red-600"
+                              }`}
+                            >
+                              {points > 0 ? "+" : ""}
+                              {points.toFixed(1)}% today
+                            </div>
+                          );
+                        })()}
                       </div>
                       <div className="min-w-[100px] rounded-lg border border-red-200 bg-red-50 px-4 py-2 text-center">
                         <div className="text-sm font-medium text-red-600">
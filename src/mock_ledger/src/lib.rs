@@ -0,0 +1,121 @@
+// Minimal ICRC-1 ledger for local testing
+//
+// Implements just the calls the backend makes (balance, fee and transfer) plus
+// a `mint` anyone may call, so tests can fund deposit accounts without a real
+// ledger. Never deploy it anywhere that matters.
+use candid::{CandidType, Deserialize, Nat, Principal};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const FEE: u64 = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+    pub amount: Nat,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+thread_local! {
+    static BALANCES: RefCell<BTreeMap<Account, Nat>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_BLOCK: RefCell<u64> = const { RefCell::new(0) };
+}
+
+// The default subaccount is all zeroes, however it was spelled
+fn normalize(account: Account) -> Account {
+    match account.subaccount {
+        Some(sub) if sub.iter().all(|b| *b == 0) => Account {
+            owner: account.owner,
+            subaccount: None,
+        },
+        _ => account,
+    }
+}
+
+fn balance(account: &Account) -> Nat {
+    BALANCES.with(|balances| {
+        balances
+            .borrow()
+            .get(account)
+            .cloned()
+            .unwrap_or_else(|| Nat::from(0u64))
+    })
+}
+
+fn set_balance(account: Account, amount: Nat) {
+    BALANCES.with(|balances| balances.borrow_mut().insert(account, amount));
+}
+
+fn next_block() -> Nat {
+    NEXT_BLOCK.with(|block| {
+        let current = *block.borrow();
+        *block.borrow_mut() = current + 1;
+        Nat::from(current)
+    })
+}
+
+#[ic_cdk::query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    balance(&normalize(account))
+}
+
+#[ic_cdk::query]
+fn icrc1_fee() -> Nat {
+    Nat::from(FEE)
+}
+
+#[ic_cdk::update]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    let fee = Nat::from(FEE);
+    if arg.fee.as_ref().is_some_and(|f| *f != fee) {
+        return Err(TransferError::BadFee { expected_fee: fee });
+    }
+
+    let from = normalize(Account {
+        owner: ic_cdk::caller(),
+        subaccount: arg.from_subaccount,
+    });
+    let to = normalize(arg.to);
+    let held = balance(&from);
+    let debit = arg.amount.clone() + fee;
+    if held < debit {
+        return Err(TransferError::InsufficientFunds { balance: held });
+    }
+
+    set_balance(from, held - debit);
+    let credited = balance(&to) + arg.amount;
+    set_balance(to, credited);
+    Ok(next_block())
+}
+
+// Test-only: creates `amount` out of thin air in `to`
+#[ic_cdk::update]
+fn mint(to: Account, amount: Nat) -> Nat {
+    let to = normalize(to);
+    let credited = balance(&to) + amount;
+    set_balance(to, credited);
+    next_block()
+}
+
+ic_cdk::export_candid!();
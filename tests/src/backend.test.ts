@@ -1,14 +1,24 @@
 import { describe, beforeEach, afterEach, it, expect, inject } from "vitest";
 import { resolve, dirname } from "path";
 import { fileURLToPath } from "url";
-import { PocketIc, type Actor } from "@dfinity/pic";
+import { PocketIc, createIdentity, type Actor } from "@dfinity/pic";
 import { Principal } from "@dfinity/principal";
+import { AnonymousIdentity, type Identity } from "@dfinity/agent";
+import { IDL } from "@dfinity/candid";
 
 // Import generated types for your canister
 import {
   type _SERVICE,
   idlFactory,
 } from "../../src/declarations/backend/backend.did.js";
+import {
+  type _SERVICE as _ARCHIVE_SERVICE,
+  idlFactory as archiveIdlFactory,
+} from "../../src/declarations/archive/archive.did.js";
+import {
+  type _SERVICE as _LEDGER_SERVICE,
+  idlFactory as ledgerIdlFactory,
+} from "../../src/declarations/mock_ledger/mock_ledger.did.js";
 
 // Define the path to your canister's WASM file
 export const WASM_PATH = resolve(
@@ -21,6 +31,37 @@ export const WASM_PATH = resolve(
   "backend.wasm",
 );
 
+export const ARCHIVE_WASM_PATH = resolve(
+  dirname(WASM_PATH),
+  "archive.wasm",
+);
+
+export const LEDGER_WASM_PATH = resolve(
+  dirname(WASM_PATH),
+  "mock_ledger.wasm",
+);
+
+// Transfer fee charged by the mock ledger
+const LEDGER_FEE = BigInt(10_000);
+
+// Balance each test's admin starts with
+const ADMIN_FUNDS = BigInt(10_000_000);
+
+// A time within the lifetime of the sample markets seeded in `init`
+const SAMPLE_MARKET_TIME = new Date("2025-06-01T00:00:00Z").getTime();
+
+// Close date of sample market 1 (seconds), Dec 31, 2025
+const SAMPLE_MARKET_CLOSE = 1767225600;
+
+// Once all three sample markets have closed (seconds)
+const SAMPLE_MARKETS_CLOSED = 1767292800;
+
+// Canister timestamps, close dates included, are nanoseconds
+const nanos = (seconds: number) => BigInt(seconds) * BigInt(1_000_000_000);
+
+// Interval of the canister's master scheduler timer
+const SCHEDULER_TICK_MS = 5_000;
+
 // The `describe` function is used to group tests together
 describe("ChainPredict ICP Backend", () => {
   // Define variables to hold our PocketIC instance, canister ID,
@@ -29,6 +70,26 @@ describe("ChainPredict ICP Backend", () => {
   // @ts-ignore - This variable is used in the setup / framework
   let canisterId: Principal;
   let actor: Actor<_SERVICE>;
+  let ledger: Actor<_LEDGER_SERVICE>;
+
+  // Controller of the canister, and so its admin
+  const admin = createIdentity("admin");
+
+  // Deposits `amount` for `identity` through the ledger, the way a user
+  // funds their balance. Uses its own actor, so the caller is unchanged.
+  const fund = async (identity: Identity, amount: bigint) => {
+    const depositor = pic.createActor<_SERVICE>(idlFactory, canisterId);
+    depositor.setIdentity(identity);
+    const account = await depositor.get_deposit_account();
+    await ledger.mint(account, amount + LEDGER_FEE);
+    expect(await depositor.notify_deposit()).toEqual({ Ok: amount });
+  };
+
+  // Markets only resolve once their close date (seconds) has passed
+  const passCloseDate = async (closeDate = SAMPLE_MARKETS_CLOSED) => {
+    await pic.setTime(closeDate * 1000);
+    await pic.tick();
+  };
 
   // The `beforeEach` hook runs before each test.
   beforeEach(async () => {
@@ -39,11 +100,27 @@ describe("ChainPredict ICP Backend", () => {
     const fixture = await pic.setupCanister<_SERVICE>({
       idlFactory,
       wasm: WASM_PATH,
+      sender: admin.getPrincipal(),
     });
 
     // Save the actor and canister ID for use in tests
     actor = fixture.actor;
+    actor.setIdentity(admin);
     canisterId = fixture.canisterId;
+
+    // Buys spend deposited balance, so tests need a ledger to deposit through
+    const ledgerFixture = await pic.setupCanister<_LEDGER_SERVICE>({
+      idlFactory: ledgerIdlFactory,
+      wasm: LEDGER_WASM_PATH,
+    });
+    ledger = ledgerFixture.actor;
+    await actor.set_ledger_canister(ledgerFixture.canisterId);
+
+    // Keep the sample markets open for trading
+    await pic.setTime(SAMPLE_MARKET_TIME);
+    await pic.tick();
+
+    await fund(admin, ADMIN_FUNDS);
   });
 
   // The `afterEach` hook runs after each test.
@@ -69,13 +146,19 @@ describe("ChainPredict ICP Backend", () => {
       "Will AI replace developers by 2030?",
       "This market resolves to YES if AI can autonomously write, test, and deploy production software without human intervention.",
       "Technology",
-      BigInt(Date.now() + 365 * 24 * 60 * 60 * 1000), // 1 year from now
+      // 1 year from now
+      nanos(Math.floor(Date.now() / 1000) + 365 * 24 * 60 * 60),
     );
 
     expect(result).toHaveProperty("Ok");
 
     const newMarkets = await actor.get_markets();
     expect(newMarkets.length).toBe(initialCount + 1);
+
+    actor.setIdentity(new AnonymousIdentity());
+    expect(
+      await actor.create_market("Ownerless?", "Nobody", "Other", BigInt(0)),
+    ).toEqual({ Err: "Anonymous principals cannot create markets" });
   });
 
   it("should get specific market by ID", async () => {
@@ -99,7 +182,10 @@ describe("ChainPredict ICP Backend", () => {
       const trade = tradeResult.Ok;
       expect(trade.market_id).toBe(BigInt(1));
       expect(trade.is_yes).toBe(true);
-      expect(trade.shares).toBe(BigInt(100));
+      expect(trade.amount).toBe(BigInt(100));
+      // Shares are priced along the curve, so above 0.5 they cost more than 1:1
+      expect(trade.price).toBeGreaterThan(BigInt(500));
+      expect(trade.shares).toBeLessThan(trade.amount);
     }
   });
 
@@ -123,7 +209,8 @@ describe("ChainPredict ICP Backend", () => {
 
     const comments = await actor.get_market_comments(BigInt(1));
     expect(comments.length).toBeGreaterThan(0);
-    expect(comments[comments.length - 1].content).toContain("test comment");
+    // Newest first
+    expect(comments[0].content).toContain("test comment");
   });
 
   it("should track treasury balance", async () => {
@@ -136,4 +223,3718 @@ describe("ChainPredict ICP Backend", () => {
     const newBalance = await actor.get_treasury_balance();
     expect(newBalance).toBeGreaterThan(initialBalance);
   });
+
+  it("should record comment edit history", async () => {
+    const commentResult = await actor.add_comment(BigInt(1), "Original take");
+    expect(commentResult).toHaveProperty("Ok");
+    if (!("Ok" in commentResult)) return;
+    const commentId = commentResult.Ok;
+
+    const editResult = await actor.edit_comment(commentId, "Revised take");
+    expect(editResult).toHaveProperty("Ok");
+
+    const history = await actor.get_comment_history(commentId);
+    expect(history).toHaveProperty("Ok");
+    if ("Ok" in history) {
+      expect(history.Ok.length).toBe(1);
+      expect(history.Ok[0].content).toBe("Original take");
+      expect(history.Ok[0].version).toBe(BigInt(1));
+    }
+  });
+
+  it("should record public market edit history", async () => {
+    const createResult = await actor.create_market(
+      "Will it rain tomorrow?",
+      "Resolves YES if it rains.",
+      "Weather",
+      nanos(Math.floor(Date.now() / 1000) + 24 * 60 * 60),
+    );
+    expect(createResult).toHaveProperty("Ok");
+    if (!("Ok" in createResult)) return;
+    const marketId = createResult.Ok;
+
+    const updateResult = await actor.update_market(
+      marketId,
+      "Will it rain tomorrow in Lisbon?",
+      "Resolves YES if it rains in Lisbon.",
+      "Weather",
+      nanos(Math.floor(Date.now() / 1000) + 24 * 60 * 60),
+    );
+    expect(updateResult).toHaveProperty("Ok");
+
+    const history = await actor.get_market_edit_history(marketId);
+    expect(history.length).toBe(1);
+    expect(history[0].title).toBe("Will it rain tomorrow?");
+  });
+
+  it("should rank markets by comment velocity", async () => {
+    await actor.add_comment(BigInt(2), "Momentum is building here");
+    await actor.add_comment(BigInt(2), "Agreed, lots of chatter");
+
+    const ranked = await actor.get_markets_by_comment_velocity(
+      BigInt(3600),
+      BigInt(10),
+    );
+    expect(ranked.length).toBeGreaterThan(0);
+    expect(ranked[0].market.id).toBe(BigInt(2));
+    expect(ranked[0].recent_comments).toBe(BigInt(2));
+    expect(ranked[0].prior_comments).toBe(BigInt(0));
+    expect(Number.isFinite(ranked[0].acceleration)).toBe(true);
+  });
+
+  it("should record the XP multiplier applied to a trade", async () => {
+    const config = await actor.get_xp_config();
+    expect(config.early_multiplier_bps).toBe(BigInt(20_000));
+    expect(config.contrarian_bonus_bps).toBe(BigInt(15_000));
+
+    // Market 1 leans NO, so buying YES is a contrarian trade. On June 1 it
+    // is 3826 bps through its life, so 2x has decayed to 1.6174x before the
+    // 1.5x bonus
+    const early = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    if (!("Ok" in early)) throw new Error("early trade failed");
+    expect(early.Ok.xp_multiplier_bps).toBe(BigInt(24_261));
+
+    // A second before close only the bonus is left on top of 1x
+    await pic.setTime((SAMPLE_MARKET_CLOSE - 1) * 1000);
+    await pic.tick();
+    const late = await actor.buy_shares(BigInt(1), true, BigInt(100));
+    if (!("Ok" in late)) throw new Error("late trade failed");
+    expect(late.Ok.xp_multiplier_bps).toBe(BigInt(15_001));
+  });
+
+  it("should reject trades at the exact close boundary without grace", async () => {
+    expect(await actor.get_close_grace_seconds()).toBe(BigInt(0));
+
+    await pic.setTime(SAMPLE_MARKET_CLOSE * 1000);
+    await pic.tick();
+
+    const tradeResult = await actor.buy_shares(BigInt(1), true, BigInt(100));
+    expect(tradeResult).toEqual({ Err: "Market is closed" });
+  });
+
+  it("should accept trades within the close grace buffer", async () => {
+    const setResult = await actor.set_close_grace_seconds(BigInt(60));
+    expect(setResult).toHaveProperty("Ok");
+    expect(await actor.get_close_grace_seconds()).toBe(BigInt(60));
+
+    await pic.setTime(SAMPLE_MARKET_CLOSE * 1000);
+    await pic.tick();
+    const inGrace = await actor.buy_shares(BigInt(1), true, BigInt(100));
+    expect(inGrace).toHaveProperty("Ok");
+
+    await pic.setTime((SAMPLE_MARKET_CLOSE + 60) * 1000);
+    await pic.tick();
+    const afterGrace = await actor.buy_shares(BigInt(1), true, BigInt(100));
+    expect(afterGrace).toEqual({ Err: "Market is closed" });
+  });
+
+  it("should manage a version-tracked market FAQ", async () => {
+    const addResult = await actor.add_market_faq(
+      BigInt(1),
+      "Which exchange is used?",
+      "Coinbase BTC-USD spot price.",
+    );
+    expect(addResult).toHaveProperty("Ok");
+    if (!("Ok" in addResult)) return;
+
+    const updateResult = await actor.update_market_faq(
+      BigInt(1),
+      addResult.Ok,
+      "Which exchange is used?",
+      "Coinbase BTC-USD spot price, daily close.",
+    );
+    expect(updateResult).toHaveProperty("Ok");
+
+    const detail = await actor.get_market_detail(BigInt(1));
+    expect(detail).toHaveLength(1);
+    expect(detail[0]?.faq.length).toBe(1);
+    expect(detail[0]?.faq[0].answer).toContain("daily close");
+
+    const history = await actor.get_market_faq_history(BigInt(1));
+    expect(history.length).toBe(2);
+    expect(history[1].answer).toBe("Coinbase BTC-USD spot price.");
+
+    // A busy entry is capped on its own and keeps how it first read
+    const other = await actor.add_market_faq(
+      BigInt(1),
+      "Source?",
+      "CoinGecko.",
+    );
+    if (!("Ok" in other)) throw new Error(other.Err);
+    for (let i = 0; i < 12; i++) {
+      await actor.update_market_faq(
+        BigInt(1),
+        addResult.Ok,
+        "Which exchange is used?",
+        `Revision ${i}`,
+      );
+    }
+    const capped = await actor.get_market_faq_history(BigInt(1));
+    const busy = capped.filter((r) => r.faq_id === addResult.Ok);
+    expect(busy).toHaveLength(10);
+    expect(busy[0].change).toEqual({ Added: null });
+    expect(busy[1].answer).toBe("Coinbase BTC-USD spot price.");
+    expect(busy[9].answer).toBe("Revision 10");
+    expect(capped.filter((r) => r.faq_id === other.Ok)).toHaveLength(1);
+
+    await actor.freeze_market_faq(BigInt(1));
+    const frozenAdd = await actor.add_market_faq(BigInt(1), "Q?", "A.");
+    expect(frozenAdd).toEqual({ Err: "The FAQ for this market is frozen" });
+  });
+
+  it("should reject comments on locked markets", async () => {
+    const before = await actor.add_comment(BigInt(1), "Visible comment");
+    expect(before).toHaveProperty("Ok");
+
+    const lockResult = await actor.lock_comments(BigInt(1));
+    expect(lockResult).toHaveProperty("Ok");
+
+    const rejected = await actor.add_comment(BigInt(1), "Too late");
+    expect(rejected).toEqual({ Err: "Comments are locked for this market" });
+
+    const comments = await actor.get_market_comments(BigInt(1));
+    expect(comments.some((c) => c.content === "Visible comment")).toBe(true);
+
+    const events = await actor.get_market_events(BigInt(1));
+    expect(events.some((e) => "CommentsLocked" in e.kind)).toBe(true);
+
+    await actor.unlock_comments(BigInt(1));
+    const after = await actor.add_comment(BigInt(1), "Back again");
+    expect(after).toHaveProperty("Ok");
+  });
+
+  it("should derive a per-user deposit subaccount", async () => {
+    const account = await actor.get_deposit_account();
+    expect(account.owner.toText()).toBe(canisterId.toText());
+    expect(account.subaccount).toHaveLength(1);
+    expect(account.subaccount[0]?.length).toBe(32);
+
+    actor.setIdentity(new AnonymousIdentity());
+    const result = await actor.notify_deposit();
+    expect(result).toEqual({ Err: "Anonymous principals cannot deposit" });
+  });
+
+  it("should withdraw internal balance to a ledger account", async () => {
+    const user = createIdentity("withdrawer");
+    await fund(user, BigInt(50_000));
+    actor.setIdentity(user);
+    const to = { owner: user.getPrincipal(), subaccount: [] as [] };
+
+    expect(await actor.withdraw(BigInt(20_000), to)).toHaveProperty("Ok");
+    expect(await ledger.icrc1_balance_of(to)).toBe(BigInt(20_000));
+    // The ledger fee is charged on top of the amount
+    expect(await actor.get_balance(user.getPrincipal())).toBe(
+      BigInt(30_000) - LEDGER_FEE,
+    );
+    expect(await actor.withdraw(BigInt(20_000), to)).toEqual({
+      Err: "Insufficient balance",
+    });
+  });
+
+  it("should report badge progress", async () => {
+    const caller = admin.getPrincipal();
+    const before = await actor.get_badge_progress(caller);
+    expect(before.length).toBeGreaterThan(0);
+    expect(before.every((b) => !b.earned && b.current === BigInt(0))).toBe(
+      true,
+    );
+
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+
+    const after = await actor.get_badge_progress(caller);
+    const firstTrade = after.find((b) => b.badge_name === "First Trade");
+    expect(firstTrade?.earned).toBe(true);
+    const activeTrader = after.find((b) => b.badge_name === "Active Trader");
+    expect(activeTrader?.current).toBe(BigInt(1));
+    expect(activeTrader?.target).toBe(BigInt(10));
+    expect(activeTrader?.earned).toBe(false);
+  });
+
+  it("should run a chunked integrity check and persist the report", async () => {
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await actor.add_comment(BigInt(1), "Checking integrity");
+
+    const started = await actor.run_integrity_check();
+    expect(started).toHaveProperty("Ok");
+
+    // Let the scheduler process the chunks over a few ticks
+    for (let i = 0; i < 3; i++) {
+      await pic.advanceTime(SCHEDULER_TICK_MS);
+      await pic.tick();
+    }
+
+    const reports = await actor.get_integrity_reports();
+    expect(reports).toHaveProperty("Ok");
+    if ("Ok" in reports) {
+      const report = reports.Ok[reports.Ok.length - 1];
+      expect("Completed" in report.status).toBe(true);
+      expect(report.trades_checked).toBe(BigInt(1));
+      expect(report.comments_checked).toBe(BigInt(1));
+      expect(report.issues).toHaveLength(0);
+    }
+
+    // Reports survive an upgrade; one cut short by it is marked failed
+    expect(await actor.run_integrity_check()).toHaveProperty("Ok");
+    await pic.upgradeCanister({ canisterId, wasm: WASM_PATH });
+    const kept = await actor.get_integrity_reports();
+    if (!("Ok" in kept)) throw new Error("reports failed");
+    expect(kept.Ok.map((r) => Object.keys(r.status)[0])).toEqual([
+      "Completed",
+      "Failed",
+    ]);
+    expect(await actor.run_integrity_check()).toHaveProperty("Ok");
+  });
+
+  it("should generate an insight on approval when enabled", async () => {
+    expect(await actor.get_auto_insight_on_approve()).toBe(false);
+    await actor.set_auto_insight_on_approve(true);
+    expect(await actor.get_auto_insight_on_approve()).toBe(true);
+
+    const createResult = await actor.create_market(
+      "Will ICP reach $50?",
+      "Resolves YES if ICP trades at or above $50.",
+      "Cryptocurrency",
+      nanos(SAMPLE_MARKET_CLOSE),
+    );
+    expect(createResult).toHaveProperty("Ok");
+    if (!("Ok" in createResult)) return;
+
+    const approveResult = await actor.approve_market(createResult.Ok);
+    expect(approveResult).toHaveProperty("Ok");
+    if ("Ok" in approveResult) {
+      expect("Active" in approveResult.Ok.status).toBe(true);
+    }
+
+    const again = await actor.approve_market(createResult.Ok);
+    expect(again).toEqual({ Err: "Market is not pending validation" });
+  });
+
+  it("should require balance to sponsor a market", async () => {
+    actor.setIdentity(createIdentity("sponsor"));
+
+    const result = await actor.sponsor_market(BigInt(1), BigInt(500), {
+      ResolutionBonus: null,
+    });
+    expect(result).toEqual({ Err: "Insufficient balance" });
+    expect(await actor.get_market_boosts(BigInt(1))).toHaveLength(0);
+  });
+
+  it("should keep sponsored boosts across an upgrade", async () => {
+    const sponsor = createIdentity("upgrade-sponsor");
+    await fund(sponsor, BigInt(1000));
+    actor.setIdentity(sponsor);
+    const first = await actor.sponsor_market(BigInt(1), BigInt(500), {
+      ResolutionBonus: null,
+    });
+    if (!("Ok" in first)) throw new Error(first.Err);
+    const boosts = await actor.get_market_boosts(BigInt(1));
+
+    await pic.upgradeCanister({ canisterId, wasm: WASM_PATH });
+    expect(await actor.get_market_boosts(BigInt(1))).toEqual(boosts);
+    // Ids carry on from where they were rather than starting over
+    const second = await actor.sponsor_market(BigInt(1), BigInt(500), {
+      ResolutionBonus: null,
+    });
+    expect(second).toEqual({ Ok: first.Ok + BigInt(1) });
+  });
+
+  it("should resolve a market and credit correct predictors", async () => {
+    const trader = createIdentity("trader");
+    await fund(trader, BigInt(100));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+
+    actor.setIdentity(admin);
+    expect(await actor.resolve_market(BigInt(1), { Yes: null })).toEqual({
+      Err: "Market cannot be resolved before it closes",
+    });
+    await passCloseDate();
+
+    actor.setIdentity(createIdentity("other"));
+    const unauthorized = await actor.resolve_market(BigInt(1), { Yes: null });
+    expect(unauthorized).toHaveProperty("Err");
+    actor.setIdentity(new AnonymousIdentity());
+    const anonymous = await actor.resolve_market(BigInt(1), { Yes: null });
+    expect(anonymous).toHaveProperty("Err");
+
+    actor.setIdentity(admin);
+    const result = await actor.resolve_market(BigInt(1), { Yes: null });
+    expect(result).toHaveProperty("Ok");
+
+    // Resolution credits are paid out by the scheduler
+    await pic.advanceTime(SCHEDULER_TICK_MS);
+    await pic.tick();
+
+    const profile = await actor.get_user_profile(trader.getPrincipal());
+    expect(profile[0]?.profile.successful_predictions).toBe(BigInt(1));
+  });
+
+  it("should look up markets by human-readable slug", async () => {
+    const market = await actor.get_market_by_slug(
+      "will-bitcoin-reach-150000-by-end-of-2025",
+    );
+    expect(market).toHaveLength(1);
+    expect(market[0]?.id).toBe(BigInt(1));
+
+    const createResult = await actor.create_market(
+      "Will Bitcoin reach $150,000 by end of 2025?",
+      "Duplicate title to exercise slug collisions.",
+      "Cryptocurrency",
+      nanos(SAMPLE_MARKET_CLOSE),
+    );
+    expect(createResult).toHaveProperty("Ok");
+    if (!("Ok" in createResult)) return;
+
+    const created = await actor.get_market(createResult.Ok);
+    expect(created[0]?.slug).toBe("will-bitcoin-reach-150000-by-end-of-2025-2");
+  });
+
+  it("should expose and manage scheduled tasks", async () => {
+    const status = await actor.get_scheduler_status();
+    const integrity = status.find((t) => t.name === "integrity_check");
+    expect(integrity).toBeDefined();
+    expect(integrity?.enabled).toBe(true);
+
+    expect(await actor.set_task_enabled("integrity_check", false)).toHaveProperty(
+      "Ok",
+    );
+    expect(await actor.trigger_task("integrity_check")).toHaveProperty("Ok");
+
+    const updated = await actor.get_scheduler_status();
+    const task = updated.find((t) => t.name === "integrity_check");
+    expect(task?.enabled).toBe(false);
+    expect(task?.last_run).toHaveLength(1);
+
+    expect(await actor.trigger_task("unknown")).toEqual({
+      Err: "Unknown task: unknown",
+    });
+  });
+
+  it("should protect the treasury reserve floor on withdrawals", async () => {
+    await actor.buy_shares(BigInt(1), true, BigInt(1_000_000));
+    const balance = await actor.get_treasury_balance();
+    expect(balance).toBeGreaterThan(LEDGER_FEE);
+
+    await actor.set_treasury_floor(BigInt(1));
+    expect(await actor.get_treasury_floor()).toBe(BigInt(1));
+
+    const to = { owner: Principal.anonymous(), subaccount: [] as [] };
+
+    // The ledger fee comes out of the treasury too, so one unit past the
+    // boundary is rejected
+    const breach = await actor.withdraw_treasury(balance - LEDGER_FEE, to);
+    expect(breach).toEqual({ Err: "Would breach treasury reserve floor" });
+
+    // Exactly at the boundary is paid out
+    const amount = balance - LEDGER_FEE - BigInt(1);
+    const atFloor = await actor.withdraw_treasury(amount, to);
+    expect(atFloor).toHaveProperty("Ok");
+    expect(await actor.get_treasury_balance()).toBe(BigInt(1));
+    expect(await ledger.icrc1_balance_of(to)).toBe(amount);
+
+    // Only collected fees leave, whatever the floor allows
+    await actor.set_treasury_floor(BigInt(0));
+    expect(await actor.withdraw_treasury(BigInt(1), to)).toEqual({
+      Err: "Withdrawal exceeds the fees collected",
+    });
+  });
+
+  it("should reject trades outside the market's trading windows", async () => {
+    const opensAt = nanos(SAMPLE_MARKET_TIME / 1000 + 3600);
+    const createResult = await actor.create_market_with_options(
+      "Will the home team win tonight?",
+      "Resolves YES if the home team wins tonight's match.",
+      "Sports",
+      nanos(SAMPLE_MARKET_CLOSE),
+      {
+        trading_schedule: [{ Windows: [[opensAt, opensAt + nanos(7200)]] }],
+        market_kind: [],
+        resolver: [],
+        resolution_criteria: [],
+        resolution_datetime: [],
+        primary_source_url: [],
+        numeric_target: [],
+        co_owners: [],
+      },
+    );
+    expect(createResult).toHaveProperty("Ok");
+    if (!("Ok" in createResult)) return;
+    await actor.approve_market(createResult.Ok);
+
+    const paused = await actor.buy_shares(createResult.Ok, true, BigInt(100));
+    expect(paused).toEqual({
+      Err: `TradingWindowClosed: next open at ${opensAt}`,
+    });
+
+    const detail = await actor.get_market_detail(createResult.Ok);
+    expect(detail[0]?.trading_open).toBe(false);
+    expect(detail[0]?.next_open).toEqual([opensAt]);
+
+    await pic.advanceTime(3600 * 1000);
+    await pic.tick();
+    const open = await actor.buy_shares(createResult.Ok, true, BigInt(100));
+    expect(open).toHaveProperty("Ok");
+  });
+
+  it("should bucket executed prices into a histogram", async () => {
+    expect(await actor.get_price_histogram(BigInt(2), true, BigInt(10))).toEqual(
+      [],
+    );
+
+    await actor.buy_shares(BigInt(2), true, BigInt(100));
+    await actor.buy_shares(BigInt(2), true, BigInt(100));
+
+    const histogram = await actor.get_price_histogram(
+      BigInt(2),
+      true,
+      BigInt(10),
+    );
+    expect(histogram).toHaveLength(10);
+    expect(histogram[0][0]).toBe(BigInt(500));
+    const total = histogram.reduce((sum, [, count]) => sum + count, BigInt(0));
+    expect(total).toBe(BigInt(2));
+  });
+
+  it("should batch-claim winnings across resolved markets", async () => {
+    const trader = createIdentity("claimer");
+    await fund(trader, BigInt(200));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await actor.buy_shares(BigInt(2), true, BigInt(100));
+
+    actor.setIdentity(admin);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    await actor.resolve_market(BigInt(2), { Yes: null });
+
+    actor.setIdentity(trader);
+    const first = await actor.claim_all_winnings(1);
+    expect(first.results).toHaveLength(1);
+    expect(first.results[0].result).toHaveProperty("Ok");
+    expect(first.remaining).toBe(1);
+
+    const second = await actor.claim_all_winnings(50);
+    expect(second.results).toHaveLength(1);
+    expect(second.remaining).toBe(0);
+
+    const balance = await actor.get_balance(trader.getPrincipal());
+    expect(balance).toBe(first.total_claimed + second.total_claimed);
+
+    const again = await actor.claim_winnings(BigInt(1));
+    expect(again).toEqual({ Err: "Winnings already claimed" });
+  });
+
+  it("should hide reads from anonymous callers when gated", async () => {
+    const fixture = await pic.setupCanister<_SERVICE>({
+      idlFactory,
+      wasm: WASM_PATH,
+      sender: admin.getPrincipal(),
+    });
+    const gated = fixture.actor;
+
+    expect(await gated.get_markets()).not.toHaveLength(0);
+
+    gated.setIdentity(admin);
+    expect(await gated.set_anonymous_reads_allowed(false)).toHaveProperty("Ok");
+    expect(await gated.get_anonymous_reads_allowed()).toBe(false);
+    expect(await gated.get_markets()).not.toHaveLength(0);
+
+    gated.setIdentity(createIdentity("member"));
+    expect(await gated.get_markets()).not.toHaveLength(0);
+
+    gated.setIdentity(new AnonymousIdentity());
+    expect(await gated.get_markets()).toHaveLength(0);
+    expect(await gated.get_market(BigInt(1))).toHaveLength(0);
+    expect(await gated.get_markets_without_trades()).toHaveLength(0);
+    expect(await gated.get_market_edit_history(BigInt(1))).toHaveLength(0);
+    expect(
+      await gated.get_price_histogram(BigInt(1), true, BigInt(10)),
+    ).toHaveLength(0);
+    expect(
+      (await gated.get_comment_stats(admin.getPrincipal())).total_comments,
+    ).toBe(BigInt(0));
+  });
+
+  it("should keep accounting consistent across a random trading sequence", async () => {
+    // Deterministic LCG so a failing sequence can be replayed
+    let seed = 42;
+    const random = (n: number) => {
+      seed = (seed * 1103515245 + 12345) % 2 ** 31;
+      return seed % n;
+    };
+    const traders = ["fuzz-a", "fuzz-b", "fuzz-c"].map((name) =>
+      createIdentity(name),
+    );
+    for (const trader of traders) {
+      await fund(trader, BigInt(50_000));
+    }
+    const poolsBefore = new Map<bigint, bigint>();
+    for (const market of await actor.get_markets()) {
+      poolsBefore.set(market.id, market.yes_liquidity + market.no_liquidity);
+    }
+
+    for (let i = 0; i < 40; i++) {
+      actor.setIdentity(traders[random(traders.length)]);
+      const marketId = BigInt(random(3) + 1);
+      const isYes = random(2) === 0;
+      if (random(4) === 0) {
+        await actor.close_position(
+          marketId,
+          isYes,
+          random(10_000) + 1,
+          BigInt(0),
+        );
+      } else {
+        await actor.buy_shares(marketId, isYes, BigInt(random(5_000)));
+      }
+    }
+
+    actor.setIdentity(admin);
+    await passCloseDate();
+    for (const id of [1, 2, 3]) {
+      await actor.resolve_market(
+        BigInt(id),
+        random(2) === 0 ? { Yes: null } : { No: null },
+      );
+    }
+
+    let claimed = BigInt(0);
+    let balances = BigInt(0);
+    for (const trader of traders) {
+      actor.setIdentity(trader);
+      const result = await actor.claim_all_winnings(50);
+      claimed += result.total_claimed;
+      const again = await actor.claim_all_winnings(50);
+      expect(again.total_claimed).toBe(BigInt(0));
+      balances += await actor.get_balance(trader.getPrincipal());
+    }
+
+    actor.setIdentity(admin);
+    let funded = BigInt(0);
+    for (const id of [1, 2, 3]) {
+      const [market] = await actor.get_market(BigInt(id));
+      const pool = market!.yes_liquidity + market!.no_liquidity;
+      // The seed liquidity never leaves the pool
+      expect(pool >= poolsBefore.get(BigInt(id))!).toBe(true);
+      funded += pool - poolsBefore.get(BigInt(id))!;
+    }
+    const treasury = await actor.get_treasury_balance();
+
+    // Winners share what was paid in, and every deposit is accounted for
+    expect(claimed <= funded).toBe(true);
+    expect(balances + treasury + funded - claimed).toBe(
+      BigInt(traders.length * 50_000),
+    );
+  });
+
+  it("should generate insights as background jobs", async () => {
+    const missing = await actor.refresh_ai_insight(BigInt(999));
+    expect(missing).toEqual({ Err: "Market not found" });
+
+    const job = await actor.refresh_ai_insight(BigInt(1));
+    expect(job).toHaveProperty("Ok");
+    if (!("Ok" in job)) return;
+
+    await pic.tick();
+    const [insight] = await actor.get_ai_insight(BigInt(1));
+    expect(insight?.job_id).toEqual([job.Ok]);
+    expect(insight?.status).toEqual({ Ready: null });
+    expect(insight?.summary).not.toBe("");
+
+    const next = await actor.refresh_ai_insight(BigInt(1));
+    expect(next).toEqual({ Ok: job.Ok + BigInt(1) });
+
+    // Jobs spend the shared LLM budget: creators are rate-limited and
+    // everyone else is refused
+    const creator = createIdentity("insight-creator");
+    actor.setIdentity(creator);
+    const created = await actor.create_market(
+      "Will the insight stay fresh?",
+      "Resolves YES if it does.",
+      "Other",
+      nanos(SAMPLE_MARKET_CLOSE),
+    );
+    if (!("Ok" in created)) throw new Error("creation failed");
+    actor.setIdentity(createIdentity("insight-spammer"));
+    expect(await actor.refresh_ai_insight(created.Ok)).toEqual({
+      Err: "Only the creator or an admin can refresh this insight",
+    });
+    actor.setIdentity(creator);
+    expect(await actor.refresh_ai_insight(created.Ok)).toHaveProperty("Ok");
+    await pic.tick();
+    expect(await actor.refresh_ai_insight(created.Ok)).toEqual({
+      Err: "Insights can only be refreshed once every 10 minutes",
+    });
+    await pic.advanceTime(10 * 60 * 1000);
+    expect(await actor.refresh_ai_insight(created.Ok)).toHaveProperty("Ok");
+  });
+
+  it("should aggregate a user's comment stats", async () => {
+    const commenter = createIdentity("commenter");
+    expect(await actor.get_comment_stats(commenter.getPrincipal())).toEqual({
+      total_comments: BigInt(0),
+      total_likes_received: BigInt(0),
+      markets_commented: BigInt(0),
+    });
+
+    actor.setIdentity(commenter);
+    await actor.add_comment(BigInt(1), "First thoughts");
+    await actor.add_comment(BigInt(1), "Second thoughts");
+    await actor.add_comment(BigInt(2), "Elsewhere");
+
+    const stats = await actor.get_comment_stats(commenter.getPrincipal());
+    expect(stats.total_comments).toBe(BigInt(3));
+    expect(stats.markets_commented).toBe(BigInt(2));
+  });
+
+  it("should render market embeds", async () => {
+    const [card] = await actor.get_market_embed(BigInt(1));
+    expect(card?.title).toContain("Bitcoin");
+    expect(card?.probability).toMatch(/^\d+%$/);
+    expect(card?.volume).toBe("~2.5k");
+    expect(card?.time_remaining).toBe("closes in 7 months");
+    expect(card!.description.length).toBeLessThanOrEqual(140);
+
+    const html = await actor.http_request({
+      method: "GET",
+      url: "/embed/1",
+      headers: [],
+      body: new Uint8Array(),
+    });
+    expect(html.status_code).toBe(200);
+    expect(new TextDecoder().decode(html.body as Uint8Array)).toContain(
+      'property="og:title"',
+    );
+
+    const json = await actor.http_request({
+      method: "GET",
+      url: "/embed/1.json",
+      headers: [],
+      body: new Uint8Array(),
+    });
+    const parsed = JSON.parse(new TextDecoder().decode(json.body as Uint8Array));
+    expect(parsed.volume).toBe("~2.5k");
+
+    const missing = await actor.http_request({
+      method: "GET",
+      url: "/embed/999",
+      headers: [],
+      body: new Uint8Array(),
+    });
+    expect(missing.status_code).toBe(404);
+  });
+
+  it("should auto-approve markets from trusted creators", async () => {
+    const trusted = createIdentity("trusted-creator");
+    const newcomer = createIdentity("newcomer");
+
+    expect(
+      await actor.set_approval_policy({
+        auto_approve: true,
+        min_xp: [BigInt(100)],
+        min_successful_predictions: [],
+      }),
+    ).toHaveProperty("Ok");
+    await actor.set_creator_trusted(trusted.getPrincipal(), true);
+    expect(await actor.get_trusted_creators()).toEqual([
+      trusted.getPrincipal(),
+    ]);
+
+    actor.setIdentity(trusted);
+    const trustedResult = await actor.create_market(
+      "Will the trusted market open?",
+      "Resolves YES if it opens.",
+      "Meta",
+      nanos(SAMPLE_MARKET_CLOSE),
+    );
+    expect(trustedResult).toHaveProperty("Ok");
+
+    actor.setIdentity(newcomer);
+    const newcomerResult = await actor.create_market(
+      "Will the newcomer market open?",
+      "Resolves YES if it opens.",
+      "Meta",
+      nanos(SAMPLE_MARKET_CLOSE),
+    );
+    expect(newcomerResult).toHaveProperty("Ok");
+    expect(
+      await actor.set_approval_policy({
+        auto_approve: false,
+        min_xp: [],
+        min_successful_predictions: [],
+      }),
+    ).toEqual({ Err: "Only admins can change the approval policy" });
+
+    if (!("Ok" in trustedResult) || !("Ok" in newcomerResult)) return;
+    const [approved] = await actor.get_market(trustedResult.Ok);
+    expect(approved?.status).toEqual({ Active: null });
+    const [pending] = await actor.get_market(newcomerResult.Ok);
+    expect(pending?.status).toEqual({ PendingValidation: null });
+  });
+
+  it("should pay queued resolution payouts exactly once across an upgrade", async () => {
+    const traders = ["payee-a", "payee-b", "payee-c"].map((name) =>
+      createIdentity(name),
+    );
+    for (const trader of traders) {
+      await fund(trader, BigInt(100));
+      actor.setIdentity(trader);
+      await actor.buy_shares(BigInt(1), true, BigInt(100));
+    }
+
+    actor.setIdentity(admin);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    expect(await actor.get_payout_queue_status()).toEqual({
+      pending: BigInt(3),
+      completed: BigInt(0),
+    });
+
+    const treasury = await actor.get_treasury_balance();
+    await pic.upgradeCanister({ canisterId, wasm: WASM_PATH });
+    expect((await actor.get_payout_queue_status()).pending).toBe(BigInt(3));
+    // Markets and trading state survive the upgrade instead of being reseeded
+    expect(await actor.get_markets()).toHaveLength(3);
+    const [resolved] = await actor.get_market(BigInt(1));
+    expect(resolved?.status).toEqual({ Resolved: null });
+    const [bySlug] = await actor.get_market_by_slug(resolved!.slug);
+    expect(bySlug?.id).toBe(BigInt(1));
+    expect(await actor.get_treasury_balance()).toBe(treasury);
+
+    for (let i = 0; i < 3; i++) {
+      await pic.advanceTime(SCHEDULER_TICK_MS);
+      await pic.tick();
+    }
+
+    expect(await actor.get_payout_queue_status()).toEqual({
+      pending: BigInt(0),
+      completed: BigInt(3),
+    });
+    for (const trader of traders) {
+      const [profile] = await actor.get_user_profile(trader.getPrincipal());
+      expect(profile?.profile.successful_predictions).toBe(BigInt(1));
+    }
+  });
+
+  it("should keep market content and access settings across an upgrade", async () => {
+    await actor.add_comment(BigInt(1), "Still here after the upgrade");
+    await actor.add_market_faq(BigInt(1), "Which price?", "Coinbase spot.");
+    await actor.lock_comments(BigInt(1));
+    const invitee = createIdentity("kept-invitee");
+    await actor.set_access_mode({ AllowList: null });
+    await actor.set_allow_listed(invitee.getPrincipal(), true);
+    await fund(invitee, BigInt(500));
+    actor.setIdentity(invitee);
+    await actor.buy_shares(BigInt(1), true, BigInt(500));
+    actor.setIdentity(admin);
+
+    const snapshot = async () => ({
+      comments: await actor.get_market_comments(BigInt(1)),
+      faq: await actor.get_market_faq_history(BigInt(1)),
+      events: await actor.get_market_events(BigInt(1)),
+      insight: await actor.get_ai_insight(BigInt(1)),
+      prices: await actor.get_price_histogram(BigInt(1), true, BigInt(10)),
+      liquidity: await actor.get_liquidity_history(
+        BigInt(1),
+        BigInt(0),
+        BigInt(2) ** BigInt(64) - BigInt(1),
+      ),
+      mode: await actor.get_access_mode(),
+      allowList: await actor.get_allow_list(),
+    });
+    const before = await snapshot();
+    expect(before.comments).toHaveLength(1);
+    expect(before.liquidity.length).toBeGreaterThan(1);
+
+    await pic.upgradeCanister({ canisterId, wasm: WASM_PATH });
+    expect(await snapshot()).toEqual(before);
+    // Ids carry on rather than reusing those already handed out
+    await actor.unlock_comments(BigInt(1));
+    const comment = await actor.add_comment(BigInt(1), "Another");
+    if (!("Ok" in comment)) throw new Error("comment failed");
+    expect(comment.Ok).toBeGreaterThan(before.comments[0]!.id);
+  });
+
+  it("should count down to the resolution deadline once closed", async () => {
+    expect(await actor.get_resolution_countdown(BigInt(1))).toEqual([]);
+
+    const resolutionWindow = 7 * 24 * 60 * 60;
+    await pic.setTime((SAMPLE_MARKET_CLOSE + 60) * 1000);
+    await pic.tick();
+    expect(await actor.get_resolution_countdown(BigInt(1))).toEqual([
+      BigInt(resolutionWindow - 60),
+    ]);
+
+    await pic.setTime((SAMPLE_MARKET_CLOSE + resolutionWindow + 3600) * 1000);
+    await pic.tick();
+    const [detail] = await actor.get_market_detail(BigInt(1));
+    expect(detail?.resolution_countdown).toEqual([BigInt(-3600)]);
+
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    expect(await actor.get_resolution_countdown(BigInt(1))).toEqual([]);
+  });
+
+  it("should list newly activated markets with creator reputation", async () => {
+    const createResult = await actor.create_market(
+      "Will the feed show this market?",
+      "Resolves YES if it does.",
+      "Meta",
+      nanos(SAMPLE_MARKET_CLOSE),
+    );
+    if (!("Ok" in createResult)) throw new Error("create failed");
+    await pic.advanceTime(1000);
+    await actor.approve_market(createResult.Ok);
+    await actor.buy_shares(createResult.Ok, true, BigInt(500));
+
+    const feed = await actor.get_new_markets(BigInt(0), BigInt(10));
+    expect(feed[0].market.id).toBe(createResult.Ok);
+    expect(feed[0].creator_reputation.markets_created).toBe(BigInt(1));
+    expect(feed[0].creator_reputation.approval_rate_bps).toBe(BigInt(10_000));
+    expect(feed[0].initial_liquidity).toBe(BigInt(10_000));
+    expect(feed[0].ai_insight).toEqual([]);
+
+    const page = await actor.get_new_markets(BigInt(1), BigInt(1));
+    expect(page).toHaveLength(1);
+    expect(page[0].market.id).not.toBe(createResult.Ok);
+  });
+
+  it("should fetch profiles in batch", async () => {
+    const first = createIdentity("batch-first");
+    const second = createIdentity("batch-second");
+    for (const trader of [first, second]) {
+      await fund(trader, BigInt(100));
+      actor.setIdentity(trader);
+      await actor.buy_shares(BigInt(1), true, BigInt(100));
+    }
+
+    const profiles = await actor.get_profiles([
+      second.getPrincipal(),
+      createIdentity("unknown").getPrincipal(),
+      first.getPrincipal(),
+    ]);
+    expect(profiles.map((p) => p.principal.toText())).toEqual([
+      second.getPrincipal().toText(),
+      first.getPrincipal().toText(),
+    ]);
+  });
+
+  it("should transfer shares between users without trading", async () => {
+    const sender = createIdentity("gifter");
+    const recipient = createIdentity("giftee");
+    await fund(sender, BigInt(1000));
+    actor.setIdentity(sender);
+    const buy = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    if (!("Ok" in buy)) throw new Error("buy failed");
+    const [before] = await actor.get_market(BigInt(1));
+
+    const half = buy.Ok.shares / BigInt(2);
+    expect(
+      await actor.transfer_shares(
+        BigInt(1),
+        recipient.getPrincipal(),
+        true,
+        half,
+      ),
+    ).toEqual({ Ok: null });
+    expect(
+      await actor.transfer_shares(
+        BigInt(1),
+        new AnonymousIdentity().getPrincipal(),
+        true,
+        BigInt(1),
+      ),
+    ).toHaveProperty("Err");
+
+    // The recipient's position cap applies to shares received too
+    actor.setIdentity(admin);
+    await actor.set_market_position_cap(BigInt(1), [half]);
+    actor.setIdentity(sender);
+    expect(
+      await actor.transfer_shares(
+        BigInt(1),
+        recipient.getPrincipal(),
+        true,
+        BigInt(1),
+      ),
+    ).toEqual({ Err: "The recipient's position limit would be exceeded" });
+
+    const [after] = await actor.get_market(BigInt(1));
+    expect(after?.yes_shares).toBe(before?.yes_shares);
+    expect(after?.total_volume).toBe(before?.total_volume);
+
+    const [senderPosition] = await actor.get_my_positions();
+    expect(senderPosition.yes_shares).toBe(buy.Ok.shares - half);
+
+    actor.setIdentity(recipient);
+    const [received] = await actor.get_my_positions();
+    expect(received.yes_shares).toBe(half);
+    expect(received.yes_cost + senderPosition.yes_cost).toBe(BigInt(1000));
+    expect(await actor.get_my_share_transfers()).toHaveLength(1);
+  });
+
+  it("should credit rounding dust to the treasury", async () => {
+    expect(await actor.get_dust_collected()).toBe(BigInt(0));
+    expect(await actor.buy_shares(BigInt(1), true, BigInt(1))).toEqual({
+      Err: "Amount is too small to buy a whole share",
+    });
+
+    let fees = BigInt(0);
+    for (let amount = 900; amount < 1000; amount += 9) {
+      const result = await actor.buy_shares(BigInt(1), true, BigInt(amount));
+      if ("Ok" in result) fees += (BigInt(amount) * BigInt(2)) / BigInt(100);
+    }
+
+    const dust = await actor.get_dust_collected();
+    expect(dust).toBeGreaterThan(BigInt(0));
+    expect(await actor.get_treasury_balance()).toBe(fees + dust);
+  });
+
+  it("should create flash markets only for eligible creators", async () => {
+    const now = nanos(SAMPLE_MARKET_TIME / 1000);
+    const flash = {
+      trading_schedule: [] as [],
+      market_kind: [{ Flash: null }],
+      resolver: [] as [],
+      resolution_criteria: [] as [],
+      resolution_datetime: [] as [],
+      primary_source_url: [] as [],
+      numeric_target: [] as [],
+      co_owners: [] as [],
+    };
+
+    const tooSoon = await actor.create_market_with_options(
+      "Will the keynote mention ICP?",
+      "Resolves YES if ICP is mentioned on stage.",
+      "Tech",
+      now + nanos(600),
+      flash,
+    );
+    expect(tooSoon).toHaveProperty("Err");
+
+    const created = await actor.create_market_with_options(
+      "Will the keynote mention ICP?",
+      "Resolves YES if ICP is mentioned on stage.",
+      "Tech",
+      now + nanos(7200),
+      flash,
+    );
+    if (!("Ok" in created)) throw new Error("flash creation failed");
+    const [market] = await actor.get_market(created.Ok);
+    expect(market?.status).toEqual({ Active: null });
+    expect(market?.market_kind).toEqual({ Flash: null });
+
+    const flashMarkets = await actor.get_markets_by_kind({ Flash: null });
+    expect(flashMarkets.map((m) => m.id)).toEqual([created.Ok]);
+
+    actor.setIdentity(createIdentity("unproven"));
+    const rejected = await actor.create_market_with_options(
+      "Will the keynote run late?",
+      "Resolves YES if it overruns.",
+      "Tech",
+      now + nanos(7200),
+      flash,
+    );
+    expect(rejected).toHaveProperty("Err");
+
+    // Once closed and unresolved, the scheduler nags for resolution
+    await pic.advanceTime(7200 * 1000 + 60_000);
+    await pic.tick();
+    await pic.advanceTime(SCHEDULER_TICK_MS);
+    await pic.tick();
+    const events = await actor.get_market_events(created.Ok);
+    expect(events.some((e) => "ResolutionReminder" in e.kind)).toBe(true);
+  });
+
+  it("should apply experiment overrides to bucketed traders", async () => {
+    const now = BigInt(SAMPLE_MARKET_TIME) * BigInt(1_000_000);
+    const day = BigInt(86_400) * BigInt(1_000_000_000);
+    const created = await actor.create_experiment(
+      "One percent fee",
+      { fee_bps: [BigInt(100)], xp_per_amount_divisor: [] },
+      BigInt(10_000),
+      now,
+      now + day,
+    );
+    if (!("Ok" in created)) throw new Error("experiment creation failed");
+
+    const overlapping = await actor.create_experiment(
+      "Free trading",
+      { fee_bps: [BigInt(0)], xp_per_amount_divisor: [] },
+      BigInt(5_000),
+      now,
+      now + day,
+    );
+    expect(overlapping).toHaveProperty("Err");
+
+    const trader = createIdentity("experiment-trader");
+    await fund(trader, BigInt(1000));
+    actor.setIdentity(trader);
+    expect((await actor.get_effective_config()).fee_bps).toBe(BigInt(100));
+    const trade = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    if (!("Ok" in trade)) throw new Error("trade failed");
+    expect(trade.Ok.fee).toBe(BigInt(10));
+    expect(trade.Ok.experiments).toEqual([
+      { experiment_id: created.Ok, treatment: true },
+    ]);
+
+    actor.setIdentity(admin);
+    const results = await actor.get_experiment_results(created.Ok);
+    if (!("Ok" in results)) throw new Error("results unavailable");
+    expect(results.Ok.treatment.trades).toBe(BigInt(1));
+    expect(results.Ok.treatment.volume).toBe(BigInt(1000));
+    expect(results.Ok.treatment.exposed).toBe(BigInt(1));
+
+    // Expired experiments stop applying
+    await pic.advanceTime(Number(day / BigInt(1_000_000)));
+    actor.setIdentity(trader);
+    expect((await actor.get_effective_config()).fee_bps).toBe(BigInt(200));
+  });
+
+  it("should block buys beyond the position cap", async () => {
+    expect(await actor.set_max_position_per_market(BigInt(1_000))).toEqual({
+      Ok: null,
+    });
+    expect(
+      await actor.set_market_position_cap(BigInt(2), [BigInt(5_000)]),
+    ).toEqual({ Ok: null });
+    expect(await actor.get_position_cap(BigInt(1))).toBe(BigInt(1_000));
+    expect(await actor.get_position_cap(BigInt(2))).toBe(BigInt(5_000));
+
+    const whale = createIdentity("whale");
+    await fund(whale, BigInt(4000));
+    actor.setIdentity(whale);
+    const first = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    if (!("Ok" in first)) throw new Error("first trade failed");
+    expect(first.Ok.shares).toBeLessThan(BigInt(1_000));
+
+    const blocked = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    expect(blocked).toEqual({ Err: "Position limit reached" });
+
+    // The cap is per side and per market
+    expect(await actor.buy_shares(BigInt(1), false, BigInt(1000))).toHaveProperty(
+      "Ok",
+    );
+    expect(await actor.buy_shares(BigInt(2), true, BigInt(1000))).toHaveProperty(
+      "Ok",
+    );
+    expect(await actor.buy_shares(BigInt(2), true, BigInt(1000))).toHaveProperty(
+      "Ok",
+    );
+
+    expect(await actor.set_max_position_per_market(BigInt(0))).toHaveProperty(
+      "Err",
+    );
+  });
+
+  it("should quote probabilities for aggregators", async () => {
+    await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    const [market] = await actor.get_market(BigInt(1));
+    const quotes = await actor.get_probabilities([BigInt(1), BigInt(999)]);
+    expect(quotes.length).toBe(2);
+    expect(quotes[1]).toEqual([]);
+    const [quote] = quotes[0];
+    const liquidity = market!.yes_liquidity + market!.no_liquidity;
+    expect(quote?.liquidity).toBe(liquidity);
+    expect(quote?.yes_bps).toBe(
+      (market!.yes_liquidity * BigInt(10_000)) / liquidity,
+    );
+    expect(quote?.stale).toBe(false);
+    expect(quote?.last_trade_ts.length).toBe(1);
+
+    const response = await actor.http_request({
+      method: "GET",
+      url: "/v1/probabilities?ids=1,999",
+      headers: [],
+      body: new Uint8Array(),
+    });
+    expect(response.status_code).toBe(200);
+    const parsed = JSON.parse(
+      new TextDecoder().decode(response.body as Uint8Array),
+    );
+    expect(parsed.quotes[0].yes_bps).toBe(Number(quote?.yes_bps));
+    expect(parsed.quotes[1]).toBeNull();
+
+    // Without a trade for a day the quote goes stale
+    await pic.advanceTime(86_400 * 1000);
+    const [[later]] = await actor.get_probabilities([BigInt(1)]);
+    expect(later?.stale).toBe(true);
+
+    const malformed = await actor.http_request({
+      method: "GET",
+      url: "/v1/probabilities?ids=1,abc",
+      headers: [],
+      body: new Uint8Array(),
+    });
+    expect(malformed.status_code).toBe(400);
+  });
+
+  it("should count markets per category", async () => {
+    actor.setIdentity(createIdentity("category-creator"));
+    for (const title of ["Will AR glasses ship?", "Will quantum win?"]) {
+      const created = await actor.create_market(
+        title,
+        "Resolves per public reporting.",
+        "Technology",
+        nanos(SAMPLE_MARKET_CLOSE),
+      );
+      expect(created).toHaveProperty("Ok");
+    }
+
+    const all = await actor.get_categories_with_counts(false);
+    expect(all[0]).toEqual(["Technology", BigInt(3)]);
+    expect(all).toContainEqual(["Finance", BigInt(1)]);
+
+    // Pending markets are not active
+    const active = await actor.get_categories_with_counts(true);
+    expect(active).toEqual([
+      ["Cryptocurrency", BigInt(1)],
+      ["Finance", BigInt(1)],
+      ["Technology", BigInt(1)],
+    ]);
+  });
+
+  it("should batch comment notifications for subscribers", async () => {
+    const lurker = createIdentity("lurker");
+    actor.setIdentity(lurker);
+    expect(await actor.subscribe_comments(BigInt(1))).toEqual({ Ok: null });
+
+    const talker = createIdentity("talker");
+    actor.setIdentity(talker);
+    await actor.set_comment_auto_subscribe(false);
+    await actor.add_comment(BigInt(1), "First thoughts");
+    await actor.add_comment(BigInt(1), "Second thoughts");
+    expect(await actor.get_my_comment_subscriptions()).toEqual([]);
+
+    const author = createIdentity("author");
+    actor.setIdentity(author);
+    await actor.add_comment(BigInt(1), "Joining in");
+    expect(await actor.get_my_comment_subscriptions()).toEqual([BigInt(1)]);
+
+    actor.setIdentity(lurker);
+    let inbox = await actor.get_my_comment_notifications();
+    expect(inbox.length).toBe(1);
+    expect(inbox[0].new_comments).toBe(BigInt(3));
+
+    // A new window opens after an hour
+    await pic.advanceTime(3_600 * 1000);
+    actor.setIdentity(talker);
+    await actor.add_comment(BigInt(1), "Still here");
+    actor.setIdentity(lurker);
+    inbox = await actor.get_my_comment_notifications();
+    expect(inbox.map((n) => n.new_comments)).toEqual([BigInt(3), BigInt(1)]);
+
+    // Authors are not notified about their own comments
+    actor.setIdentity(author);
+    inbox = await actor.get_my_comment_notifications();
+    expect(inbox.map((n) => n.new_comments)).toEqual([BigInt(1)]);
+
+    actor.setIdentity(lurker);
+    await actor.unsubscribe_comments(BigInt(1));
+    await actor.clear_comment_notifications();
+    actor.setIdentity(talker);
+    await actor.add_comment(BigInt(1), "Anyone?");
+    actor.setIdentity(lurker);
+    expect(await actor.get_my_comment_notifications()).toEqual([]);
+  });
+
+  it("should waive fees for a user's first trades", async () => {
+    expect(await actor.set_free_trades(BigInt(2))).toEqual({ Ok: null });
+
+    const newcomer = createIdentity("newcomer");
+    await fund(newcomer, BigInt(3000));
+    actor.setIdentity(newcomer);
+    const me = newcomer.getPrincipal();
+    expect(await actor.get_free_trades_remaining(me)).toBe(BigInt(2));
+
+    for (let i = 0; i < 2; i++) {
+      const trade = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+      if (!("Ok" in trade)) throw new Error("trade failed");
+      expect(trade.Ok.fee).toBe(BigInt(0));
+    }
+    expect(await actor.get_free_trades_remaining(me)).toBe(BigInt(0));
+
+    const charged = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    if (!("Ok" in charged)) throw new Error("trade failed");
+    expect(charged.Ok.fee).toBe(BigInt(20));
+
+    // Waived trades still count towards XP and volume
+    const [profile] = await actor.get_user_profile(me);
+    expect(profile?.profile.total_trades).toBe(BigInt(3));
+    expect(profile!.profile.xp).toBeGreaterThan(BigInt(0));
+    const [market] = await actor.get_market(BigInt(1));
+    expect(market!.total_volume).toBeGreaterThanOrEqual(BigInt(3000));
+  });
+
+  it("should log every balance mutation so history replays to the balance", async () => {
+    const trader = createIdentity("ledger-user");
+    const me = trader.getPrincipal();
+    await fund(trader, BigInt(1000));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(1000));
+
+    actor.setIdentity(admin);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+
+    actor.setIdentity(trader);
+    const claimed = await actor.claim_winnings(BigInt(1));
+    if (!("Ok" in claimed)) throw new Error("claim failed");
+    const sponsored = await actor.sponsor_market(BigInt(3), BigInt(100), {
+      ResolutionBonus: null,
+    });
+    expect(sponsored).toHaveProperty("Ok");
+
+    // The unspent bonus pool is refunded through the payout queue
+    actor.setIdentity(admin);
+    await actor.resolve_market(BigInt(3), { No: null });
+    await pic.advanceTime(SCHEDULER_TICK_MS);
+    await pic.tick();
+
+    actor.setIdentity(trader);
+    const firstPage = await actor.get_my_balance_history([], BigInt(2));
+    expect(firstPage.entries.map((e) => Object.keys(e.reason)[0])).toEqual([
+      "Refund",
+      "BoostFunding",
+    ]);
+    const secondPage = await actor.get_my_balance_history(
+      firstPage.next_cursor,
+      BigInt(3),
+    );
+    expect(secondPage.next_cursor).toEqual([]);
+    const entries = [...firstPage.entries, ...secondPage.entries];
+    expect(entries[2].reason).toEqual({ WinningsCredit: null });
+    expect(entries[2].delta).toBe(claimed.Ok);
+    expect(entries[3].reason).toEqual({ TradeDebit: null });
+    expect(entries[3].delta).toBe(BigInt(-1000));
+    expect(entries[4].reason).toEqual({ Deposit: null });
+
+    // Replaying from zero reproduces the balance after every entry
+    let replayed = BigInt(0);
+    for (const entry of [...entries].reverse()) {
+      replayed += entry.delta;
+      expect(entry.balance_after).toBe(replayed);
+    }
+    expect(replayed).toBe(await actor.get_balance(me));
+
+    expect(await actor.get_balance_history(me, [], BigInt(10))).toHaveProperty(
+      "Err",
+    );
+    actor.setIdentity(admin);
+    const adminView = await actor.get_balance_history(me, [], BigInt(10));
+    if (!("Ok" in adminView)) throw new Error("admin view failed");
+    expect(adminView.Ok.entries).toHaveLength(5);
+  });
+
+  it("should report price change over a window", async () => {
+    // Only the creation snapshot, long before the window
+    expect(await actor.get_price_change(BigInt(1), BigInt(86_400))).toEqual(
+      [],
+    );
+
+    await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    const [single] = await actor.get_price_change(BigInt(1), BigInt(86_400));
+    expect(single?.change_bps).toBe(BigInt(0));
+    expect(single?.from_bps).toBe(single?.to_bps);
+
+    await actor.buy_shares(BigInt(1), true, BigInt(5000));
+    const [change] = await actor.get_price_change(BigInt(1), BigInt(86_400));
+    expect(change!.to_bps).toBeGreaterThan(change!.from_bps);
+    expect(change!.change_bps).toBe(change!.to_bps - change!.from_bps);
+
+    // The baseline reaches back to creation when the window covers it
+    const [sinceCreation] = await actor.get_price_change(
+      BigInt(1),
+      BigInt(10 * 365 * 86_400),
+    );
+    expect(sinceCreation?.from_bps).toBe(BigInt(6000));
+
+    expect(await actor.get_price_change(BigInt(999), BigInt(60))).toEqual([]);
+  });
+
+  it("should gate actions behind the allow-list in soft launch mode", async () => {
+    expect(await actor.set_access_mode({ AllowList: null })).toEqual({
+      Ok: null,
+    });
+
+    const invitee = createIdentity("invitee");
+    const outsider = createIdentity("outsider");
+    await fund(invitee, BigInt(100));
+    actor.setIdentity(invitee);
+    expect((await actor.get_access_status()).allowed).toBe(false);
+    expect(await actor.buy_shares(BigInt(1), true, BigInt(100))).toHaveProperty(
+      "Err",
+    );
+    expect(await actor.add_comment(BigInt(1), "Hello")).toHaveProperty("Err");
+    // Browsing stays public
+    expect((await actor.get_markets()).length).toBeGreaterThan(0);
+
+    expect(await actor.request_access()).toEqual({ Ok: null });
+    expect((await actor.get_access_status()).requested).toBe(true);
+    actor.setIdentity(outsider);
+    await actor.request_access();
+
+    actor.setIdentity(admin);
+    const requests = await actor.get_access_requests();
+    if (!("Ok" in requests)) throw new Error("requests unavailable");
+    expect(requests.Ok).toHaveLength(2);
+    expect(
+      await actor.approve_access_requests([invitee.getPrincipal()]),
+    ).toEqual({ Ok: BigInt(1) });
+
+    actor.setIdentity(invitee);
+    expect(await actor.get_access_status()).toEqual({
+      mode: { AllowList: null },
+      allowed: true,
+      requested: false,
+    });
+    expect(await actor.get_my_notifications()).toMatchObject([
+      { kind: { AccessGranted: null } },
+    ]);
+    expect(await actor.buy_shares(BigInt(1), true, BigInt(100))).toHaveProperty(
+      "Ok",
+    );
+    expect(
+      await actor.transfer_shares(
+        BigInt(1),
+        outsider.getPrincipal(),
+        true,
+        BigInt(1),
+      ),
+    ).toEqual({ Err: "The recipient has not been given access yet" });
+
+    actor.setIdentity(outsider);
+    expect(await actor.add_comment(BigInt(1), "Let me in")).toHaveProperty(
+      "Err",
+    );
+
+    // Opening up lets everyone in and keeps the list for later
+    actor.setIdentity(admin);
+    await actor.set_access_mode({ Open: null });
+    actor.setIdentity(outsider);
+    expect(await actor.add_comment(BigInt(1), "Finally")).toHaveProperty("Ok");
+    actor.setIdentity(admin);
+    const list = await actor.get_allow_list();
+    expect(list).toEqual({ Ok: [invitee.getPrincipal()] });
+  });
+
+  it("should let a designated resolver settle a market", async () => {
+    const creator = createIdentity("delegating-creator");
+    const oracle = createIdentity("oracle");
+    actor.setIdentity(creator);
+    const created = await actor.create_market_with_options(
+      "Will the oracle report rain?",
+      "Resolves YES if the weather oracle reports rain.",
+      "Weather",
+      nanos(SAMPLE_MARKET_CLOSE),
+      {
+        trading_schedule: [],
+        market_kind: [],
+        resolver: [oracle.getPrincipal()],
+        resolution_criteria: [],
+        resolution_datetime: [],
+        primary_source_url: [],
+        numeric_target: [],
+        co_owners: [],
+      },
+    );
+    if (!("Ok" in created)) throw new Error("creation failed");
+    actor.setIdentity(admin);
+    await actor.approve_market(created.Ok);
+
+    // Only the creator or an admin may change the delegation
+    actor.setIdentity(oracle);
+    expect(
+      await actor.set_market_resolver(created.Ok, [oracle.getPrincipal()]),
+    ).toHaveProperty("Err");
+
+    await passCloseDate();
+    actor.setIdentity(createIdentity("bystander"));
+    expect(
+      await actor.resolve_market(created.Ok, { Yes: null }),
+    ).toHaveProperty("Err");
+
+    actor.setIdentity(oracle);
+    const resolved = await actor.resolve_market(created.Ok, { Yes: null });
+    if (!("Ok" in resolved)) throw new Error("delegated resolution failed");
+    expect(resolved.Ok.resolved_outcome).toEqual([{ Yes: null }]);
+    expect(resolved.Ok.resolver).toEqual([oracle.getPrincipal()]);
+
+    // Revoked resolvers lose the right on other markets
+    actor.setIdentity(admin);
+    expect(
+      await actor.set_market_resolver(BigInt(2), [oracle.getPrincipal()]),
+    ).toEqual({ Ok: null });
+    expect(
+      await actor.set_market_resolver(BigInt(2), []),
+    ).toEqual({ Ok: null });
+    actor.setIdentity(oracle);
+    expect(
+      await actor.resolve_market(BigInt(2), { Yes: null }),
+    ).toHaveProperty("Err");
+  });
+
+  it("should enforce resolution criteria verdicts", async () => {
+    const created = await actor.create_market_with_options(
+      "Will BTC print 150k before year end?",
+      "Resolves per the checklist below.",
+      "Cryptocurrency",
+      nanos(SAMPLE_MARKET_CLOSE),
+      {
+        trading_schedule: [],
+        market_kind: [],
+        resolver: [],
+        resolution_criteria: [
+          {
+            statements: [
+              "BTC/USD printed at or above 150,000 on Coinbase",
+              "The print happened before Dec 31 23:59 UTC",
+            ],
+            rule: { All: null },
+            verdicts: [],
+          },
+        ],
+        resolution_datetime: [],
+        primary_source_url: [],
+        numeric_target: [],
+        co_owners: [],
+      },
+    );
+    if (!("Ok" in created)) throw new Error("creation failed");
+    await actor.approve_market(created.Ok);
+    await passCloseDate();
+
+    // Free-form resolution is refused once criteria exist
+    expect(
+      await actor.resolve_market(created.Ok, { No: null }),
+    ).toHaveProperty("Err");
+    expect(
+      await actor.resolve_market_with_verdicts(created.Ok, [true], true),
+    ).toHaveProperty("Err");
+    expect(
+      await actor.resolve_market_with_verdicts(created.Ok, [true, false], true),
+    ).toEqual({ Err: "Outcome does not follow from the criteria verdicts" });
+
+    const resolved = await actor.resolve_market_with_verdicts(
+      created.Ok,
+      [true, false],
+      false,
+    );
+    if (!("Ok" in resolved)) throw new Error("resolution failed");
+    const [market] = await actor.get_market(created.Ok);
+    expect(market?.resolution_criteria[0]?.verdicts).toEqual([[true, false]]);
+
+    const tooMany = await actor.create_market_with_options(
+      "Too many conditions?",
+      "Six statements.",
+      "Misc",
+      nanos(SAMPLE_MARKET_CLOSE),
+      {
+        trading_schedule: [],
+        market_kind: [],
+        resolver: [],
+        resolution_criteria: [
+          {
+            statements: ["a", "b", "c", "d", "e", "f"],
+            rule: { Any: null },
+            verdicts: [],
+          },
+        ],
+        resolution_datetime: [],
+        primary_source_url: [],
+        numeric_target: [],
+        co_owners: [],
+      },
+    );
+    expect(tooMany).toHaveProperty("Err");
+  });
+
+  it("should list the largest recent trades with market titles", async () => {
+    await actor.buy_shares(BigInt(1), true, BigInt(200));
+    await pic.advanceTime(2 * 3_600 * 1000);
+    await actor.buy_shares(BigInt(2), false, BigInt(5000));
+    await actor.buy_shares(BigInt(3), true, BigInt(1000));
+
+    const lastHour = await actor.get_largest_trades(BigInt(3_600), BigInt(10));
+    expect(lastHour).toHaveLength(2);
+    expect(lastHour[0].trade.market_id).toBe(BigInt(2));
+    expect(lastHour[0].trade.shares).toBeGreaterThan(lastHour[1].trade.shares);
+    const [market] = await actor.get_market(BigInt(2));
+    expect(lastHour[0].market_title).toBe(market?.title);
+
+    const limited = await actor.get_largest_trades(BigInt(86_400), BigInt(1));
+    expect(limited).toHaveLength(1);
+    expect(limited[0].trade.market_id).toBe(BigInt(2));
+  });
+
+  it("should sort comments by the authors' stake", async () => {
+    const holder = createIdentity("big-holder");
+    const minnow = createIdentity("minnow");
+    const lurker = createIdentity("no-stake");
+    await fund(holder, BigInt(2000));
+    await fund(minnow, BigInt(100));
+
+    actor.setIdentity(holder);
+    await actor.buy_shares(BigInt(1), true, BigInt(2000));
+    await actor.add_comment(BigInt(1), "Holding a lot");
+    actor.setIdentity(minnow);
+    await actor.buy_shares(BigInt(1), false, BigInt(100));
+    await actor.add_comment(BigInt(1), "Holding a little");
+    actor.setIdentity(lurker);
+    await actor.add_comment(BigInt(1), "Just watching");
+
+    const newest = await actor.get_market_comments_page(
+      BigInt(1),
+      { Newest: null },
+      BigInt(0),
+      BigInt(10),
+    );
+    expect(newest.comments.map((c) => c.comment.content)).toEqual([
+      "Just watching",
+      "Holding a little",
+      "Holding a lot",
+    ]);
+
+    const staked = await actor.get_market_comments_page(
+      BigInt(1),
+      { StakeWeighted: null },
+      BigInt(0),
+      BigInt(10),
+    );
+    expect(staked.comments.map((c) => c.author_position)).toEqual([
+      { Medium: null },
+      { Small: null },
+      { None: null },
+    ]);
+
+    const secondPage = await actor.get_market_comments_page(
+      BigInt(1),
+      { StakeWeighted: null },
+      BigInt(2),
+      BigInt(10),
+    );
+    expect(secondPage.comments.map((c) => c.comment.content)).toEqual([
+      "Just watching",
+    ]);
+  });
+
+  it("should create profiles explicitly and idempotently", async () => {
+    expect(await actor.create_profile([])).toHaveProperty("Err");
+
+    const alice = createIdentity("profile-alice");
+    actor.setIdentity(alice);
+    expect(await actor.create_profile(["no spaces allowed"])).toHaveProperty(
+      "Err",
+    );
+    const created = await actor.create_profile(["alice_01"]);
+    if (!("Ok" in created)) throw new Error("profile creation failed");
+    expect(created.Ok.username).toBe("alice_01");
+    expect(created.Ok.total_trades).toBe(BigInt(0));
+
+    // Repeat calls return the existing profile
+    const again = await actor.create_profile(["someone_else"]);
+    expect(again).toEqual(created);
+
+    actor.setIdentity(createIdentity("profile-bob"));
+    expect(await actor.create_profile(["ALICE_01"])).toEqual({
+      Err: "Username is already taken",
+    });
+    const generated = await actor.create_profile([]);
+    if (!("Ok" in generated)) throw new Error("profile creation failed");
+    expect(generated.Ok.username).toMatch(/^User/);
+
+    // Trading later keeps the chosen profile
+    await fund(alice, BigInt(100));
+    actor.setIdentity(alice);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    const [profile] = await actor.get_user_profile(alice.getPrincipal());
+    expect(profile?.profile.username).toBe("alice_01");
+    expect(profile?.profile.total_trades).toBe(BigInt(1));
+  });
+
+  it("should archive resolved markets and proxy reads to the archive", async () => {
+    const archive = await pic.setupCanister<_ARCHIVE_SERVICE>({
+      idlFactory: archiveIdlFactory,
+      wasm: ARCHIVE_WASM_PATH,
+      arg: IDL.encode(
+        [IDL.Record({ writer: IDL.Principal })],
+        [{ writer: canisterId }],
+      ),
+    });
+
+    const trader = createIdentity("archived-trader");
+    await fund(trader, BigInt(100));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await actor.add_comment(BigInt(1), "Before the archive");
+    actor.setIdentity(admin);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+
+    expect(await actor.archive_resolved_markets(BigInt(0), 10)).toEqual({
+      Err: "No archive canister set",
+    });
+    await actor.set_archive_canister([archive.canisterId]);
+
+    const result = await actor.archive_resolved_markets(BigInt(0), 10);
+    if (!("Ok" in result)) throw new Error("archiving failed");
+    expect(result.Ok).toHaveLength(1);
+    expect(result.Ok[0]?.market_id).toBe(BigInt(1));
+    expect(result.Ok[0]?.result).toHaveProperty("Ok");
+
+    const [market] = await actor.get_market(BigInt(1));
+    expect(market?.archived_to).toEqual([archive.canisterId]);
+
+    // Reads are served from the archive canister
+    const trades = await actor.get_market_trades(BigInt(1));
+    expect(trades).toHaveLength(1);
+    expect(trades[0]?.amount).toBe(BigInt(100));
+    const comments = await actor.get_market_comments(BigInt(1));
+    expect(comments.map((c) => c.content)).toEqual(["Before the archive"]);
+
+    expect(await actor.add_comment(BigInt(1), "Too late")).toEqual({
+      Err: "Market is archived",
+    });
+  });
+
+  it("should preview resolution payouts without resolving", async () => {
+    const yes = createIdentity("preview-yes");
+    const no = createIdentity("preview-no");
+    await fund(yes, BigInt(100));
+    await fund(no, BigInt(100));
+    actor.setIdentity(yes);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    actor.setIdentity(no);
+    await actor.buy_shares(BigInt(1), false, BigInt(100));
+
+    // Traders cannot preview
+    expect(
+      await actor.preview_resolution(BigInt(1), { Yes: null }),
+    ).toEqual([]);
+
+    actor.setIdentity(admin);
+    const preview = await actor.preview_resolution(BigInt(1), { Yes: null });
+    expect(preview).toHaveLength(1);
+    expect(preview[0]?.[0]).toEqual(yes.getPrincipal());
+    expect(preview[0]?.[1]).toBeGreaterThan(BigInt(0));
+
+    const [market] = await actor.get_market(BigInt(1));
+    expect(market?.status).toEqual({ Active: null });
+
+    // Resolving then pays exactly the previewed amount
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    actor.setIdentity(yes);
+    expect(await actor.claim_winnings(BigInt(1))).toEqual({
+      Ok: preview[0]?.[1],
+    });
+  });
+
+  it("should run auditable tie-break draws", async () => {
+    const candidates = ["tie-a", "tie-b", "tie-c"].map((name) =>
+      createIdentity(name).getPrincipal(),
+    );
+
+    actor.setIdentity(createIdentity("not-admin"));
+    expect(await actor.draw_tie_break("Tournament #1", candidates)).toEqual({
+      Err: "Only admins can run draws",
+    });
+
+    actor.setIdentity(admin);
+    const duplicate = [candidates[0]!, candidates[0]!];
+    expect(
+      await actor.draw_tie_break("Tournament #1", duplicate),
+    ).toHaveProperty("Err");
+
+    const result = await actor.draw_tie_break("Tournament #1", candidates);
+    if (!("Ok" in result)) throw new Error("draw failed");
+    expect(result.Ok.seed).toHaveLength(32);
+    expect(result.Ok.candidates).toEqual(candidates);
+    expect([...result.Ok.order].map((p) => p.toText()).sort()).toEqual(
+      candidates.map((p) => p.toText()).sort(),
+    );
+
+    const [audit] = await actor.get_draw_audit(result.Ok.id);
+    expect(audit).toEqual(result.Ok);
+    expect(await actor.get_draw_audit(BigInt(999))).toEqual([]);
+
+    // Published draws stay verifiable after an upgrade
+    await pic.upgradeCanister({ canisterId, wasm: WASM_PATH });
+    expect(await actor.get_draw_audit(result.Ok.id)).toEqual([result.Ok]);
+  });
+
+  it("should reward the earliest correct trader at resolution", async () => {
+    const first = createIdentity("early-bird");
+    const second = createIdentity("late-comer");
+    for (const trader of [first, second]) {
+      await fund(trader, BigInt(100));
+      actor.setIdentity(trader);
+      await actor.buy_shares(BigInt(1), true, BigInt(100));
+      await pic.advanceTime(1000);
+    }
+
+    actor.setIdentity(admin);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    const events = await actor.get_market_events(BigInt(1));
+    const earliest = events.flatMap((e) =>
+      "EarliestCorrect" in e.kind ? [e.kind.EarliestCorrect] : [],
+    );
+    // A lone earliest trader needs no draw
+    expect(earliest).toEqual([{ trader: first.getPrincipal(), draw_id: [] }]);
+  });
+
+  it("should sort market comments newest, oldest and most liked", async () => {
+    for (const content of ["first", "second", "third"]) {
+      await actor.add_comment(BigInt(2), content);
+    }
+    type Sort = Parameters<typeof actor.get_market_comments_sorted>[1];
+    const contents = async (sort: Sort) =>
+      (
+        await actor.get_market_comments_sorted(
+          BigInt(2),
+          sort,
+          BigInt(0),
+          BigInt(10),
+        )
+      ).map((c) => c.content);
+
+    expect(await contents({ Newest: null })).toEqual([
+      "third",
+      "second",
+      "first",
+    ]);
+    expect(await contents({ Oldest: null })).toEqual([
+      "first",
+      "second",
+      "third",
+    ]);
+    // Likes are not tracked, so this falls back to newest first
+    expect(await contents({ MostLiked: null })).toEqual([
+      "third",
+      "second",
+      "first",
+    ]);
+
+    const page = await actor.get_market_comments_sorted(
+      BigInt(2),
+      { Oldest: null },
+      BigInt(1),
+      BigInt(1),
+    );
+    expect(page.map((c) => c.content)).toEqual(["second"]);
+
+    // The unsorted endpoint defaults to newest first
+    const all = await actor.get_market_comments(BigInt(2));
+    expect(all.map((c) => c.content)).toEqual(["third", "second", "first"]);
+  });
+
+  it("should cap XP per source per day and keep an XP ledger", async () => {
+    await actor.set_xp_source_rule(
+      { Trade: null },
+      { rate_bps: BigInt(10_000), daily_cap: BigInt(15) },
+    );
+
+    const farmer = createIdentity("xp-farmer");
+    await fund(farmer, BigInt(2000));
+    actor.setIdentity(farmer);
+    await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    await actor.buy_shares(BigInt(1), true, BigInt(1000));
+
+    const [profile] = await actor.get_user_profile(farmer.getPrincipal());
+    expect(profile?.profile.xp).toBe(BigInt(15));
+
+    // The capped-out trade granted nothing, so it has no entry
+    const history = await actor.get_my_xp_history([], BigInt(10));
+    expect(history.entries).toHaveLength(1);
+    expect(history.entries[0]?.source).toEqual({ Trade: null });
+    expect(history.entries[0]?.amount).toBe(BigInt(15));
+    expect(history.next_cursor).toEqual([]);
+
+    expect(
+      await actor.get_top_xp_earners({ Trade: null }, BigInt(0), BigInt(5)),
+    ).toHaveProperty("Err");
+
+    actor.setIdentity(admin);
+    const day = BigInt(Math.floor(SAMPLE_MARKET_TIME / 86_400_000));
+    expect(
+      await actor.get_top_xp_earners({ Trade: null }, day, BigInt(5)),
+    ).toEqual({ Ok: [[farmer.getPrincipal(), BigInt(15)]] });
+
+    // Admin grants are ledgered under their own source
+    expect(
+      await actor.admin_grant_xp(farmer.getPrincipal(), BigInt(40)),
+    ).toEqual({ Ok: BigInt(40) });
+    actor.setIdentity(farmer);
+    const [latest] = (await actor.get_my_xp_history([], BigInt(1))).entries;
+    expect(latest?.source).toEqual({ Admin: null });
+  });
+
+  it("should prune the oldest comments past the per-market cap", async () => {
+    expect(await actor.get_max_comments_per_market()).toBe(BigInt(1000));
+    expect(await actor.set_max_comments_per_market(BigInt(2))).toEqual({
+      Ok: null,
+    });
+
+    for (const content of ["oldest", "middle", "newest"]) {
+      await actor.add_comment(BigInt(3), content);
+    }
+    await actor.add_comment(BigInt(2), "other market");
+
+    const comments = await actor.get_market_comments(BigInt(3));
+    expect(comments.map((c) => c.content)).toEqual(["newest", "middle"]);
+    expect(await actor.get_market_comments(BigInt(2))).toHaveLength(1);
+  });
+
+  it("should track daily quests and pay rewards on claim", async () => {
+    const quester = createIdentity("quester");
+    await fund(quester, BigInt(100));
+    actor.setIdentity(quester);
+    expect(await actor.claim_quest_reward(BigInt(1))).toEqual({
+      Err: "Quest not completed today",
+    });
+
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await actor.add_comment(BigInt(1), "Holding YES");
+
+    const quests = await actor.get_my_quests();
+    const completed = quests
+      .filter((q) => q.completed)
+      .map((q) => q.quest.id);
+    // Trade of 100+, comment on a held market, first trade in the category
+    expect(completed).toEqual([BigInt(1), BigInt(2), BigInt(4)]);
+
+    const [before] = await actor.get_user_profile(quester.getPrincipal());
+    expect(await actor.claim_quest_reward(BigInt(1))).toEqual({
+      Ok: BigInt(20),
+    });
+    expect(await actor.claim_quest_reward(BigInt(1))).toEqual({
+      Err: "Quest reward already claimed",
+    });
+    const [after] = await actor.get_user_profile(quester.getPrincipal());
+    expect(after!.profile.xp - before!.profile.xp).toBe(BigInt(20));
+
+    const history = await actor.get_my_xp_history([], BigInt(1));
+    expect(history.entries[0]?.source).toEqual({ Quest: null });
+    expect((await actor.get_my_quests())[0]?.claimed).toBe(true);
+
+    // Progress resets at UTC midnight
+    await pic.advanceTime(24 * 60 * 60 * 1000);
+    await pic.tick();
+    expect((await actor.get_my_quests()).some((q) => q.progress > 0)).toBe(
+      false,
+    );
+  });
+
+  it("should record treasury inflows and daily revenue", async () => {
+    const before = await actor.get_treasury_balance();
+    await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    await pic.advanceTime(24 * 60 * 60 * 1000);
+    await pic.tick();
+    await actor.buy_shares(BigInt(2), false, BigInt(1000));
+    const collected = (await actor.get_treasury_balance()) - before;
+
+    const inflows = await actor.get_treasury_inflows(
+      BigInt(0),
+      BigInt(2) ** BigInt(63),
+    );
+    expect(inflows.map((i) => i.market_id)).toEqual([BigInt(1), BigInt(2)]);
+    expect(inflows.reduce((sum, i) => sum + i.amount, BigInt(0))).toBe(
+      collected,
+    );
+
+    const revenue = await actor.get_daily_revenue(BigInt(3));
+    expect(revenue).toHaveLength(3);
+    expect(revenue.map(([, amount]) => amount)).toEqual([
+      BigInt(0),
+      inflows[0]!.amount,
+      inflows[1]!.amount,
+    ]);
+    expect(revenue[1]![0] - revenue[0]![0]).toBe(BigInt(86_400));
+  });
+
+  it("should report order flow and rank one-sided markets as trending", async () => {
+    await actor.buy_shares(BigInt(1), true, BigInt(300));
+    await actor.buy_shares(BigInt(1), false, BigInt(100));
+    // Less volume, but all on one side
+    await actor.buy_shares(BigInt(2), true, BigInt(350));
+
+    const flow = await actor.get_flow(BigInt(1));
+    expect(flow.yes_vol_1h).toBe(BigInt(300));
+    expect(flow.no_vol_1h).toBe(BigInt(100));
+    expect(flow.yes_vol_24h).toBe(BigInt(300));
+    expect(flow.net_flow_bps).toBe(BigInt(5000));
+
+    const [detail] = await actor.get_market_detail(BigInt(1));
+    expect(detail?.flow).toEqual(flow);
+
+    const trending = await actor.get_trending_markets(BigInt(10));
+    expect(trending.map((t) => t.market.id)).toEqual([BigInt(2), BigInt(1)]);
+    expect(trending[0]?.score).toBe(BigInt(700));
+    expect(trending[1]?.score).toBe(BigInt(600));
+
+    // Two hours later the 1h window is empty, the 24h one is not
+    await pic.advanceTime(2 * 60 * 60 * 1000);
+    await pic.tick();
+    const later = await actor.get_flow(BigInt(1));
+    expect(later.yes_vol_1h).toBe(BigInt(0));
+    expect(later.yes_vol_24h).toBe(BigInt(300));
+  });
+
+  it("should auto-archive old resolved markets on a timer", async () => {
+    const archive = await pic.setupCanister<_ARCHIVE_SERVICE>({
+      idlFactory: archiveIdlFactory,
+      wasm: ARCHIVE_WASM_PATH,
+      arg: IDL.encode(
+        [IDL.Record({ writer: IDL.Principal })],
+        [{ writer: canisterId }],
+      ),
+    });
+    await actor.set_archive_canister([archive.canisterId]);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+
+    expect(await actor.get_auto_archive_config()).toEqual({
+      enabled: false,
+      older_than_days: BigInt(30),
+    });
+    expect(await actor.set_auto_archive_age_days(BigInt(1))).toEqual({
+      Ok: null,
+    });
+    expect(await actor.set_auto_archive_enabled(true)).toEqual({ Ok: null });
+
+    // Not old enough yet
+    await actor.trigger_task("auto_archive");
+    await pic.tick(5);
+    expect((await actor.get_auto_archive_runs()).at(-1)?.archived).toBe(
+      BigInt(0),
+    );
+
+    await pic.advanceTime(2 * 24 * 60 * 60 * 1000);
+    await actor.trigger_task("auto_archive");
+    await pic.tick(5);
+
+    // The hourly timer may have run too, so count across runs
+    const runs = await actor.get_auto_archive_runs();
+    expect(runs.reduce((sum, run) => sum + run.archived, BigInt(0))).toBe(
+      BigInt(1),
+    );
+    expect(runs.every((run) => run.failed === BigInt(0))).toBe(true);
+    const [market] = await actor.get_market(BigInt(1));
+    expect(market?.archived_to).toEqual([archive.canisterId]);
+  });
+
+  it("should validate and return structured market fields", async () => {
+    const options = {
+      trading_schedule: [] as [],
+      market_kind: [] as [],
+      resolver: [] as [],
+      resolution_criteria: [] as [],
+      resolution_datetime: [nanos(SAMPLE_MARKET_CLOSE + 86_400)] as [bigint],
+      primary_source_url: ["https://www.coinbase.com/price/bitcoin"] as [
+        string,
+      ],
+      numeric_target: [["BTC/USD", BigInt(150_000)]] as [[string, bigint]],
+      co_owners: [] as [],
+    };
+    const create = (overrides: Partial<typeof options>) =>
+      actor.create_market_with_options(
+        "Will BTC close above $150,000?",
+        "Resolves YES if BTC/USD trades at or above $150,000 on Coinbase.",
+        "Cryptocurrency",
+        nanos(SAMPLE_MARKET_CLOSE),
+        { ...options, ...overrides },
+      );
+
+    expect(
+      await create({
+        resolution_datetime: [nanos(SAMPLE_MARKET_CLOSE) - BigInt(1)],
+      }),
+    ).toEqual({ Err: "Resolution date cannot be before the close date" });
+    expect(
+      await create({ primary_source_url: ["http://example.com"] }),
+    ).toHaveProperty("Err");
+    const zeroTarget = await create({ numeric_target: [["BTC/USD", BigInt(0)]] });
+    expect(zeroTarget).toHaveProperty("Err");
+
+    const created = await create({});
+    if (!("Ok" in created)) throw new Error("creation failed");
+    const [market] = await actor.get_market(created.Ok);
+    expect(market?.numeric_target).toEqual([["BTC/USD", BigInt(150_000)]]);
+    expect(market?.resolution_datetime).toEqual([
+      nanos(SAMPLE_MARKET_CLOSE + 86_400),
+    ]);
+
+    // A target the prose does not mention is flagged by the AI review
+    const mismatched = await create({
+      numeric_target: [["BTC/USD", BigInt(200_000)]],
+    });
+    if (!("Ok" in mismatched)) throw new Error("creation failed");
+    await actor.refresh_ai_insight(mismatched.Ok);
+    await pic.tick();
+    const [insight] = await actor.get_ai_insight(mismatched.Ok);
+    expect(
+      insight?.risks.some((risk) => risk.includes("200000 is not stated")),
+    ).toBe(true);
+  });
+
+  it("should build a daily activity heatmap from trades and comments", async () => {
+    const active = createIdentity("heatmap-user");
+    await fund(active, BigInt(200));
+    actor.setIdentity(active);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await actor.add_comment(BigInt(1), "Day one");
+    await pic.advanceTime(2 * 24 * 60 * 60 * 1000);
+    await pic.tick();
+    await actor.buy_shares(BigInt(2), false, BigInt(100));
+
+    const heatmap = await actor.get_activity_heatmap(
+      active.getPrincipal(),
+      BigInt(4),
+    );
+    expect(heatmap.map(([, count]) => count)).toEqual([
+      BigInt(0),
+      BigInt(2),
+      BigInt(0),
+      BigInt(1),
+    ]);
+    expect(heatmap[3]![0] - heatmap[0]![0]).toBe(BigInt(3 * 86_400));
+  });
+
+  it("should triage markets in bulk with per-market results", async () => {
+    actor.setIdentity(createIdentity("spammer"));
+    const ids: bigint[] = [];
+    for (let i = 0; i < 3; i++) {
+      const created = await actor.create_market(
+        `Spam market number ${i}`,
+        "Buy my token",
+        "Other",
+        nanos(SAMPLE_MARKET_CLOSE),
+      );
+      if (!("Ok" in created)) throw new Error("creation failed");
+      ids.push(created.Ok);
+    }
+    expect(await actor.reject_markets(ids, "Spam")).toEqual({
+      Err: "Only admins can moderate markets",
+    });
+
+    actor.setIdentity(admin);
+    const approved = await actor.approve_markets([ids[0]!, BigInt(999)]);
+    expect(approved).toEqual({
+      Ok: [
+        { market_id: ids[0], result: { Ok: null } },
+        { market_id: BigInt(999), result: { Err: "Market not found" } },
+      ],
+    });
+
+    // The approved one cannot be approved or rejected again
+    const rejected = await actor.reject_markets(ids, "Spam");
+    if (!("Ok" in rejected)) throw new Error("batch failed");
+    expect(rejected.Ok.map((r) => "Ok" in r.result)).toEqual([
+      false,
+      true,
+      true,
+    ]);
+    expect(rejected.Ok[0]?.result).toEqual({
+      Err: "Market is not pending validation",
+    });
+
+    const archived = await actor.archive_markets([ids[0]!], "Spam");
+    expect(archived).toEqual({
+      Ok: [{ market_id: ids[0], result: { Ok: null } }],
+    });
+    expect(await actor.archive_markets([ids[0]!], " ")).toHaveProperty("Err");
+
+    const listed = (await actor.get_markets()).map((m) => m.id);
+    expect(ids.some((id) => listed.includes(id))).toBe(false);
+
+    const events = await actor.get_market_events(ids[1]!);
+    expect(events.at(-1)?.kind).toEqual({ Rejected: { reason: "Spam" } });
+  });
+
+  it("should only ask the LLM canister for insights when enabled", async () => {
+    actor.setIdentity(createIdentity("llm-user"));
+    expect(await actor.set_llm_enabled(true)).toEqual({
+      Err: "Only admins can configure AI insights",
+    });
+
+    actor.setIdentity(admin);
+    expect(await actor.get_llm_enabled()).toBe(false);
+    await actor.refresh_ai_insight(BigInt(1));
+    await pic.tick();
+    const [mock] = await actor.get_ai_insight(BigInt(1));
+    expect(mock?.status).toEqual({ Ready: null });
+
+    // The LLM canister is not installed here, so the real path fails loudly
+    expect(await actor.set_llm_enabled(true)).toEqual({ Ok: null });
+    await actor.refresh_ai_insight(BigInt(1));
+    const [kept] = await actor.get_ai_insight(BigInt(1));
+    expect(kept?.status).toEqual({ Ready: null });
+    expect(kept?.job_id).toEqual(mock?.job_id);
+    await pic.tick();
+    await pic.tick();
+    const [failed] = await actor.get_ai_insight(BigInt(1));
+    if (!failed || !("Failed" in failed.status)) throw new Error("not failed");
+    expect(failed.status.Failed).toContain("AI analysis call failed");
+  });
+
+  it("should respect notification preferences and muted markets", async () => {
+    const trader = createIdentity("prefs-user");
+    await fund(trader, BigInt(200));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await actor.buy_shares(BigInt(2), true, BigInt(100));
+
+    const defaults = await actor.get_notification_prefs();
+    expect(defaults).toHaveLength(9);
+    expect(defaults).toContainEqual(["WhaleTrade", { Disabled: null }]);
+    expect(defaults).toContainEqual(["MarketResolved", { Enabled: null }]);
+
+    // Kinds this canister does not know are kept as-is
+    expect(
+      await actor.set_notification_prefs([
+        ["CommentReply", { Disabled: null }],
+        ["FutureKind", { Enabled: null }],
+      ]),
+    ).toEqual({ Ok: null });
+    const prefs = await actor.get_notification_prefs();
+    expect(prefs).toContainEqual(["CommentReply", { Disabled: null }]);
+    expect(prefs.at(-1)).toEqual(["FutureKind", { Enabled: null }]);
+
+    expect(await actor.mute_market(BigInt(2))).toEqual({ Ok: null });
+    expect(await actor.get_muted_markets()).toEqual([BigInt(2)]);
+    await actor.subscribe_comments(BigInt(1));
+
+    actor.setIdentity(createIdentity("prefs-commenter"));
+    await actor.add_comment(BigInt(1), "Anyone still holding?");
+    actor.setIdentity(admin);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    await actor.resolve_market(BigInt(2), { Yes: null });
+
+    actor.setIdentity(trader);
+    expect(await actor.get_my_comment_notifications()).toEqual([]);
+    const inbox = await actor.get_my_notifications();
+    expect(inbox.map((n) => n.kind)).toEqual([
+      { MarketResolved: { market_id: BigInt(1), outcome: { Yes: null } } },
+    ]);
+  });
+
+  it("should rank profiles by configurable XP level thresholds", async () => {
+    const trader = createIdentity("level-user");
+    await fund(trader, BigInt(100));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    const [profile] = await actor.get_user_profile(trader.getPrincipal());
+    expect(profile?.level).toBe(BigInt(1));
+    expect(profile?.rank_name).toBe("Novice");
+
+    expect(await actor.set_level_thresholds([[BigInt(0), "Rookie"]])).toEqual({
+      Err: "Only admins can update level thresholds",
+    });
+
+    actor.setIdentity(admin);
+    const xp = profile!.profile.xp;
+    expect(
+      await actor.set_level_thresholds([
+        [BigInt(0), "Rookie"],
+        [xp, "Pro"],
+        [xp + BigInt(1), "Legend"],
+      ]),
+    ).toEqual({ Ok: null });
+    const [ranked] = await actor.get_user_profile(trader.getPrincipal());
+    expect(ranked?.level).toBe(BigInt(2));
+    expect(ranked?.rank_name).toBe("Pro");
+
+    expect(
+      await actor.set_level_thresholds([[BigInt(5), "Late start"]]),
+    ).toEqual({ Err: "The first level must start at 0 XP" });
+  });
+
+  it("should value positions by shares rather than cash paid", async () => {
+    const trader = createIdentity("portfolio-user");
+    await fund(trader, BigInt(100));
+    actor.setIdentity(trader);
+    const trade = await actor.buy_shares(BigInt(1), true, BigInt(100));
+    if (!("Ok" in trade)) throw new Error("trade failed");
+
+    const [value] = await actor.get_my_portfolio();
+    expect(value?.position.yes_shares).toBe(trade.Ok.shares);
+    expect(value?.position.yes_cost).toBe(BigInt(100));
+    expect(value?.yes_pool_share_bps).toBeGreaterThan(BigInt(0));
+    expect(value?.no_pool_share_bps).toBe(BigInt(0));
+    expect(value?.payout_if_no).toBe(BigInt(0));
+
+    // The same share-based figure the resolver sees in the preview
+    actor.setIdentity(admin);
+    const preview = await actor.preview_resolution(BigInt(1), { Yes: null });
+    expect(preview).toContainEqual([
+      trader.getPrincipal(),
+      value!.payout_if_yes,
+    ]);
+  });
+
+  it("should list untraded markets oldest first for cleanup", async () => {
+    // The sample markets all have volume
+    expect(await actor.get_markets_without_trades()).toEqual([]);
+
+    actor.setIdentity(createIdentity("abandoned-creator"));
+    const ids: bigint[] = [];
+    for (let i = 0; i < 2; i++) {
+      const created = await actor.create_market(
+        `Abandoned market number ${i}`,
+        "Nobody will trade this",
+        "Other",
+        nanos(SAMPLE_MARKET_CLOSE),
+      );
+      if (!("Ok" in created)) throw new Error("creation failed");
+      ids.push(created.Ok);
+      await pic.advanceTime(60_000);
+    }
+    const worklist = await actor.get_markets_without_trades();
+    expect(worklist.map((m) => m.id)).toEqual(ids);
+
+    actor.setIdentity(admin);
+    await actor.archive_markets([ids[0]!], "Abandoned");
+    const remaining = await actor.get_markets_without_trades();
+    expect(remaining.map((m) => m.id)).toEqual([ids[1]]);
+  });
+
+  it("should hide poll results until voting and compare them at resolution", async () => {
+    const first = createIdentity("poll-first");
+    actor.setIdentity(first);
+    const voted = await actor.vote_poll(BigInt(1), { Yes: null });
+    if (!("Ok" in voted)) throw new Error("vote failed");
+    expect(voted.Ok.tally).toEqual([
+      { yes: BigInt(1), no: BigInt(0), unsure: BigInt(0) },
+    ]);
+    expect(await actor.vote_poll(BigInt(1), { No: null })).toEqual({
+      Err: "You already voted in this poll",
+    });
+
+    // Others only see the turnout until they vote
+    actor.setIdentity(createIdentity("poll-second"));
+    const [before] = await actor.get_poll(BigInt(1));
+    expect(before?.total_votes).toBe(BigInt(1));
+    expect(before?.tally).toEqual([]);
+    await actor.vote_poll(BigInt(1), { Unsure: null });
+
+    actor.setIdentity(admin);
+    expect(await actor.get_resolution_comparison(BigInt(1))).toEqual({
+      Err: "Market is not resolved",
+    });
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    const [after] = await actor.get_poll(BigInt(1));
+    expect(after?.closed).toBe(true);
+    expect(after?.tally).toHaveLength(1);
+
+    const comparison = await actor.get_resolution_comparison(BigInt(1));
+    if (!("Ok" in comparison)) throw new Error("comparison failed");
+    expect(comparison.Ok.outcome).toBe(true);
+    expect(comparison.Ok.crowd_lean).toEqual([true]);
+  });
+
+  it("should clamp the XP a single whale trade can earn", async () => {
+    expect(await actor.get_max_xp_per_trade()).toBe(BigInt(1000));
+
+    const whale = createIdentity("xp-whale");
+    await fund(whale, BigInt(100_000));
+    actor.setIdentity(whale);
+    await actor.buy_shares(BigInt(1), true, BigInt(100_000));
+    const [profile] = await actor.get_user_profile(whale.getPrincipal());
+    expect(profile?.profile.xp).toBe(BigInt(1000));
+    expect(await actor.set_max_xp_per_trade(BigInt(0))).toEqual({
+      Err: "Only admins can update the XP configuration",
+    });
+
+    actor.setIdentity(admin);
+    expect(await actor.set_max_xp_per_trade(BigInt(50))).toEqual({ Ok: null });
+    const other = createIdentity("xp-whale-2");
+    await fund(other, BigInt(100_000));
+    actor.setIdentity(other);
+    await actor.buy_shares(BigInt(2), true, BigInt(100_000));
+    const [capped] = await actor.get_user_profile(other.getPrincipal());
+    expect(capped?.profile.xp).toBe(BigInt(50));
+  });
+
+  it("should stake, pay and slash registered resolvers", async () => {
+    const resolver = createIdentity("pro-resolver");
+    actor.setIdentity(resolver);
+    expect(
+      await actor.register_resolver(BigInt(1000), BigInt(10), ["Sports"]),
+    ).toEqual({ Err: "Insufficient balance" });
+
+    await fund(resolver, BigInt(1000));
+    const registered = await actor.register_resolver(
+      BigInt(1000),
+      BigInt(10),
+      ["Sports"],
+    );
+    expect(registered).toHaveProperty("Ok");
+    expect(await actor.list_resolvers(["Crypto"])).toEqual([]);
+    expect(await actor.list_resolvers(["sports"])).toHaveLength(1);
+
+    const creator = createIdentity("resolver-client");
+    actor.setIdentity(creator);
+    const options = {
+      trading_schedule: [],
+      market_kind: [],
+      resolver: [resolver.getPrincipal()],
+      resolution_criteria: [],
+      resolution_datetime: [],
+      primary_source_url: [],
+      numeric_target: [],
+      co_owners: [],
+    } as Parameters<typeof actor.create_market_with_options>[4];
+    const closeDate = SAMPLE_MARKETS_CLOSED + 24 * 60 * 60;
+    const create = (category: string) =>
+      actor.create_market_with_options(
+        "Will the away team win the derby?",
+        "Resolves YES if the away team wins.",
+        category,
+        nanos(closeDate),
+        options,
+      );
+    expect(await create("Crypto")).toEqual({
+      Err: "The chosen resolver does not cover this category",
+    });
+    const created = await create("Sports");
+    if (!("Ok" in created)) throw new Error("creation failed");
+    const marketId = created.Ok;
+
+    actor.setIdentity(resolver);
+    expect(await actor.withdraw_resolver_stake()).toEqual({
+      Err: "Resolver has pending assignments",
+    });
+
+    const trader = createIdentity("resolver-disputer");
+    await fund(trader, BigInt(500));
+    actor.setIdentity(admin);
+    await actor.approve_markets([marketId]);
+    actor.setIdentity(trader);
+    await actor.buy_shares(marketId, false, BigInt(500));
+    await passCloseDate(closeDate);
+    actor.setIdentity(resolver);
+    await actor.resolve_market(marketId, { Yes: null });
+    const [assignment] = await actor.get_resolver_assignment(marketId);
+    expect(assignment?.status).toEqual({ Pending: null });
+    // The bond and the owed fee survive an upgrade
+    await pic.upgradeCanister({ canisterId, wasm: WASM_PATH });
+    expect(await actor.get_resolver_assignment(marketId)).toEqual([
+      assignment,
+    ]);
+    expect(await actor.list_resolvers(["sports"])).toHaveLength(1);
+
+    actor.setIdentity(trader);
+    expect(
+      await actor.dispute_resolution(marketId, "The away team lost"),
+    ).toEqual({ Ok: null });
+    actor.setIdentity(admin);
+    expect(await actor.settle_resolution_dispute(marketId, true)).toEqual({
+      Ok: null,
+    });
+    expect(await actor.get_balance(trader.getPrincipal())).toBe(BigInt(250));
+
+    const [profile] = await actor.get_resolver(resolver.getPrincipal());
+    expect(profile?.stake).toBe(BigInt(500));
+    expect(profile?.disputes_upheld).toBe(BigInt(1));
+    expect(await actor.list_resolvers([])).toEqual([]);
+
+    actor.setIdentity(resolver);
+    expect(await actor.withdraw_resolver_stake()).toEqual({ Ok: BigInt(500) });
+  });
+
+  it("should cache a keyword sentiment estimate while the LLM is off", async () => {
+    actor.setIdentity(createIdentity("sentiment-bull"));
+    await actor.add_comment(BigInt(2), "Bullish, this is going up");
+    actor.setIdentity(createIdentity("sentiment-bear"));
+    await actor.add_comment(BigInt(2), "No way, I doubt it");
+    expect(await actor.analyze_comment_sentiment(BigInt(2))).toEqual({
+      Err: "Only admins can analyze comment sentiment",
+    });
+    expect(await actor.get_comment_sentiment(BigInt(2))).toEqual([]);
+
+    actor.setIdentity(admin);
+    const analyzed = await actor.analyze_comment_sentiment(BigInt(2));
+    if (!("Ok" in analyzed)) throw new Error("analysis failed");
+    expect(analyzed.Ok.bullish_pct).toBe(BigInt(50));
+    expect(await actor.get_comment_sentiment(BigInt(2))).toEqual([analyzed.Ok]);
+
+    // Served from the cache until the TTL runs out
+    await actor.add_comment(BigInt(2), "Buy buy buy");
+    expect(await actor.analyze_comment_sentiment(BigInt(2))).toEqual(analyzed);
+    await pic.advanceTime(60 * 60 * 1000);
+    expect(await actor.get_comment_sentiment(BigInt(2))).toEqual([]);
+  });
+
+  it("should attach referenced markets to comment pages", async () => {
+    actor.setIdentity(createIdentity("market-linker"));
+    const posted = await actor.add_comment(
+      BigInt(1),
+      "Compare with #2 and market 3, not #999 or #1",
+    );
+    if (!("Ok" in posted)) throw new Error("comment failed");
+
+    const page = await actor.get_market_comments_page(
+      BigInt(1),
+      { Newest: null },
+      BigInt(0),
+      BigInt(10),
+    );
+    expect(page.comments[0]?.comment.market_refs).toEqual([
+      BigInt(2),
+      BigInt(3),
+    ]);
+    expect(page.referenced_markets.map((m) => m.id)).toEqual([
+      BigInt(2),
+      BigInt(3),
+    ]);
+
+    // Edits only count newly added references
+    await actor.edit_comment(posted.Ok, "Compare with #2 and #3 only");
+    const [stats] = await actor.get_market_stats(BigInt(2));
+    expect(stats?.discussion_mentions).toBe(BigInt(1));
+    const [referrer] = await actor.get_market_stats(BigInt(1));
+    expect(referrer?.comment_count).toBe(BigInt(1));
+    expect(referrer?.discussion_mentions).toBe(BigInt(0));
+  });
+
+  it("should restrict new markets to configured category keys", async () => {
+    const sports = {
+      key: "Sports",
+      display_name: "Sports",
+      icon: "⚽",
+      color: "#22AA55",
+    };
+    actor.setIdentity(createIdentity("category-editor"));
+    expect(await actor.upsert_category(sports)).toEqual({
+      Err: "Only admins can manage categories",
+    });
+
+    actor.setIdentity(admin);
+    expect(await actor.upsert_category({ ...sports, color: "green" })).toEqual({
+      Err: "Colors must be given as #RRGGBB",
+    });
+    expect(await actor.upsert_category(sports)).toEqual({ Ok: null });
+    expect(await actor.get_categories_meta()).toEqual([sports]);
+
+    const closeDate = nanos(Math.floor(Date.now() / 1000) + 24 * 60 * 60);
+    expect(
+      await actor.create_market("Derby", "Who wins?", "Weather", closeDate),
+    ).toEqual({ Err: "Unknown category: Weather" });
+    expect(
+      await actor.create_market("Derby", "Who wins?", "Sports", closeDate),
+    ).toHaveProperty("Ok");
+
+    // Removing the last category reopens the taxonomy
+    expect(await actor.remove_category("Sports")).toEqual({ Ok: null });
+    expect(await actor.get_categories_meta()).toEqual([]);
+    expect(
+      await actor.create_market("Rain", "Will it rain?", "Weather", closeDate),
+    ).toHaveProperty("Ok");
+  });
+
+  it("should enforce trading limits and self-exclusion", async () => {
+    const trader = createIdentity("limited-trader");
+    await fund(trader, BigInt(300));
+    actor.setIdentity(trader);
+    const tight = {
+      daily_limit: [BigInt(150)] as [bigint],
+      weekly_limit: [] as [],
+    };
+    const set = await actor.set_trading_limits(tight);
+    if (!("Ok" in set)) throw new Error("limits rejected");
+    expect(set.Ok.limits).toEqual(tight);
+
+    expect(await actor.buy_shares(BigInt(1), true, BigInt(100))).toHaveProperty(
+      "Ok",
+    );
+    const blocked = await actor.buy_shares(BigInt(1), true, BigInt(100));
+    if (!("Err" in blocked)) throw new Error("trade should be capped");
+    expect(blocked.Err).toMatch(/^DailyLimitExceeded: limit 150, resets at \d+/);
+
+    // Raising the cap waits out the cool-down
+    const loosened = await actor.set_trading_limits({
+      daily_limit: [BigInt(1000)],
+      weekly_limit: [],
+    });
+    if (!("Ok" in loosened)) throw new Error("limits rejected");
+    expect(loosened.Ok.limits).toEqual(tight);
+    expect(loosened.Ok.pending).toEqual([
+      { daily_limit: [BigInt(1000)], weekly_limit: [] },
+    ]);
+    expect(await actor.buy_shares(BigInt(1), true, BigInt(100))).toHaveProperty(
+      "Err",
+    );
+
+    const excluded = await actor.self_exclude(BigInt(86_400));
+    if (!("Ok" in excluded)) throw new Error("exclusion rejected");
+    expect(await actor.buy_shares(BigInt(2), true, BigInt(10))).toEqual({
+      Err: `SelfExcluded: until ${excluded.Ok}`,
+    });
+    expect((await actor.get_trading_limits()).spent_last_day).toBe(BigInt(100));
+
+    actor.setIdentity(admin);
+    expect(await actor.repair_spend_counters()).toEqual({ Ok: BigInt(1) });
+  });
+
+  it("should list recent resolutions newest first", async () => {
+    const orders: [string, bigint, boolean, bigint][] = [
+      ["yes-winner", BigInt(1), true, BigInt(100)],
+      ["no-loser", BigInt(1), false, BigInt(100)],
+      ["no-winner", BigInt(2), false, BigInt(50)],
+    ];
+    for (const [name, marketId, isYes, amount] of orders) {
+      const trader = createIdentity(name);
+      await fund(trader, amount);
+      actor.setIdentity(trader);
+      await actor.buy_shares(marketId, isYes, amount);
+    }
+
+    actor.setIdentity(admin);
+    expect(await actor.get_recent_resolutions(BigInt(10))).toEqual([]);
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    await pic.advanceTime(1000);
+    await actor.resolve_market(BigInt(2), { No: null });
+
+    const feed = await actor.get_recent_resolutions(BigInt(10));
+    expect(feed.map((view) => view.market_id)).toEqual([BigInt(2), BigInt(1)]);
+    expect(feed[0]?.resolved_outcome).toEqual({ No: null });
+    expect(feed[0]?.winners).toBe(BigInt(1));
+    expect(feed[1]?.winners).toBe(BigInt(1));
+    expect(feed[1]?.resolved_at).toBeLessThan(feed[0]!.resolved_at);
+    expect(await actor.get_recent_resolutions(BigInt(1))).toHaveLength(1);
+  });
+
+  it("should write a daily digest for active categories", async () => {
+    const trader = createIdentity("digest-trader");
+    await fund(trader, BigInt(350));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await actor.buy_shares(BigInt(1), true, BigInt(200));
+    await actor.buy_shares(BigInt(2), false, BigInt(50));
+
+    actor.setIdentity(admin);
+    expect(await actor.trigger_task("category_digests")).toEqual({ Ok: null });
+
+    // Technology saw a single trade, below the activity bar
+    expect(await actor.get_latest_digest("Technology")).toEqual([]);
+    const [latest] = await actor.get_latest_digest("Cryptocurrency");
+    expect(latest?.digest.text).toContain("#1");
+    expect(latest?.digest.market_ids).toEqual([BigInt(1)]);
+    expect(latest?.markets.map((m) => m.id)).toEqual([BigInt(1)]);
+
+    // One digest per category per day
+    await actor.trigger_task("category_digests");
+    expect(
+      await actor.get_digest_history("Cryptocurrency", BigInt(10)),
+    ).toHaveLength(1);
+  });
+
+  it("should cap the number of active markets", async () => {
+    const closeDate = nanos(Math.floor(Date.now() / 1000) + 24 * 60 * 60);
+    expect(await actor.set_max_active_markets(BigInt(3))).toEqual({ Ok: null });
+    expect(await actor.get_active_market_limit()).toEqual({
+      max_active_markets: BigInt(3),
+      active_markets: BigInt(3),
+    });
+
+    actor.setIdentity(createIdentity("capped-creator"));
+    expect(
+      await actor.create_market("Capped", "Blocked?", "Other", closeDate),
+    ).toEqual({
+      Err: "The platform is at its limit of 3 active markets; try again once one resolves",
+    });
+
+    // Admins can still create, but approval counts against the cap
+    actor.setIdentity(admin);
+    const pending = await actor.create_market(
+      "Admin market",
+      "Queued for approval",
+      "Other",
+      closeDate,
+    );
+    if (!("Ok" in pending)) throw new Error("admin creation failed");
+    expect(await actor.approve_market(pending.Ok)).toHaveProperty("Err");
+
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    actor.setIdentity(createIdentity("capped-creator"));
+    expect(
+      await actor.create_market("Capped", "Unblocked", "Other", closeDate),
+    ).toHaveProperty("Ok");
+    actor.setIdentity(admin);
+    expect(await actor.approve_market(pending.Ok)).toHaveProperty("Ok");
+  });
+
+  it("should report the fees a trader has paid", async () => {
+    const trader = createIdentity("fee-payer");
+    await fund(trader, BigInt(1500));
+    actor.setIdentity(trader);
+    const first = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    const second = await actor.buy_shares(BigInt(2), false, BigInt(500));
+    if (!("Ok" in first) || !("Ok" in second)) throw new Error("trade failed");
+
+    const total = first.Ok.fee + second.Ok.fee;
+    expect(total).toBeGreaterThan(BigInt(0));
+    expect(await actor.get_fees_paid(trader.getPrincipal())).toBe(total);
+    expect(
+      await actor.get_fees_paid(createIdentity("no-trades").getPrincipal()),
+    ).toBe(BigInt(0));
+
+    const portfolio = await actor.get_my_portfolio();
+    const market1 = portfolio.find((v) => v.position.market_id === BigInt(1));
+    expect(market1?.fees_paid).toBe(first.Ok.fee);
+  });
+
+  it("should share market management with co-owners", async () => {
+    const creator = createIdentity("team-lead");
+    const partner = createIdentity("team-partner");
+    const closeDate = nanos(SAMPLE_MARKETS_CLOSED);
+    actor.setIdentity(creator);
+    const created = await actor.create_market_with_options(
+      "Team market",
+      "Built together",
+      "Other",
+      closeDate,
+      {
+        trading_schedule: [],
+        market_kind: [],
+        resolver: [],
+        resolution_criteria: [],
+        resolution_datetime: [],
+        primary_source_url: [],
+        numeric_target: [],
+        co_owners: [[partner.getPrincipal()]],
+      },
+    );
+    if (!("Ok" in created)) throw new Error("creation failed");
+    const id = created.Ok;
+
+    // Co-owners edit while pending and manage the FAQ, but cannot resolve
+    actor.setIdentity(partner);
+    expect(
+      await actor.update_market(id, "Team market", "Edited", "Other", closeDate),
+    ).toHaveProperty("Ok");
+    expect(
+      await actor.add_market_faq(id, "Source?", "The team blog."),
+    ).toHaveProperty("Ok");
+    expect(await actor.delegate_resolution(id, [])).toEqual({
+      Err: "Only the market creator can change its owners",
+    });
+
+    actor.setIdentity(admin);
+    await actor.approve_market(id);
+    await passCloseDate();
+    actor.setIdentity(partner);
+    expect(await actor.resolve_market(id, { Yes: null })).toHaveProperty("Err");
+
+    actor.setIdentity(creator);
+    expect(
+      await actor.delegate_resolution(id, [partner.getPrincipal()]),
+    ).toHaveProperty("Ok");
+    actor.setIdentity(partner);
+    expect(await actor.resolve_market(id, { Yes: null })).toHaveProperty("Ok");
+
+    const [detail] = await actor.get_market_detail(id);
+    expect(detail?.market.co_owners).toEqual([partner.getPrincipal()]);
+    expect(detail?.ownership_history.map((e) => Object.keys(e.kind)[0])).toEqual(
+      ["CoOwnerAdded", "ResolutionDelegated"],
+    );
+  });
+
+  it("should reject near-duplicate market titles", async () => {
+    const closeDate = nanos(Math.floor(Date.now() / 1000) + 24 * 60 * 60);
+    const similar = await actor.find_similar_markets(
+      "Bitcoin reaches $150,000 by the end of 2025",
+    );
+    expect(similar[0]?.[0]).toBe(BigInt(1));
+    expect(similar[0]?.[1]).toBeGreaterThan(0.5);
+
+    actor.setIdentity(createIdentity("copycat"));
+    expect(
+      await actor.create_market(
+        "will bitcoin reach 150,000 by the end of 2025",
+        "Same question again",
+        "Cryptocurrency",
+        closeDate,
+      ),
+    ).toEqual({ Err: "A very similar market already exists: #1" });
+
+    // Admins may bypass, and a threshold of 0 turns the check off
+    actor.setIdentity(admin);
+    expect(await actor.set_similarity_threshold_bps(BigInt(0))).toEqual({
+      Ok: null,
+    });
+    actor.setIdentity(createIdentity("copycat"));
+    expect(
+      await actor.create_market(
+        "will bitcoin reach 150,000 by the end of 2025",
+        "Same question again",
+        "Cryptocurrency",
+        closeDate,
+      ),
+    ).toHaveProperty("Ok");
+  });
+
+  it("should chart simulated buys along the pricing curve", async () => {
+    const [chart] = await actor.get_depth(BigInt(1), 10);
+    if (!chart) throw new Error("no depth chart");
+    expect(chart.base_depth).toBe(BigInt(1000));
+    expect(chart.yes_shares).toBe(BigInt(450));
+    expect(chart.yes_levels.length).toBeGreaterThan(0);
+    expect(chart.yes_levels.length).toBeLessThanOrEqual(10);
+    // Half of the YES curve depth (1000 + 450 shares)
+    expect(chart.yes_levels.at(-1)?.amount).toBe(BigInt(725));
+    expect(chart.yes_levels.at(-1)!.post_trade_yes_bps).toBeGreaterThan(
+      chart.yes_bps,
+    );
+
+    // The chart matches what a trade of that size actually gets
+    const level = chart.yes_levels.at(-1)!;
+    const checker = createIdentity("depth-checker");
+    await fund(checker, level.amount);
+    actor.setIdentity(checker);
+    const [own] = await actor.get_depth(BigInt(1), 10);
+    const trade = await actor.buy_shares(BigInt(1), true, level.amount);
+    if (!("Ok" in trade)) throw new Error("trade failed");
+    expect(trade.Ok.shares).toBe(own!.yes_levels.at(-1)!.shares);
+
+    const [capped] = await actor.get_depth(BigInt(1), 255);
+    expect(capped!.no_levels.length).toBeLessThanOrEqual(20);
+    expect(await actor.get_depth(BigInt(999), 10)).toEqual([]);
+  });
+
+  it("should record pool liquidity after each trade", async () => {
+    const before = await actor.get_liquidity_history(
+      BigInt(1),
+      BigInt(0),
+      BigInt(2) ** BigInt(64) - BigInt(1),
+    );
+    expect(before).toHaveLength(1);
+    expect(before[0]?.yes_liquidity).toBe(BigInt(4500));
+
+    const trader = createIdentity("liquidity-trader");
+    await fund(trader, BigInt(200));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    await pic.advanceTime(60_000);
+    await actor.buy_shares(BigInt(1), false, BigInt(100));
+
+    const all = await actor.get_liquidity_history(
+      BigInt(1),
+      BigInt(0),
+      BigInt(2) ** BigInt(64) - BigInt(1),
+    );
+    expect(all).toHaveLength(3);
+    expect(all[1]!.yes_liquidity).toBeGreaterThan(BigInt(4500));
+    expect(all[2]!.no_liquidity).toBeGreaterThan(all[1]!.no_liquidity);
+
+    // The window is inclusive on both ends
+    const last = await actor.get_liquidity_history(
+      BigInt(1),
+      all[2]!.timestamp,
+      all[2]!.timestamp,
+    );
+    expect(last).toEqual([all[2]]);
+  });
+
+  it("should store opaque per-user settings with limits", async () => {
+    actor.setIdentity(createIdentity("settings-user"));
+    expect(await actor.get_my_settings()).toEqual([]);
+
+    const prefs = new TextEncoder().encode('{"sort":"volume"}');
+    expect(await actor.set_settings(prefs)).toEqual({ Ok: null });
+    const [stored] = await actor.get_my_settings();
+    expect(Array.from(stored ?? [])).toEqual(Array.from(prefs));
+
+    expect(await actor.set_settings(new Uint8Array(4097))).toEqual({
+      Err: "Settings cannot exceed 4096 bytes",
+    });
+
+    // 60 saves per hour, the first one included
+    for (let i = 0; i < 59; i++) {
+      await actor.set_settings(prefs);
+    }
+    expect(await actor.set_settings(prefs)).toEqual({
+      Err: "Settings were saved too often; try again later",
+    });
+    await pic.advanceTime(60 * 60 * 1000);
+    expect(await actor.set_settings(new Uint8Array())).toEqual({ Ok: null });
+    expect(await actor.get_my_settings()).toEqual([]);
+  });
+
+  it("should refresh an insight after a large price move", async () => {
+    await actor.refresh_ai_insight(BigInt(1));
+    await pic.tick();
+    const [before] = await actor.get_ai_insight(BigInt(1));
+    expect(before?.generated_yes_bps).toEqual([BigInt(4500)]);
+
+    // A small trade stays under the default 1000 bps threshold
+    await actor.buy_shares(BigInt(1), true, BigInt(100));
+    const [unchanged] = await actor.get_ai_insight(BigInt(1));
+    expect(unchanged?.job_id).toEqual(before?.job_id);
+
+    await actor.buy_shares(BigInt(1), true, BigInt(3000));
+    await pic.tick();
+    const [after] = await actor.get_ai_insight(BigInt(1));
+    const [market] = await actor.get_market(BigInt(1));
+    if (!market) throw new Error("market missing");
+    const yesBps =
+      (market.yes_liquidity * BigInt(10000)) /
+      (market.yes_liquidity + market.no_liquidity);
+    expect(after?.job_id).not.toEqual(before?.job_id);
+    expect(after?.status).toEqual({ Ready: null });
+    expect(after?.generated_yes_bps).toEqual([yesBps]);
+
+    expect(await actor.set_insight_refresh_threshold_bps(BigInt(10001))).toEqual(
+      { Err: "The threshold cannot exceed 10000 bps" },
+    );
+    expect(await actor.set_insight_refresh_threshold_bps(BigInt(0))).toEqual({
+      Ok: null,
+    });
+    await actor.buy_shares(BigInt(1), false, BigInt(5000));
+    const [kept] = await actor.get_ai_insight(BigInt(1));
+    expect(kept?.job_id).toEqual(after?.job_id);
+  });
+
+  it("should describe the resolution timeline of closed markets", async () => {
+    const trader = createIdentity("timeline-trader");
+    await fund(trader, BigInt(1000));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(1), true, BigInt(500));
+    const [open] = await actor.get_market_detail(BigInt(1));
+    expect(open?.resolution_timeline).toEqual([]);
+
+    // A listed resolver's outcome sits in the dispute window first
+    const resolver = createIdentity("timeline-resolver");
+    await fund(resolver, BigInt(1000));
+    actor.setIdentity(resolver);
+    await actor.register_resolver(BigInt(1000), BigInt(10), ["Sports"]);
+    const closeDate = SAMPLE_MARKETS_CLOSED + 24 * 60 * 60;
+    const created = await actor.create_market_with_options(
+      "Will the home side keep a clean sheet?",
+      "Resolves YES if they concede no goals.",
+      "Sports",
+      nanos(closeDate),
+      {
+        trading_schedule: [],
+        market_kind: [],
+        resolver: [resolver.getPrincipal()],
+        resolution_criteria: [],
+        resolution_datetime: [],
+        primary_source_url: [],
+        numeric_target: [],
+        co_owners: [],
+      },
+    );
+    if (!("Ok" in created)) throw new Error("creation failed");
+    actor.setIdentity(admin);
+    await actor.approve_markets([created.Ok]);
+    actor.setIdentity(trader);
+    await actor.buy_shares(created.Ok, false, BigInt(500));
+    await passCloseDate(closeDate);
+    actor.setIdentity(resolver);
+    await actor.resolve_market(created.Ok, { Yes: null });
+
+    actor.setIdentity(trader);
+    const [pending] = await actor.get_market_detail(created.Ok);
+    const [window] = pending?.resolution_timeline ?? [];
+    expect(window?.phase).toEqual({ DisputeWindow: null });
+    expect(window?.dispute_window_remaining[0]).toBeGreaterThan(
+      BigInt(2 * 86400 - 60),
+    );
+    await actor.dispute_resolution(created.Ok, "They conceded twice");
+    const [disputed] = await actor.get_market_detail(created.Ok);
+    expect(disputed?.resolution_timeline[0]?.phase).toEqual({ Disputed: null });
+    expect(disputed?.resolution_timeline[0]?.dispute_window_remaining).toEqual(
+      [],
+    );
+
+    // Closed and waiting on a resolver, then final with winnings to claim
+    const [closed] = await actor.get_market_detail(BigInt(1));
+    const [awaiting] = closed?.resolution_timeline ?? [];
+    expect(awaiting?.phase).toEqual({ AwaitingResolution: null });
+    expect(awaiting?.resolution_deadline).toBe(
+      nanos(SAMPLE_MARKET_CLOSE + 7 * 24 * 60 * 60),
+    );
+    expect(awaiting?.resolved_at).toEqual([]);
+    expect(awaiting?.claimable).toBe(BigInt(0));
+    expect(awaiting?.evidence_submitted).toBe(false);
+
+    actor.setIdentity(admin);
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    actor.setIdentity(trader);
+    const [resolved] = await actor.get_market_detail(BigInt(1));
+    const [final] = resolved?.resolution_timeline ?? [];
+    expect(final?.phase).toEqual({ Final: null });
+    expect(final?.resolved_at).toHaveLength(1);
+    expect(final?.claimable).toBeGreaterThan(BigInt(0));
+
+    await actor.claim_winnings(BigInt(1));
+    const [claimed] = await actor.get_market_detail(BigInt(1));
+    expect(claimed?.resolution_timeline[0]?.claimable).toBe(BigInt(0));
+  });
+
+  it("should place batched orders in sequence", async () => {
+    const maker = createIdentity("batch-maker");
+    await fund(maker, BigInt(2200));
+    actor.setIdentity(maker);
+    const treasuryBefore = await actor.get_treasury_balance();
+    const results = await actor.buy_shares_batch([
+      [BigInt(1), true, BigInt(1000)],
+      [BigInt(999), true, BigInt(1000)],
+      [BigInt(1), true, BigInt(1000)],
+      [BigInt(2), false, BigInt(0)],
+    ]);
+    expect(results[1]).toEqual({ Err: "Market not found" });
+    expect(results[3]).toEqual({ Err: "Amount must be greater than 0" });
+    const [first, , second] = results;
+    if (!("Ok" in first) || !("Ok" in second)) throw new Error("order failed");
+    // The second order pays the price the first one moved to
+    expect(second.Ok.price).toBeGreaterThan(first.Ok.price);
+
+    const [profile] = await actor.get_user_profile(maker.getPrincipal());
+    expect(profile?.profile.total_trades).toBe(BigInt(2));
+    expect(await actor.get_fees_paid(maker.getPrincipal())).toBe(
+      first.Ok.fee + second.Ok.fee,
+    );
+    expect(await actor.get_treasury_balance()).toBe(
+      treasuryBefore + first.Ok.fee + first.Ok.dust + second.Ok.fee +
+        second.Ok.dust,
+    );
+
+    const oversized = await actor.buy_shares_batch(
+      Array.from({ length: 21 }, () => [BigInt(2), true, BigInt(10)]),
+    );
+    expect(oversized.filter((result) => "Ok" in result)).toHaveLength(20);
+    expect(oversized[20]).toEqual({ Err: "Batches are limited to 20 orders" });
+  });
+
+  it("should pay maker incentives from a capped pool", async () => {
+    const trader = createIdentity("incentive-trader");
+    await fund(trader, BigInt(17_000));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(2), true, BigInt(10000));
+    const config = {
+      rule: { MinoritySide: null },
+      rebate_bps: BigInt(5000),
+      pool_cap: BigInt(150),
+      daily_trader_cap: BigInt(60),
+    };
+    expect(await actor.enable_incentives(BigInt(1), config)).toEqual({
+      Err: "Only admins can manage incentive programs",
+    });
+
+    actor.setIdentity(admin);
+    expect(await actor.enable_incentives(BigInt(1), config)).toEqual({
+      Ok: null,
+    });
+    expect(await actor.fund_incentives(BigInt(1), BigInt(151))).toEqual({
+      Err: "The incentive pool can take at most 150 more",
+    });
+    const treasury = await actor.get_treasury_balance();
+    expect(await actor.fund_incentives(BigInt(1), BigInt(100))).toEqual({
+      Ok: null,
+    });
+    expect(await actor.get_treasury_balance()).toBe(treasury - BigInt(100));
+
+    actor.setIdentity(trader);
+    const [info] = await actor.get_incentives(BigInt(1));
+    expect(info?.pool).toBe(BigInt(100));
+    expect(info?.remaining_today).toBe(BigInt(60));
+
+    // Market 1 sits at 45% YES, so YES is the minority side
+    const minority = await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    const majority = await actor.buy_shares(BigInt(1), false, BigInt(1000));
+    const capped = await actor.buy_shares(BigInt(1), true, BigInt(5000));
+    if (!("Ok" in minority) || !("Ok" in majority) || !("Ok" in capped)) {
+      throw new Error("trade failed");
+    }
+    expect(minority.Ok.incentive_rebate).toEqual([minority.Ok.fee / BigInt(2)]);
+    expect(majority.Ok.incentive_rebate).toEqual([BigInt(0)]);
+    expect(capped.Ok.incentive_rebate).toEqual([
+      BigInt(60) - minority.Ok.fee / BigInt(2),
+    ]);
+    expect(await actor.get_balance(trader.getPrincipal())).toBe(BigInt(60));
+
+    const [spent] = await actor.get_incentives(BigInt(1));
+    expect(spent?.remaining_today).toBe(BigInt(0));
+    expect(spent?.paid_out).toBe(BigInt(60));
+    // The pool and the daily caps survive an upgrade
+    await pic.upgradeCanister({ canisterId, wasm: WASM_PATH });
+    expect(await actor.get_incentives(BigInt(1))).toEqual([spent]);
+
+    // Ending the program returns the rest of the pool to the treasury
+    actor.setIdentity(admin);
+    const beforeEnd = await actor.get_treasury_balance();
+    expect(await actor.end_incentives(BigInt(1))).toEqual({ Ok: null });
+    expect(await actor.get_treasury_balance()).toBe(beforeEnd + BigInt(40));
+    expect(await actor.get_incentives(BigInt(1))).toEqual([]);
+  });
+
+  it("should snapshot the platform configuration", async () => {
+    const defaults = await actor.get_config();
+    expect(defaults.fee_bps).toBe(BigInt(200));
+    expect(defaults.max_comments_per_market).toBe(BigInt(1000));
+    expect(defaults.similarity_threshold_bps).toBe(BigInt(8000));
+    expect(defaults.insight_refresh_threshold_bps).toBe(BigInt(1000));
+    expect(defaults.llm_enabled).toBe(false);
+    expect(defaults.llm_daily_calls).toBe(BigInt(200));
+    expect(defaults.access_mode).toEqual({ Open: null });
+
+    await actor.set_max_active_markets(BigInt(25));
+    await actor.set_treasury_floor(BigInt(500));
+    await actor.set_close_grace_seconds(BigInt(60));
+    const updated = await actor.get_config();
+    expect(updated.max_active_markets).toBe(BigInt(25));
+    expect(updated.treasury_floor).toBe(BigInt(500));
+    expect(updated.close_grace_seconds).toBe(BigInt(60));
+    expect(updated.flash).toEqual(await actor.get_flash_config());
+    expect(updated.xp).toEqual(await actor.get_xp_config());
+  });
+
+  it("should refund every position when a market resolves Invalid", async () => {
+    const yesTrader = createIdentity("invalid-yes");
+    const noTrader = createIdentity("invalid-no");
+    await fund(yesTrader, BigInt(1000));
+    await fund(noTrader, BigInt(500));
+    actor.setIdentity(yesTrader);
+    await actor.buy_shares(BigInt(1), true, BigInt(1000));
+    actor.setIdentity(noTrader);
+    await actor.buy_shares(BigInt(1), false, BigInt(500));
+
+    actor.setIdentity(admin);
+    expect(
+      await actor.preview_resolution(BigInt(1), { Invalid: null }),
+    ).toEqual([
+      [yesTrader.getPrincipal(), BigInt(979)],
+      [noTrader.getPrincipal(), BigInt(489)],
+    ]);
+    await passCloseDate();
+    const resolved = await actor.resolve_market(BigInt(1), { Invalid: null });
+    if (!("Ok" in resolved)) throw new Error("resolution failed");
+    expect(resolved.Ok.resolved_outcome).toEqual([{ Invalid: null }]);
+
+    // Refunds at cost on both sides, scaled to the 1469 the pool kept after
+    // fees and dust, but nobody is credited a prediction
+    for (const [trader, refund] of [
+      [yesTrader, BigInt(979)],
+      [noTrader, BigInt(489)],
+    ] as const) {
+      actor.setIdentity(trader);
+      expect(await actor.claim_winnings(BigInt(1))).toEqual({ Ok: refund });
+      const [profile] = await actor.get_user_profile(trader.getPrincipal());
+      expect(profile?.profile.successful_predictions).toBe(BigInt(0));
+    }
+
+    const [feed] = await actor.get_recent_resolutions(BigInt(1));
+    expect(feed?.resolved_outcome).toEqual({ Invalid: null });
+    expect(feed?.winners).toBe(BigInt(0));
+
+    // The seeded insight leaned YES; N/A neither confirms nor refutes it
+    const comparison = await actor.get_resolution_comparison(BigInt(1));
+    if (!("Ok" in comparison)) throw new Error("no comparison");
+    expect(comparison.Ok.ai_lean).toEqual([true]);
+    expect(comparison.Ok.ai_correct).toEqual([]);
+  });
+
+  it("should reward resolvers once the dispute window passes", async () => {
+    const creator = createIdentity("reward-creator");
+    const resolver = createIdentity("reward-resolver");
+    const me = resolver.getPrincipal();
+    actor.setIdentity(creator);
+    expect(await actor.set_resolution_reward_bps(BigInt(5000))).toEqual({
+      Err: "Only admins can set the resolution reward",
+    });
+
+    await fund(resolver, BigInt(1000));
+    actor.setIdentity(resolver);
+    await actor.register_resolver(BigInt(1000), BigInt(10), ["Meta"]);
+    const create = (title: string, listed: boolean) => {
+      actor.setIdentity(creator);
+      return actor.create_market_with_options(
+        title,
+        "Resolves YES if it does.",
+        "Meta",
+        nanos(SAMPLE_MARKET_CLOSE),
+        {
+          trading_schedule: [],
+          market_kind: [],
+          resolver: listed ? [me] : [],
+          resolution_criteria: [],
+          resolution_datetime: [],
+          primary_source_url: [],
+          numeric_target: [],
+          co_owners: [],
+        },
+      );
+    };
+    const listed = await create("Will the reward path pay?", true);
+    const unlisted = await create("Will the admin path pay?", false);
+    if (!("Ok" in listed) || !("Ok" in unlisted)) {
+      throw new Error("create failed");
+    }
+
+    actor.setIdentity(admin);
+    await actor.approve_markets([listed.Ok, unlisted.Ok]);
+    expect(await actor.set_resolution_reward_bps(BigInt(10_000))).toEqual({
+      Ok: null,
+    });
+    const trader = createIdentity("reward-trader");
+    await fund(trader, BigInt(20_000));
+    actor.setIdentity(trader);
+    const trade = await actor.buy_shares(listed.Ok, true, BigInt(10000));
+    if (!("Ok" in trade)) throw new Error("trade failed");
+    await actor.buy_shares(unlisted.Ok, true, BigInt(10000));
+
+    // Markets without a listed resolver have no dispute path, so no reward
+    await passCloseDate();
+    actor.setIdentity(admin);
+    await actor.resolve_market(unlisted.Ok, { Yes: null });
+    actor.setIdentity(resolver);
+    await actor.resolve_market(listed.Ok, { Yes: null });
+    actor.setIdentity(admin);
+    await actor.trigger_task("resolution_rewards");
+    expect(await actor.get_resolution_rewards_earned(me)).toBe(BigInt(0));
+
+    // The reward tops the resolver's fee up to the market's fees, no more
+    await pic.advanceTime(2 * 24 * 60 * 60 * 1000 + 60_000);
+    await pic.tick();
+    await actor.trigger_task("resolver_fees");
+    await actor.trigger_task("resolution_rewards");
+    const reward = trade.Ok.fee - BigInt(10);
+    expect(await actor.get_resolution_rewards_earned(me)).toBe(reward);
+    expect(await actor.get_balance(me)).toBe(trade.Ok.fee);
+
+    // Paid once only
+    await actor.trigger_task("resolution_rewards");
+    expect(await actor.get_balance(me)).toBe(trade.Ok.fee);
+  });
+
+  it("should attach chart references and allow-listed links", async () => {
+    const now = BigInt(await pic.getTime()) * BigInt(1_000_000);
+    const seeded = BigInt(1737273600) * BigInt(1_000_000_000);
+    const chart = {
+      PriceChartRef: {
+        market_id: BigInt(2),
+        from_ts: seeded,
+        to_ts: now,
+      },
+    };
+    const posted = await actor.add_comment_with_attachment(
+      BigInt(1),
+      "Same shape as this one",
+      chart,
+    );
+    if (!("Ok" in posted)) throw new Error("comment failed");
+    const comments = await actor.get_market_comments(BigInt(1));
+    const comment = comments.find((c) => c.id === posted.Ok);
+    expect(comment?.attachment).toEqual([chart]);
+
+    const chartOf = (market_id: bigint, from_ts: bigint, to_ts: bigint) => ({
+      PriceChartRef: { market_id, from_ts, to_ts },
+    });
+    expect(
+      await actor.add_comment_with_attachment(
+        BigInt(1),
+        "Missing",
+        chartOf(BigInt(999), BigInt(0), now),
+      ),
+    ).toEqual({ Err: "Charted market not found" });
+    expect(
+      await actor.add_comment_with_attachment(
+        BigInt(1),
+        "Future",
+        chartOf(BigInt(2), seeded, now + BigInt(60e9)),
+      ),
+    ).toEqual({
+      Err: "Chart range must run forward between the market's creation and now",
+    });
+
+    const link = (url: string) => ({ ExternalLink: { url } });
+    expect(
+      await actor.add_comment_with_attachment(
+        BigInt(1),
+        "Source",
+        link("https://www.coindesk.com/markets"),
+      ),
+    ).toEqual({ Err: "Links to www.coindesk.com are not allowed" });
+
+    expect(await actor.set_link_domains(["CoinDesk.com"])).toEqual({
+      Ok: null,
+    });
+    expect(
+      await actor.add_comment_with_attachment(
+        BigInt(1),
+        "Source",
+        link("https://www.coindesk.com/markets"),
+      ),
+    ).toHaveProperty("Ok");
+    for (const url of [
+      "http://coindesk.com/",
+      "https://coindesk.com@evil.example/",
+      "https://notcoindesk.com/",
+    ]) {
+      expect(
+        await actor.add_comment_with_attachment(BigInt(1), "Bad", link(url)),
+      ).toHaveProperty("Err");
+    }
+
+    const user = createIdentity("link-admin-wannabe");
+    actor.setIdentity(user);
+    expect(await actor.set_link_domains([])).toEqual({
+      Err: "Only admins can manage link domains",
+    });
+  });
+
+  it("should split unique traders by side", async () => {
+    expect(await actor.get_participation_split(BigInt(999))).toEqual([]);
+    expect(await actor.get_participation_split(BigInt(3))).toEqual([
+      { yes_traders: BigInt(0), no_traders: BigInt(0), both: BigInt(0) },
+    ]);
+
+    const bull = createIdentity("split-bull");
+    const hedger = createIdentity("split-hedger");
+    await fund(bull, BigInt(200));
+    await fund(hedger, BigInt(200));
+    actor.setIdentity(bull);
+    await actor.buy_shares(BigInt(3), true, BigInt(100));
+    await actor.buy_shares(BigInt(3), true, BigInt(100));
+    actor.setIdentity(hedger);
+    await actor.buy_shares(BigInt(3), true, BigInt(100));
+    await actor.buy_shares(BigInt(3), false, BigInt(100));
+
+    expect(await actor.get_participation_split(BigInt(3))).toEqual([
+      { yes_traders: BigInt(2), no_traders: BigInt(1), both: BigInt(1) },
+    ]);
+  });
+
+  it("should keep market cards in step with trades and comments", async () => {
+    const cards = await actor.get_market_cards(BigInt(0), BigInt(10));
+    expect(cards.map((c) => c.market_id)).toEqual([
+      BigInt(1),
+      BigInt(2),
+      BigInt(3),
+    ]);
+    expect(cards[0]?.yes_bps).toBe(BigInt(4500));
+
+    const trader = createIdentity("card-trader");
+    await fund(trader, BigInt(500));
+    actor.setIdentity(trader);
+    await actor.buy_shares(BigInt(2), true, BigInt(500));
+    await actor.add_comment(BigInt(2), "Card should count this");
+
+    const [card] = await actor.get_market_cards(BigInt(1), BigInt(1));
+    expect(card?.market_id).toBe(BigInt(2));
+    expect(card?.participation.yes_traders).toBe(BigInt(1));
+    expect(card?.comments).toBe(BigInt(1));
+    expect(card?.trending_score).toBeGreaterThan(BigInt(0));
+
+    const trending = await actor.get_trending_market_cards(BigInt(5));
+    expect(trending.map((c) => c.market_id)).toEqual([BigInt(2)]);
+    const found = await actor.search_market_cards("CRYPTO", BigInt(5));
+    expect(found.map((c) => c.market_id)).toContain(BigInt(1));
+
+    expect(await actor.rebuild_market_cards()).toEqual({
+      Err: "Only admins can rebuild market cards",
+    });
+    actor.setIdentity(admin);
+    // Incremental updates match a rebuild from scratch
+    expect(await actor.get_stale_market_cards()).toEqual({ Ok: [] });
+    expect(await actor.rebuild_market_cards()).toEqual({ Ok: BigInt(3) });
+    expect(await actor.get_stale_market_cards()).toEqual({ Ok: [] });
+  });
+
+  it("should cap the liquidity floor at a new market's pools", async () => {
+    expect(await actor.get_min_market_liquidity()).toBe(BigInt(0));
+    expect(await actor.set_min_market_liquidity(BigInt(5001))).toEqual({
+      Err: "The floor cannot exceed the 5000 a new market starts with",
+    });
+    expect(await actor.set_min_market_liquidity(BigInt(5000))).toEqual({
+      Ok: null,
+    });
+    expect(await actor.get_min_market_liquidity()).toBe(BigInt(5000));
+    expect((await actor.get_config()).min_market_liquidity).toBe(BigInt(5000));
+
+    actor.setIdentity(createIdentity("floor-user"));
+    expect(await actor.set_min_market_liquidity(BigInt(100))).toEqual({
+      Err: "Only admins can set the minimum market liquidity",
+    });
+  });
+
+  it("should refuse sales that take a pool under the floor", async () => {
+    const trader = createIdentity("floor-seller");
+    await fund(trader, BigInt(1000));
+    actor.setIdentity(trader);
+    const bought = await actor.buy_shares(BigInt(3), true, BigInt(1000));
+    if (!("Ok" in bought)) throw new Error("buy failed");
+    const [market] = await actor.get_market(BigInt(3));
+
+    // A floor at the pool's current level leaves no room to sell
+    actor.setIdentity(admin);
+    await actor.set_min_market_liquidity(market!.yes_liquidity);
+    actor.setIdentity(trader);
+    expect(
+      await actor.close_position(BigInt(3), true, 5000, BigInt(0)),
+    ).toEqual({ Err: "Would breach minimum market liquidity" });
+    expect(await actor.get_market(BigInt(3))).toEqual([market]);
+
+    // Selling back only returns what the buy put in on top of the 3000
+    actor.setIdentity(admin);
+    await actor.set_min_market_liquidity(BigInt(3000));
+    actor.setIdentity(trader);
+    const sold = await actor.close_position(
+      BigInt(3),
+      true,
+      10000,
+      BigInt(0),
+    );
+    if (!("Ok" in sold)) throw new Error(sold.Err);
+    const [after] = await actor.get_market(BigInt(3));
+    expect(after!.yes_liquidity).toBeGreaterThanOrEqual(BigInt(3000));
+  });
+
+  it("should call resolution hooks and disable failing ones", async () => {
+    const missing = Principal.fromText("rdmx6-jaaaa-aaaaa-aaadq-cai");
+    const good = await actor.register_canister_hook(
+      BigInt(1),
+      canisterId,
+      "get_markets",
+    );
+    const bad = await actor.register_canister_hook(
+      BigInt(1),
+      missing,
+      "on_resolved",
+    );
+    expect(good).toHaveProperty("Ok");
+    expect(bad).toHaveProperty("Ok");
+    expect(
+      await actor.register_canister_hook(BigInt(1), missing, "on resolved"),
+    ).toHaveProperty("Err");
+
+    await passCloseDate();
+    await actor.resolve_market(BigInt(1), { Yes: null });
+    expect(
+      (await actor.get_canister_hooks(BigInt(1))).every(
+        (h) => h.pending.length === 1,
+      ),
+    ).toBe(true);
+
+    for (let run = 0; run < 3; run++) {
+      await actor.trigger_task("canister_hooks");
+      await pic.tick(5);
+    }
+
+    const [delivered, failed] = await actor.get_canister_hooks(BigInt(1));
+    expect(delivered?.delivered_at).toHaveLength(1);
+    expect(delivered?.pending).toEqual([]);
+    expect(failed?.enabled).toBe(false);
+    expect(failed?.consecutive_failures).toBe(3);
+    expect(failed?.last_error[0]).toContain("Hook call failed");
+
+    expect(
+      await actor.register_canister_hook(BigInt(1), missing, "later"),
+    ).toEqual({ Err: "Market is already resolved" });
+  });
+
+  it("should track daily activity streaks", async () => {
+    const regular = createIdentity("streak-regular");
+    const day = 24 * 60 * 60 * 1000;
+    expect(await actor.get_activity_streak(regular.getPrincipal())).toBe(
+      BigInt(0),
+    );
+
+    await fund(regular, BigInt(60));
+    actor.setIdentity(regular);
+    await actor.add_comment(BigInt(1), "Day one");
+    for (let i = 1; i < 7; i++) {
+      await pic.advanceTime(day);
+      await actor.buy_shares(BigInt(1), true, BigInt(10));
+    }
+    expect(await actor.get_activity_streak(regular.getPrincipal())).toBe(
+      BigInt(7),
+    );
+    const [ranked] = await actor.get_user_profile(regular.getPrincipal());
+    expect(ranked?.profile.badges).toContain("Daily Regular");
+
+    // A quiet day so far keeps the streak; a whole missed day ends it
+    await pic.advanceTime(day);
+    expect(await actor.get_activity_streak(regular.getPrincipal())).toBe(
+      BigInt(7),
+    );
+    await pic.advanceTime(day);
+    expect(await actor.get_activity_streak(regular.getPrincipal())).toBe(
+      BigInt(0),
+    );
+  });
+
+  it("should quote odds in every format and read them back", async () => {
+    expect(await actor.get_odds(BigInt(2), { American: null })).toEqual({
+      Ok: {
+        market_id: BigInt(2),
+        yes: { American: -150 },
+        no: { American: 150 },
+        yes_display: "-150",
+        no_display: "+150",
+      },
+    });
+    const decimal = await actor.get_odds(BigInt(1), { Decimal: null });
+    expect("Ok" in decimal && decimal.Ok.yes_display).toBe("2.22");
+    const fractional = await actor.get_odds(BigInt(1), { Fractional: null });
+    expect("Ok" in fractional && fractional.Ok.yes).toEqual({
+      Fractional: { num: BigInt(61), den: BigInt(50) },
+    });
+    expect(await actor.get_odds(BigInt(999), { Probability: null })).toEqual({
+      Err: "Market not found",
+    });
+
+    expect(await actor.probability_from_odds({ American: 150 })).toEqual({
+      Ok: BigInt(4000),
+    });
+    expect(await actor.probability_from_odds({ American: 50 })).toEqual({
+      Err: "American odds are at least +100 or at most -100",
+    });
+  });
+
+  it("should weigh XP by time left using nanosecond canister time", async () => {
+    // Market 1 leans NO, so buying NO earns no contrarian bonus
+    const early = await actor.buy_shares(BigInt(1), false, BigInt(100));
+    if (!("Ok" in early)) throw new Error("early trade failed");
+    expect(early.Ok.xp_multiplier_bps).toBe(BigInt(16_174));
+
+    // One second before close the multiplier has decayed to the late end
+    await pic.setTime((SAMPLE_MARKET_CLOSE - 1) * 1000);
+    await pic.tick();
+    const late = await actor.buy_shares(BigInt(1), false, BigInt(100));
+    if (!("Ok" in late)) throw new Error("late trade failed");
+    expect(late.Ok.xp_multiplier_bps).toBe(BigInt(10_001));
+
+    await pic.setTime(SAMPLE_MARKET_CLOSE * 1000);
+    await pic.tick();
+    expect(await actor.buy_shares(BigInt(1), false, BigInt(100))).toEqual({
+      Err: "Market is closed",
+    });
+  });
+
+  it("should flag traders dealing only on their own markets", async () => {
+    const dealer = createIdentity("self-dealer");
+    actor.setIdentity(dealer);
+    const created = await actor.create_market(
+      "Will the self-dealt market move?",
+      "Resolves YES if it moves.",
+      "Meta",
+      nanos(SAMPLE_MARKET_CLOSE),
+    );
+    if (!("Ok" in created)) throw new Error("create failed");
+    actor.setIdentity(admin);
+    await actor.approve_market(created.Ok);
+
+    await fund(dealer, BigInt(300));
+    actor.setIdentity(dealer);
+    const tradeIds: bigint[] = [];
+    for (const isYes of [true, false, true]) {
+      const trade = await actor.buy_shares(created.Ok, isYes, BigInt(100));
+      if ("Ok" in trade) tradeIds.push(trade.Ok.id);
+    }
+    expect(await actor.get_suspicious_activity(BigInt(7))).toEqual({
+      Err: "Only admins can review suspicious activity",
+    });
+
+    actor.setIdentity(admin);
+    await actor.trigger_task("surveillance");
+    const report = await actor.get_suspicious_activity(BigInt(7));
+    if (!("Ok" in report)) throw new Error("report failed");
+    const flag = report.Ok.find((f) => "SelfMarketTrading" in f.kind);
+    expect(flag?.principals).toEqual([dealer.getPrincipal()]);
+    expect(flag?.market_id).toEqual([created.Ok]);
+    expect(flag?.evidence).toEqual(tradeIds);
+    expect(flag?.status).toEqual({ Open: null });
+
+    // A review survives the next run finding the same pattern
+    await actor.review_suspicious_flag(flag!.id, { FalsePositive: null });
+    await actor.trigger_task("surveillance");
+    const rerun = await actor.get_suspicious_activity(BigInt(7));
+    if (!("Ok" in rerun)) throw new Error("report failed");
+    expect(rerun.Ok.find((f) => f.id === flag!.id)?.status).toEqual({
+      FalsePositive: null,
+    });
+  });
+
+  it("should filter markets by volume range, busiest first", async () => {
+    const range = async (min: number, max?: number) => {
+      const result = await actor.get_markets_by_volume_range(
+        BigInt(min),
+        max === undefined ? [] : [BigInt(max)],
+      );
+      return "Ok" in result ? result.Ok.map((m) => m.id) : result.Err;
+    };
+
+    expect(await range(1000)).toEqual([BigInt(1), BigInt(2), BigInt(3)]);
+    expect(await range(1200, 1800)).toEqual([BigInt(2), BigInt(3)]);
+    expect(await range(2000, 1000)).toBe(
+      "The minimum volume cannot exceed the maximum",
+    );
+  });
+
+  it("should only revert a trade nobody has traded after", async () => {
+    await actor.set_trade_undo(BigInt(2), true);
+    const first = createIdentity("fat-finger");
+    await fund(first, BigInt(500));
+    actor.setIdentity(first);
+    const mistake = await actor.buy_shares(BigInt(2), true, BigInt(500));
+    if (!("Ok" in mistake)) throw new Error("buy failed");
+
+    const second = createIdentity("follower");
+    await fund(second, BigInt(100));
+    actor.setIdentity(second);
+    const follow = await actor.buy_shares(BigInt(2), false, BigInt(100));
+    if (!("Ok" in follow)) throw new Error("buy failed");
+
+    actor.setIdentity(first);
+    expect(await actor.revert_trade(mistake.Ok.id)).toEqual({
+      Err: "Another trade has executed on this market since",
+    });
+
+    // The latest trade can go, restoring the pool and the volume
+    actor.setIdentity(second);
+    const treasury = await actor.get_treasury_balance();
+    const reverted = await actor.revert_trade(follow.Ok.id);
+    if (!("Ok" in reverted)) throw new Error(reverted.Err);
+    expect(reverted.Ok.reverted_at).toHaveLength(1);
+    const market = await actor.get_market(BigInt(2));
+    expect(market[0]?.no_liquidity).toBe(BigInt(4000));
+    expect(market[0]?.total_volume).toBe(BigInt(1800 + 500));
+    // The fee and dust come back; the treasury keeps a 0.5% penalty instead
+    expect(await actor.get_balance(second.getPrincipal())).toBe(BigInt(99));
+    expect(await actor.get_treasury_balance()).toBe(
+      treasury - follow.Ok.fee - follow.Ok.dust + BigInt(1),
+    );
+
+    await pic.advanceTime(61 * 1000);
+    actor.setIdentity(first);
+    expect(await actor.revert_trade(mistake.Ok.id)).toEqual({
+      Err: "The undo window for this trade has passed",
+    });
+  });
+
+  it("should return boosted XP to the pool when a buy is reverted", async () => {
+    await actor.set_trade_undo(BigInt(2), true);
+    const sponsor = createIdentity("xp-sponsor");
+    await fund(sponsor, BigInt(500));
+    actor.setIdentity(sponsor);
+    const boost = await actor.sponsor_market(BigInt(2), BigInt(500), {
+      XpMultiplier: { multiplier_bps: BigInt(20_000) },
+    });
+    if (!("Ok" in boost)) throw new Error(boost.Err);
+
+    const trader = createIdentity("boosted-undo");
+    await fund(trader, BigInt(1000));
+    actor.setIdentity(trader);
+    const bought = await actor.buy_shares(BigInt(2), true, BigInt(1000));
+    if (!("Ok" in bought)) throw new Error("buy failed");
+    const drawn = await actor.get_market_boosts(BigInt(2));
+    expect(drawn[0].remaining).toBeLessThan(BigInt(500));
+
+    const reverted = await actor.revert_trade(bought.Ok.id);
+    expect(reverted).toHaveProperty("Ok");
+    const restored = await actor.get_market_boosts(BigInt(2));
+    expect(restored[0].remaining).toBe(BigInt(500));
+  });
+
+  it("should close a position by fraction down to exactly zero", async () => {
+    const trader = createIdentity("closer");
+    await fund(trader, BigInt(1000));
+    actor.setIdentity(trader);
+    const bought = await actor.buy_shares(BigInt(2), true, BigInt(1000));
+    if (!("Ok" in bought)) throw new Error("buy failed");
+
+    expect(
+      await actor.close_position(BigInt(2), true, 0, BigInt(0)),
+    ).toEqual({ Err: "Fraction must be between 1 and 10000 bps" });
+    expect(
+      await actor.close_position(BigInt(2), false, 5000, BigInt(0)),
+    ).toEqual({ Err: "NoPosition: no shares held on this side" });
+    // Half of a 1000 buy cannot pay 1000, so the floor refuses it untouched
+    const greedy = await actor.close_position(
+      BigInt(2),
+      true,
+      5000,
+      BigInt(1000),
+    );
+    if ("Ok" in greedy) throw new Error("sold below min_proceeds");
+    expect(greedy.Err).toMatch(
+      /^Slippage: sale pays \d+ below the minimum 1000$/,
+    );
+
+    const half = await actor.close_position(BigInt(2), true, 5000, BigInt(1));
+    if (!("Ok" in half)) throw new Error(half.Err);
+    expect(half.Ok.sale.shares).toBe(bought.Ok.shares / BigInt(2));
+    expect(half.Ok.remaining.yes_shares).toBe(
+      bought.Ok.shares - half.Ok.sale.shares,
+    );
+
+    const rest = await actor.close_position(
+      BigInt(2),
+      true,
+      10000,
+      BigInt(0),
+    );
+    if (!("Ok" in rest)) throw new Error(rest.Err);
+    expect(rest.Ok.remaining.yes_shares).toBe(BigInt(0));
+    expect(rest.Ok.remaining.yes_cost).toBe(BigInt(0));
+    expect(await actor.get_balance(trader.getPrincipal())).toBe(
+      half.Ok.sale.proceeds + rest.Ok.sale.proceeds,
+    );
+    expect(
+      await actor.close_position(BigInt(2), true, 10000, BigInt(0)),
+    ).toEqual({ Err: "NoPosition: no shares held on this side" });
+  });
+
+  it("should move the Top10 badge with the leaderboard", async () => {
+    const join = async (name: string, xp: number) => {
+      const identity = createIdentity(name);
+      actor.setIdentity(identity);
+      await actor.create_profile([]);
+      actor.setIdentity(admin);
+      await actor.admin_grant_xp(identity.getPrincipal(), BigInt(xp));
+      return identity.getPrincipal();
+    };
+    const badges = async (principal: Principal) =>
+      (await actor.get_profiles([principal]))[0]?.badges ?? [];
+
+    const climber = await join("climber", 500);
+    await actor.trigger_task("leaderboard_badges");
+    expect(await badges(climber)).toContain("Top10");
+
+    const rivals: Principal[] = [];
+    for (let i = 0; i < 10; i++) rivals.push(await join(`rival${i}`, 1000));
+    await actor.trigger_task("leaderboard_badges");
+    expect(await badges(climber)).not.toContain("Top10");
+    expect(await badges(rivals[0])).toContain("Top10");
+  });
+
+  it("should page markets and trades through the v1 bot API", async () => {
+    const page = await actor.api_v1_list_markets(BigInt(0), BigInt(2));
+    if (!("Ok" in page)) throw new Error("list failed");
+    expect(page.Ok.markets.map((m) => m.id)).toEqual([BigInt(1), BigInt(2)]);
+    expect(page.Ok.next_offset).toEqual([BigInt(2)]);
+    expect(page.Ok.markets[0].status).toEqual({ Open: null });
+    expect(page.Ok.markets[0].close_time).toBe(nanos(SAMPLE_MARKET_CLOSE));
+    // Plain queries report the budget without spending it
+    expect(page.Ok.meta.api_version).toBe(1);
+    expect(page.Ok.meta.rate.remaining).toBe(page.Ok.meta.rate.limit);
+
+    expect(await actor.api_v1_market(BigInt(99))).toEqual({
+      Err: { MarketNotFound: null },
+    });
+
+    await actor.buy_shares(BigInt(1), false, BigInt(100));
+    const trades = await actor.api_v1_trades(BigInt(1), [], BigInt(10));
+    if (!("Ok" in trades)) throw new Error("trades failed");
+    expect(trades.Ok.trades).toHaveLength(1);
+    expect(trades.Ok.trades[0].yes).toBe(false);
+    expect(trades.Ok.next_cursor).toEqual([]);
+  });
 });